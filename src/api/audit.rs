@@ -0,0 +1,132 @@
+//! Audit trail of the mutations applied on behalf of clients.
+//!
+//! Each mutation is appended as a `$timestamp $peer_addr $command\n` line, where
+//! the timestamp is in seconds since the Unix epoch and values are left out.
+//! Once the file would grow beyond its size limit, it is renamed with a `.1`
+//! suffix, replacing any earlier rotated file, and a fresh one is started.
+
+use anyhow::{Context, Result};
+use std::{
+    ffi::OsString,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+/// Handle to an audit log, cheap to clone and share between connections.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    appender: Arc<Mutex<Appender>>,
+}
+
+#[derive(Debug)]
+struct Appender {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    max_len: u64,
+}
+
+impl AuditLog {
+    /// Appends to the file at `path`, rotating it once it would grow beyond `max_len` bytes.
+    pub async fn open(path: impl AsRef<Path>, max_len: u64) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = open_for_append(&path).await?;
+        let len = file.metadata().await?.len();
+
+        Ok(Self {
+            appender: Arc::new(Mutex::new(Appender {
+                path,
+                file,
+                len,
+                max_len,
+            })),
+        })
+    }
+
+    /// Records that `peer_addr` had `command` applied.
+    pub async fn record(&self, peer_addr: Option<SocketAddr>, command: &str) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let line = format!(
+            "{}.{:03} {} {}\n",
+            timestamp.as_secs(),
+            timestamp.subsec_millis(),
+            peer_addr.map_or_else(|| "-".into(), |addr| addr.to_string()),
+            command
+        );
+
+        self.appender.lock().await.append(line.as_bytes()).await
+    }
+}
+
+impl Appender {
+    async fn append(&mut self, line: &[u8]) -> Result<()> {
+        if self.len > 0 && self.len + line.len() as u64 > self.max_len {
+            self.rotate().await?;
+        }
+
+        self.file
+            .write_all(line)
+            .await
+            .context("unable to append to audit log")?;
+        self.file.flush().await?;
+        self.len += line.len() as u64;
+
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        let mut rotated = OsString::from(self.path.as_os_str());
+        rotated.push(".1");
+
+        fs::rename(&self.path, &rotated)
+            .await
+            .context("unable to rotate audit log")?;
+        self.file = open_for_append(&self.path).await?;
+        self.len = 0;
+
+        Ok(())
+    }
+}
+
+async fn open_for_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("unable to open audit log at {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn log_rotates_once_it_would_exceed_max_len() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let log = AuditLog::open(&path, 32).await.unwrap();
+
+        // Action.
+        log.record(None, "SET first").await.unwrap();
+        log.record(None, "SET second").await.unwrap();
+        log.record(None, "SET third").await.unwrap();
+
+        // Post-condition.
+        let current = fs::read_to_string(&path).await.unwrap();
+        let rotated = fs::read_to_string(dir.path().join("audit.log.1"))
+            .await
+            .unwrap();
+        assert!(current.ends_with(" - SET third\n"), "{}", current);
+        assert!(rotated.contains(" - SET second\n"), "{}", rotated);
+        assert!(!rotated.contains("SET first"), "{}", rotated);
+    }
+}
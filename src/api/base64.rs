@@ -0,0 +1,91 @@
+//! Base64 with the standard alphabet and padding, as per RFC 4648, so that
+//! arbitrary bytes may travel through the line-based wire protocol.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+const PAD: u8 = b'=';
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | u32::from(*byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let sextet = (group >> (18 - 6 * i)) & 0x3f;
+                encoded.push(char::from(ALPHABET[sextet as usize]));
+            } else {
+                encoded.push(char::from(PAD));
+            }
+        }
+    }
+    encoded
+}
+
+/// Returns the bytes `encoded` stands for, unless it is not padded base64.
+pub fn decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    if !encoded.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+    let quads = encoded.chunks(4);
+    let last = quads.len().saturating_sub(1);
+    for (n, quad) in quads.enumerate() {
+        let padding = quad.iter().rev().take_while(|c| **c == PAD).count();
+        if padding > 2 || (padding > 0 && n != last) {
+            return None;
+        }
+        let mut group = 0u32;
+        for (i, c) in quad[..4 - padding].iter().enumerate() {
+            let sextet = ALPHABET.iter().position(|a| a == c)? as u32;
+            group |= sextet << (18 - 6 * i);
+        }
+        let len = 3 - padding;
+        bytes.extend(group.to_be_bytes()[1..=len].iter());
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_and_decodes_rfc_4648_vectors() {
+        let cases = vec![
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+
+        for (plain, encoded) in cases {
+            assert_eq!(encode(plain.as_bytes()), encoded, "{}", plain);
+            assert_eq!(
+                decode(encoded.as_bytes()),
+                Some(plain.as_bytes().to_vec()),
+                "{}",
+                encoded
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let cases = vec![
+            (b"Zg=".as_ref(), "unpadded"),
+            (b"Zg==Zg==".as_ref(), "padding before the end"),
+            (b"Z===".as_ref(), "too much padding"),
+            (b"Zm9*".as_ref(), "character outside the alphabet"),
+        ];
+
+        for (encoded, reason) in cases {
+            assert_eq!(decode(encoded), None, "{}", reason);
+        }
+    }
+}
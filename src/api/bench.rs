@@ -0,0 +1,175 @@
+//! Load generator issuing a mix of `GET` and `SET` over concurrent connections via [`Client`],
+//! measuring the latency of each request.
+
+use super::client::{self, Client};
+use anyhow::{Context, Result};
+use std::{
+    fmt,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use tokio::time::{self, MissedTickBehavior};
+
+/// What load to generate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// Connections issuing requests concurrently.
+    pub connections: usize,
+    /// How long to issue requests for.
+    pub duration: Duration,
+    /// Target of requests per second across every connection, `None` issuing them as fast as
+    /// possible.
+    pub rate: Option<u64>,
+    /// Fraction of requests that are `SET`s, the others being `GET`s.
+    pub set_ratio: f64,
+    /// Number of distinct keys requested.
+    pub keys: u64,
+    /// Size of the values set, in bytes.
+    pub value_bytes: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            connections: 8,
+            duration: Duration::from_secs(10),
+            rate: None,
+            set_ratio: 0.2,
+            keys: 1000,
+            value_bytes: 64,
+        }
+    }
+}
+
+/// Outcome of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Requests answered.
+    pub ops: u64,
+    pub elapsed: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+impl Report {
+    pub fn ops_per_sec(&self) -> f64 {
+        self.ops as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ops in {:.2?} ({:.0} ops/sec), p50 {:.2?}, p99 {:.2?}",
+            self.ops,
+            self.elapsed,
+            self.ops_per_sec(),
+            self.p50,
+            self.p99
+        )
+    }
+}
+
+/// Issues requests to the server at `addr` as described by `options`, failing on the first
+/// request that fails.
+pub async fn run(addr: SocketAddr, options: &Options) -> Result<Report> {
+    let mut clients = Vec::with_capacity(options.connections);
+    for _ in 0..options.connections {
+        clients.push(Client::connect(addr).await?);
+    }
+
+    let start = Instant::now();
+    let deadline = start + options.duration;
+    let workers: Vec<_> = clients
+        .into_iter()
+        .map(|client| tokio::spawn(work(client, options.clone(), deadline)))
+        .collect();
+    let mut latencies = Vec::new();
+    for worker in workers {
+        latencies.extend(worker.await.context("benchmark connection panicked")??);
+    }
+    let elapsed = start.elapsed();
+
+    latencies.sort_unstable();
+    Ok(Report {
+        ops: latencies.len() as u64,
+        elapsed,
+        p50: percentile(&latencies, 50),
+        p99: percentile(&latencies, 99),
+    })
+}
+
+/// Issues requests over `client` until `deadline`, returning the latency of each.
+async fn work(mut client: Client, options: Options, deadline: Instant) -> Result<Vec<Duration>> {
+    let value = "v".repeat(options.value_bytes.max(1));
+    let mut pace = options.rate.filter(|rate| *rate > 0).map(|rate| {
+        let period = Duration::from_secs_f64(options.connections as f64 / rate as f64);
+        let mut pace = time::interval(period);
+        pace.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        pace
+    });
+
+    let mut latencies = Vec::new();
+    while Instant::now() < deadline {
+        if let Some(pace) = &mut pace {
+            pace.tick().await;
+        }
+        let key = format!("bench:{}", client::random() % options.keys.max(1));
+        let sent = Instant::now();
+        if (client::random() as f64 / u64::MAX as f64) < options.set_ratio {
+            client.set(&key, &value).await?;
+        } else {
+            client.get(&key).await?;
+        }
+        latencies.push(sent.elapsed());
+    }
+    Ok(latencies)
+}
+
+/// Returns the `p`-th percentile of `sorted`, or zero if empty.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    match sorted.len() {
+        0 => Duration::ZERO,
+        n => sorted[(n * p / 100).min(n - 1)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::Server, storage::inmemory};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn bench_runs_against_local_server() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(listener, inmemory::start()).start());
+        let options = Options {
+            connections: 4,
+            duration: Duration::from_millis(200),
+            rate: Some(1000),
+            set_ratio: 0.5,
+            keys: 10,
+            value_bytes: 16,
+        };
+
+        // Action.
+        let report = run(addr, &options).await.unwrap();
+
+        // Post-condition.
+        assert!(report.ops > 0, "{}", report);
+        assert!(report.p50 <= report.p99, "{}", report);
+    }
+
+    #[test]
+    fn percentile_picks_from_sorted_latencies() {
+        let latencies: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&latencies, 50), Duration::from_millis(51));
+        assert_eq!(percentile(&latencies, 99), Duration::from_millis(100));
+        assert_eq!(percentile(&[], 99), Duration::ZERO);
+    }
+}
@@ -0,0 +1,256 @@
+//! Offline bulk transfer of data between a store and a file of commands, or
+//! between two stores.
+//!
+//! Files hold one `SET $key $value\n` command per line, i.e. the same wire
+//! protocol clients speak, so an export can be imported back as is.
+//! Comments and blank lines are skipped on import.
+//!
+//! Values that cannot be written as is, e.g. holding spaces or line breaks,
+//! are exported in base64 between `ENCODING base64\n` and `ENCODING raw\n`,
+//! which requires the `session` feature.
+
+use super::{
+    codec::{Codec, ValueEncoding},
+    types::Request,
+};
+use crate::storage::{types::Value, Store};
+use anyhow::{bail, Context, Result};
+use bytes::BytesMut;
+use futures::StreamExt;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, FramedRead};
+
+/// Applies every `SET` command read from `reader` to `store`, returning how many were applied.
+pub async fn import<R, S>(reader: R, store: &mut S) -> Result<usize>
+where
+    R: AsyncRead + Unpin,
+    S: Store<Err = anyhow::Error>,
+{
+    let mut requests = FramedRead::new(reader, Codec::default());
+
+    let mut imported = 0;
+    // Only ENCODING commands switch it, which builds without the session feature reject.
+    #[cfg_attr(not(feature = "session"), allow(unused_mut))]
+    let mut encoding = ValueEncoding::Raw;
+    while let Some(req) = requests.next().await {
+        match req.with_context(|| format!("unable to import command #{}", imported + 1))? {
+            Request::Set { key, value } => {
+                let value = match encoding {
+                    ValueEncoding::Raw => value.into(),
+                    ValueEncoding::Base64 => super::base64::decode(value.as_bytes())
+                        .with_context(|| format!("malformed base64 value of key {}", key))?
+                        .into(),
+                };
+                store.set(key, value).await?
+            }
+            #[cfg(feature = "session")]
+            Request::Encoding { encoding: next } => {
+                encoding = next;
+                continue;
+            }
+            req => bail!("only SET commands can be imported, found {:?}", req),
+        }
+        imported += 1;
+    }
+
+    Ok(imported)
+}
+
+/// Writes a `SET` command per key in `store` to `writer`, sorted by key, returning how many were written.
+pub async fn export<W, S>(mut writer: W, store: &S) -> Result<usize>
+where
+    W: AsyncWrite + Unpin,
+    S: Store<Err = anyhow::Error>,
+{
+    let mut pairs = store.snapshot().await?;
+    pairs.sort();
+
+    let mut encoding = ValueEncoding::Raw;
+    for (key, value) in &pairs {
+        let raw = [b"SET ", key.as_bytes(), b" ", value, b"\n"].concat();
+        let (command, needed) = if settable_raw(&raw, value) {
+            (raw, ValueEncoding::Raw)
+        } else if cfg!(feature = "session") {
+            let encoded = super::base64::encode(value);
+            let command = [b"SET ", key.as_bytes(), b" ", encoded.as_bytes(), b"\n"].concat();
+            (command, ValueEncoding::Base64)
+        } else {
+            bail!(
+                "value of key {} can only be exported with the session feature",
+                key
+            );
+        };
+        if needed != encoding {
+            encoding = needed;
+            writer
+                .write_all(format!("ENCODING {}\n", encoding).as_bytes())
+                .await
+                .context("unable to export command")?;
+        }
+        writer
+            .write_all(&command)
+            .await
+            .context("unable to export command")?;
+    }
+    if encoding != ValueEncoding::Raw {
+        writer
+            .write_all(b"ENCODING raw\n")
+            .await
+            .context("unable to export command")?;
+    }
+    writer.flush().await?;
+
+    Ok(pairs.len())
+}
+
+/// Whether the `SET` command `raw`, holding `value` as is, is read back as setting `value`.
+fn settable_raw(raw: &[u8], value: &Value) -> bool {
+    match Codec::default().decode(&mut BytesMut::from(raw)) {
+        Ok(Some(Request::Set {
+            key: _,
+            value: read,
+        })) => read.as_bytes() == value.as_ref(),
+        _ => false,
+    }
+}
+
+/// Copies every pair in a snapshot of `src` into `dst`, returning how many were copied.
+///
+/// Keys in `dst` absent from `src` are left as is.
+pub async fn migrate<S, D>(src: &S, dst: &mut D) -> Result<usize>
+where
+    S: Store<Err = anyhow::Error>,
+    D: Store<Err = anyhow::Error>,
+{
+    let pairs = src.snapshot().await?;
+    let migrated = pairs.len();
+
+    for (key, value) in pairs {
+        dst.set(key.clone(), value)
+            .await
+            .with_context(|| format!("unable to migrate key {}", key))?;
+    }
+
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::inmemory;
+
+    #[tokio::test]
+    async fn import_of_export_restores_every_pair() {
+        // Pre-condition.
+        let mut source = inmemory::start();
+        source.set("k".into(), "a".into()).await.unwrap();
+        source.set("j".into(), "b".into()).await.unwrap();
+
+        let mut exported = Vec::new();
+        let exported_count = export(&mut exported, &source).await.unwrap();
+
+        let mut target = inmemory::start();
+
+        // Action.
+        let imported_count = import(exported.as_slice(), &mut target).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(exported, b"SET j b\nSET k a\n");
+        assert_eq!(exported_count, 2);
+        assert_eq!(imported_count, 2);
+
+        let mut pairs = target.snapshot().await.unwrap();
+        pairs.sort();
+        assert_eq!(
+            pairs,
+            vec![("j".into(), "b".into()), ("k".into(), "a".into())]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "session")]
+    async fn import_of_export_restores_values_unfit_for_set_command() {
+        // Pre-condition.
+        let mut source = inmemory::start();
+        source.set("a".into(), "x y".into()).await.unwrap();
+        source.set("b".into(), "x\nSET c z".into()).await.unwrap();
+        source.set("c".into(), "z".into()).await.unwrap();
+        source
+            .set("d".into(), b"\xff\r".to_vec().into())
+            .await
+            .unwrap();
+
+        let mut exported = Vec::new();
+        export(&mut exported, &source).await.unwrap();
+
+        let mut target = inmemory::start();
+
+        // Action.
+        let imported_count = import(exported.as_slice(), &mut target).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(imported_count, 4);
+        let mut expected = source.snapshot().await.unwrap();
+        expected.sort();
+        let mut pairs = target.snapshot().await.unwrap();
+        pairs.sort();
+        assert_eq!(pairs, expected);
+    }
+
+    #[tokio::test]
+    async fn import_of_export_restores_lists() {
+        // Pre-condition.
+        let mut source = inmemory::start();
+        source.lpush("l".into(), "a".into()).await.unwrap();
+        source.lpush("l".into(), "b".into()).await.unwrap();
+
+        let mut exported = Vec::new();
+        export(&mut exported, &source).await.unwrap();
+
+        let mut target = inmemory::start();
+
+        // Action.
+        import(exported.as_slice(), &mut target).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(exported, b"SET l a,b\n");
+        assert_eq!(target.get("l").await.unwrap(), Some("a,b".into()));
+        assert_eq!(target.llen("l").await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn migrate_copies_every_pair_into_other_store() {
+        // Pre-condition.
+        let mut source = inmemory::start();
+        source.fill("k".into(), 100).await.unwrap();
+
+        let mut target = inmemory::start();
+        target.set("k:0".into(), "stale".into()).await.unwrap();
+        target.set("j".into(), "b".into()).await.unwrap();
+
+        // Action.
+        let migrated = migrate(&source, &mut target).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(migrated, 100);
+
+        let mut expected = source.snapshot().await.unwrap();
+        expected.push(("j".into(), "b".into()));
+        expected.sort();
+        let mut pairs = target.snapshot().await.unwrap();
+        pairs.sort();
+        assert_eq!(pairs, expected);
+    }
+
+    #[tokio::test]
+    async fn import_rejects_non_set_command() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+
+        // Action.
+        let imported = import(b"SET k a\nGET k\n".as_ref(), &mut store).await;
+
+        // Post-condition.
+        assert!(imported.is_err());
+    }
+}
@@ -0,0 +1,351 @@
+//! Client of the wire protocol, reconnecting to the server on connection loss.
+//!
+//! Only idempotent commands, e.g. `GET` and `SET`, are retried over a new
+//! connection, since a request whose connection was lost may have been
+//! applied before the response got through.
+
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::SocketAddr,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time,
+};
+use tracing::info;
+
+/// Backoff between attempts to reach the server.
+///
+/// The `n`-th retry waits `base * 2^n`, capped at `max`, plus a random delay
+/// of up to `jitter` so that clients disconnected together do not reconnect
+/// in lockstep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub base: Duration,
+    pub max: Duration,
+    pub jitter: Duration,
+    /// Upper bound on how many times a request is retried, `0` disabling retries.
+    pub attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_millis(50),
+            max: Duration::from_secs(5),
+            jitter: Duration::from_millis(50),
+            attempts: 8,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fails right away instead of retrying.
+    pub fn never() -> Self {
+        Self {
+            attempts: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Returns how long to wait before the `retry`-th retry, counting from `0`.
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base
+            .checked_mul(2u32.saturating_pow(retry))
+            .map_or(self.max, |backoff| backoff.min(self.max));
+        let jitter = match self.jitter.as_nanos() {
+            0 => 0,
+            nanos => u128::from(random()) % nanos,
+        };
+        backoff + Duration::from_nanos(jitter as u64)
+    }
+}
+
+#[derive(Debug)]
+pub struct Client {
+    addr: SocketAddr,
+    policy: RetryPolicy,
+    /// Connection to the server, unless lost since.
+    conn: Option<Connection>,
+}
+
+impl Client {
+    /// Connects to the server at `addr`, failing on connection loss.
+    pub async fn connect(addr: SocketAddr) -> Result<Self> {
+        Self::connect_with_retry(addr, RetryPolicy::never()).await
+    }
+
+    /// Connects to the server at `addr`, reconnecting according to `policy` whenever unable to.
+    pub async fn connect_with_retry(addr: SocketAddr, policy: RetryPolicy) -> Result<Self> {
+        let mut client = Self {
+            addr,
+            policy,
+            conn: None,
+        };
+        client.reconnect().await?;
+        Ok(client)
+    }
+
+    /// Returns the value under `key`, if any.
+    pub async fn get(&mut self, key: &str) -> Result<Option<String>> {
+        let line = self.call(&format!("GET {}", word("key", key)?)).await?;
+        match line.split_once(' ') {
+            Some(("OKAY", pair)) => {
+                let (_, value) = pair
+                    .split_once(' ')
+                    .with_context(|| format!("missing value from {:?}", line))?;
+                Ok(Some(value.to_owned()))
+            }
+            Some(("FAIL", _)) => Ok(None),
+            _ => bail!("unexpected response from server: {:?}", line),
+        }
+    }
+
+    /// Sets `key` to `value`.
+    pub async fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let request = format!("SET {} {}", word("key", key)?, word("value", value)?);
+        let line = self.call(&request).await?;
+        match line.split_once(' ') {
+            Some(("OKAY", _)) => Ok(()),
+            _ => bail!("unexpected response from server: {:?}", line),
+        }
+    }
+
+    /// Sends `request`, returning its response, over a new connection if the current one is lost.
+    async fn call(&mut self, request: &str) -> Result<String> {
+        let mut retry = 0;
+        loop {
+            let conn = match &mut self.conn {
+                Some(conn) => conn,
+                None => self.reconnect().await?,
+            };
+
+            let response = match conn.send(request).await {
+                Ok(()) => conn.receive_some().await,
+                Err(e) => Err(e),
+            };
+            match response {
+                Ok(line) => return Ok(line),
+                Err(e) if retry < self.policy.attempts => {
+                    info!(reason = %e, addr = %self.addr, "lost connection to server");
+                    self.conn = None;
+                    time::sleep(self.policy.delay(retry)).await;
+                    retry += 1;
+                }
+                Err(e) => {
+                    self.conn = None;
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Connects to the server anew, retrying according to the policy.
+    async fn reconnect(&mut self) -> Result<&mut Connection> {
+        let mut retry = 0;
+        let conn = loop {
+            match Connection::open(self.addr).await {
+                Ok(conn) => break conn,
+                Err(e) if retry < self.policy.attempts => {
+                    info!(reason = %e, addr = %self.addr, "unable to reach server");
+                    time::sleep(self.policy.delay(retry)).await;
+                    retry += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        Ok(self.conn.insert(conn))
+    }
+}
+
+/// Returns `s`, failing if it would not make it through the wire protocol as a single component.
+fn word<'s>(what: &str, s: &'s str) -> Result<&'s str> {
+    match s.chars().find(|c| c.is_whitespace()) {
+        Some(c) => bail!("{} {:?} contains whitespace {:?}", what, s, c),
+        None => Ok(s),
+    }
+}
+
+/// Returns a random number, as the keys of a fresh [`RandomState`] are.
+pub(super) fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Line-oriented connection to a server.
+#[derive(Debug)]
+pub(super) struct Connection(BufReader<TcpStream>);
+
+impl Connection {
+    pub(super) async fn open(addr: SocketAddr) -> Result<Self> {
+        let conn = TcpStream::connect(addr)
+            .await
+            .with_context(|| format!("unable to connect to server at {}", addr))?;
+        Ok(Self(BufReader::new(conn)))
+    }
+
+    pub(super) async fn send(&mut self, request: &str) -> Result<()> {
+        let request = [request.as_bytes(), b"\n"].concat();
+        self.0
+            .write_all(&request)
+            .await
+            .context("unable to send request to server")
+    }
+
+    /// Returns the next response line, stripped of its terminator, unless the server hung up.
+    ///
+    /// Heartbeat pings are answered along the way.
+    pub(super) async fn receive(&mut self) -> Result<Option<String>> {
+        loop {
+            let mut line = String::new();
+            let read = self
+                .0
+                .read_line(&mut line)
+                .await
+                .context("unable to receive response from server")?;
+            if read == 0 {
+                return Ok(None);
+            }
+
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            if line == "PING" {
+                self.send("PONG").await?;
+                continue;
+            }
+            return Ok(Some(line.to_owned()));
+        }
+    }
+
+    pub(super) async fn receive_some(&mut self) -> Result<String> {
+        self.receive()
+            .await?
+            .context("server closed connection midway")
+    }
+
+    /// Returns the next response line stripped of `prefix`, failing unless it starts with it.
+    pub(super) async fn expect(&mut self, prefix: &str) -> Result<String> {
+        let line = self.receive_some().await?;
+        match line.strip_prefix(prefix) {
+            Some(rest) => Ok(rest.to_owned()),
+            None => bail!("expected {:?} from server, found {:?}", prefix, line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::Server, storage::inmemory};
+    use tokio::{net::TcpListener, sync::oneshot};
+
+    #[tokio::test]
+    async fn client_recovers_from_restarted_server() {
+        // Pre-condition.
+        let store = inmemory::start();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_signal) = oneshot::channel();
+        let server = tokio::spawn(
+            Server::new(listener, store.clone())
+                .shutdown_grace(Duration::ZERO)
+                .start_until(async {
+                    let _ = shutdown_signal.await;
+                }),
+        );
+
+        let policy = RetryPolicy {
+            base: Duration::from_millis(10),
+            max: Duration::from_millis(100),
+            jitter: Duration::from_millis(10),
+            attempts: 20,
+        };
+        let mut client = Client::connect_with_retry(addr, policy).await.unwrap();
+        client.set("k", "toy").await.unwrap();
+
+        shutdown.send(()).unwrap();
+        server.await.unwrap();
+
+        // Action.
+        let restart = tokio::spawn(async move {
+            time::sleep(Duration::from_millis(50)).await;
+            let listener = TcpListener::bind(addr).await.unwrap();
+            Server::new(listener, store).start().await
+        });
+        let value = client.get("k").await;
+
+        // Post-condition.
+        assert_eq!(value.unwrap(), Some("toy".into()));
+        restart.abort();
+    }
+
+    #[tokio::test]
+    async fn client_without_retries_fails_once_server_is_gone() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_signal) = oneshot::channel();
+        let server = tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .shutdown_grace(Duration::ZERO)
+                .start_until(async {
+                    let _ = shutdown_signal.await;
+                }),
+        );
+        let mut client = Client::connect(addr).await.unwrap();
+
+        // Action.
+        shutdown.send(()).unwrap();
+        server.await.unwrap();
+        let value = client.get("k").await;
+
+        // Post-condition.
+        assert!(value.is_err());
+    }
+
+    #[tokio::test]
+    async fn client_refuses_arguments_with_whitespace() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(listener, inmemory::start()).start());
+        let mut client = Client::connect(addr).await.unwrap();
+
+        // Action.
+        let set = client.set("k", "toy storage").await;
+
+        // Post-condition.
+        assert!(set.is_err());
+        assert_eq!(client.get("k").await.unwrap(), None);
+    }
+
+    #[test]
+    fn delay_grows_exponentially_up_to_max_plus_jitter() {
+        let policy = RetryPolicy {
+            base: Duration::from_millis(10),
+            max: Duration::from_millis(100),
+            jitter: Duration::from_millis(5),
+            attempts: 10,
+        };
+
+        let cases = vec![
+            (0, Duration::from_millis(10), "first retry"),
+            (2, Duration::from_millis(40), "third retry"),
+            (4, Duration::from_millis(100), "capped at max"),
+            (u32::MAX, Duration::from_millis(100), "overflowing backoff"),
+        ];
+
+        for (retry, backoff, reason) in cases {
+            let delay = policy.delay(retry);
+            assert!(
+                delay >= backoff && delay < backoff + policy.jitter,
+                "{}: {:?}",
+                reason,
+                delay
+            );
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Registry of the clients connected to a server, through which they may be terminated.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+pub type ConnId = u64;
+
+/// Connected client, as listed by `CLIENT LIST`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientInfo {
+    pub id: ConnId,
+    pub peer_addr: SocketAddr,
+    /// How long the client has been connected for.
+    pub age: Duration,
+}
+
+/// Handle to the registry, cheap to clone and share between connections.
+#[derive(Debug, Clone, Default)]
+pub struct Clients {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: AtomicU64,
+    connected: Mutex<HashMap<ConnId, Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    peer_addr: SocketAddr,
+    connected_at: Instant,
+    kill: Arc<Notify>,
+}
+
+/// Membership of a connection in the registry, left once dropped.
+#[derive(Debug)]
+pub struct Registration {
+    id: ConnId,
+    kill: Arc<Notify>,
+    clients: Clients,
+}
+
+impl Clients {
+    /// Adds the client connected from `peer_addr` for as long as the registration lives.
+    pub fn register(&self, peer_addr: SocketAddr) -> Registration {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let kill = Arc::new(Notify::new());
+        self.inner.connected.lock().unwrap().insert(
+            id,
+            Entry {
+                peer_addr,
+                connected_at: Instant::now(),
+                kill: Arc::clone(&kill),
+            },
+        );
+
+        Registration {
+            id,
+            kill,
+            clients: self.clone(),
+        }
+    }
+
+    /// Returns every connected client, oldest first.
+    pub fn list(&self) -> Vec<ClientInfo> {
+        let now = Instant::now();
+        let mut clients: Vec<_> = self
+            .inner
+            .connected
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| ClientInfo {
+                id: *id,
+                peer_addr: entry.peer_addr,
+                age: now.duration_since(entry.connected_at),
+            })
+            .collect();
+        clients.sort_unstable_by_key(|client| client.id);
+        clients
+    }
+
+    /// Terminates the clients connected from `peer_addr`, returning whether there were any.
+    pub fn kill(&self, peer_addr: SocketAddr) -> bool {
+        let connected = self.inner.connected.lock().unwrap();
+        let mut killed = false;
+        for entry in connected
+            .values()
+            .filter(|entry| entry.peer_addr == peer_addr)
+        {
+            entry.kill.notify_one();
+            killed = true;
+        }
+        killed
+    }
+}
+
+impl Registration {
+    /// Returns the signal notified once the client is terminated via [`Clients::kill`].
+    pub fn kill_signal(&self) -> Arc<Notify> {
+        Arc::clone(&self.kill)
+    }
+
+    /// Returns the registry the client is part of.
+    pub fn clients(&self) -> &Clients {
+        &self.clients
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.clients
+            .inner
+            .connected
+            .lock()
+            .unwrap()
+            .remove(&self.id);
+    }
+}
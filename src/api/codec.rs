@@ -4,32 +4,579 @@
 //! and response are line-delimited and further split by whitespaces into
 //! components.
 //!
+//! Requests may end with either `\n` or `\r\n`, the latter being stripped of
+//! its `\r` before parsing. Responses end with `\n` by default, or `\r\n`
+//! depending on the codec's [`LineEnding`].
+//!
+//! Lines starting with `#` and blank lines are skipped without a response,
+//! so that files of commands may be annotated.
+//!
+//! Lines are only decoded once complete, hence a multibyte character split
+//! across reads is reassembled byte-exact. A line that is not valid UTF-8 as
+//! a whole, e.g. holding a value of arbitrary bytes, is a malformed request
+//! rather than being decoded lossily.
+//!
 //! # Request
 //!
 //! - GET
 //!     - `GET $key\n`
-//! - SET
+//! - GETDEL
+//!     - `GETDEL $key\n`
+//! - GETRANGE
+//!     - `GETRANGE $key $start $end\n`
+//! - GETOR
+//!     - `GETOR $key $default\n`
+//! - CHECKSUM
+//!     - `CHECKSUM $key\n`
+//! - BGET
+//!     - `BGET $key $timeout\n`
+//! - SET (`$value` is empty if omitted or made of whitespace only)
 //!     - `SET $key $value\n`
+//!     - `SET $key $value GET\n`
+//! - SETNX
+//!     - `SETNX $key $value\n`
+//! - SETIFMATCH
+//!     - `SETIFMATCH $key $pattern $value\n`
+//! - SETIDEM
+//!     - `SETIDEM $key $token $value\n`
+//! - MSETNX
+//!     - `MSETNX $key $value [$key $value ...]\n`
+//! - DELIFEQ
+//!     - `DELIFEQ $key $expected\n`
+//! - RENAME
+//!     - `RENAME $src $dst\n`
+//! - MULTI
+//!     - `MULTI\n`
+//! - EXEC
+//!     - `EXEC\n`
+//! - DISCARD
+//!     - `DISCARD\n`
+//! - WATCH
+//!     - `WATCH $key [$key ...]\n`
+//! - UNWATCH
+//!     - `UNWATCH\n`
+//! - DUMP
+//!     - `DUMP\n`
+//! - GETPREFIX
+//!     - `GETPREFIX $prefix\n`
+//! - CHILDREN
+//!     - `CHILDREN $prefix $separator\n`
+//! - PSUBSCRIBE
+//!     - `PSUBSCRIBE $prefix\n`
+//! - SUBSCRIBE-EXPIRED
+//!     - `SUBSCRIBE-EXPIRED\n`
+//! - COMPACT
+//!     - `COMPACT\n`
+//! - SYNC
+//!     - `SYNC\n`
+//! - FILL
+//!     - `FILL $prefix $count\n`
+//! - DBSIZE
+//!     - `DBSIZE\n`
+//! - DBLIST
+//!     - `DBLIST\n`
+//! - MEMUSAGE
+//!     - `MEMUSAGE $prefix\n`
+//! - FINDVAL (scanning every value, hence meant for debugging)
+//!     - `FINDVAL $substring\n`
+//! - CAPABILITIES
+//!     - `CAPABILITIES\n`
+//! - LASTSEQ
+//!     - `LASTSEQ\n`
+//! - GETLOG
+//!     - `GETLOG $count\n`
+//! - INFO
+//!     - `INFO\n`
+//! - TIME
+//!     - `TIME\n`
+//! - USE
+//!     - `USE $name\n`
+//! - CLIENT LIST
+//!     - `CLIENT LIST\n`
+//! - CLIENT KILL
+//!     - `CLIENT KILL $peer_addr\n`
+//! - CONFIG GET
+//!     - `CONFIG GET $param\n`
+//! - CONFIG SET
+//!     - `CONFIG SET $param $value\n`
+//! - PONG (answer to `PING`, itself left unanswered)
+//!     - `PONG\n`
+//! - IDLETIME
+//!     - `IDLETIME $key\n`
+//! - HOTKEYS
+//!     - `HOTKEYS $count\n`
+//! - HELLO
+//!     - `HELLO $version\n`
+//! - AUTH
+//!     - `AUTH $password\n`
+//! - ENCODING (`$encoding` being `raw` or `base64`)
+//!     - `ENCODING $encoding\n`
+//! - EXPIRE
+//!     - `EXPIRE $key $seconds\n`
+//! - TTL
+//!     - `TTL $key\n`
+//! - GETTTL
+//!     - `GETTTL $key\n`
+//! - SETEX
+//!     - `SETEX $key $seconds $value\n`
+//! - INCRBY
+//!     - `INCRBY $key $delta\n`
+//! - LPUSH
+//!     - `LPUSH $key $item\n`
+//! - LLEN
+//!     - `LLEN $key\n`
+//! - SETCHUNK (`$seq` counting the chunks of `$key` from `0`)
+//!     - `SETCHUNK $key $seq $data\n`
+//! - SETCHUNKEND
+//!     - `SETCHUNKEND $key\n`
+//! - MAX
+//!     - `MAX $key $candidate\n`
+//! - MIN
+//!     - `MIN $key $candidate\n`
+//! - DEADLINE (prefixing any other request)
+//!     - `DEADLINE $milliseconds $request\n`
 //!
 //! # Response
 //!
 //! - GET
 //!     - OK
-//!         - `OKAY $key\n`
+//!         - `OKAY $key $value\n`
+//!     - FAIL (`$key` does not exist)
+//!         - `FAIL $key\n`, or `NIL $key\n` depending on the codec's [`MissingResponse`]
+//! - GETDEL
+//!     - OK (`$key` is removed)
+//!         - `OKAY $key $value\n`
+//!     - FAIL (`$key` does not exist)
+//!         - `FAIL $key\n`
+//! - GETRANGE (`$value` holds the bytes from `$start` up to `$end` inclusive, negative
+//!   indices counting from the end and out-of-bounds ones being clamped)
+//!     - OK
+//!         - `OKAY $key $value\n`
+//!     - FAIL (`$key` does not exist)
+//!         - `FAIL $key\n`
+//! - BGET (waits up to `$timeout` milliseconds for `$key` to be set unless it exists)
+//!     - OK
+//!         - `OKAY $key $value\n`
+//!     - FAIL (`$timeout` elapsed)
+//!         - `FAIL $key\n`
+//! - CHECKSUM (`$hash` is the CRC32 of the value under `$key` as 8 hex digits)
+//!     - OK
+//!         - `OKAY $key $hash\n`
+//!     - FAIL (`$key` does not exist)
+//!         - `FAIL $key\n`
+//! - GETOR (`$value` is `$default` when `$key` does not exist)
+//!     - OK
+//!         - `OKAY $key $value\n`
 //! - SET
 //!     - OK
 //!         - `OKAY $key $value\n`
 //!     - FAIL
 //!         - `FAIL $key\n`
+//! - SET with `GET` (`$value` is the one `$key` held before)
+//!     - OK
+//!         - `OKAY $key $value\n`
+//!     - FAIL (`$key` did not exist, yet is set all the same)
+//!         - `FAIL $key\n`
+//! - SETNX
+//!     - OK
+//!         - `OKAY $key\n`
+//!     - FAIL (`$key` already exists)
+//!         - `FAIL $key\n`
+//! - SETIFMATCH
+//!     - OK
+//!         - `OKAY $key\n`
+//!     - FAIL (`$key` is missing, or its value does not start with `$pattern`)
+//!         - `FAIL $key\n`
+//! - SETIDEM (a `$token` applied recently is not applied again)
+//!     - OK (applied just now)
+//!         - `OKAY $key APPLIED\n`
+//!     - OK (deduplicated, leaving `$key` as is)
+//!         - `OKAY $key DUPLICATE\n`
+//! - MSETNX
+//!     - OK (every `$key` is set)
+//!         - `OKAY\n`
+//!     - FAIL (some `$key` exists, none being set)
+//!         - `FAIL\n`
+//! - DELIFEQ
+//!     - OK (`$key` is removed)
+//!         - `OKAY $key\n`
+//!     - FAIL (`$key` does not exist, or its value differs from `$expected`)
+//!         - `FAIL $key\n`
+//! - RENAME
+//!     - OK
+//!         - `OKAY\n`
+//!     - FAIL (`$src` does not exist)
+//!         - `FAIL\n`
+//! - MULTI
+//!     - OK
+//!         - `OKAY\n`
+//!     - FAIL (already in a transaction)
+//!         - `FAIL\n`
+//! - Any command queued in a transaction
+//!     - OK
+//!         - `OKAY QUEUED\n`
+//! - EXEC
+//!     - OK (followed by one response line per queued command)
+//!         - `OKAY $count\n`
+//!     - FAIL (not in a transaction)
+//!         - `FAIL\n`
+//!     - FAIL (a watched key changed since `WATCH`, nothing being applied)
+//!         - `FAIL ABORTED\n`
+//! - DISCARD
+//!     - OK
+//!         - `OKAY\n`
+//!     - FAIL (not in a transaction)
+//!         - `FAIL\n`
+//! - WATCH (the keys are watched until the next `EXEC`, `DISCARD` or `UNWATCH`)
+//!     - OK
+//!         - `OKAY\n`
+//!     - FAIL (already in a transaction)
+//!         - `FAIL\n`
+//! - UNWATCH
+//!     - OK
+//!         - `OKAY\n`
+//! - DUMP (one line per pair, streamed from a snapshot of the store)
+//!     - OK
+//!         - `OKAY $key $value\n`
+//!         - ...
+//!         - `OKAY END\n`
+//! - GETPREFIX (`$count` lines follow, one `$key $value\n` line per pair whose key starts with
+//!   `$prefix`, sorted by key; a value may contain spaces, hence spans the rest of its line)
+//!     - OK
+//!         - `OKAY $count\n`
+//!         - `$key $value\n`
+//!         - ...
+//! - CHILDREN (`$count` lines follow, one `$segment\n` line per distinct segment following
+//!   `$prefix` up to `$separator` among keys starting with `$prefix`, sorted)
+//!     - OK
+//!         - `OKAY $count\n`
+//!         - `$segment\n`
+//!         - ...
+//! - PSUBSCRIBE (one line per mutation of a key under `$prefix`, streamed until the client
+//!   sends any other line)
+//!     - OK
+//!         - `OKAY $prefix\n`
+//!         - `NOTIFY SET $key\n`
+//!         - `NOTIFY DEL $key\n`
+//!         - `NOTIFY EXPIRE $key\n`
+//!         - ...
+//!         - `OKAY END\n`
+//! - SUBSCRIBE-EXPIRED (one line per key removed once expired, streamed until the client sends
+//!   any other line)
+//!     - OK
+//!         - `OKAY\n`
+//!         - `EXPIRED $key\n`
+//!         - ...
+//!         - `OKAY END\n`
+//! - COMPACT (storage no longer affecting data, e.g. superseded log records, is discarded)
+//!     - OK
+//!         - `OKAY\n`
+//! - SYNC (every write applied so far is durable)
+//!     - OK
+//!         - `OKAY\n`
+//! - IDLETIME
+//!     - OK (seconds since `$key` was last read or written)
+//!         - `OKAY $key $seconds\n`
+//!     - FAIL (`$key` does not exist)
+//!         - `FAIL $key\n`
+//! - HOTKEYS (up to `$count` lines follow, one `$key\n` line per key, those read or written
+//!   most recently first)
+//!     - OK
+//!         - `OKAY $count\n`
+//!         - `$key\n`
+//!         - ...
+//! - HELLO (`$version` is the one supported by the server)
+//!     - OK
+//!         - `OKAY $version\n`
+//!     - FAIL (requested version is unsupported)
+//!         - `FAIL $version\n`
+//! - AUTH
+//!     - OK
+//!         - `OKAY\n`
+//!     - FAIL (unknown password)
+//!         - `FAIL\n`
+//! - ENCODING (values written by the client are decoded from, and values read back encoded to,
+//!   `$encoding` for the rest of the connection)
+//!     - OK
+//!         - `OKAY $encoding\n`
+//! - EXPIRE
+//!     - OK
+//!         - `OKAY $key\n`
+//!     - FAIL (`$key` does not exist)
+//!         - `FAIL $key\n`
+//! - TTL
+//!     - OK (`$seconds` is `-1` when `$key` never expires)
+//!         - `OKAY $key $seconds\n`
+//!     - FAIL (`$key` does not exist)
+//!         - `FAIL $key\n`
+//! - GETTTL
+//!     - OK (`$seconds` is `-1` when `$key` never expires)
+//!         - `OKAY $key $seconds $value\n`
+//!     - FAIL (`$key` does not exist)
+//!         - `FAIL $key\n`
+//! - SETEX (`$key` expires once `$seconds` have passed)
+//!     - OK
+//!         - `OKAY $key\n`
+//! - INCRBY (a missing `$key` counts as `0`)
+//!     - OK (`$value` is the integer under `$key` once `$delta` is added)
+//!         - `OKAY $key $value\n`
+//!     - FAIL (the value under `$key` is not an integer, or the result would overflow)
+//!         - `FAIL $key\n`
+//! - LPUSH, LLEN (a missing `$key` holds an empty list)
+//!     - OK (`$len` is the number of items under `$key`, once `$item` is appended for LPUSH)
+//!         - `OKAY $key $len\n`
+//! - SETCHUNK (`$data` is held back until `SETCHUNKEND`)
+//!     - OK
+//!         - `OKAY $key\n`
+//!     - FAIL (`$seq` is not the next one, `$expected`, the chunk being ignored)
+//!         - `FAIL $key $expected\n`
+//! - SETCHUNKEND (the chunks of `$key` are concatenated in order and set as its value)
+//!     - OK
+//!         - `OKAY $key\n`
+//!     - FAIL (no chunk of `$key` is held back)
+//!         - `FAIL $key\n`
+//! - MAX, MIN (a missing `$key` is set to `$candidate`)
+//!     - OK (`$value` is the greater, respectively lesser, of `$candidate` and the integer
+//!       under `$key`, which now holds it)
+//!         - `OKAY $key $value\n`
+//!     - FAIL (the value under `$key` is not an integer)
+//!         - `FAIL $key\n`
+//! - DEADLINE (the response to `$request`, unless its store operations take longer than
+//!   `$milliseconds`)
+//!     - ERR
+//!         - `ERR deadline exceeded\n`
+//! - FILL (keys `$prefix:0` up to `$prefix:$count` exclusive are set to their index)
+//!     - OK
+//!         - `OKAY $created\n`
+//! - DBSIZE
+//!     - OK
+//!         - `OKAY $size\n`
+//! - DBLIST (`$count` lines follow, one `$name $size\n` line per store selected so far with
+//!   `USE`, sorted by name)
+//!     - OK
+//!         - `OKAY $count\n`
+//!         - `$name $size\n`
+//!         - ...
+//! - MEMUSAGE (`$count` lines follow, one `$key $bytes\n` line per key starting with `$prefix`,
+//!   `$bytes` being the size of its value, largest first)
+//!     - OK
+//!         - `OKAY $count\n`
+//!         - `$key $bytes\n`
+//!         - ...
+//! - FINDVAL (`$count` lines follow, one `$key\n` line per key whose value contains
+//!   `$substring`, sorted)
+//!     - OK
+//!         - `OKAY $count\n`
+//!         - `$key\n`
+//!         - ...
+//! - CAPABILITIES (`$features` lists those supported by the store among `expiry`, `sync`, `scan`
+//!   and `subscribe`, separated by spaces)
+//!     - OK
+//!         - `OKAY $features\n`
+//! - LASTSEQ (`$seq` is bumped by every command that mutates data, starting from `0`)
+//!     - OK
+//!         - `OKAY $seq\n`
+//! - GETLOG (up to `$count` lines follow, one `$seq $op $key\n` line per mutation among the
+//!   latest ones recorded, oldest first, `$op` being `SET`, `DEL` or `EXPIRE` and `$seq` the
+//!   `LASTSEQ` of the command applying it)
+//!     - OK
+//!         - `OKAY $count\n`
+//!         - `$seq $op $key\n`
+//!         - ...
+//! - INFO (`$count` lines follow, one `$name $value\n` line per field, i.e. `queue_depth` and
+//!   `queue_capacity`, how many commands await the store and may do so before further ones must
+//!   wait)
+//!     - OK
+//!         - `OKAY $count\n`
+//!         - `$name $value\n`
+//!         - ...
+//! - TIME (seconds since the Unix epoch as of the server's clock, and since it started)
+//!     - OK
+//!         - `OKAY $unix_secs $uptime_secs\n`
+//! - USE
+//!     - OK
+//!         - `OKAY\n`
+//!     - FAIL (the server hosts no named stores, or in a transaction)
+//!         - `FAIL\n`
+//! - CONFIG GET (`$value` is `none` for an unset setting)
+//!     - OK
+//!         - `OKAY $param $value\n`
+//!     - FAIL (there is no setting named `$param`)
+//!         - `FAIL $param\n`
+//! - CONFIG SET (only `command-timeout-ms`, `read-only`, and `max-connections-per-ip` may be
+//!   set, `none` unsetting them)
+//!     - OK
+//!         - `OKAY $param\n`
+//!     - ERR (`$param` may not be set, or `$value` is malformed)
+//!         - `ERR $reason\n`
+//! - CLIENT LIST
+//!     - OK (followed by one `$id $peer_addr $age_seconds\n` line per connected client)
+//!         - `OKAY $count\n`
+//!     - FAIL (the server keeps no registry of clients)
+//!         - `FAIL\n`
+//! - CLIENT KILL
+//!     - OK (every client connected from `$peer_addr` is disconnected)
+//!         - `OKAY\n`
+//!     - FAIL (no client is connected from `$peer_addr`)
+//!         - `FAIL\n`
+//! - Probe of an idle client, if the server sends heartbeats (expecting any request, e.g. `PONG`)
+//!     - `PING\n`
+//! - Any command the store is too busy to accept in time
+//!     - FAIL
+//!         - `FAIL BUSY\n`
+//! - Any command touching keys outside of the prefix granted by AUTH
+//!     - ERR
+//!         - `ERR $reason\n`
+//! - Any malformed request, if the server replies to those
+//!     - ERR
+//!         - `ERR $reason\n`
+//!
+//! # Validation
+//!
+//! Decoded requests are validated according to a [`Validation`] policy, such
+//! that a key or value that could not be read back through this protocol is
+//! rejected upfront.
 
-use super::types::{Request, Response, Status};
-use anyhow::{bail, Context, Result};
-use bytes::BytesMut;
-use tokio_util::codec::{Decoder, Encoder, LinesCodec};
+use super::{
+    metrics::FrameMetrics,
+    types::{Request, Response, Status},
+};
+#[cfg(feature = "extended")]
+use crate::storage::types::Extremum;
+use crate::storage::types::{LogEntry, Mutation};
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{BufMut, BytesMut};
+#[cfg(any(
+    feature = "session",
+    feature = "expiry",
+    feature = "admin",
+    feature = "extended"
+))]
+use std::num::{IntErrorKind, ParseIntError};
+use std::{
+    fmt::{self, Write},
+    io,
+    str::FromStr,
+    sync::Arc,
+};
+use tokio_util::codec::{Decoder, Encoder, LinesCodec, LinesCodecError};
+
+/// Version of the wire protocol spoken by this codec, as negotiated through `HELLO`.
+pub const PROTOCOL_VERSION: u32 = 1;
 
 #[derive(Default, Debug)]
 pub struct Codec {
     lines: LinesCodec,
+    validation: Validation,
+    line_ending: LineEnding,
+    missing_response: MissingResponse,
+    /// Size beyond which a response is replaced by an error, in bytes, if bounded.
+    max_response_bytes: Option<usize>,
+    metrics: Option<Arc<FrameMetrics>>,
+}
+
+/// Context of errors caused by a well-delimited but invalid request line,
+/// after which decoding may carry on with the next line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MalformedRequest;
+
+impl fmt::Display for MalformedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unable to parse request")
+    }
+}
+
+/// Policy on which characters keys and values may contain.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// Rejects only characters that break the protocol, i.e. line feeds, spaces and a trailing
+    /// carriage return, which would be taken for part of a `\r\n` line ending.
+    #[default]
+    Lenient,
+    /// Additionally rejects any other whitespace or control character.
+    Strict,
+}
+
+/// Terminator of response lines.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`
+    #[default]
+    Lf,
+    /// `\r\n`
+    CrLf,
+}
+
+/// Status answering a `GET` of a missing key.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MissingResponse {
+    /// `FAIL $key`
+    #[default]
+    Fail,
+    /// `NIL $key`, telling a missing key apart from a failure.
+    Nil,
+}
+
+/// How values are written by clients and read back, as negotiated per connection.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueEncoding {
+    /// Values as they are, hence unable to hold line breaks or spaces.
+    #[default]
+    Raw,
+    /// Values in base64, hence able to hold arbitrary bytes.
+    Base64,
+}
+
+impl Codec {
+    pub fn with_validation(validation: Validation) -> Self {
+        Self {
+            lines: LinesCodec::default(),
+            validation,
+            line_ending: LineEnding::default(),
+            missing_response: MissingResponse::default(),
+            max_response_bytes: None,
+            metrics: None,
+        }
+    }
+
+    /// Terminates response lines with `line_ending`, requests being accepted with either.
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Answers a `GET` of a missing key with `missing_response`.
+    pub fn with_missing_response(mut self, missing_response: MissingResponse) -> Self {
+        self.missing_response = missing_response;
+        self
+    }
+
+    /// Answers with an error instead of any response taking more than `max` bytes, terminator
+    /// included, e.g. a `GETPREFIX` matching a huge number of keys.
+    pub fn with_max_response_bytes(mut self, max: usize) -> Self {
+        self.max_response_bytes = Some(max);
+        self
+    }
+
+    /// Records the size of every frame decoded or encoded into `metrics`, along with the
+    /// command of every request and whether every response reports an error.
+    pub fn with_metrics(mut self, metrics: Arc<FrameMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn parse(&self, line: &str) -> Result<Request> {
+        if let Some(metrics) = &self.metrics {
+            metrics.request_bytes.observe(line.len());
+        }
+
+        let request = Request::from_wire(line)?;
+        request.validate(self.validation)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.count_command(line.split(' ').next().unwrap_or_default());
+        }
+        Ok(request)
+    }
 }
 
 impl Decoder for Codec {
@@ -38,13 +585,40 @@ impl Decoder for Codec {
     type Error = anyhow::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        self.lines
-            .decode(src)
-            .context("unable to decode request line")?
-            .as_deref()
-            .map(Request::from_wire)
-            .transpose()
-            .context("unable to parse request")
+        loop {
+            let line = match self.lines.decode(src) {
+                // The offending line is consumed already, hence decoding may carry on.
+                Err(LinesCodecError::Io(e)) if e.kind() == io::ErrorKind::InvalidData => {
+                    return Err(anyhow!("request line is not valid UTF-8"))
+                        .context(MalformedRequest)
+                }
+                line => line.context("unable to decode request line")?,
+            };
+            match line.as_deref() {
+                Some(line) if is_noop(line) => continue,
+                line => {
+                    return line
+                        .map(|line| self.parse(line))
+                        .transpose()
+                        .context(MalformedRequest)
+                }
+            }
+        }
+    }
+}
+
+/// Whether `line` is a comment or blank, hence skipped without a response.
+fn is_noop(line: &str) -> bool {
+    line.starts_with('#') || line.trim().is_empty()
+}
+
+/// Returns how many bytes `response` takes on the wire, terminator included, as encoded by
+/// default.
+pub fn wire_len(response: &Response) -> usize {
+    let mut dst = BytesMut::new();
+    match Codec::default().encode(response.clone(), &mut dst) {
+        Ok(()) => dst.len(),
+        Err(_) => 0,
     }
 }
 
@@ -52,17 +626,127 @@ impl Encoder<Response> for Codec {
     type Error = anyhow::Error;
 
     fn encode(&mut self, item: Response, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        self.lines
-            .encode(item.into_wire(), dst)
-            .context("unable to encode response line")
+        // Written straight into `dst`, whose capacity is reused across responses.
+        let start = dst.len();
+        let mut status = item.status();
+        item.encode_to(self.line_ending, self.missing_response, dst)?;
+        dst.extend_from_slice(self.line_ending.into_wire());
+        if let Some(max) = self.max_response_bytes {
+            let size = dst.len() - start;
+            if size > max {
+                dst.truncate(start);
+                let too_large = Response::Error {
+                    message: format!("response of {} bytes exceeds limit of {}", size, max),
+                };
+                status = too_large.status();
+                too_large.encode_to(self.line_ending, self.missing_response, dst)?;
+                dst.extend_from_slice(self.line_ending.into_wire());
+            }
+        }
+        if let (Some(metrics), Status::Error) = (&self.metrics, status) {
+            metrics.count_error();
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.response_bytes.observe(dst.len() - start);
+        }
+        Ok(())
     }
 }
 
+/// Commands by the cargo feature of their family, whose builds without it reject them.
+///
+/// The others, e.g. `GET` and `SET`, are always present.
+const FAMILIES: &[(&str, bool, &[&str])] = &[
+    (
+        "session",
+        cfg!(feature = "session"),
+        &["HELLO", "AUTH", "ENCODING", "DEADLINE", "PONG"],
+    ),
+    (
+        "transactions",
+        cfg!(feature = "transactions"),
+        &["MULTI", "EXEC", "DISCARD", "WATCH", "UNWATCH"],
+    ),
+    (
+        "pubsub",
+        cfg!(feature = "pubsub"),
+        &["PSUBSCRIBE", "SUBSCRIBE-EXPIRED"],
+    ),
+    (
+        "expiry",
+        cfg!(feature = "expiry"),
+        &["IDLETIME", "HOTKEYS", "EXPIRE", "TTL", "SETEX", "GETTTL"],
+    ),
+    (
+        "admin",
+        cfg!(feature = "admin"),
+        &[
+            "DUMP",
+            "COMPACT",
+            "SYNC",
+            "DBSIZE",
+            "DBLIST",
+            "LASTSEQ",
+            "INFO",
+            "TIME",
+            "CLIENT",
+            "CONFIG",
+            "USE",
+            "FILL",
+            "MEMUSAGE",
+            "FINDVAL",
+            "GETLOG",
+            "CAPABILITIES",
+        ],
+    ),
+    (
+        "extended",
+        cfg!(feature = "extended"),
+        &[
+            "GETDEL",
+            "GETRANGE",
+            "CHECKSUM",
+            "BGET",
+            "GETOR",
+            "SETNX",
+            "SETIFMATCH",
+            "SETIDEM",
+            "MSETNX",
+            "DELIFEQ",
+            "RENAME",
+            "GETPREFIX",
+            "CHILDREN",
+            "INCRBY",
+            "LPUSH",
+            "LLEN",
+            "SETCHUNK",
+            "SETCHUNKEND",
+            "MAX",
+            "MIN",
+        ],
+    ),
+];
+
+/// Returns the feature of the family of `command`, if this build leaves it out.
+fn disabled_feature(command: &str) -> Option<&'static str> {
+    FAMILIES
+        .iter()
+        .find(|(_, enabled, commands)| !enabled && commands.contains(&command))
+        .map(|(feature, _, _)| *feature)
+}
+
 impl Request {
     fn from_wire(line: &str) -> Result<Self> {
         let mut components = line.split(' ');
 
         let command = components.next().context("missing command")?;
+        if let Some(feature) = disabled_feature(command) {
+            bail!(
+                "command not supported in this build: {} requires feature {}",
+                command,
+                feature
+            );
+        }
 
         match command {
             "GET" => {
@@ -73,183 +757,2709 @@ impl Request {
 
                 Ok(Request::Get { key })
             }
+            #[cfg(feature = "extended")]
+            "GETDEL" => {
+                let key = components
+                    .next()
+                    .context("missing key from GETDEL command")?
+                    .into();
+
+                Ok(Request::GetDel { key })
+            }
+            #[cfg(feature = "extended")]
+            "CHECKSUM" => {
+                let key = components
+                    .next()
+                    .context("missing key from CHECKSUM command")?
+                    .into();
+
+                Ok(Request::Checksum { key })
+            }
+            #[cfg(feature = "extended")]
+            "GETRANGE" => {
+                let key = components
+                    .next()
+                    .context("missing key from GETRANGE command")?
+                    .into();
+
+                let start = integer("GETRANGE", 2, "start", components.next())?;
+
+                let end = integer("GETRANGE", 3, "end", components.next())?;
+
+                Ok(Request::GetRange { key, start, end })
+            }
+            #[cfg(feature = "extended")]
+            "BGET" => {
+                let key = components
+                    .next()
+                    .context("missing key from BGET command")?
+                    .into();
+
+                let timeout_ms = integer("BGET", 2, "timeout", components.next())?;
+
+                Ok(Request::BGet { key, timeout_ms })
+            }
+            #[cfg(feature = "extended")]
+            "GETOR" => {
+                let key = components
+                    .next()
+                    .context("missing key from GETOR command")?
+                    .into();
+
+                let default = components
+                    .next()
+                    .context("missing default from GETOR command")?
+                    .into();
+
+                Ok(Request::GetOr { key, default })
+            }
             "SET" => {
                 let key = components
                     .next()
                     .context("missing key from SET command")?
                     .into();
 
+                // A value omitted, or made of whitespace only, is empty.
                 let value = components
                     .next()
-                    .context("missing value from SET command")?
+                    .filter(|value| !value.trim().is_empty())
+                    .unwrap_or_default()
                     .into();
 
-                Ok(Request::Set { key, value })
+                match components.find(|option| !option.is_empty()) {
+                    None => Ok(Request::Set { key, value }),
+                    Some("GET") => Ok(Request::SetGet { key, value }),
+                    Some(option) => bail!("unrecognized SET option: {}", option),
+                }
             }
-            _ => bail!("unrecognized command: {}", command),
-        }
-    }
-}
+            #[cfg(feature = "extended")]
+            "SETNX" => {
+                let key = components
+                    .next()
+                    .context("missing key from SETNX command")?
+                    .into();
 
-impl Response {
-    fn into_wire(self) -> String {
-        let status = self.status().into_wire();
-        match self {
-            Response::Set { key } => {
-                format!("{} {}", status, key)
-            }
-            Response::Get { key, value } => value
-                .map(|value| format!("{} {} {}", status, key, value))
-                .unwrap_or_else(|| format!("{} {}", status, key)),
-        }
-    }
-}
+                let value = components
+                    .next()
+                    .context("missing value from SETNX command")?
+                    .into();
 
-impl Status {
-    fn into_wire(self) -> &'static str {
-        match self {
-            Status::Okay => "OKAY",
-            Status::Fail => "FAIL",
-        }
-    }
-}
+                Ok(Request::SetNx { key, value })
+            }
+            #[cfg(feature = "extended")]
+            "SETIFMATCH" => {
+                let key = components
+                    .next()
+                    .context("missing key from SETIFMATCH command")?
+                    .into();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use proptest::prelude::*;
+                let pattern = components
+                    .next()
+                    .context("missing pattern from SETIFMATCH command")?
+                    .into();
 
-    #[test]
-    fn succeeds_to_encode_status() {
-        let cases = vec![(Status::Okay, "OKAY"), (Status::Fail, "FAIL")];
+                let value = components
+                    .next()
+                    .context("missing value from SETIFMATCH command")?
+                    .into();
 
-        cases
-            .into_iter()
-            .for_each(|(status, expected_encoded_status)| {
-                // Pre-condition.
-                // Action.
-                let encoded_status = status.into_wire();
-                // Post-condition.
-                assert_eq!(encoded_status, expected_encoded_status);
-            });
-    }
+                Ok(Request::SetIfMatch {
+                    key,
+                    pattern,
+                    value,
+                })
+            }
+            #[cfg(feature = "extended")]
+            "SETIDEM" => {
+                let key = components
+                    .next()
+                    .context("missing key from SETIDEM command")?
+                    .into();
 
-    proptest! {
-        #[test]
-        fn fails_to_decode_request_with_invalid_command(command in invalid_request_command()) {
-            // Pre-condition.
-            let mut message = BytesMut::from(format!("{}\n", command).as_str());
-            let mut decoder = Codec::default();
+                let token = components
+                    .next()
+                    .context("missing token from SETIDEM command")?
+                    .into();
 
-            // Action.
-            let request = decoder.decode(&mut message);
+                let value = components
+                    .next()
+                    .context("missing value from SETIDEM command")?
+                    .into();
 
-            // Post-condition.
-            assert!(request.is_err());
-            assert!(message.is_empty());
-        }
-    }
+                Ok(Request::SetIdem { key, token, value })
+            }
+            #[cfg(feature = "extended")]
+            "MSETNX" => {
+                let mut pairs = Vec::new();
+                while let Some(key) = components.next() {
+                    let value = components
+                        .next()
+                        .with_context(|| format!("missing value of {} from MSETNX command", key))?;
+                    pairs.push((key.into(), value.into()));
+                }
+                if pairs.is_empty() {
+                    bail!("missing key from MSETNX command");
+                }
 
-    #[test]
-    fn fails_to_decodes_malformed_request() {
-        let cases = vec![
-            (b"GET\n".as_ref(), "get without key"),
-            (b"SET\n".as_ref(), "set without key"),
-            (b"SET key\n".as_ref(), "set without value"),
-        ];
+                Ok(Request::MSetNx { pairs })
+            }
+            #[cfg(feature = "extended")]
+            "DELIFEQ" => {
+                let key = components
+                    .next()
+                    .context("missing key from DELIFEQ command")?
+                    .into();
 
-        cases.into_iter().for_each(|(message, reason)| {
-            // Pre-condition.
-            let mut decoder = Codec::default();
-            let mut message = BytesMut::from(message);
+                let expected = components
+                    .next()
+                    .context("missing expected value from DELIFEQ command")?
+                    .into();
 
-            // Action.
-            let request = decoder.decode(&mut message);
+                Ok(Request::DelIfEquals { key, expected })
+            }
+            #[cfg(feature = "extended")]
+            "RENAME" => {
+                let src = components
+                    .next()
+                    .context("missing source key from RENAME command")?
+                    .into();
 
-            // Post-condition.
-            assert!(request.is_err(), "{}", reason);
-            assert!(message.is_empty(), "{}", reason);
-        });
-    }
+                let dst = components
+                    .next()
+                    .context("missing destination key from RENAME command")?
+                    .into();
+
+                Ok(Request::Rename { src, dst })
+            }
+            #[cfg(feature = "transactions")]
+            "MULTI" => Ok(Request::Multi),
+            #[cfg(feature = "transactions")]
+            "EXEC" => Ok(Request::Exec),
+            #[cfg(feature = "admin")]
+            "DUMP" => Ok(Request::Dump),
+            #[cfg(feature = "extended")]
+            "GETPREFIX" => {
+                let prefix = components
+                    .next()
+                    .context("missing prefix from GETPREFIX command")?
+                    .into();
+
+                Ok(Request::GetPrefix { prefix })
+            }
+            #[cfg(feature = "extended")]
+            "CHILDREN" => {
+                let prefix = components
+                    .next()
+                    .context("missing prefix from CHILDREN command")?
+                    .into();
+
+                let separator = components
+                    .next()
+                    .filter(|separator| !separator.is_empty())
+                    .context("missing separator from CHILDREN command")?
+                    .into();
+
+                Ok(Request::Children { prefix, separator })
+            }
+            #[cfg(feature = "pubsub")]
+            "PSUBSCRIBE" => {
+                let prefix = components
+                    .next()
+                    .context("missing prefix from PSUBSCRIBE command")?
+                    .into();
+
+                Ok(Request::PSubscribe { prefix })
+            }
+            #[cfg(feature = "pubsub")]
+            "SUBSCRIBE-EXPIRED" => Ok(Request::SubscribeExpired),
+            #[cfg(feature = "admin")]
+            "COMPACT" => Ok(Request::Compact),
+            #[cfg(feature = "admin")]
+            "SYNC" => Ok(Request::Sync),
+            #[cfg(feature = "session")]
+            "PONG" => Ok(Request::Pong),
+            #[cfg(feature = "admin")]
+            "DBSIZE" => Ok(Request::DbSize),
+            #[cfg(feature = "admin")]
+            "DBLIST" => Ok(Request::DbList),
+            #[cfg(feature = "admin")]
+            "LASTSEQ" => Ok(Request::LastSeq),
+            #[cfg(feature = "admin")]
+            "CAPABILITIES" => Ok(Request::Capabilities),
+            #[cfg(feature = "admin")]
+            "GETLOG" => {
+                let count = integer("GETLOG", 1, "count", components.next())?;
+
+                Ok(Request::GetLog { count })
+            }
+            #[cfg(feature = "admin")]
+            "INFO" => Ok(Request::Info),
+            #[cfg(feature = "admin")]
+            "TIME" => Ok(Request::Time),
+            #[cfg(feature = "admin")]
+            "CLIENT" => match components
+                .next()
+                .context("missing subcommand from CLIENT command")?
+            {
+                "LIST" => Ok(Request::ClientList),
+                "KILL" => {
+                    let peer_addr = components
+                        .next()
+                        .context("missing address from CLIENT KILL command")?
+                        .parse()
+                        .context("malformed address in CLIENT KILL command")?;
+
+                    Ok(Request::ClientKill { peer_addr })
+                }
+                subcommand => bail!("unrecognized CLIENT subcommand: {}", subcommand),
+            },
+            #[cfg(feature = "admin")]
+            "CONFIG" => match components
+                .next()
+                .context("missing subcommand from CONFIG command")?
+            {
+                "GET" => {
+                    let param = components
+                        .next()
+                        .context("missing parameter from CONFIG GET command")?
+                        .into();
+
+                    Ok(Request::ConfigGet { param })
+                }
+                "SET" => {
+                    let param = components
+                        .next()
+                        .context("missing parameter from CONFIG SET command")?
+                        .into();
+
+                    let value = components
+                        .next()
+                        .context("missing value from CONFIG SET command")?
+                        .into();
+
+                    Ok(Request::ConfigSet { param, value })
+                }
+                subcommand => bail!("unrecognized CONFIG subcommand: {}", subcommand),
+            },
+            #[cfg(feature = "admin")]
+            "USE" => {
+                let name = components
+                    .next()
+                    .context("missing name from USE command")?
+                    .into();
+
+                Ok(Request::Use { name })
+            }
+            #[cfg(feature = "admin")]
+            "MEMUSAGE" => {
+                let prefix = components
+                    .next()
+                    .context("missing prefix from MEMUSAGE command")?
+                    .into();
+
+                Ok(Request::MemUsage { prefix })
+            }
+            #[cfg(feature = "admin")]
+            "FINDVAL" => {
+                let substring = components
+                    .next()
+                    .filter(|substring| !substring.is_empty())
+                    .context("missing substring from FINDVAL command")?
+                    .into();
+
+                Ok(Request::FindVal { substring })
+            }
+            #[cfg(feature = "admin")]
+            "FILL" => {
+                let prefix = components
+                    .next()
+                    .context("missing prefix from FILL command")?
+                    .into();
+
+                let count = integer("FILL", 2, "count", components.next())?;
+
+                Ok(Request::Fill { prefix, count })
+            }
+            #[cfg(feature = "transactions")]
+            "DISCARD" => Ok(Request::Discard),
+            #[cfg(feature = "transactions")]
+            "WATCH" => {
+                let keys: Vec<String> = components.map(Into::into).collect();
+                if keys.is_empty() {
+                    bail!("missing key from WATCH command");
+                }
+
+                Ok(Request::Watch { keys })
+            }
+            #[cfg(feature = "transactions")]
+            "UNWATCH" => Ok(Request::Unwatch),
+            #[cfg(feature = "expiry")]
+            "IDLETIME" => {
+                let key = components
+                    .next()
+                    .context("missing key from IDLETIME command")?
+                    .into();
+
+                Ok(Request::IdleTime { key })
+            }
+            #[cfg(feature = "expiry")]
+            "HOTKEYS" => {
+                let count = integer("HOTKEYS", 1, "count", components.next())?;
+
+                Ok(Request::HotKeys { count })
+            }
+            #[cfg(feature = "session")]
+            "HELLO" => {
+                let version = integer("HELLO", 1, "version", components.next())?;
+
+                Ok(Request::Hello { version })
+            }
+            #[cfg(feature = "session")]
+            "ENCODING" => {
+                let encoding = components
+                    .next()
+                    .context("missing encoding from ENCODING command")?
+                    .parse()?;
+
+                Ok(Request::Encoding { encoding })
+            }
+            #[cfg(feature = "session")]
+            "AUTH" => {
+                let password = components
+                    .next()
+                    .context("missing password from AUTH command")?
+                    .into();
+
+                Ok(Request::Auth { password })
+            }
+            #[cfg(feature = "expiry")]
+            "EXPIRE" => {
+                let key = components
+                    .next()
+                    .context("missing key from EXPIRE command")?
+                    .into();
+
+                let seconds = integer("EXPIRE", 2, "seconds", components.next())?;
+
+                Ok(Request::Expire { key, seconds })
+            }
+            #[cfg(feature = "expiry")]
+            "TTL" => {
+                let key = components
+                    .next()
+                    .context("missing key from TTL command")?
+                    .into();
+
+                Ok(Request::Ttl { key })
+            }
+            #[cfg(feature = "expiry")]
+            "SETEX" => {
+                let key = components
+                    .next()
+                    .context("missing key from SETEX command")?
+                    .into();
+
+                let seconds = integer("SETEX", 2, "seconds", components.next())?;
+
+                let value = components
+                    .next()
+                    .context("missing value from SETEX command")?
+                    .into();
+
+                Ok(Request::SetEx {
+                    key,
+                    seconds,
+                    value,
+                })
+            }
+            #[cfg(feature = "expiry")]
+            "GETTTL" => {
+                let key = components
+                    .next()
+                    .context("missing key from GETTTL command")?
+                    .into();
+
+                Ok(Request::GetWithTtl { key })
+            }
+            #[cfg(feature = "session")]
+            "DEADLINE" => {
+                let timeout_ms = integer("DEADLINE", 1, "milliseconds", components.next())?;
+
+                let request = components.collect::<Vec<_>>().join(" ");
+                let request = match Request::from_wire(&request)
+                    .context("malformed request within DEADLINE")?
+                {
+                    Request::Deadline {
+                        timeout_ms: _,
+                        request: _,
+                    } => bail!("nested DEADLINE"),
+                    request => Box::new(request),
+                };
+
+                Ok(Request::Deadline {
+                    timeout_ms,
+                    request,
+                })
+            }
+            #[cfg(feature = "extended")]
+            "INCRBY" => {
+                let key = components
+                    .next()
+                    .context("missing key from INCRBY command")?
+                    .into();
+
+                let delta = integer("INCRBY", 2, "delta", components.next())?;
+
+                Ok(Request::IncrBy { key, delta })
+            }
+            #[cfg(feature = "extended")]
+            "LPUSH" => {
+                let key = components
+                    .next()
+                    .context("missing key from LPUSH command")?
+                    .into();
+
+                let item = components
+                    .next()
+                    .filter(|item| !item.is_empty())
+                    .context("missing item from LPUSH command")?
+                    .into();
+
+                Ok(Request::LPush { key, item })
+            }
+            #[cfg(feature = "extended")]
+            "LLEN" => {
+                let key = components
+                    .next()
+                    .context("missing key from LLEN command")?
+                    .into();
+
+                Ok(Request::LLen { key })
+            }
+            #[cfg(feature = "extended")]
+            "SETCHUNK" => {
+                let key = components
+                    .next()
+                    .context("missing key from SETCHUNK command")?
+                    .into();
+
+                let seq = integer("SETCHUNK", 2, "seq", components.next())?;
+
+                let data = components
+                    .next()
+                    .filter(|data| !data.is_empty())
+                    .context("missing data from SETCHUNK command")?
+                    .into();
+
+                Ok(Request::SetChunk { key, seq, data })
+            }
+            #[cfg(feature = "extended")]
+            "SETCHUNKEND" => {
+                let key = components
+                    .next()
+                    .context("missing key from SETCHUNKEND command")?
+                    .into();
+
+                Ok(Request::SetChunkEnd { key })
+            }
+            #[cfg(feature = "extended")]
+            "MAX" | "MIN" => {
+                let extremum = match command {
+                    "MAX" => Extremum::Max,
+                    _ => Extremum::Min,
+                };
+                let key = components
+                    .next()
+                    .with_context(|| format!("missing key from {} command", command))?
+                    .into();
+
+                let candidate = integer(command, 2, "candidate", components.next())?;
+
+                Ok(Request::SetExtremum {
+                    key,
+                    extremum,
+                    candidate,
+                })
+            }
+            _ => bail!("unrecognized command: {}", command),
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "session",
+    feature = "expiry",
+    feature = "admin",
+    feature = "extended"
+))]
+/// Parses the argument at `position` (counting from 1) of `command`, named `name`, as an integer.
+fn integer<T>(command: &str, position: usize, name: &str, arg: Option<&str>) -> Result<T>
+where
+    T: FromStr<Err = ParseIntError>,
+{
+    let arg = arg.with_context(|| format!("missing {} from {} command", name, command))?;
+    let e = match arg.parse::<T>() {
+        Ok(integer) => return Ok(integer),
+        Err(e) => e,
+    };
+
+    let expected = match e.kind() {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => bail!(
+            "integer out of range for argument {} of {}, got {}",
+            position,
+            command,
+            arg
+        ),
+        // Negative, yet otherwise an integer of `T`.
+        IntErrorKind::InvalidDigit
+            if arg
+                .strip_prefix('-')
+                .is_some_and(|magnitude| magnitude.parse::<T>().is_ok()) =>
+        {
+            "non-negative integer"
+        }
+        _ => "integer",
+    };
+    bail!(
+        "expected {} for argument {} of {}, got {}",
+        expected,
+        position,
+        command,
+        arg
+    )
+}
+
+impl Request {
+    /// Checks that every key and value in this request is allowed by `validation`.
+    pub fn validate(&self, validation: Validation) -> Result<()> {
+        match self {
+            Request::Get { key }
+            | Request::GetDel { key }
+            | Request::GetRange {
+                key,
+                start: _,
+                end: _,
+            }
+            | Request::IdleTime { key }
+            | Request::Checksum { key }
+            | Request::Expire { key, seconds: _ }
+            | Request::IncrBy { key, delta: _ }
+            | Request::LLen { key }
+            | Request::SetChunkEnd { key }
+            | Request::SetExtremum {
+                key,
+                extremum: _,
+                candidate: _,
+            }
+            | Request::BGet { key, timeout_ms: _ }
+            | Request::GetWithTtl { key }
+            | Request::Ttl { key } => validation.check("key", key),
+            Request::Fill { prefix, count: _ } => validation.check("prefix", prefix),
+            Request::Use { name } => validation.check("name", name),
+            Request::PSubscribe { prefix }
+            | Request::GetPrefix { prefix }
+            | Request::MemUsage { prefix } => validation.check("prefix", prefix),
+            Request::FindVal { substring } => validation.check("value", substring),
+            Request::Children { prefix, separator } => {
+                validation.check("prefix", prefix)?;
+                validation.check("separator", separator)
+            }
+            Request::Set { key, value }
+            | Request::SetGet { key, value }
+            | Request::SetNx { key, value }
+            | Request::SetEx {
+                key,
+                seconds: _,
+                value,
+            }
+            | Request::GetOr {
+                key,
+                default: value,
+            }
+            | Request::DelIfEquals {
+                key,
+                expected: value,
+            }
+            | Request::LPush { key, item: value }
+            | Request::SetChunk {
+                key,
+                seq: _,
+                data: value,
+            } => {
+                validation.check("key", key)?;
+                validation.check("value", value)
+            }
+            Request::Rename { src, dst } => {
+                validation.check("key", src)?;
+                validation.check("key", dst)
+            }
+            Request::SetIfMatch {
+                key,
+                pattern,
+                value,
+            } => {
+                validation.check("key", key)?;
+                validation.check("value", pattern)?;
+                validation.check("value", value)
+            }
+            Request::SetIdem { key, token, value } => {
+                validation.check("key", key)?;
+                validation.check("token", token)?;
+                validation.check("value", value)
+            }
+            Request::MSetNx { pairs } => pairs.iter().try_for_each(|(key, value)| {
+                validation.check("key", key)?;
+                validation.check("value", value)
+            }),
+            Request::Deadline {
+                timeout_ms: _,
+                request,
+            } => request.validate(validation),
+            Request::Watch { keys } => keys.iter().try_for_each(|key| validation.check("key", key)),
+            Request::Multi
+            | Request::Exec
+            | Request::Discard
+            | Request::Unwatch
+            | Request::Dump
+            | Request::Compact
+            | Request::Sync
+            | Request::SubscribeExpired
+            | Request::DbSize
+            | Request::DbList
+            | Request::Capabilities
+            | Request::LastSeq
+            | Request::GetLog { count: _ }
+            | Request::HotKeys { count: _ }
+            | Request::Info
+            | Request::Time
+            | Request::ClientList
+            | Request::ClientKill { peer_addr: _ }
+            | Request::ConfigGet { param: _ }
+            | Request::ConfigSet { param: _, value: _ }
+            | Request::Pong
+            | Request::Hello { version: _ }
+            | Request::Auth { password: _ }
+            | Request::Encoding { encoding: _ } => Ok(()),
+        }
+    }
+}
+
+impl Validation {
+    fn check(self, what: &str, s: &str) -> Result<()> {
+        match s.chars().find(|c| self.forbids(*c)) {
+            Some(c) => bail!("{} {:?} contains forbidden character {:?}", what, s, c),
+            None if s.ends_with('\r') => {
+                bail!("{} {:?} ends with forbidden character '\\r'", what, s)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn forbids(self, c: char) -> bool {
+        match self {
+            Validation::Lenient => matches!(c, '\n' | ' '),
+            Validation::Strict => c.is_whitespace() || c.is_control(),
+        }
+    }
+}
+
+impl FromStr for Validation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lenient" => Ok(Validation::Lenient),
+            "strict" => Ok(Validation::Strict),
+            _ => bail!("unrecognized validation policy: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Validation::Lenient => f.write_str("lenient"),
+            Validation::Strict => f.write_str("strict"),
+        }
+    }
+}
+
+impl LineEnding {
+    fn into_wire(self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::CrLf),
+            _ => bail!("unrecognized line ending: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LineEnding::Lf => f.write_str("lf"),
+            LineEnding::CrLf => f.write_str("crlf"),
+        }
+    }
+}
+
+impl FromStr for MissingResponse {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fail" => Ok(MissingResponse::Fail),
+            "nil" => Ok(MissingResponse::Nil),
+            _ => bail!("unrecognized missing response: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for MissingResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissingResponse::Fail => f.write_str("fail"),
+            MissingResponse::Nil => f.write_str("nil"),
+        }
+    }
+}
+
+impl FromStr for ValueEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "raw" => Ok(ValueEncoding::Raw),
+            "base64" => Ok(ValueEncoding::Base64),
+            _ => bail!("unrecognized value encoding: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for ValueEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueEncoding::Raw => f.write_str("raw"),
+            ValueEncoding::Base64 => f.write_str("base64"),
+        }
+    }
+}
+
+/// Writes `$status $name`, followed by ` $value` if any.
+fn write_named(
+    dst: &mut BytesMut,
+    status: &str,
+    name: &str,
+    value: Option<impl fmt::Display>,
+) -> fmt::Result {
+    write!(dst, "{} {}", status, name)?;
+    match value {
+        Some(value) => write!(dst, " {}", value),
+        None => Ok(()),
+    }
+}
+
+impl Response {
+    /// Encodes this response into `dst` as lines separated by `line_ending`, without the
+    /// trailing one.
+    ///
+    /// Values are written as is, hence the lines are made of bytes rather than a string.
+    fn encode_to(
+        self,
+        line_ending: LineEnding,
+        missing_response: MissingResponse,
+        dst: &mut BytesMut,
+    ) -> fmt::Result {
+        let status = match (&self, missing_response) {
+            (
+                Response::Get {
+                    key: _,
+                    value: None,
+                },
+                MissingResponse::Nil,
+            ) => Status::Nil,
+            _ => self.status(),
+        }
+        .into_wire();
+        match self {
+            Response::ConfigSet { param: key }
+            | Response::Set { key }
+            | Response::SetNx { key, set: _ }
+            | Response::SetIfMatch { key, set: _ }
+            | Response::DelIfEquals { key, deleted: _ }
+            | Response::Expire { key, ok: _ }
+            | Response::PSubscribe { prefix: key } => write!(dst, "{} {}", status, key),
+            Response::SetIdem { key, applied } => {
+                let outcome = if applied { "APPLIED" } else { "DUPLICATE" };
+                write!(dst, "{} {} {}", status, key, outcome)
+            }
+            Response::Get { key, value }
+            | Response::GetDel { key, value }
+            | Response::GetRange { key, value }
+            | Response::SetGet { key, value } => {
+                write!(dst, "{} {}", status, key)?;
+                if let Some(value) = value {
+                    dst.put_u8(b' ');
+                    dst.extend_from_slice(&value);
+                }
+                Ok(())
+            }
+            Response::IdleTime { key, seconds } => write_named(dst, status, &key, seconds),
+            Response::Checksum { key, hash } => {
+                write_named(dst, status, &key, hash.map(|hash| format!("{:08x}", hash)))
+            }
+            Response::Ttl {
+                key,
+                seconds: value,
+            }
+            | Response::IncrBy { key, value }
+            | Response::SetExtremum { key, value } => write_named(dst, status, &key, value),
+            Response::ListLen { key, len } => write_named(dst, status, &key, Some(len)),
+            Response::SetChunk { key, expected } => write_named(dst, status, &key, expected),
+            Response::SetChunkEnd { key, ok: _ } => write!(dst, "{} {}", status, key),
+            Response::ConfigGet { param, value } => write_named(dst, status, &param, value),
+            Response::GetWithTtl {
+                key,
+                value,
+                ttl_secs,
+            } => {
+                write!(dst, "{} {}", status, key)?;
+                if let Some((value, ttl_secs)) = value.zip(ttl_secs) {
+                    write!(dst, " {} ", ttl_secs)?;
+                    dst.extend_from_slice(&value);
+                }
+                Ok(())
+            }
+            Response::Rename { ok: _ }
+            | Response::Multi { ok: _ }
+            | Response::Discard { ok: _ }
+            | Response::Watch { ok: _ }
+            | Response::Unwatch
+            | Response::Auth { ok: _ }
+            | Response::MSetNx { set: _ }
+            | Response::Use { ok: _ }
+            | Response::ClientKill { ok: _ }
+            | Response::ClientList { clients: None }
+            | Response::Compact
+            | Response::Sync
+            | Response::SubscribeExpired
+            | Response::Exec { responses: None } => dst.write_str(status),
+            Response::Queued => write!(dst, "{} QUEUED", status),
+            Response::DumpEnd | Response::SubscriptionEnd => write!(dst, "{} END", status),
+            Response::Notify { mutation } => match mutation {
+                Mutation::Set { key } => write!(dst, "NOTIFY SET {}", key),
+                Mutation::Del { key } => write!(dst, "NOTIFY DEL {}", key),
+                Mutation::Expire { key } => write!(dst, "NOTIFY EXPIRE {}", key),
+                Mutation::Expired { key } => write!(dst, "EXPIRED {}", key),
+            },
+            Response::Busy => write!(dst, "{} BUSY", status),
+            Response::Aborted => write!(dst, "{} ABORTED", status),
+            Response::Ping => dst.write_str("PING"),
+            Response::Error { message } => write!(dst, "{} {}", status, message),
+            Response::Fill { created } => write!(dst, "{} {}", status, created),
+            Response::DbSize { size } => write!(dst, "{} {}", status, size),
+            Response::LastSeq { seq } => write!(dst, "{} {}", status, seq),
+            Response::Capabilities { capabilities } => {
+                dst.write_str(status)?;
+                for name in capabilities.names() {
+                    write!(dst, " {}", name)?;
+                }
+                Ok(())
+            }
+            Response::Time {
+                unix_secs,
+                uptime_secs,
+            } => write!(dst, "{} {} {}", status, unix_secs, uptime_secs),
+            Response::Hello { version, ok: _ } => write!(dst, "{} {}", status, version),
+            Response::Encoding { encoding } => write!(dst, "{} {}", status, encoding),
+            Response::Info { fields } => {
+                write!(dst, "{} {}", status, fields.len())?;
+                for (name, value) in fields {
+                    dst.extend_from_slice(line_ending.into_wire());
+                    write!(dst, "{} {}", name, value)?;
+                }
+                Ok(())
+            }
+            Response::GetLog { entries } => {
+                write!(dst, "{} {}", status, entries.len())?;
+                for LogEntry { seq, mutation } in entries {
+                    dst.extend_from_slice(line_ending.into_wire());
+                    match mutation {
+                        Mutation::Set { key } => write!(dst, "{} SET {}", seq, key)?,
+                        Mutation::Del { key } => write!(dst, "{} DEL {}", seq, key)?,
+                        Mutation::Expire { key } => write!(dst, "{} EXPIRE {}", seq, key)?,
+                        Mutation::Expired { key } => write!(dst, "{} EXPIRED {}", seq, key)?,
+                    }
+                }
+                Ok(())
+            }
+            Response::MemUsage { sizes } | Response::DbList { sizes } => {
+                write!(dst, "{} {}", status, sizes.len())?;
+                for (key, size) in sizes {
+                    dst.extend_from_slice(line_ending.into_wire());
+                    write!(dst, "{} {}", key, size)?;
+                }
+                Ok(())
+            }
+            Response::Children { segments: lines }
+            | Response::FindVal { keys: lines }
+            | Response::HotKeys { keys: lines } => {
+                write!(dst, "{} {}", status, lines.len())?;
+                for line in lines {
+                    dst.extend_from_slice(line_ending.into_wire());
+                    dst.write_str(&line)?;
+                }
+                Ok(())
+            }
+            Response::GetPrefix { pairs } => {
+                write!(dst, "{} {}", status, pairs.len())?;
+                for (key, value) in pairs {
+                    dst.extend_from_slice(line_ending.into_wire());
+                    write!(dst, "{} ", key)?;
+                    dst.extend_from_slice(&value);
+                }
+                Ok(())
+            }
+            Response::ClientList {
+                clients: Some(clients),
+            } => {
+                write!(dst, "{} {}", status, clients.len())?;
+                for client in clients {
+                    dst.extend_from_slice(line_ending.into_wire());
+                    write!(
+                        dst,
+                        "{} {} {}",
+                        client.id,
+                        client.peer_addr,
+                        client.age.as_secs()
+                    )?;
+                }
+                Ok(())
+            }
+            Response::Exec {
+                responses: Some(responses),
+            } => {
+                write!(dst, "{} {}", status, responses.len())?;
+                for response in responses {
+                    dst.extend_from_slice(line_ending.into_wire());
+                    response.encode_to(line_ending, missing_response, dst)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Status {
+    fn into_wire(self) -> &'static str {
+        match self {
+            Status::Okay => "OKAY",
+            Status::Fail => "FAIL",
+            Status::Error => "ERR",
+            Status::Nil => "NIL",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::clients::ClientInfo,
+        storage::types::{Capabilities, Extremum},
+    };
+    use proptest::prelude::*;
+    use std::{net::SocketAddr, time::Duration};
+
+    #[test]
+    fn succeeds_to_encode_status() {
+        let cases = vec![
+            (Status::Okay, "OKAY"),
+            (Status::Fail, "FAIL"),
+            (Status::Error, "ERR"),
+            (Status::Nil, "NIL"),
+        ];
+
+        cases
+            .into_iter()
+            .for_each(|(status, expected_encoded_status)| {
+                // Pre-condition.
+                // Action.
+                let encoded_status = status.into_wire();
+                // Post-condition.
+                assert_eq!(encoded_status, expected_encoded_status);
+            });
+    }
+
+    proptest! {
+        #[test]
+        fn fails_to_decode_request_with_invalid_command(command in invalid_request_command()) {
+            // Pre-condition.
+            let mut message = BytesMut::from(format!("{}\n", command).as_str());
+            let mut decoder = Codec::default();
+
+            // Action.
+            let request = decoder.decode(&mut message);
+
+            // Post-condition.
+            assert!(request.is_err());
+            assert!(message.is_empty());
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn fails_to_decode_request_with_non_integer_argument(arg in "[^0-9\\s+-]\\S*") {
+            for (message, position, command) in vec![
+                (format!("GETRANGE key {} 1\n", arg), 2, "GETRANGE"),
+                (format!("EXPIRE key {}\n", arg), 2, "EXPIRE"),
+                (format!("FILL key {}\n", arg), 2, "FILL"),
+                (format!("INCRBY key {}\n", arg), 2, "INCRBY"),
+                (format!("SETCHUNK key {} data\n", arg), 2, "SETCHUNK"),
+                (format!("BGET key {}\n", arg), 2, "BGET"),
+                (format!("HOTKEYS {}\n", arg), 1, "HOTKEYS"),
+            ].into_iter().filter(|(_, _, command)| disabled_feature(command).is_none()) {
+                // Pre-condition.
+                let mut decoder = Codec::default();
+                let mut message = BytesMut::from(message.as_str());
+
+                // Action.
+                let request = decoder.decode(&mut message);
+
+                // Post-condition.
+                let reason = format!("{:#}", request.unwrap_err());
+                let expected = format!("expected integer for argument {} of {}, got {}", position, command, arg);
+                prop_assert!(reason.contains(&expected), "{}", reason);
+            }
+        }
+
+        #[test]
+        fn fails_to_decode_request_with_overflowing_argument(
+            magnitude in (i64::MAX as u128 + 1)..=u128::MAX,
+            negative in any::<bool>(),
+        ) {
+            let arg = if negative { format!("-{}", magnitude) } else { magnitude.to_string() };
+            for (message, position, command) in vec![
+                (format!("GETRANGE key 0 {}\n", arg), 3, "GETRANGE"),
+                (format!("INCRBY key {}\n", arg), 2, "INCRBY"),
+            ].into_iter().filter(|(_, _, command)| disabled_feature(command).is_none()) {
+                // Pre-condition.
+                let mut decoder = Codec::default();
+                let mut message = BytesMut::from(message.as_str());
+
+                // Action.
+                let request = decoder.decode(&mut message);
+
+                // Post-condition.
+                let reason = format!("{:#}", request.unwrap_err());
+                let expected = format!("integer out of range for argument {} of {}, got {}", position, command, arg);
+                prop_assert!(reason.contains(&expected), "{}", reason);
+            }
+        }
+    }
+
+    #[test]
+    fn describes_why_integer_argument_is_malformed() {
+        let cases = vec![
+            (
+                b"EXPIRE key -1\n".as_ref(),
+                "expected non-negative integer for argument 2 of EXPIRE, got -1",
+            ),
+            (
+                b"SETEX key 18446744073709551616 value\n".as_ref(),
+                "integer out of range for argument 2 of SETEX, got 18446744073709551616",
+            ),
+            (
+                b"HELLO one\n".as_ref(),
+                "expected integer for argument 1 of HELLO, got one",
+            ),
+            (b"FILL key\n".as_ref(), "missing count from FILL command"),
+        ];
+
+        cases
+            .into_iter()
+            .filter(|(message, _)| in_build(message))
+            .for_each(|(message, expected_reason)| {
+                // Pre-condition.
+                let mut decoder = Codec::default();
+                let mut message = BytesMut::from(message);
+
+                // Action.
+                let request = decoder.decode(&mut message);
+
+                // Post-condition.
+                let reason = format!("{:#}", request.unwrap_err());
+                assert!(reason.contains(expected_reason), "{}", reason);
+            });
+    }
+
+    #[test]
+    #[cfg(not(any(
+        feature = "session",
+        feature = "transactions",
+        feature = "pubsub",
+        feature = "expiry",
+        feature = "admin",
+        feature = "extended"
+    )))]
+    fn minimal_build_recognizes_only_get_and_set() {
+        let recognized = ["GET key", "SET key value"];
+        let excluded = FAMILIES.iter().flat_map(|(_, _, commands)| commands.iter());
+
+        for line in recognized {
+            assert!(Request::from_wire(line).is_ok(), "{}", line);
+        }
+        for command in excluded {
+            let reason = format!("{:#}", Request::from_wire(command).unwrap_err());
+            assert!(
+                reason.starts_with("command not supported in this build"),
+                "{}",
+                reason
+            );
+        }
+    }
+
+    #[test]
+    fn fails_to_decodes_malformed_request() {
+        let cases = vec![
+            (b"GET\n".as_ref(), "get without key"),
+            (b"GETDEL\n".as_ref(), "getdel without key"),
+            (b"GETRANGE\n".as_ref(), "getrange without key"),
+            (b"GETRANGE key\n".as_ref(), "getrange without start"),
+            (b"GETRANGE key 0\n".as_ref(), "getrange without end"),
+            (
+                b"GETRANGE key a 1\n".as_ref(),
+                "getrange with malformed start",
+            ),
+            (
+                b"GETRANGE key 0 b\n".as_ref(),
+                "getrange with malformed end",
+            ),
+            (b"GETOR\n".as_ref(), "getor without key"),
+            (b"GETOR key\n".as_ref(), "getor without default"),
+            (b"SET\n".as_ref(), "set without key"),
+            (
+                b"SET key value NX\n".as_ref(),
+                "set with unrecognized option",
+            ),
+            (b"SETNX\n".as_ref(), "setnx without key"),
+            (b"SETNX key\n".as_ref(), "setnx without value"),
+            (b"SETIFMATCH\n".as_ref(), "setifmatch without key"),
+            (b"SETIFMATCH key\n".as_ref(), "setifmatch without pattern"),
+            (b"SETIFMATCH key v1\n".as_ref(), "setifmatch without value"),
+            (b"SETIDEM\n".as_ref(), "setidem without key"),
+            (b"DELIFEQ\n".as_ref(), "delifeq without key"),
+            (b"DELIFEQ key\n".as_ref(), "delifeq without expected value"),
+            (b"SETIDEM key\n".as_ref(), "setidem without token"),
+            (b"SETIDEM key t1\n".as_ref(), "setidem without value"),
+            (b"MSETNX\n".as_ref(), "msetnx without key"),
+            (b"MSETNX k1 a k2\n".as_ref(), "msetnx without last value"),
+            (b"RENAME\n".as_ref(), "rename without source"),
+            (b"RENAME src\n".as_ref(), "rename without destination"),
+            (b"IDLETIME\n".as_ref(), "idletime without key"),
+            (b"HOTKEYS\n".as_ref(), "hotkeys without count"),
+            (b"HOTKEYS -1\n".as_ref(), "hotkeys with negative count"),
+            (b"CHECKSUM\n".as_ref(), "checksum without key"),
+            (b"HELLO\n".as_ref(), "hello without version"),
+            (b"HELLO one\n".as_ref(), "hello with non-numeric version"),
+            (b"AUTH\n".as_ref(), "auth without password"),
+            (b"ENCODING\n".as_ref(), "encoding without name"),
+            (b"ENCODING hex\n".as_ref(), "encoding with unknown name"),
+            (b"EXPIRE\n".as_ref(), "expire without key"),
+            (b"EXPIRE key\n".as_ref(), "expire without seconds"),
+            (b"SETEX\n".as_ref(), "setex without key"),
+            (b"SETEX key\n".as_ref(), "setex without seconds"),
+            (
+                b"SETEX key ten value\n".as_ref(),
+                "setex with malformed seconds",
+            ),
+            (b"SETEX key 10\n".as_ref(), "setex without value"),
+            (b"FILL\n".as_ref(), "fill without prefix"),
+            (b"USE\n".as_ref(), "use without name"),
+            (b"CLIENT\n".as_ref(), "client without subcommand"),
+            (b"CLIENT PAUSE\n".as_ref(), "client with unknown subcommand"),
+            (b"CLIENT KILL\n".as_ref(), "client kill without address"),
+            (b"CONFIG\n".as_ref(), "config without subcommand"),
+            (b"CONFIG RESET\n".as_ref(), "config with unknown subcommand"),
+            (b"CONFIG SET\n".as_ref(), "config set without parameter"),
+            (
+                b"CONFIG SET read-only\n".as_ref(),
+                "config set without value",
+            ),
+            (b"CONFIG GET\n".as_ref(), "config get without parameter"),
+            (
+                b"CLIENT KILL nowhere\n".as_ref(),
+                "client kill with malformed address",
+            ),
+            (b"FILL key\n".as_ref(), "fill without count"),
+            (b"FILL key -1\n".as_ref(), "fill with negative count"),
+            (b"EXPIRE key -1\n".as_ref(), "expire with negative seconds"),
+            (b"TTL\n".as_ref(), "ttl without key"),
+            (b"GETTTL\n".as_ref(), "getttl without key"),
+            (b"PSUBSCRIBE\n".as_ref(), "psubscribe without prefix"),
+            (b"GETPREFIX\n".as_ref(), "getprefix without prefix"),
+            (b"CHILDREN\n".as_ref(), "children without prefix"),
+            (b"MEMUSAGE\n".as_ref(), "memusage without prefix"),
+            (b"FINDVAL\n".as_ref(), "findval without substring"),
+            (b"FINDVAL \n".as_ref(), "findval with empty substring"),
+            (b"GETLOG\n".as_ref(), "getlog without count"),
+            (b"GETLOG -1\n".as_ref(), "getlog with negative count"),
+            (b"CHILDREN user:\n".as_ref(), "children without separator"),
+            (b"DEADLINE\n".as_ref(), "deadline without milliseconds"),
+            (b"DEADLINE 50\n".as_ref(), "deadline without request"),
+            (
+                b"DEADLINE soon GET key\n".as_ref(),
+                "deadline with malformed milliseconds",
+            ),
+            (
+                b"DEADLINE 50 GET\n".as_ref(),
+                "deadline with malformed request",
+            ),
+            (
+                b"DEADLINE 50 DEADLINE 10 GET key\n".as_ref(),
+                "deadline within deadline",
+            ),
+            (b"WATCH\n".as_ref(), "watch without key"),
+            (b"INCRBY\n".as_ref(), "incrby without key"),
+            (b"INCRBY key\n".as_ref(), "incrby without delta"),
+            (b"INCRBY key one\n".as_ref(), "incrby with malformed delta"),
+            (b"INCRBY key 1.5\n".as_ref(), "incrby with fractional delta"),
+            (b"LPUSH\n".as_ref(), "lpush without key"),
+            (b"LPUSH key\n".as_ref(), "lpush without item"),
+            (b"LPUSH key \n".as_ref(), "lpush with empty item"),
+            (b"LLEN\n".as_ref(), "llen without key"),
+            (b"SETCHUNK key\n".as_ref(), "setchunk without seq"),
+            (b"SETCHUNK key 0\n".as_ref(), "setchunk without data"),
+            (
+                b"SETCHUNK key -1 data\n".as_ref(),
+                "setchunk with negative seq",
+            ),
+            (b"SETCHUNKEND\n".as_ref(), "setchunkend without key"),
+            (b"MAX\n".as_ref(), "max without key"),
+            (b"MAX key\n".as_ref(), "max without candidate"),
+            (b"MIN key one\n".as_ref(), "min with malformed candidate"),
+            (b"BGET\n".as_ref(), "bget without key"),
+            (b"BGET key\n".as_ref(), "bget without timeout"),
+            (b"BGET key -1\n".as_ref(), "bget with negative timeout"),
+        ];
+
+        cases.into_iter().for_each(|(message, reason)| {
+            // Pre-condition.
+            let mut decoder = Codec::default();
+            let mut message = BytesMut::from(message);
+
+            // Action.
+            let request = decoder.decode(&mut message);
+
+            // Post-condition.
+            assert!(request.is_err(), "{}", reason);
+            assert!(message.is_empty(), "{}", reason);
+        });
+    }
+
+    #[test]
+    fn succeeds_to_decode_wellformed_request() {
+        let cases = vec![
+            (
+                b"GET key\n".as_ref(),
+                Request::Get { key: "key".into() },
+                "get key",
+            ),
+            (
+                b"GETDEL key\n".as_ref(),
+                Request::GetDel { key: "key".into() },
+                "getdel key",
+            ),
+            (
+                b"GETRANGE key 0 -1\n".as_ref(),
+                Request::GetRange {
+                    key: "key".into(),
+                    start: 0,
+                    end: -1,
+                },
+                "getrange key",
+            ),
+            (
+                b"GETOR key default\n".as_ref(),
+                Request::GetOr {
+                    key: "key".into(),
+                    default: "default".into(),
+                },
+                "getor key with default",
+            ),
+            (
+                b"SET key value\n".as_ref(),
+                Request::Set {
+                    key: "key".into(),
+                    value: "value".into(),
+                },
+                "set key to value",
+            ),
+            (
+                b"SET key\n".as_ref(),
+                Request::Set {
+                    key: "key".into(),
+                    value: "".into(),
+                },
+                "set key without value",
+            ),
+            (
+                b"SET key \n".as_ref(),
+                Request::Set {
+                    key: "key".into(),
+                    value: "".into(),
+                },
+                "set key to empty value",
+            ),
+            (
+                b"SET key \t \n".as_ref(),
+                Request::Set {
+                    key: "key".into(),
+                    value: "".into(),
+                },
+                "set key to whitespace only",
+            ),
+            (
+                b"SET key value \n".as_ref(),
+                Request::Set {
+                    key: "key".into(),
+                    value: "value".into(),
+                },
+                "set key to value followed by space",
+            ),
+            (
+                b"SET key value GET\n".as_ref(),
+                Request::SetGet {
+                    key: "key".into(),
+                    value: "value".into(),
+                },
+                "set key to value returning old value",
+            ),
+            (
+                b"SETNX key value\n".as_ref(),
+                Request::SetNx {
+                    key: "key".into(),
+                    value: "value".into(),
+                },
+                "setnx key to value",
+            ),
+            (
+                b"SETIFMATCH key v1 v2\n".as_ref(),
+                Request::SetIfMatch {
+                    key: "key".into(),
+                    pattern: "v1".into(),
+                    value: "v2".into(),
+                },
+                "setifmatch key to value",
+            ),
+            (
+                b"SETIDEM key t1 value\n".as_ref(),
+                Request::SetIdem {
+                    key: "key".into(),
+                    token: "t1".into(),
+                    value: "value".into(),
+                },
+                "setidem key to value",
+            ),
+            (
+                b"MSETNX k1 a k2 b\n".as_ref(),
+                Request::MSetNx {
+                    pairs: vec![("k1".into(), "a".into()), ("k2".into(), "b".into())],
+                },
+                "msetnx two keys",
+            ),
+            (
+                b"DELIFEQ key value\n".as_ref(),
+                Request::DelIfEquals {
+                    key: "key".into(),
+                    expected: "value".into(),
+                },
+                "delifeq key",
+            ),
+            (
+                b"RENAME src dst\n".as_ref(),
+                Request::Rename {
+                    src: "src".into(),
+                    dst: "dst".into(),
+                },
+                "rename src to dst",
+            ),
+            (b"MULTI\n".as_ref(), Request::Multi, "multi"),
+            (b"EXEC\n".as_ref(), Request::Exec, "exec"),
+            (b"DISCARD\n".as_ref(), Request::Discard, "discard"),
+            (
+                b"WATCH k j\n".as_ref(),
+                Request::Watch {
+                    keys: vec!["k".into(), "j".into()],
+                },
+                "watch keys",
+            ),
+            (b"UNWATCH\n".as_ref(), Request::Unwatch, "unwatch"),
+            (b"DUMP\n".as_ref(), Request::Dump, "dump"),
+            (
+                b"DEADLINE 50 SET key value\n".as_ref(),
+                Request::Deadline {
+                    timeout_ms: 50,
+                    request: Box::new(Request::Set {
+                        key: "key".into(),
+                        value: "value".into(),
+                    }),
+                },
+                "deadline of set key to value",
+            ),
+            (
+                b"PSUBSCRIBE user:\n".as_ref(),
+                Request::PSubscribe {
+                    prefix: "user:".into(),
+                },
+                "psubscribe prefix",
+            ),
+            (
+                b"SUBSCRIBE-EXPIRED\n".as_ref(),
+                Request::SubscribeExpired,
+                "subscribe-expired",
+            ),
+            (
+                b"GETPREFIX user:\n".as_ref(),
+                Request::GetPrefix {
+                    prefix: "user:".into(),
+                },
+                "getprefix prefix",
+            ),
+            (
+                b"CHILDREN user:1: :\n".as_ref(),
+                Request::Children {
+                    prefix: "user:1:".into(),
+                    separator: ":".into(),
+                },
+                "children prefix separator",
+            ),
+            (
+                b"MEMUSAGE user:\n".as_ref(),
+                Request::MemUsage {
+                    prefix: "user:".into(),
+                },
+                "memusage prefix",
+            ),
+            (
+                b"FINDVAL needle\n".as_ref(),
+                Request::FindVal {
+                    substring: "needle".into(),
+                },
+                "findval substring",
+            ),
+            (b"COMPACT\n".as_ref(), Request::Compact, "compact"),
+            (b"SYNC\n".as_ref(), Request::Sync, "sync"),
+            (b"PONG\n".as_ref(), Request::Pong, "pong"),
+            (b"DBSIZE\n".as_ref(), Request::DbSize, "dbsize"),
+            (b"DBLIST\n".as_ref(), Request::DbList, "dblist"),
+            (b"LASTSEQ\n".as_ref(), Request::LastSeq, "lastseq"),
+            (
+                b"CAPABILITIES\n".as_ref(),
+                Request::Capabilities,
+                "capabilities",
+            ),
+            (
+                b"GETLOG 10\n".as_ref(),
+                Request::GetLog { count: 10 },
+                "getlog",
+            ),
+            (b"INFO\n".as_ref(), Request::Info, "info"),
+            (b"TIME\n".as_ref(), Request::Time, "time"),
+            (
+                b"CLIENT LIST\n".as_ref(),
+                Request::ClientList,
+                "client list",
+            ),
+            (
+                b"CLIENT KILL 127.0.0.1:4242\n".as_ref(),
+                Request::ClientKill {
+                    peer_addr: SocketAddr::from(([127, 0, 0, 1], 4242)),
+                },
+                "client kill address",
+            ),
+            (
+                b"CONFIG GET read-only\n".as_ref(),
+                Request::ConfigGet {
+                    param: "read-only".into(),
+                },
+                "config get parameter",
+            ),
+            (
+                b"CONFIG SET read-only true\n".as_ref(),
+                Request::ConfigSet {
+                    param: "read-only".into(),
+                    value: "true".into(),
+                },
+                "config set parameter",
+            ),
+            (
+                b"USE app\n".as_ref(),
+                Request::Use { name: "app".into() },
+                "use name",
+            ),
+            (
+                b"FILL key 100\n".as_ref(),
+                Request::Fill {
+                    prefix: "key".into(),
+                    count: 100,
+                },
+                "fill key prefix",
+            ),
+            (
+                b"IDLETIME key\n".as_ref(),
+                Request::IdleTime { key: "key".into() },
+                "idletime key",
+            ),
+            (
+                b"HOTKEYS 3\n".as_ref(),
+                Request::HotKeys { count: 3 },
+                "hotkeys",
+            ),
+            (
+                b"CHECKSUM key\n".as_ref(),
+                Request::Checksum { key: "key".into() },
+                "checksum key",
+            ),
+            (
+                b"HELLO 1\n".as_ref(),
+                Request::Hello { version: 1 },
+                "hello version",
+            ),
+            (
+                b"AUTH secret\n".as_ref(),
+                Request::Auth {
+                    password: "secret".into(),
+                },
+                "auth password",
+            ),
+            (
+                b"ENCODING base64\n".as_ref(),
+                Request::Encoding {
+                    encoding: ValueEncoding::Base64,
+                },
+                "encoding base64",
+            ),
+            (
+                b"EXPIRE key 10\n".as_ref(),
+                Request::Expire {
+                    key: "key".into(),
+                    seconds: 10,
+                },
+                "expire key in seconds",
+            ),
+            (
+                b"SETEX key 10 value\n".as_ref(),
+                Request::SetEx {
+                    key: "key".into(),
+                    seconds: 10,
+                    value: "value".into(),
+                },
+                "setex key in seconds to value",
+            ),
+            (
+                b"TTL key\n".as_ref(),
+                Request::Ttl { key: "key".into() },
+                "ttl key",
+            ),
+            (
+                b"GETTTL key\n".as_ref(),
+                Request::GetWithTtl { key: "key".into() },
+                "getttl key",
+            ),
+            (
+                b"INCRBY key -5\n".as_ref(),
+                Request::IncrBy {
+                    key: "key".into(),
+                    delta: -5,
+                },
+                "incrby key by negative delta",
+            ),
+            (
+                b"LPUSH key a\n".as_ref(),
+                Request::LPush {
+                    key: "key".into(),
+                    item: "a".into(),
+                },
+                "lpush key item",
+            ),
+            (
+                b"LLEN key\n".as_ref(),
+                Request::LLen { key: "key".into() },
+                "llen key",
+            ),
+            (
+                b"SETCHUNK key 2 data\n".as_ref(),
+                Request::SetChunk {
+                    key: "key".into(),
+                    seq: 2,
+                    data: "data".into(),
+                },
+                "setchunk key seq data",
+            ),
+            (
+                b"SETCHUNKEND key\n".as_ref(),
+                Request::SetChunkEnd { key: "key".into() },
+                "setchunkend key",
+            ),
+            (
+                b"MAX key 7\n".as_ref(),
+                Request::SetExtremum {
+                    key: "key".into(),
+                    extremum: Extremum::Max,
+                    candidate: 7,
+                },
+                "max",
+            ),
+            (
+                b"MIN key -7\n".as_ref(),
+                Request::SetExtremum {
+                    key: "key".into(),
+                    extremum: Extremum::Min,
+                    candidate: -7,
+                },
+                "min",
+            ),
+            (
+                b"BGET key 500\n".as_ref(),
+                Request::BGet {
+                    key: "key".into(),
+                    timeout_ms: 500,
+                },
+                "bget key for 500ms",
+            ),
+        ];
+
+        cases
+            .into_iter()
+            .filter(|(message, _, _)| in_build(message))
+            .for_each(|(message, expected_request, reason)| {
+                // Pre-condition.
+                let mut decoder = Codec::default();
+                let mut message = BytesMut::from(message);
+
+                // Action.
+                let request = decoder.decode(&mut message).unwrap();
+
+                // Post-condition.
+                assert_eq!(request, Some(expected_request), "{}", reason);
+                assert!(message.is_empty(), "{}", reason);
+            });
+    }
 
     #[test]
-    fn succeeds_to_decode_wellformed_request() {
+    fn validation_rejects_forbidden_characters() {
+        let cases = vec![
+            (Validation::Lenient, "a\nb", "line feed"),
+            (Validation::Lenient, "ab\r", "trailing carriage return"),
+            (Validation::Lenient, "a b", "space"),
+            (Validation::Strict, "a b", "space"),
+            (Validation::Strict, "a\tb", "tab"),
+            (Validation::Strict, "a\u{7}b", "bell"),
+        ];
+
+        cases.into_iter().for_each(|(validation, key, reason)| {
+            // Pre-condition.
+            let request = Request::Get { key: key.into() };
+
+            // Action.
+            let validated = request.validate(validation);
+
+            // Post-condition.
+            assert!(validated.is_err(), "{}", reason);
+        });
+    }
+
+    #[test]
+    fn lenient_validation_accepts_non_breaking_characters() {
+        // Pre-condition.
+        let request = Request::Set {
+            key: "k\ty".into(),
+            value: "\u{7}\rb".into(),
+        };
+
+        // Action.
+        let validated = request.validate(Validation::Lenient);
+
+        // Post-condition.
+        assert!(validated.is_ok());
+    }
+
+    #[test]
+    fn decodes_crlf_terminated_request_without_carriage_return() {
+        let cases = vec![
+            (
+                b"GET key\r\n".as_ref(),
+                Request::Get { key: "key".into() },
+                "get",
+            ),
+            (
+                b"SET key value\r\n".as_ref(),
+                Request::Set {
+                    key: "key".into(),
+                    value: "value".into(),
+                },
+                "set",
+            ),
+        ];
+
+        cases
+            .into_iter()
+            .for_each(|(message, expected_request, reason)| {
+                // Pre-condition.
+                let mut decoder = Codec::default();
+                let mut message = BytesMut::from(message);
+
+                // Action.
+                let request = decoder.decode(&mut message).unwrap();
+
+                // Post-condition.
+                assert_eq!(request, Some(expected_request), "{}", reason);
+            });
+    }
+
+    #[test]
+    fn skips_comment_and_blank_lines() {
+        // Pre-condition.
+        let mut decoder = Codec::default();
+        let mut message = BytesMut::from(
+            b"# Greeting.\nGET key\n\n   \r\n#SET key value\nSET key value\n#".as_ref(),
+        );
+
+        // Action.
+        let first = decoder.decode(&mut message).unwrap();
+        let second = decoder.decode(&mut message).unwrap();
+        let third = decoder.decode(&mut message).unwrap();
+
+        // Post-condition.
+        assert_eq!(first, Some(Request::Get { key: "key".into() }));
+        assert_eq!(
+            second,
+            Some(Request::Set {
+                key: "key".into(),
+                value: "value".into(),
+            })
+        );
+        assert_eq!(third, None);
+        assert_eq!(message.as_ref(), b"#");
+    }
+
+    #[test]
+    fn reassembles_multibyte_value_split_byte_by_byte() {
+        // Pre-condition.
+        let mut decoder = Codec::default();
+        let mut buffer = BytesMut::new();
+        let message = "SET key h\u{e9}\u{1f980}\n".as_bytes();
+
+        // Action.
+        let mut decoded = Vec::new();
+        for byte in message {
+            buffer.put_u8(*byte);
+            decoded.push(decoder.decode(&mut buffer).unwrap());
+        }
+
+        // Post-condition.
+        let (last, partial) = decoded.split_last().unwrap();
+        assert!(partial.iter().all(Option::is_none));
+        match last {
+            Some(Request::Set { key, value }) => {
+                assert_eq!(key, "key");
+                assert_eq!(value.as_bytes(), "h\u{e9}\u{1f980}".as_bytes());
+                assert!(!value.contains(char::REPLACEMENT_CHARACTER));
+            }
+            request => panic!("expected SET, got {:?}", request),
+        }
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn invalid_utf8_line_is_malformed_and_skipped() {
+        // Pre-condition.
+        let mut decoder = Codec::default();
+        let mut message = BytesMut::from(b"SET key \xf0\x9f\xa6\nGET key\n".as_ref());
+
+        // Action.
+        let first = decoder.decode(&mut message);
+        let second = decoder.decode(&mut message).unwrap();
+
+        // Post-condition.
+        let e = first.unwrap_err();
+        assert!(e.is::<MalformedRequest>(), "{:#}", e);
+        assert_eq!(second, Some(Request::Get { key: "key".into() }));
+    }
+
+    #[test]
+    fn keeps_carriage_return_within_crlf_terminated_value() {
+        // Pre-condition.
+        let mut decoder = Codec::default();
+        let mut message = BytesMut::from(b"SET key a\rb\r\n".as_ref());
+
+        // Action.
+        let request = decoder.decode(&mut message).unwrap();
+
+        // Post-condition.
+        assert_eq!(
+            request,
+            Some(Request::Set {
+                key: "key".into(),
+                value: "a\rb".into(),
+            })
+        );
+        assert!(message.is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn validated_set_request_roundtrips(
+            validation in validation(),
+            key in any::<String>(),
+            value in any::<String>(),
+        ) {
+            // Pre-condition.
+            let mut message = BytesMut::from(format!("SET {} {}\n", key, value).as_str());
+            let mut decoder = Codec::with_validation(validation);
+
+            let request = Request::Set { key, value };
+            prop_assume!(request.validate(validation).is_ok());
+
+            // Action.
+            let decoded = decoder.decode(&mut message).unwrap();
+
+            // Post-condition.
+            // A value made of whitespace only is read as empty.
+            let expected = match request {
+                Request::Set { key, value } if value.trim().is_empty() => Request::Set {
+                    key,
+                    value: String::new(),
+                },
+                request => request,
+            };
+            prop_assert_eq!(decoded, Some(expected));
+        }
+    }
+
+    #[test]
+    fn succeeds_to_encode_response() {
         let cases = vec![
             (
-                b"GET key\n".as_ref(),
-                Request::Get { key: "key".into() },
-                "get key",
+                Response::Get {
+                    key: "key".into(),
+                    value: None,
+                },
+                b"FAIL key\n".as_ref(),
+                "get without value",
+            ),
+            (
+                Response::Get {
+                    key: "key".into(),
+                    value: Some("value".into()),
+                },
+                b"OKAY key value\n".as_ref(),
+                "get with value",
+            ),
+            (
+                Response::Get {
+                    key: "key".into(),
+                    value: Some("".into()),
+                },
+                b"OKAY key \n".as_ref(),
+                "get with empty value",
+            ),
+            (
+                Response::GetDel {
+                    key: "key".into(),
+                    value: Some("value".into()),
+                },
+                b"OKAY key value\n".as_ref(),
+                "getdel existing key",
+            ),
+            (
+                Response::GetDel {
+                    key: "key".into(),
+                    value: None,
+                },
+                b"FAIL key\n".as_ref(),
+                "getdel missing key",
+            ),
+            (
+                Response::Set { key: "key".into() },
+                b"OKAY key\n".as_ref(),
+                "set key",
+            ),
+            (
+                Response::SetGet {
+                    key: "key".into(),
+                    value: Some("old".into()),
+                },
+                b"OKAY key old\n".as_ref(),
+                "set key displacing old value",
+            ),
+            (
+                Response::SetGet {
+                    key: "key".into(),
+                    value: None,
+                },
+                b"FAIL key\n".as_ref(),
+                "set missing key",
+            ),
+            (
+                Response::SetNx {
+                    key: "key".into(),
+                    set: true,
+                },
+                b"OKAY key\n".as_ref(),
+                "setnx missing key",
+            ),
+            (
+                Response::SetNx {
+                    key: "key".into(),
+                    set: false,
+                },
+                b"FAIL key\n".as_ref(),
+                "setnx existing key",
+            ),
+            (
+                Response::SetIfMatch {
+                    key: "key".into(),
+                    set: true,
+                },
+                b"OKAY key\n".as_ref(),
+                "setifmatch matching key",
+            ),
+            (
+                Response::SetIfMatch {
+                    key: "key".into(),
+                    set: false,
+                },
+                b"FAIL key\n".as_ref(),
+                "setifmatch mismatching key",
+            ),
+            (
+                Response::SetIdem {
+                    key: "key".into(),
+                    applied: true,
+                },
+                b"OKAY key APPLIED\n".as_ref(),
+                "setidem applied",
+            ),
+            (
+                Response::SetIdem {
+                    key: "key".into(),
+                    applied: false,
+                },
+                b"OKAY key DUPLICATE\n".as_ref(),
+                "setidem deduplicated",
+            ),
+            (
+                Response::MSetNx { set: true },
+                b"OKAY\n".as_ref(),
+                "msetnx set",
+            ),
+            (
+                Response::MSetNx { set: false },
+                b"FAIL\n".as_ref(),
+                "msetnx some key exists",
+            ),
+            (
+                Response::DelIfEquals {
+                    key: "key".into(),
+                    deleted: true,
+                },
+                b"OKAY key\n".as_ref(),
+                "delifeq matching key",
+            ),
+            (
+                Response::DelIfEquals {
+                    key: "key".into(),
+                    deleted: false,
+                },
+                b"FAIL key\n".as_ref(),
+                "delifeq mismatching key",
+            ),
+            (
+                Response::Rename { ok: true },
+                b"OKAY\n".as_ref(),
+                "rename existing key",
+            ),
+            (
+                Response::Rename { ok: false },
+                b"FAIL\n".as_ref(),
+                "rename missing key",
+            ),
+            (Response::Queued, b"OKAY QUEUED\n".as_ref(), "queued"),
+            (Response::DumpEnd, b"OKAY END\n".as_ref(), "dump end"),
+            (
+                Response::GetPrefix {
+                    pairs: vec![
+                        ("user:1".into(), "toy".into()),
+                        ("user:2".into(), "toy storage".into()),
+                    ],
+                },
+                b"OKAY 2\nuser:1 toy\nuser:2 toy storage\n".as_ref(),
+                "getprefix",
+            ),
+            (
+                Response::GetPrefix { pairs: vec![] },
+                b"OKAY 0\n".as_ref(),
+                "getprefix without match",
+            ),
+            (
+                Response::Children {
+                    segments: vec!["address".into(), "name".into()],
+                },
+                b"OKAY 2\naddress\nname\n".as_ref(),
+                "children",
+            ),
+            (
+                Response::FindVal {
+                    keys: vec!["user:1".into(), "user:3".into()],
+                },
+                b"OKAY 2\nuser:1\nuser:3\n".as_ref(),
+                "findval",
             ),
             (
-                b"SET key value\n".as_ref(),
-                Request::Set {
+                Response::HotKeys {
+                    keys: vec!["user:3".into(), "user:1".into()],
+                },
+                b"OKAY 2\nuser:3\nuser:1\n".as_ref(),
+                "hotkeys",
+            ),
+            (
+                Response::MemUsage {
+                    sizes: vec![("user:2".into(), 11), ("user:1".into(), 3)],
+                },
+                b"OKAY 2\nuser:2 11\nuser:1 3\n".as_ref(),
+                "memusage",
+            ),
+            (
+                Response::DbList {
+                    sizes: vec![("app0".into(), 2), ("app2".into(), 1)],
+                },
+                b"OKAY 2\napp0 2\napp2 1\n".as_ref(),
+                "dblist",
+            ),
+            (
+                Response::Notify {
+                    mutation: Mutation::Set { key: "key".into() },
+                },
+                b"NOTIFY SET key\n".as_ref(),
+                "notify set",
+            ),
+            (
+                Response::Notify {
+                    mutation: Mutation::Del { key: "key".into() },
+                },
+                b"NOTIFY DEL key\n".as_ref(),
+                "notify del",
+            ),
+            (
+                Response::Notify {
+                    mutation: Mutation::Expire { key: "key".into() },
+                },
+                b"NOTIFY EXPIRE key\n".as_ref(),
+                "notify expire",
+            ),
+            (
+                Response::SubscribeExpired,
+                b"OKAY\n".as_ref(),
+                "subscribe-expired",
+            ),
+            (
+                Response::Notify {
+                    mutation: Mutation::Expired { key: "key".into() },
+                },
+                b"EXPIRED key\n".as_ref(),
+                "notify expired",
+            ),
+            (Response::Compact, b"OKAY\n".as_ref(), "compact"),
+            (Response::Sync, b"OKAY\n".as_ref(), "sync"),
+            (Response::Ping, b"PING\n".as_ref(), "ping"),
+            (Response::Busy, b"FAIL BUSY\n".as_ref(), "busy"),
+            (Response::Aborted, b"FAIL ABORTED\n".as_ref(), "aborted"),
+            (Response::Watch { ok: true }, b"OKAY\n".as_ref(), "watch"),
+            (
+                Response::Watch { ok: false },
+                b"FAIL\n".as_ref(),
+                "watch within transaction",
+            ),
+            (Response::Unwatch, b"OKAY\n".as_ref(), "unwatch"),
+            (Response::Use { ok: true }, b"OKAY\n".as_ref(), "use"),
+            (
+                Response::ClientList {
+                    clients: Some(vec![ClientInfo {
+                        id: 7,
+                        peer_addr: SocketAddr::from(([127, 0, 0, 1], 4242)),
+                        age: Duration::from_millis(3500),
+                    }]),
+                },
+                b"OKAY 1\n7 127.0.0.1:4242 3\n".as_ref(),
+                "client list",
+            ),
+            (
+                Response::Use { ok: false },
+                b"FAIL\n".as_ref(),
+                "use unavailable",
+            ),
+            (
+                Response::Fill { created: 100 },
+                b"OKAY 100\n".as_ref(),
+                "fill",
+            ),
+            (Response::DbSize { size: 3 }, b"OKAY 3\n".as_ref(), "dbsize"),
+            (
+                Response::LastSeq { seq: 7 },
+                b"OKAY 7\n".as_ref(),
+                "lastseq",
+            ),
+            (
+                Response::Capabilities {
+                    capabilities: Capabilities {
+                        expiry: true,
+                        sync: false,
+                        scan: true,
+                        subscribe: false,
+                    },
+                },
+                b"OKAY expiry scan\n".as_ref(),
+                "capabilities",
+            ),
+            (
+                Response::Capabilities {
+                    capabilities: Capabilities::default(),
+                },
+                b"OKAY\n".as_ref(),
+                "no capabilities",
+            ),
+            (
+                Response::GetLog {
+                    entries: vec![
+                        LogEntry {
+                            seq: 1,
+                            mutation: Mutation::Set { key: "k".into() },
+                        },
+                        LogEntry {
+                            seq: 2,
+                            mutation: Mutation::Del { key: "k".into() },
+                        },
+                    ],
+                },
+                b"OKAY 2\n1 SET k\n2 DEL k\n".as_ref(),
+                "getlog",
+            ),
+            (
+                Response::Info {
+                    fields: vec![
+                        ("queue_depth".into(), "3".into()),
+                        ("queue_capacity".into(), "32".into()),
+                    ],
+                },
+                b"OKAY 2\nqueue_depth 3\nqueue_capacity 32\n".as_ref(),
+                "info",
+            ),
+            (
+                Response::Time {
+                    unix_secs: 1_700_000_000,
+                    uptime_secs: 42,
+                },
+                b"OKAY 1700000000 42\n".as_ref(),
+                "time",
+            ),
+            (
+                Response::ConfigGet {
+                    param: "validation".into(),
+                    value: Some("strict".into()),
+                },
+                b"OKAY validation strict\n".as_ref(),
+                "config get setting",
+            ),
+            (
+                Response::ConfigGet {
+                    param: "unknown".into(),
+                    value: None,
+                },
+                b"FAIL unknown\n".as_ref(),
+                "config get unknown setting",
+            ),
+            (
+                Response::ConfigSet {
+                    param: "read-only".into(),
+                },
+                b"OKAY read-only\n".as_ref(),
+                "config set",
+            ),
+            (
+                Response::IncrBy {
                     key: "key".into(),
-                    value: "value".into(),
+                    value: Some(-2),
                 },
-                "set key to value",
+                b"OKAY key -2\n".as_ref(),
+                "incrby integer",
+            ),
+            (
+                Response::IncrBy {
+                    key: "key".into(),
+                    value: None,
+                },
+                b"FAIL key\n".as_ref(),
+                "incrby non-integer",
+            ),
+            (
+                Response::ListLen {
+                    key: "key".into(),
+                    len: 3,
+                },
+                b"OKAY key 3\n".as_ref(),
+                "list length",
+            ),
+            (
+                Response::SetChunk {
+                    key: "key".into(),
+                    expected: None,
+                },
+                b"OKAY key\n".as_ref(),
+                "setchunk in order",
+            ),
+            (
+                Response::SetChunk {
+                    key: "key".into(),
+                    expected: Some(1),
+                },
+                b"FAIL key 1\n".as_ref(),
+                "setchunk out of order",
+            ),
+            (
+                Response::SetChunkEnd {
+                    key: "key".into(),
+                    ok: true,
+                },
+                b"OKAY key\n".as_ref(),
+                "setchunkend",
+            ),
+            (
+                Response::SetChunkEnd {
+                    key: "key".into(),
+                    ok: false,
+                },
+                b"FAIL key\n".as_ref(),
+                "setchunkend without chunks",
+            ),
+            (
+                Response::SetExtremum {
+                    key: "key".into(),
+                    value: Some(7),
+                },
+                b"OKAY key 7\n".as_ref(),
+                "extremum integer",
+            ),
+            (
+                Response::SetExtremum {
+                    key: "key".into(),
+                    value: None,
+                },
+                b"FAIL key\n".as_ref(),
+                "extremum non-integer",
+            ),
+            (
+                Response::GetWithTtl {
+                    key: "key".into(),
+                    value: Some("value".into()),
+                    ttl_secs: Some(-1),
+                },
+                b"OKAY key -1 value\n".as_ref(),
+                "getttl persistent key",
+            ),
+            (
+                Response::GetWithTtl {
+                    key: "key".into(),
+                    value: None,
+                    ttl_secs: None,
+                },
+                b"FAIL key\n".as_ref(),
+                "getttl missing key",
+            ),
+            (
+                Response::Exec {
+                    responses: Some(vec![
+                        Response::Set { key: "key".into() },
+                        Response::Get {
+                            key: "key".into(),
+                            value: Some("value".into()),
+                        },
+                    ]),
+                },
+                b"OKAY 2\nOKAY key\nOKAY key value\n".as_ref(),
+                "exec with responses",
+            ),
+            (
+                Response::Exec { responses: None },
+                b"FAIL\n".as_ref(),
+                "exec without multi",
+            ),
+            (
+                Response::IdleTime {
+                    key: "key".into(),
+                    seconds: Some(3),
+                },
+                b"OKAY key 3\n".as_ref(),
+                "idletime existing key",
+            ),
+            (
+                Response::IdleTime {
+                    key: "key".into(),
+                    seconds: None,
+                },
+                b"FAIL key\n".as_ref(),
+                "idletime missing key",
+            ),
+            (
+                Response::Checksum {
+                    key: "key".into(),
+                    hash: Some(0xcbf43926),
+                },
+                b"OKAY key cbf43926\n".as_ref(),
+                "checksum existing key",
+            ),
+            (
+                Response::Checksum {
+                    key: "key".into(),
+                    hash: Some(0x3c),
+                },
+                b"OKAY key 0000003c\n".as_ref(),
+                "checksum padded to 8 digits",
+            ),
+            (
+                Response::Checksum {
+                    key: "key".into(),
+                    hash: None,
+                },
+                b"FAIL key\n".as_ref(),
+                "checksum missing key",
+            ),
+            (
+                Response::Hello {
+                    version: 1,
+                    ok: true,
+                },
+                b"OKAY 1\n".as_ref(),
+                "hello supported version",
+            ),
+            (
+                Response::Hello {
+                    version: 1,
+                    ok: false,
+                },
+                b"FAIL 1\n".as_ref(),
+                "hello unsupported version",
+            ),
+            (
+                Response::Auth { ok: true },
+                b"OKAY\n".as_ref(),
+                "auth known password",
+            ),
+            (
+                Response::Auth { ok: false },
+                b"FAIL\n".as_ref(),
+                "auth unknown password",
+            ),
+            (
+                Response::Encoding {
+                    encoding: ValueEncoding::Base64,
+                },
+                b"OKAY base64\n".as_ref(),
+                "encoding",
+            ),
+            (
+                Response::Error {
+                    message: "unrecognized command: FOO".into(),
+                },
+                b"ERR unrecognized command: FOO\n".as_ref(),
+                "error",
             ),
         ];
 
         cases
             .into_iter()
-            .for_each(|(message, expected_request, reason)| {
+            .for_each(|(response, expected_message, reason)| {
                 // Pre-condition.
-                let mut decoder = Codec::default();
-                let mut message = BytesMut::from(message);
+                let mut encoder = Codec::default();
+                let mut message = BytesMut::default();
 
                 // Action.
-                let request = decoder.decode(&mut message).unwrap();
+                encoder.encode(response, &mut message).unwrap();
 
                 // Post-condition.
-                assert_eq!(request, Some(expected_request), "{}", reason);
-                assert!(message.is_empty(), "{}", reason);
+                assert_eq!(message, expected_message, "{}", reason)
             });
     }
 
     #[test]
-    fn succeeds_to_encode_response() {
-        let cases = vec![
-            (
-                Response::Get {
-                    key: "key".into(),
-                    value: None,
-                },
-                b"FAIL key\n".as_ref(),
-                "get without value",
-            ),
-            (
+    fn encodes_responses_after_bytes_pending_in_buffer() {
+        // Pre-condition.
+        let mut encoder = Codec::default();
+        let mut message = BytesMut::with_capacity(64);
+        message.extend_from_slice(b"OKAY k\n");
+
+        // Action.
+        encoder
+            .encode(
                 Response::Get {
-                    key: "key".into(),
-                    value: Some("value".into()),
+                    key: "k".into(),
+                    value: Some("v".into()),
                 },
-                b"OKAY key value\n".as_ref(),
-                "get with value",
-            ),
+                &mut message,
+            )
+            .unwrap();
+        let pending = message.split();
+        encoder
+            .encode(Response::DbSize { size: 2 }, &mut message)
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(pending, b"OKAY k\nOKAY k v\n".as_ref());
+        assert_eq!(message, b"OKAY 2\n".as_ref());
+    }
+
+    #[test]
+    fn encodes_response_with_chosen_line_ending() {
+        let cases = vec![
+            (LineEnding::Lf, b"OKAY 1\nOKAY k\n".as_ref(), "lf"),
+            (LineEnding::CrLf, b"OKAY 1\r\nOKAY k\r\n".as_ref(), "crlf"),
+        ];
+
+        cases
+            .into_iter()
+            .for_each(|(line_ending, expected_message, reason)| {
+                // Pre-condition.
+                let mut encoder = Codec::default().with_line_ending(line_ending);
+                let mut message = BytesMut::default();
+                let response = Response::Exec {
+                    responses: Some(vec![Response::Set { key: "k".into() }]),
+                };
+
+                // Action.
+                encoder.encode(response, &mut message).unwrap();
+
+                // Post-condition.
+                assert_eq!(message, expected_message, "{}", reason)
+            });
+    }
+
+    #[test]
+    fn replaces_response_beyond_max_bytes_with_error() {
+        // Pre-condition.
+        let mut encoder = Codec::default().with_max_response_bytes(16);
+        let mut message = BytesMut::default();
+        let responses = vec![
+            Response::GetPrefix {
+                pairs: vec![
+                    ("user:1".into(), "toy".into()),
+                    ("user:2".into(), "toy storage".into()),
+                ],
+            },
+            Response::Get {
+                key: "k".into(),
+                value: Some("a".into()),
+            },
+        ];
+
+        // Action.
+        for response in responses {
+            encoder.encode(response, &mut message).unwrap();
+        }
+
+        // Post-condition.
+        assert_eq!(
+            message,
+            b"ERR response of 37 bytes exceeds limit of 16\nOKAY k a\n".as_ref()
+        )
+    }
+
+    #[test]
+    fn encodes_missing_key_with_chosen_response() {
+        let cases = vec![
             (
-                Response::Set { key: "key".into() },
-                b"OKAY key\n".as_ref(),
-                "set key",
+                MissingResponse::Fail,
+                b"FAIL k\nOKAY j a\n".as_ref(),
+                "fail",
             ),
+            (MissingResponse::Nil, b"NIL k\nOKAY j a\n".as_ref(), "nil"),
         ];
 
         cases
             .into_iter()
-            .for_each(|(response, expected_message, reason)| {
+            .for_each(|(missing_response, expected_message, reason)| {
                 // Pre-condition.
-                let mut encoder = Codec::default();
+                let mut encoder = Codec::default().with_missing_response(missing_response);
                 let mut message = BytesMut::default();
+                let responses = vec![
+                    Response::Get {
+                        key: "k".into(),
+                        value: None,
+                    },
+                    Response::Get {
+                        key: "j".into(),
+                        value: Some("a".into()),
+                    },
+                ];
 
                 // Action.
-                encoder.encode(response, &mut message).unwrap();
+                for response in responses {
+                    encoder.encode(response, &mut message).unwrap();
+                }
 
                 // Post-condition.
                 assert_eq!(message, expected_message, "{}", reason)
             });
     }
 
+    #[test]
+    fn nil_response_applies_to_get_only() {
+        // Pre-condition.
+        let mut encoder = Codec::default().with_missing_response(MissingResponse::Nil);
+        let mut message = BytesMut::default();
+        let response = Response::Exec {
+            responses: Some(vec![
+                Response::Get {
+                    key: "k".into(),
+                    value: None,
+                },
+                Response::GetDel {
+                    key: "k".into(),
+                    value: None,
+                },
+            ]),
+        };
+
+        // Action.
+        encoder.encode(response, &mut message).unwrap();
+
+        // Post-condition.
+        assert_eq!(message, b"OKAY 2\nNIL k\nFAIL k\n".as_ref());
+    }
+
+    #[test]
+    fn crlf_codec_decodes_either_line_ending() {
+        let cases = vec![
+            (b"GET key\n".as_ref(), "lf"),
+            (b"GET key\r\n".as_ref(), "crlf"),
+        ];
+
+        cases.into_iter().for_each(|(message, reason)| {
+            // Pre-condition.
+            let mut decoder = Codec::default().with_line_ending(LineEnding::CrLf);
+            let mut message = BytesMut::from(message);
+
+            // Action.
+            let request = decoder.decode(&mut message).unwrap();
+
+            // Post-condition.
+            assert_eq!(
+                request,
+                Some(Request::Get { key: "key".into() }),
+                "{}",
+                reason
+            );
+        });
+    }
+
+    #[test]
+    fn records_frame_sizes_in_metrics() {
+        // Pre-condition.
+        let metrics = Arc::new(FrameMetrics::default());
+        let mut codec = Codec::default().with_metrics(metrics.clone());
+        let mut message = BytesMut::from(format!("SET k {}\n", "v".repeat(2000)).as_str());
+
+        // Action.
+        codec.decode(&mut message).unwrap();
+        codec
+            .encode(Response::Set { key: "k".into() }, &mut BytesMut::new())
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(metrics.request_bytes.counts(), vec![0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(metrics.request_bytes.sum(), 2006);
+        assert_eq!(metrics.response_bytes.counts(), vec![1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(metrics.response_bytes.sum(), 7);
+    }
+
+    /// Whether the command of `message` is recognized by this build.
+    fn in_build(message: &[u8]) -> bool {
+        let line = String::from_utf8_lossy(message);
+        let command = line.split([' ', '\n']).next().unwrap_or_default();
+        disabled_feature(command).is_none()
+    }
+
+    fn validation() -> impl Strategy<Value = Validation> {
+        prop_oneof![Just(Validation::Lenient), Just(Validation::Strict)]
+    }
+
     fn invalid_request_command() -> impl Strategy<Value = String> {
         any::<String>().prop_filter("valid command", |cmd| {
-            !vec!["GET", "SET"].contains(&cmd.as_str())
+            !is_noop(cmd.split('\n').next().unwrap_or_default())
+                && ![
+                    "GET",
+                    "GETDEL",
+                    "GETRANGE",
+                    "SET",
+                    "SETNX",
+                    "RENAME",
+                    "MULTI",
+                    "EXEC",
+                    "DISCARD",
+                    "WATCH",
+                    "UNWATCH",
+                    "DUMP",
+                    "PONG",
+                    "IDLETIME",
+                    "HOTKEYS",
+                    "CHECKSUM",
+                    "SETEX",
+                    "FILL",
+                    "DBSIZE",
+                    "DBLIST",
+                    "USE",
+                    "CLIENT",
+                    "INCRBY",
+                    "LPUSH",
+                    "LLEN",
+                    "SETCHUNK",
+                    "SETCHUNKEND",
+                    "COMPACT",
+                    "SYNC",
+                    "CONFIG",
+                    "BGET",
+                    "GETTTL",
+                    "PSUBSCRIBE",
+                    "SUBSCRIBE-EXPIRED",
+                    "DEADLINE",
+                    "SETIFMATCH",
+                    "SETIDEM",
+                    "MSETNX",
+                    "DELIFEQ",
+                    "LASTSEQ",
+                    "GETLOG",
+                    "CAPABILITIES",
+                    "INFO",
+                    "TIME",
+                    "MAX",
+                    "MIN",
+                    "GETPREFIX",
+                    "CHILDREN",
+                    "MEMUSAGE",
+                    "FINDVAL",
+                ]
+                .contains(&cmd.as_str())
         })
     }
 }
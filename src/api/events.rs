@@ -0,0 +1,43 @@
+//! Lifecycle events of a server, fed to embedders through a channel of their own.
+//!
+//! Events are sent without waiting, so a full channel drops them rather than
+//! slowing down the connections they describe.
+
+use super::types::Request;
+use std::net::SocketAddr;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::debug;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    /// A client connected from `peer_addr`.
+    Connected { peer_addr: SocketAddr },
+    /// The client at `peer_addr`, if known, sent `request`.
+    Command {
+        peer_addr: Option<SocketAddr>,
+        request: Request,
+    },
+    /// The client at `peer_addr` disconnected, or was disconnected.
+    Disconnected { peer_addr: SocketAddr },
+}
+
+/// Handle to the channel events are sent to, cheap to clone and share between connections.
+#[derive(Debug, Clone)]
+pub struct Events {
+    sender: mpsc::Sender<ServerEvent>,
+}
+
+impl Events {
+    pub fn new(sender: mpsc::Sender<ServerEvent>) -> Self {
+        Self { sender }
+    }
+
+    /// Sends `event` unless the channel is full or closed, in which case it is dropped.
+    pub fn emit(&self, event: ServerEvent) {
+        match self.sender.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(event)) => debug!(?event, "dropping event: channel full"),
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Read replica tailing the writes of a leader.
+//!
+//! A follower connects to its leader as a client twice: one connection
+//! subscribes to every mutation via `PSUBSCRIBE`, while the other reads the
+//! values written along with their time to live via `GETTTL`, since
+//! notifications carry keys only. Keys expired by the leader are notified as
+//! removed, hence removed locally as well.
+//!
+//! On every (re)connection, the follower subscribes first, then resyncs from
+//! a `DUMP` of the leader in a single transaction, removing local keys the
+//! leader lacks. Mutations missed while disconnected are thus not lost, and
+//! those notified during the resync are merely applied twice.
+//!
+//! The leader must neither require `HELLO` nor `AUTH`, and must serve
+//! `GETTTL` and `TTL`.
+
+use super::client::Connection;
+use crate::storage::{
+    types::{Key, Operation},
+    Store,
+};
+use anyhow::{bail, Context, Result};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use tokio::time;
+use tracing::{error, info};
+
+pub struct FollowerTask<S> {
+    leader: SocketAddr,
+    store: S,
+    retry_interval: Duration,
+}
+
+impl<S> FollowerTask<S>
+where
+    S: Store<Err = anyhow::Error>,
+{
+    pub fn new(leader: SocketAddr, store: S) -> Self {
+        Self {
+            leader,
+            store,
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Waits `interval` before reconnecting to the leader once disconnected.
+    pub fn retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Applies the writes of the leader to the store forever, reconnecting whenever disconnected.
+    pub async fn start(mut self) {
+        loop {
+            match self.follow().await {
+                Ok(()) => info!(leader = %self.leader, "leader closed connection"),
+                Err(e) => error!(reason = %e, leader = %self.leader, "lost leader"),
+            }
+            time::sleep(self.retry_interval).await;
+        }
+    }
+
+    async fn follow(&mut self) -> Result<()> {
+        let mut notifications = Connection::open(self.leader).await?;
+        notifications.send("PSUBSCRIBE ").await?;
+        notifications.expect("OKAY ").await?;
+
+        let mut reads = Connection::open(self.leader).await?;
+        let resynced = self.resync(&mut reads).await?;
+        info!(leader = %self.leader, keys = resynced, "following leader");
+
+        while let Some(line) = notifications.receive().await? {
+            match line.split_once(' ') {
+                Some(("NOTIFY", mutation)) => self.apply(mutation, &mut reads).await?,
+                _ => bail!("unexpected notification from leader: {:?}", line),
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces every pair in the store with those of the leader, along with their time to live,
+    /// returning how many there are.
+    async fn resync(&mut self, leader: &mut Connection) -> Result<usize> {
+        leader.send("DUMP").await?;
+        let mut pairs = HashMap::new();
+        loop {
+            let line = leader.expect("OKAY ").await?;
+            match line.split_once(' ') {
+                Some((key, value)) => {
+                    pairs.insert(Key::from(key), value.to_owned());
+                }
+                None if line == "END" => break,
+                None => bail!("unexpected pair from leader: {:?}", line),
+            }
+        }
+
+        // Read before applying anything, lest a key expire locally before its time to live is set.
+        let mut ttls = Vec::new();
+        for key in pairs.keys() {
+            if let Some(ttl) = ttl_on(leader, key).await? {
+                ttls.push((key.clone(), ttl));
+            }
+        }
+
+        let mut ops: Vec<_> = self
+            .store
+            .snapshot()
+            .await?
+            .into_iter()
+            .filter(|(key, _)| !pairs.contains_key(key))
+            .map(|(key, _)| Operation::GetDel { key })
+            .collect();
+        let count = pairs.len();
+        ops.extend(pairs.into_iter().map(|(key, value)| Operation::Set {
+            key,
+            value: value.into(),
+        }));
+        self.store.transaction(ops).await?;
+        for (key, ttl) in ttls {
+            self.store.expire(key, ttl).await?;
+        }
+        Ok(count)
+    }
+
+    /// Applies `mutation`, as in `SET $key`, `DEL $key` or `EXPIRE $key`, reading the value
+    /// written or the time to live set from `leader`.
+    async fn apply(&mut self, mutation: &str, leader: &mut Connection) -> Result<()> {
+        match mutation.split_once(' ') {
+            Some(("SET", key)) => {
+                leader.send(&format!("GETTTL {}", key)).await?;
+                let line = leader.receive_some().await?;
+                let fields: Vec<_> = line.splitn(4, ' ').collect();
+                match fields.as_slice() {
+                    ["OKAY", key, "-1", value] => {
+                        self.store
+                            .set((*key).into(), (*value).to_owned().into())
+                            .await
+                    }
+                    ["OKAY", key, seconds, value] => {
+                        let ttl = parse_ttl(seconds, &line)?;
+                        let value = (*value).to_owned().into();
+                        self.store.set_ex((*key).into(), value, ttl).await
+                    }
+                    // Removed again since, which is notified next.
+                    ["FAIL", _] => Ok(()),
+                    _ => bail!("unexpected response from leader: {:?}", line),
+                }
+            }
+            Some(("DEL", key)) => self.store.get_del(key.into()).await.map(drop),
+            Some(("EXPIRE", key)) => match ttl_on(leader, key).await? {
+                Some(ttl) => self.store.expire(key.into(), ttl).await.map(drop),
+                // Persistent or removed again since, e.g. written again, which is notified next.
+                None => Ok(()),
+            },
+            _ => bail!("unexpected mutation from leader: {:?}", mutation),
+        }
+    }
+}
+
+/// Reads the time to live of `key` from `leader`, `None` if it never expires or is missing.
+async fn ttl_on(leader: &mut Connection, key: &str) -> Result<Option<Duration>> {
+    leader.send(&format!("TTL {}", key)).await?;
+    let line = leader.receive_some().await?;
+    let fields: Vec<_> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["OKAY", _, "-1"] | ["FAIL", _] => Ok(None),
+        ["OKAY", _, seconds] => parse_ttl(seconds, &line).map(Some),
+        _ => bail!("unexpected response from leader: {:?}", line),
+    }
+}
+
+fn parse_ttl(seconds: &str, line: &str) -> Result<Duration> {
+    let seconds = seconds
+        .parse()
+        .with_context(|| format!("malformed TTL from {:?}", line))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+// Leaders serve `PSUBSCRIBE`, `DUMP` and `TTL` only when built with the features a follower
+// requires.
+#[cfg(all(test, feature = "pubsub", feature = "admin", feature = "expiry"))]
+mod tests {
+    use super::*;
+    use crate::{
+        api::Server,
+        storage::{
+            inmemory,
+            types::{Expiry, Value},
+        },
+    };
+    use tokio::{net::TcpListener, sync::oneshot};
+
+    #[tokio::test]
+    async fn resync_replaces_local_pairs_with_those_of_leader() {
+        // Pre-condition.
+        let mut leader = inmemory::start();
+        leader.set("k".into(), "a".into()).await.unwrap();
+        leader.set("j".into(), "b".into()).await.unwrap();
+        leader
+            .set_ex("e".into(), "c".into(), Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(listener, leader).start());
+
+        let mut local = inmemory::start();
+        local.set("k".into(), "stale".into()).await.unwrap();
+        local.set("i".into(), "gone".into()).await.unwrap();
+        let mut follower = FollowerTask::new(addr, local.clone());
+
+        // Action.
+        let mut reads = Connection::open(addr).await.unwrap();
+        let resynced = follower.resync(&mut reads).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(resynced, 3);
+        let mut pairs = local.snapshot().await.unwrap();
+        pairs.sort();
+        assert_eq!(
+            pairs,
+            vec![
+                ("e".into(), "c".into()),
+                ("j".into(), "b".into()),
+                ("k".into(), "a".into())
+            ]
+        );
+        assert_eq!(local.ttl("k").await.unwrap(), Some(Expiry::Never));
+        match local.ttl("e").await.unwrap() {
+            Some(Expiry::In(ttl)) => assert!(ttl <= Duration::from_secs(60), "{:?}", ttl),
+            expiry => panic!("unexpected expiry {:?}", expiry),
+        }
+    }
+
+    #[tokio::test]
+    async fn expire_on_leader_sets_time_to_live_locally() {
+        // Pre-condition.
+        let mut leader = inmemory::start();
+        leader.set("k".into(), "a".into()).await.unwrap();
+        leader
+            .expire("k".into(), Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(listener, leader).start());
+
+        let mut local = inmemory::start();
+        local.set("k".into(), "a".into()).await.unwrap();
+        let mut follower = FollowerTask::new(addr, local.clone());
+
+        // Action.
+        let mut reads = Connection::open(addr).await.unwrap();
+        follower.apply("EXPIRE k", &mut reads).await.unwrap();
+
+        // Post-condition.
+        match local.ttl("k").await.unwrap() {
+            Some(Expiry::In(ttl)) => assert!(ttl <= Duration::from_secs(60), "{:?}", ttl),
+            expiry => panic!("unexpected expiry {:?}", expiry),
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_to_restarted_leader() {
+        // Pre-condition.
+        let mut first = inmemory::start();
+        first.set("k".into(), "a".into()).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_signal) = oneshot::channel();
+        let leader = tokio::spawn(
+            Server::new(listener, first)
+                .shutdown_grace(Duration::ZERO)
+                .start_until(async {
+                    let _ = shutdown_signal.await;
+                }),
+        );
+
+        let local = inmemory::start();
+        tokio::spawn(
+            FollowerTask::new(addr, local.clone())
+                .retry_interval(Duration::from_millis(10))
+                .start(),
+        );
+        await_value(&local, "k", Some("a".into())).await;
+
+        // Action.
+        shutdown.send(()).unwrap();
+        leader.await.unwrap();
+
+        let mut second = inmemory::start();
+        second.set("j".into(), "b".into()).await.unwrap();
+        let listener = TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(Server::new(listener, second).start());
+
+        // Post-condition.
+        await_value(&local, "j", Some("b".into())).await;
+        assert_eq!(local.get("k").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn key_set_to_expire_on_leader_disappears_locally() {
+        // Pre-condition.
+        let mut leader = inmemory::start();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(listener, leader.clone()).start());
+
+        let local = inmemory::start();
+        tokio::spawn(FollowerTask::new(addr, local.clone()).start());
+        leader.set("ready".into(), "a".into()).await.unwrap();
+        await_value(&local, "ready", Some("a".into())).await;
+
+        // Action.
+        leader
+            .set_ex("k".into(), "a".into(), Duration::from_secs(2))
+            .await
+            .unwrap();
+        await_value(&local, "k", Some("a".into())).await;
+        let ttl = local.ttl("k").await.unwrap();
+
+        // Post-condition.
+        assert!(matches!(ttl, Some(Expiry::In(_))), "{:?}", ttl);
+        await_value(&local, "k", None).await;
+    }
+
+    /// Waits a while for `key` to hold `expected` in `store`.
+    async fn await_value(
+        store: &impl Store<Err = anyhow::Error>,
+        key: &str,
+        expected: Option<Value>,
+    ) {
+        for _ in 0..500 {
+            if store.get(key).await.unwrap() == expected {
+                return;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(store.get(key).await.unwrap(), expected);
+    }
+}
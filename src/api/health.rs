@@ -0,0 +1,244 @@
+//! HTTP endpoint through which orchestrators probe the server.
+//!
+//! - `GET /livez` answers `200 OK` for as long as the process runs.
+//! - `GET /readyz` answers `200 OK` once the store serves operations, or
+//!   `503 Service Unavailable` unless it answers a ping in time.
+//!
+//! Any other request is answered with `404 Not Found`, and every connection
+//! is closed after a single response, or without any unless the request line
+//! arrives in time.
+
+use super::server::AcceptBackoff;
+use crate::storage::Store;
+use anyhow::{Context, Result};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    time,
+};
+use tracing::{error, info};
+
+pub struct HealthServer<S> {
+    listener: TcpListener,
+    store: S,
+    timeout: Duration,
+    read_timeout: Duration,
+    accept_backoff: AcceptBackoff,
+}
+
+impl<S> HealthServer<S>
+where
+    S: Store<Err = anyhow::Error> + Clone + Send + Sync + 'static,
+{
+    pub fn new(listener: TcpListener, store: S) -> Self {
+        Self {
+            listener,
+            store,
+            timeout: Duration::from_secs(1),
+            read_timeout: Duration::from_secs(5),
+            accept_backoff: AcceptBackoff::default(),
+        }
+    }
+
+    /// Deems the store unready unless it answers a ping within `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Drops connections whose request line does not arrive within `read_timeout`.
+    pub fn read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    pub async fn start(self) {
+        self.accept_backoff
+            .accept_with(
+                || self.listener.accept(),
+                |conn, peer_addr| {
+                    let (store, timeout, read_timeout) =
+                        (self.store.clone(), self.timeout, self.read_timeout);
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(conn, &store, timeout, read_timeout).await {
+                            error!(reason = %e, peer_addr = %peer_addr, "unable to answer probe");
+                        }
+                    });
+                },
+            )
+            .await
+    }
+}
+
+async fn respond<C, S>(conn: C, store: &S, timeout: Duration, read_timeout: Duration) -> Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    S: Store<Err = anyhow::Error>,
+{
+    let mut conn = BufReader::new(conn);
+    let mut request_line = String::new();
+    time::timeout(read_timeout, conn.read_line(&mut request_line))
+        .await
+        .context("probe not read in time")?
+        .context("unable to read probe")?;
+
+    let status = match request_line.split(' ').take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/livez"] => "200 OK",
+        ["GET", "/readyz"] => match time::timeout(timeout, store.ping()).await {
+            Ok(Ok(())) => "200 OK",
+            Ok(Err(e)) => {
+                info!(reason = %e, "store is not ready");
+                "503 Service Unavailable"
+            }
+            Err(_) => {
+                info!("store did not answer ping in time");
+                "503 Service Unavailable"
+            }
+        },
+        _ => "404 Not Found",
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    );
+    conn.write_all(response.as_bytes())
+        .await
+        .context("unable to answer probe")?;
+    conn.shutdown().await.context("unable to answer probe")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{
+        inmemory,
+        types::{Key, KeyRef, Operation, Outcome, Value},
+    };
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use std::net::SocketAddr;
+    use tokio::{io::AsyncReadExt, net::TcpStream};
+
+    #[tokio::test]
+    async fn livez_and_readyz_succeed_while_store_runs() {
+        // Pre-condition.
+        let addr = serve(inmemory::start()).await;
+
+        // Action.
+        let livez = probe(addr, "/livez").await;
+        let readyz = probe(addr, "/readyz").await;
+        let other = probe(addr, "/other").await;
+
+        // Post-condition.
+        assert!(livez.starts_with("HTTP/1.1 200 OK\r\n"), "{}", livez);
+        assert!(readyz.starts_with("HTTP/1.1 200 OK\r\n"), "{}", readyz);
+        assert!(other.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", other);
+    }
+
+    #[tokio::test]
+    async fn readyz_fails_while_store_is_down() {
+        // Pre-condition.
+        let addr = serve(DownStore).await;
+
+        // Action.
+        let livez = probe(addr, "/livez").await;
+        let readyz = probe(addr, "/readyz").await;
+
+        // Post-condition.
+        assert!(livez.starts_with("HTTP/1.1 200 OK\r\n"), "{}", livez);
+        assert!(
+            readyz.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{}",
+            readyz
+        );
+    }
+
+    #[tokio::test]
+    async fn silent_probe_is_dropped_without_holding_up_others() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            HealthServer::new(listener, inmemory::start())
+                .read_timeout(Duration::from_millis(50))
+                .start(),
+        );
+        let mut silent = TcpStream::connect(addr).await.unwrap();
+
+        // Action.
+        let livez = probe(addr, "/livez").await;
+        let mut dropped = String::new();
+        time::timeout(Duration::from_secs(5), silent.read_to_string(&mut dropped))
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Post-condition.
+        assert!(livez.starts_with("HTTP/1.1 200 OK\r\n"), "{}", livez);
+        assert_eq!(dropped, "");
+    }
+
+    async fn serve<S>(store: S) -> SocketAddr
+    where
+        S: Store<Err = anyhow::Error> + Clone + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(HealthServer::new(listener, store).start());
+        addr
+    }
+
+    /// Sends a `GET` request for `path` to `addr`, returning the whole response.
+    async fn probe(addr: SocketAddr, path: &str) -> String {
+        let mut conn = TcpStream::connect(addr).await.unwrap();
+        conn.write_all(format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path).as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        conn.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    /// Store whose backend is gone, failing every operation.
+    #[derive(Debug, Clone)]
+    struct DownStore;
+
+    #[async_trait]
+    impl Store for DownStore {
+        type Err = anyhow::Error;
+
+        async fn get<'k>(&self, _: KeyRef<'k>) -> Result<Option<Value>> {
+            Err(anyhow!("store is down"))
+        }
+
+        async fn set(&mut self, _: Key, _: Value) -> Result<()> {
+            Err(anyhow!("store is down"))
+        }
+
+        async fn set_nx(&mut self, _: Key, _: Value) -> Result<bool> {
+            Err(anyhow!("store is down"))
+        }
+
+        async fn rename(&mut self, _: Key, _: Key) -> Result<bool> {
+            Err(anyhow!("store is down"))
+        }
+
+        async fn get_del(&mut self, _: Key) -> Result<Option<Value>> {
+            Err(anyhow!("store is down"))
+        }
+
+        async fn transaction(&mut self, _: Vec<Operation>) -> Result<Vec<Outcome>> {
+            Err(anyhow!("store is down"))
+        }
+
+        async fn snapshot(&self) -> Result<Vec<(Key, Value)>> {
+            Err(anyhow!("store is down"))
+        }
+
+        async fn ping(&self) -> Result<()> {
+            Err(anyhow!("store is down"))
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Metrics of the traffic exchanged with clients.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// Inclusive upper bounds of the histogram buckets, in bytes, larger
+/// observations falling into one last unbounded bucket.
+pub const BUCKETS: [usize; 6] = [64, 256, 1024, 4096, 16384, 65536];
+
+/// Histogram over [`BUCKETS`], cheap to update concurrently.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    counts: [AtomicU64; BUCKETS.len() + 1],
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, value: usize) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the number of observations in each bucket, the unbounded one last.
+    pub fn counts(&self) -> Vec<u64> {
+        self.counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// Returns the sum of every observation.
+    pub fn sum(&self) -> u64 {
+        self.sum.load(Ordering::Relaxed)
+    }
+
+    fn to_json(&self) -> String {
+        let counts: Vec<_> = self.counts().iter().map(ToString::to_string).collect();
+        format!(
+            "{{\"counts\":[{}],\"sum\":{}}}",
+            counts.join(","),
+            self.sum()
+        )
+    }
+}
+
+/// Sizes of the frames exchanged with clients, terminators included for responses only,
+/// along with what they carried.
+#[derive(Debug, Default)]
+pub struct FrameMetrics {
+    pub request_bytes: Histogram,
+    pub response_bytes: Histogram,
+    /// Well-formed requests received, by command.
+    commands: Mutex<BTreeMap<String, u64>>,
+    /// Responses reporting an error, i.e. `ERR` lines.
+    errors: AtomicU64,
+}
+
+impl FrameMetrics {
+    pub fn count_command(&self, command: &str) {
+        let mut commands = self.commands.lock().unwrap();
+        match commands.get_mut(command) {
+            Some(count) => *count += 1,
+            None => {
+                commands.insert(command.to_owned(), 1);
+            }
+        }
+    }
+
+    pub fn count_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns how many well-formed requests of each command were received.
+    pub fn commands(&self) -> BTreeMap<String, u64> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    /// Returns how many responses reported an error.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Serializes every metric into a JSON object.
+    ///
+    /// Commands are plain uppercase words, hence written as keys without escaping.
+    pub fn to_json(&self) -> String {
+        let mut commands = String::new();
+        for (i, (command, count)) in self.commands().iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let _ = write!(commands, "{}\"{}\":{}", separator, command, count);
+        }
+        format!(
+            "{{\"commands\":{{{}}},\"errors\":{},\"request_bytes\":{},\"response_bytes\":{}}}",
+            commands,
+            self.errors(),
+            self.request_bytes.to_json(),
+            self.response_bytes.to_json()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observations_fall_into_first_bucket_bounding_them() {
+        // Pre-condition.
+        let histogram = Histogram::default();
+
+        // Action.
+        histogram.observe(0);
+        histogram.observe(64);
+        histogram.observe(65);
+        histogram.observe(1 << 20);
+
+        // Post-condition.
+        assert_eq!(histogram.counts(), vec![2, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(histogram.sum(), 129 + (1 << 20));
+    }
+
+    #[test]
+    fn serializes_into_json_object() {
+        // Pre-condition.
+        let metrics = FrameMetrics::default();
+        metrics.request_bytes.observe(5);
+        metrics.count_command("SET");
+        metrics.count_command("GET");
+        metrics.count_command("GET");
+        metrics.count_error();
+
+        // Action.
+        let json = metrics.to_json();
+
+        // Post-condition.
+        assert_eq!(
+            json,
+            "{\"commands\":{\"GET\":2,\"SET\":1},\"errors\":1,\
+             \"request_bytes\":{\"counts\":[1,0,0,0,0,0,0],\"sum\":5},\
+             \"response_bytes\":{\"counts\":[0,0,0,0,0,0,0],\"sum\":0}}"
+        );
+    }
+}
@@ -2,9 +2,21 @@ use self::codec::Codec;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::codec::Framed;
 
+pub mod audit;
+pub mod base64;
+pub mod bench;
+pub mod bulk;
+pub mod client;
+pub mod clients;
 pub mod codec;
+pub mod events;
+pub mod follower;
+pub mod health;
+pub mod metrics;
+pub mod namespaces;
 pub mod server;
 pub mod service;
+pub mod settings;
 pub mod types;
 
 pub use server::Server;
@@ -0,0 +1,67 @@
+//! Independent stores selected by name, so that a single server can host several.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// Handle to stores by name, cheap to clone and share between connections.
+pub struct Namespaces<S> {
+    open: Arc<dyn Fn(&str) -> S + Send + Sync>,
+    list: Arc<dyn Fn() -> Vec<(String, S)> + Send + Sync>,
+}
+
+impl<S> Namespaces<S> {
+    /// Hosts stores made by `create`, each on first use of its name.
+    pub fn new(create: impl Fn() -> S + Send + Sync + 'static) -> Self
+    where
+        S: Clone + Send + 'static,
+    {
+        let stores = Arc::new(Mutex::new(BTreeMap::new()));
+        let listed = Arc::clone(&stores);
+        Self {
+            open: Arc::new(move |name| {
+                stores
+                    .lock()
+                    .unwrap()
+                    .entry(name.to_owned())
+                    .or_insert_with(&create)
+                    .clone()
+            }),
+            list: Arc::new(move || {
+                listed
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(name, store): (&String, &S)| (name.clone(), store.clone()))
+                    .collect()
+            }),
+        }
+    }
+
+    /// Returns the store named `name`, creating it unless it exists.
+    pub fn open(&self, name: &str) -> S {
+        (self.open)(name)
+    }
+
+    /// Returns every store created so far along with its name, sorted by name.
+    pub fn list(&self) -> Vec<(String, S)> {
+        (self.list)()
+    }
+}
+
+impl<S> Clone for Namespaces<S> {
+    fn clone(&self) -> Self {
+        Self {
+            open: Arc::clone(&self.open),
+            list: Arc::clone(&self.list),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Namespaces<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Namespaces").finish_non_exhaustive()
+    }
+}
@@ -1,19 +1,103 @@
 //! Network server meant to interact to service requests from clients.
 
 use crate::{
-    api::{framed, StoreService},
+    api::{
+        audit::AuditLog,
+        clients::Clients,
+        codec::{Codec, LineEnding, MissingResponse, Validation},
+        events::{Events, ServerEvent},
+        metrics::FrameMetrics,
+        namespaces::Namespaces,
+        service::{Config, Heartbeat},
+        settings::{or_none, Settings, Tunable},
+        StoreService,
+    },
     storage::Store,
 };
-use std::net::SocketAddr;
+use futures::{future, Future};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io, mem,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::{
+    fs,
     io::{AsyncRead, AsyncWrite},
     net::TcpListener,
+    sync::mpsc,
+    task::JoinSet,
+    time,
 };
-use tracing::{error, info, span, Level};
+use tokio_util::codec::Framed;
+use tracing::{error, info, span, Instrument, Level};
 
 pub struct Server<S> {
-    listener: TcpListener,
+    listeners: Vec<TcpListener>,
     store: S,
+    config: Config,
+    validation: Validation,
+    line_ending: LineEnding,
+    missing_response: MissingResponse,
+    max_response_bytes: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    connections_per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    accept_backoff: AcceptBackoff,
+    /// Number of accept loops run concurrently on each listener.
+    accept_workers: usize,
+    namespaces: Option<Namespaces<S>>,
+    metrics: Option<Arc<FrameMetrics>>,
+    /// File the metrics are written to as JSON once shut down, if any.
+    metrics_dump: Option<PathBuf>,
+    clients: Clients,
+    /// Tasks serving the connections, some of which may have finished.
+    connections: Mutex<JoinSet<()>>,
+    /// Upper bound on how long connections may take to finish on shutdown, unbounded if unset.
+    shutdown_grace: Option<Duration>,
+}
+
+/// Delay before accepting again after failing to, doubling from `initial` up to `max`
+/// on consecutive failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AcceptBackoff {
+    initial: Duration,
+    max: Duration,
+}
+
+impl Default for AcceptBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(5),
+            max: Duration::from_secs(1),
+        }
+    }
+}
+
+impl AcceptBackoff {
+    /// Hands every connection yielded by `accept` to `handle`, backing off whenever it fails.
+    pub(crate) async fn accept_with<A, F, C, H>(&self, mut accept: A, mut handle: H)
+    where
+        A: FnMut() -> F,
+        F: Future<Output = io::Result<(C, SocketAddr)>>,
+        H: FnMut(C, SocketAddr),
+    {
+        let mut backoff = self.initial;
+        loop {
+            match accept().await {
+                Ok((conn, peer_addr)) => {
+                    backoff = self.initial;
+                    handle(conn, peer_addr)
+                }
+                Err(e) => {
+                    error!(reason = %e, backoff = ?backoff, "unable to accept connection");
+                    time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.max);
+                }
+            }
+        }
+    }
 }
 
 impl<S> Server<S>
@@ -21,38 +105,1057 @@ where
     S: Store<Err = anyhow::Error> + Clone + Send + Sync + 'static,
 {
     pub fn new(listener: TcpListener, store: S) -> Self {
-        Self { listener, store }
+        Self {
+            listeners: vec![listener],
+            store,
+            config: Config {
+                started: Some(time::Instant::now()),
+                ..Config::default()
+            },
+            validation: Validation::default(),
+            line_ending: LineEnding::default(),
+            missing_response: MissingResponse::default(),
+            max_response_bytes: None,
+            max_connections_per_ip: None,
+            connections_per_ip: Arc::default(),
+            accept_backoff: AcceptBackoff::default(),
+            accept_workers: 1,
+            namespaces: None,
+            metrics: None,
+            metrics_dump: None,
+            clients: Clients::default(),
+            connections: Mutex::default(),
+            shutdown_grace: None,
+        }
+    }
+
+    /// Serves `store` on a listener bound elsewhere, e.g. one passed by a service manager.
+    pub fn from_std(listener: std::net::TcpListener, store: S) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Self::new(TcpListener::from_std(listener)?, store))
+    }
+
+    /// Additionally accepts connections from `listener`, served by the same store.
+    pub fn listen_on(mut self, listener: TcpListener) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Fails store operations that take longer than `timeout` instead of awaiting them forever.
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.config.command_timeout = Some(timeout);
+        self
+    }
+
+    /// Requires clients to negotiate the protocol version via `HELLO` before any other command.
+    pub fn require_hello(mut self, require: bool) -> Self {
+        self.config.require_hello = require;
+        self
+    }
+
+    /// Answers malformed requests with an `ERR` line instead of closing the connection.
+    pub fn reply_errors(mut self, reply: bool) -> Self {
+        self.config.reply_errors = reply;
+        self
+    }
+
+    /// Answers a request that cannot be decoded with a final `ERR` line before closing the
+    /// connection, unless replying to errors keeps it open.
+    pub fn error_before_close(mut self, error: bool) -> Self {
+        self.config.error_before_close = error;
+        self
+    }
+
+    /// Refuses requests writing to the store, e.g. to serve a replica.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.config.read_only = read_only;
+        self
+    }
+
+    /// Requires clients to `AUTH` with one of `credentials`, mapping each password to the
+    /// prefix of the keys it grants access to, unless there are none.
+    pub fn credentials(mut self, credentials: HashMap<String, String>) -> Self {
+        self.config.credentials = Arc::new(credentials);
+        self
+    }
+
+    /// Sends `PING` to clients idle for `interval`, disconnecting those that send nothing
+    /// within `deadline` afterwards.
+    pub fn heartbeat(mut self, interval: Duration, deadline: Duration) -> Self {
+        self.config.heartbeat = Some(Heartbeat { interval, deadline });
+        self
+    }
+
+    /// Records every mutation applied on behalf of clients in `log`.
+    pub fn audit_log(mut self, log: AuditLog) -> Self {
+        self.config.audit = Some(log);
+        self
+    }
+
+    /// Reports connections opening and closing, and every command they send, to `events`.
+    pub fn events(mut self, events: mpsc::Sender<ServerEvent>) -> Self {
+        self.config.events = Some(Events::new(events));
+        self
+    }
+
+    /// Validates keys and values in requests according to `validation`.
+    pub fn validation(mut self, validation: Validation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Terminates response lines with `line_ending`.
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Answers a `GET` of a missing key with `missing_response`.
+    pub fn missing_response(mut self, missing_response: MissingResponse) -> Self {
+        self.missing_response = missing_response;
+        self
+    }
+
+    /// Closes the connection of a subscriber slow to read once the notifications yet to be sent
+    /// to it take more than `max` bytes, instead of holding further ones back.
+    pub fn max_output_buffer(mut self, max: usize) -> Self {
+        self.config.max_output_buffer = Some(max);
+        self
+    }
+
+    /// Answers requests reading or writing a key longer than `max` bytes with an error instead
+    /// of serving them.
+    pub fn max_key_bytes(mut self, max: usize) -> Self {
+        self.config.max_key_bytes = Some(max);
+        self
+    }
+
+    /// Runs `workers` accept loops concurrently on each listener, at least one, so that a burst
+    /// of connections is not taken one at a time.
+    pub fn accept_workers(mut self, workers: usize) -> Self {
+        self.accept_workers = workers.max(1);
+        self
+    }
+
+    /// Answers with an error instead of any response taking more than `max` bytes.
+    pub fn max_response_bytes(mut self, max: usize) -> Self {
+        self.max_response_bytes = Some(max);
+        self
+    }
+
+    /// Refuses connections from a peer IP that already has `max` connections open.
+    pub fn max_connections_per_ip(mut self, max: usize) -> Self {
+        self.max_connections_per_ip = Some(max);
+        self
+    }
+
+    /// Waits from `initial` up to `max`, doubling on consecutive failures, before accepting
+    /// connections again after failing to, e.g. when running out of file descriptors.
+    pub fn accept_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.accept_backoff = AcceptBackoff { initial, max };
+        self
+    }
+
+    /// Aborts connections still open once `grace` has elapsed since shutdown, instead of
+    /// awaiting them forever.
+    pub fn shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = Some(grace);
+        self
+    }
+
+    /// Lets clients switch via `USE $name` to a store of its own, made by `create` on first use.
+    pub fn namespaces(mut self, create: impl Fn() -> S + Send + Sync + 'static) -> Self {
+        self.namespaces = Some(Namespaces::new(create));
+        self
+    }
+
+    /// Records the size of every frame exchanged with clients into `metrics`.
+    pub fn frame_metrics(mut self, metrics: Arc<FrameMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Writes the metrics as JSON to `path` once shut down, recording them even without
+    /// [`Self::frame_metrics`].
+    pub fn metrics_dump(mut self, path: impl Into<PathBuf>) -> Self {
+        self.metrics.get_or_insert_with(Arc::default);
+        self.metrics_dump = Some(path.into());
+        self
+    }
+
+    /// Returns the local address of every listener.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
     }
 
     pub async fn start(self) {
-        while let Ok((conn, peer_addr)) = self.listener.accept().await {
-            self.handle(conn, peer_addr)
+        self.start_until(future::pending()).await
+    }
+
+    /// Serves connections until `shutdown` completes, then stops accepting and lets open
+    /// connections finish within the shutdown grace period, aborting those that do not.
+    ///
+    /// Each accept worker runs as a task of its own, so that workers accept in parallel.
+    ///
+    /// The metrics are dumped last, so that they account for every connection.
+    pub async fn start_until(mut self, shutdown: impl Future<Output = ()>) {
+        self.config.settings = Some(Arc::new(self.settings()));
+
+        let listeners: Vec<_> = mem::take(&mut self.listeners)
+            .into_iter()
+            .map(Arc::new)
+            .collect();
+        let server = Arc::new(self);
+        let mut accepting = JoinSet::new();
+        for listener in &listeners {
+            for _ in 0..server.accept_workers {
+                let (server, listener) = (Arc::clone(&server), Arc::clone(listener));
+                accepting
+                    .spawn(async move { server.accept_from(&listener).await }.in_current_span());
+            }
         }
+        tokio::select! {
+            _ = async { while accepting.join_next().await.is_some() {} } => {}
+            _ = shutdown => info!("shutting down"),
+        }
+        accepting.shutdown().await;
+        drop(listeners);
+
+        let mut connections = mem::take(&mut *server.connections.lock().unwrap());
+        let drained = async { while connections.join_next().await.is_some() {} };
+        match server.shutdown_grace {
+            Some(grace) => {
+                if time::timeout(grace, drained).await.is_err() {
+                    info!(
+                        count = connections.len(),
+                        "aborting connections open past shutdown grace"
+                    );
+                    connections.shutdown().await;
+                }
+            }
+            None => drained.await,
+        }
+
+        if let (Some(metrics), Some(path)) = (&server.metrics, &server.metrics_dump) {
+            match fs::write(path, metrics.to_json()).await {
+                Ok(()) => info!("dumped metrics to {}", path.display()),
+                Err(e) => error!(reason = %e, "unable to dump metrics"),
+            }
+        }
+    }
+
+    async fn accept_from(&self, listener: &TcpListener) {
+        self.accept_with(|| listener.accept()).await
+    }
+
+    /// Serves every connection yielded by `accept`, backing off whenever it fails.
+    async fn accept_with<A, F, C>(&self, accept: A)
+    where
+        A: FnMut() -> F,
+        F: Future<Output = io::Result<(C, SocketAddr)>>,
+        C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        self.accept_backoff
+            .accept_with(accept, |conn, peer_addr| self.handle(conn, peer_addr))
+            .await
     }
 
     fn handle<C>(&self, conn: C, peer_addr: SocketAddr)
     where
         C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
     {
-        let service = self.new_service(conn);
+        // Everything logged while serving the connection, service included, nests within this span.
+        let span = span!(Level::INFO, "connection", peer_addr = %peer_addr);
 
-        tokio::spawn(async move {
-            let span = span!(Level::INFO, "connection", peer_addr = %peer_addr);
-            let _enter = span.enter();
+        let slot = match self.claim_slot(peer_addr.ip()) {
+            Some(slot) => slot,
+            None => {
+                span.in_scope(|| info!("refusing connection: too many from peer"));
+                return;
+            }
+        };
+
+        let events = self.config.events.clone();
+        if let Some(events) = &events {
+            events.emit(ServerEvent::Connected { peer_addr });
+        }
 
-            info!("serving new connection");
+        let service = self
+            .new_service(conn)
+            .with_peer_addr(peer_addr)
+            .with_registration(self.clients.register(peer_addr));
 
-            match service.start().await {
-                Ok(_) => info!("bye"),
-                Err(e) => error!(reason = %e, "oops"),
+        let mut connections = self.connections.lock().unwrap();
+        while connections.try_join_next().is_some() {}
+        connections.spawn(
+            async move {
+                let _slot = slot;
+                info!("serving new connection");
+
+                match service.start().await {
+                    Ok(_) => info!("bye"),
+                    Err(e) => error!(reason = %e, "oops"),
+                }
+
+                if let Some(events) = events {
+                    events.emit(ServerEvent::Disconnected { peer_addr });
+                }
             }
-        });
+            .instrument(span),
+        );
+    }
+
+    /// Returns the effective settings, named after the matching command-line options.
+    fn settings(&self) -> Settings {
+        let heartbeat = self.config.heartbeat;
+        let fixed = BTreeMap::from([
+            ("require-hello", self.config.require_hello.to_string()),
+            ("reply-errors", self.config.reply_errors.to_string()),
+            (
+                "error-before-close",
+                self.config.error_before_close.to_string(),
+            ),
+            (
+                "heartbeat-interval-ms",
+                or_none(heartbeat.map(|h| h.interval.as_millis())),
+            ),
+            (
+                "heartbeat-deadline-ms",
+                or_none(heartbeat.map(|h| h.deadline.as_millis())),
+            ),
+            // Clearing it live would make a follower writable, hence fixed.
+            ("read-only", self.config.read_only.to_string()),
+            ("validation", self.validation.to_string()),
+            ("line-ending", self.line_ending.to_string()),
+            ("missing-response", self.missing_response.to_string()),
+            ("max-response-bytes", or_none(self.max_response_bytes)),
+            ("accept-workers", self.accept_workers.to_string()),
+            (
+                "max-output-buffer-bytes",
+                or_none(self.config.max_output_buffer),
+            ),
+            ("max-key-bytes", or_none(self.config.max_key_bytes)),
+            (
+                "shutdown-grace-secs",
+                or_none(self.shutdown_grace.map(|grace| grace.as_secs())),
+            ),
+        ]);
+        let tunable = Tunable {
+            command_timeout: self.config.command_timeout,
+            max_connections_per_ip: self.max_connections_per_ip,
+        };
+        Settings::new(fixed, tunable)
+    }
+
+    /// Counts a new connection from `ip`, unless it would exceed the per-IP limit.
+    fn claim_slot(&self, ip: IpAddr) -> Option<Slot> {
+        let mut connections = self.connections_per_ip.lock().unwrap();
+        let count = connections.get(&ip).copied().unwrap_or_default();
+        let max = match &self.config.settings {
+            Some(settings) => settings.tunable().max_connections_per_ip,
+            None => self.max_connections_per_ip,
+        };
+        // Refused peers are not counted, so they leave no entry behind.
+        if max.is_some_and(|max| count >= max) {
+            return None;
+        }
+        connections.insert(ip, count + 1);
+
+        Some(Slot {
+            ip,
+            connections: Arc::clone(&self.connections_per_ip),
+        })
     }
 
     fn new_service<C>(&self, conn: C) -> StoreService<C, S>
     where
         C: AsyncRead + AsyncWrite + Unpin,
     {
-        StoreService::new(framed(conn), self.store.clone())
+        let mut codec = Codec::with_validation(self.validation)
+            .with_line_ending(self.line_ending)
+            .with_missing_response(self.missing_response);
+        if let Some(max) = self.max_response_bytes {
+            codec = codec.with_max_response_bytes(max);
+        }
+        if let Some(metrics) = &self.metrics {
+            codec = codec.with_metrics(Arc::clone(metrics));
+        }
+        let frames = Framed::new(conn, codec);
+        let service = StoreService::with_config(frames, self.store.clone(), self.config.clone());
+        match &self.namespaces {
+            Some(namespaces) => service.with_namespaces(namespaces.clone()),
+            None => service,
+        }
+    }
+}
+
+/// Connection counted against the per-IP limit until dropped.
+struct Slot {
+    ip: IpAddr,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::types::Request, storage::inmemory};
+    use std::time::Instant;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpStream,
+        sync::oneshot,
+    };
+
+    #[tokio::test]
+    async fn command_logs_include_peer_addr() {
+        // Pre-condition.
+        let logs = Logs::default();
+        let _subscriber = tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_ansi(false)
+                .with_writer(logs.clone())
+                .finish(),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(listener, inmemory::start()).start());
+
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let peer_addr = client.get_ref().local_addr().unwrap();
+
+        // Action.
+        client.write_all(b"GET k\n").await.unwrap();
+        client.read_line(&mut String::new()).await.unwrap();
+
+        // Post-condition.
+        let logs = logs.contents();
+        let line = logs
+            .lines()
+            .find(|line| line.contains("get: key: k"))
+            .expect("get command should be logged");
+        assert!(
+            line.contains(&format!("peer_addr={}", peer_addr)),
+            "{}",
+            line
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_on_listener_adopted_from_raw_fd() {
+        use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+        // Pre-condition.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let fd = listener.into_raw_fd();
+        // SAFETY: `fd` was just released by the listener, hence owned by nothing else.
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        tokio::spawn(
+            Server::from_std(listener, inmemory::start())
+                .unwrap()
+                .start(),
+        );
+
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+        // Action.
+        client.write_all(b"SET k a\nGET k\n").await.unwrap();
+
+        // Post-condition.
+        let mut response = String::new();
+        client.read_line(&mut response).await.unwrap();
+        client.read_line(&mut response).await.unwrap();
+        assert_eq!(response, "OKAY k\nOKAY k a\n");
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "admin", feature = "extended"))]
+    async fn refuses_getprefix_result_beyond_max_response_bytes() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .max_response_bytes(32)
+                .start(),
+        );
+
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        client.write_all(b"FILL user: 100\n").await.unwrap();
+        let mut response = String::new();
+        client.read_line(&mut response).await.unwrap();
+        response.clear();
+
+        // Action.
+        client
+            .write_all(b"GETPREFIX user:\nDBSIZE\n")
+            .await
+            .unwrap();
+
+        // Post-condition.
+        client.read_line(&mut response).await.unwrap();
+        assert!(
+            response.starts_with("ERR response of ")
+                && response.ends_with(" exceeds limit of 32\n"),
+            "{}",
+            response
+        );
+        response.clear();
+        client.read_line(&mut response).await.unwrap();
+        assert_eq!(response, "OKAY 100\n");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn burst_of_connections_is_served_by_several_accept_workers() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .accept_workers(4)
+                .start(),
+        );
+
+        // Action.
+        let clients: Vec<_> = (0..100)
+            .map(|i| {
+                tokio::spawn(async move {
+                    let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+                    let request = format!("SET k{} {}\nGET k{}\n", i, i, i);
+                    client.write_all(request.as_bytes()).await.unwrap();
+
+                    let mut response = String::new();
+                    client.read_line(&mut response).await.unwrap();
+                    client.read_line(&mut response).await.unwrap();
+                    response
+                })
+            })
+            .collect();
+
+        // Post-condition.
+        for (i, client) in clients.into_iter().enumerate() {
+            let response = time::timeout(Duration::from_secs(5), client)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(response, format!("OKAY k{}\nOKAY k{} {}\n", i, i, i));
+        }
+    }
+
+    #[tokio::test]
+    async fn every_listener_is_served_by_the_same_store() {
+        // Pre-condition.
+        let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (first_addr, second_addr) = (first.local_addr().unwrap(), second.local_addr().unwrap());
+        tokio::spawn(
+            Server::new(first, inmemory::start())
+                .listen_on(second)
+                .start(),
+        );
+
+        let mut first_client = BufReader::new(TcpStream::connect(first_addr).await.unwrap());
+        let mut second_client = BufReader::new(TcpStream::connect(second_addr).await.unwrap());
+
+        // Action.
+        first_client.write_all(b"SET k a\n").await.unwrap();
+        first_client.read_line(&mut String::new()).await.unwrap();
+
+        second_client.write_all(b"GET k\n").await.unwrap();
+        let mut response = String::new();
+        second_client.read_line(&mut response).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(response, "OKAY k a\n");
+    }
+
+    #[tokio::test]
+    async fn connections_beyond_per_ip_limit_are_refused() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .max_connections_per_ip(2)
+                .start(),
+        );
+
+        // Action.
+        let mut responses = Vec::new();
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            client.write_all(b"GET k\n").await.unwrap();
+            let mut response = String::new();
+            client.read_line(&mut response).await.unwrap_or_default();
+            responses.push(response);
+            clients.push(client);
+        }
+
+        // Post-condition.
+        assert_eq!(responses, vec!["FAIL k\n", "FAIL k\n", ""]);
+    }
+
+    #[tokio::test]
+    async fn closed_connection_frees_its_per_ip_slot() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .max_connections_per_ip(1)
+                .start(),
+        );
+
+        let mut first = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        first.write_all(b"GET k\n").await.unwrap();
+        first.read_line(&mut String::new()).await.unwrap();
+        first.get_mut().shutdown().await.unwrap();
+        first.read_line(&mut String::new()).await.unwrap();
+
+        // Action.
+        let mut second = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        second.write_all(b"GET k\n").await.unwrap();
+        let mut response = String::new();
+        second.read_line(&mut response).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(response, "FAIL k\n");
+    }
+
+    #[tokio::test]
+    async fn per_ip_slots_are_forgotten_once_unused() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut server = Server::new(listener, inmemory::start()).max_connections_per_ip(1);
+        let first = IpAddr::from([127, 0, 0, 1]);
+        let second = IpAddr::from([127, 0, 0, 2]);
+
+        // Action.
+        let slot = server.claim_slot(first);
+        let refused = server.claim_slot(first);
+        server.max_connections_per_ip = Some(0);
+        let never = server.claim_slot(second);
+        let claimed = server.connections_per_ip.lock().unwrap().clone();
+        drop(slot);
+
+        // Post-condition.
+        assert!(refused.is_none() && never.is_none());
+        assert_eq!(claimed, HashMap::from([(first, 1)]));
+        assert!(server.connections_per_ip.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accept_error_is_followed_by_further_accepts() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = Server::new(listener, inmemory::start());
+
+        let (client, conn) = tokio::io::duplex(1024);
+        let mut accepts = vec![
+            Err(io::Error::from_raw_os_error(24)),
+            Err(io::Error::from_raw_os_error(24)),
+            Ok((conn, SocketAddr::from(([127, 0, 0, 1], 4242)))),
+        ]
+        .into_iter();
+        let accept = move || {
+            let accepted = accepts.next();
+            async move {
+                match accepted {
+                    Some(accepted) => accepted,
+                    None => future::pending().await,
+                }
+            }
+        };
+        tokio::spawn(async move { server.accept_with(accept).await });
+
+        let mut client = BufReader::new(client);
+
+        // Action.
+        client.write_all(b"GET k\n").await.unwrap();
+        let mut response = String::new();
+        client.read_line(&mut response).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(response, "FAIL k\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn killed_client_is_disconnected() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(listener, inmemory::start()).start());
+
+        let mut admin = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let mut victim = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let victim_addr = victim.get_ref().local_addr().unwrap();
+        victim.write_all(b"GET k\n").await.unwrap();
+        victim.read_line(&mut String::new()).await.unwrap();
+
+        admin.write_all(b"CLIENT LIST\n").await.unwrap();
+        let mut header = String::new();
+        admin.read_line(&mut header).await.unwrap();
+        let mut listed = Vec::new();
+        for _ in 0..2 {
+            let mut line = String::new();
+            admin.read_line(&mut line).await.unwrap();
+            listed.push(line.split(' ').nth(1).unwrap().to_owned());
+        }
+
+        // Action.
+        admin
+            .write_all(format!("CLIENT KILL {}\n", victim_addr).as_bytes())
+            .await
+            .unwrap();
+        let mut killed = String::new();
+        admin.read_line(&mut killed).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(header, "OKAY 2\n");
+        assert!(listed.contains(&victim_addr.to_string()), "{:?}", listed);
+        assert_eq!(killed, "OKAY\n");
+
+        let mut rest = String::new();
+        assert_eq!(victim.read_line(&mut rest).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn config_get_answers_effective_settings() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .max_connections_per_ip(3)
+                .validation(Validation::Strict)
+                .start(),
+        );
+
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+        // Action.
+        let mut responses = Vec::new();
+        for param in [
+            "max-connections-per-ip",
+            "validation",
+            "command-timeout-ms",
+            "unknown",
+        ] {
+            client
+                .write_all(format!("CONFIG GET {}\n", param).as_bytes())
+                .await
+                .unwrap();
+            let mut response = String::new();
+            client.read_line(&mut response).await.unwrap();
+            responses.push(response);
+        }
+
+        // Post-condition.
+        assert_eq!(
+            responses,
+            vec![
+                "OKAY max-connections-per-ip 3\n",
+                "OKAY validation strict\n",
+                "OKAY command-timeout-ms none\n",
+                "FAIL unknown\n",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn config_set_adjusts_tunable_settings_of_every_connection() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(listener, inmemory::start()).start());
+
+        let mut admin = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        client.write_all(b"SET k a\n").await.unwrap();
+        client.read_line(&mut String::new()).await.unwrap();
+
+        // Action.
+        let mut responses = Vec::new();
+        for request in [
+            "CONFIG SET max-connections-per-ip 2\n",
+            "CONFIG SET read-only true\n",
+            "CONFIG SET validation strict\n",
+        ] {
+            admin.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            admin.read_line(&mut response).await.unwrap();
+            responses.push(response);
+        }
+
+        client.write_all(b"SET k b\nGET k\n").await.unwrap();
+        let (mut set, mut get) = (String::new(), String::new());
+        client.read_line(&mut set).await.unwrap();
+        client.read_line(&mut get).await.unwrap();
+
+        let mut refused = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+        // Post-condition.
+        assert_eq!(
+            responses,
+            vec![
+                "OKAY max-connections-per-ip\n",
+                "ERR read-only cannot be set at runtime\n",
+                "ERR validation cannot be set at runtime\n",
+            ]
+        );
+        assert_eq!(set, "OKAY k\n");
+        assert_eq!(get, "OKAY k b\n");
+        assert_eq!(refused.read_line(&mut String::new()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn config_set_cannot_make_read_only_server_writable() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .read_only(true)
+                .start(),
+        );
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+        // Action.
+        client
+            .write_all(b"CONFIG SET read-only false\nSET k a\n")
+            .await
+            .unwrap();
+        let (mut config_set, mut set) = (String::new(), String::new());
+        client.read_line(&mut config_set).await.unwrap();
+        client.read_line(&mut set).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(config_set, "ERR read-only cannot be set at runtime\n");
+        assert_eq!(set, "ERR read-only server\n");
+    }
+
+    #[tokio::test]
+    async fn events_report_connection_lifecycle_in_order() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (events, mut received) = mpsc::channel(16);
+        tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .events(events)
+                .start(),
+        );
+
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let peer_addr = client.get_ref().local_addr().unwrap();
+
+        // Action.
+        client.write_all(b"SET k v\n").await.unwrap();
+        client.read_line(&mut String::new()).await.unwrap();
+        drop(client);
+
+        let mut drained = Vec::new();
+        for _ in 0..3 {
+            drained.push(received.recv().await.unwrap());
+        }
+
+        // Post-condition.
+        assert_eq!(
+            drained,
+            vec![
+                ServerEvent::Connected { peer_addr },
+                ServerEvent::Command {
+                    peer_addr: Some(peer_addr),
+                    request: Request::Set {
+                        key: "k".into(),
+                        value: "v".into(),
+                    },
+                },
+                ServerEvent::Disconnected { peer_addr },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn bget_is_unblocked_by_set_of_another_connection() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(listener, inmemory::start()).start());
+
+        let mut waiter = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let mut writer = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+        // Action.
+        waiter.write_all(b"BGET other 50\n").await.unwrap();
+        let mut timed_out = String::new();
+        waiter.read_line(&mut timed_out).await.unwrap();
+
+        waiter.write_all(b"BGET k 5000\n").await.unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+        writer.write_all(b"SET k v\n").await.unwrap();
+        writer.read_line(&mut String::new()).await.unwrap();
+        let mut unblocked = String::new();
+        waiter.read_line(&mut unblocked).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(timed_out, "FAIL other\n");
+        assert_eq!(unblocked, "OKAY k v\n");
+    }
+
+    #[tokio::test]
+    async fn shutdown_lets_connections_finish_until_grace_elapses() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_signal) = oneshot::channel();
+        let grace = Duration::from_millis(200);
+        let server = tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .shutdown_grace(grace)
+                .start_until(async {
+                    let _ = shutdown_signal.await;
+                }),
+        );
+
+        let mut fast = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let mut stuck = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        for client in [&mut fast, &mut stuck] {
+            client.write_all(b"GET k\n").await.unwrap();
+            client.read_line(&mut String::new()).await.unwrap();
+        }
+
+        // Action.
+        let started = Instant::now();
+        shutdown.send(()).unwrap();
+
+        fast.write_all(b"SET k a\n").await.unwrap();
+        let mut response = String::new();
+        fast.read_line(&mut response).await.unwrap();
+        fast.get_mut().shutdown().await.unwrap();
+
+        server.await.unwrap();
+
+        // Post-condition.
+        assert_eq!(response, "OKAY k\n");
+        assert_eq!(fast.read_line(&mut String::new()).await.unwrap(), 0);
+
+        assert!(started.elapsed() >= grace);
+        assert_eq!(stuck.read_line(&mut String::new()).await.unwrap(), 0);
+
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn shutdown_stops_every_accept_worker() {
+        // Pre-condition.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_signal) = oneshot::channel();
+        let server = tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .accept_workers(4)
+                .start_until(async {
+                    let _ = shutdown_signal.await;
+                }),
+        );
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        client.write_all(b"GET k\n").await.unwrap();
+        client.read_line(&mut String::new()).await.unwrap();
+        client.get_mut().shutdown().await.unwrap();
+
+        // Action.
+        shutdown.send(()).unwrap();
+        time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Post-condition.
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_dumps_final_metrics() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.json");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_signal) = oneshot::channel();
+        let server = tokio::spawn(
+            Server::new(listener, inmemory::start())
+                .reply_errors(true)
+                .metrics_dump(&path)
+                .start_until(async {
+                    let _ = shutdown_signal.await;
+                }),
+        );
+
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        for request in ["SET k a\n", "GET k\n", "GET j\n", "BOGUS\n"] {
+            client.write_all(request.as_bytes()).await.unwrap();
+            client.read_line(&mut String::new()).await.unwrap();
+        }
+        client.get_mut().shutdown().await.unwrap();
+
+        // Action.
+        shutdown.send(()).unwrap();
+        server.await.unwrap();
+
+        // Post-condition.
+        let dumped = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            dumped.starts_with("{\"commands\":{\"GET\":2,\"SET\":1},\"errors\":1,"),
+            "{}",
+            dumped
+        );
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl Logs {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            io::Write::write(&mut *self.0.lock().unwrap(), buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl tracing_subscriber::fmt::MakeWriter<'_> for Logs {
+        type Writer = Self;
+
+        fn make_writer(&self) -> Self::Writer {
+            self.clone()
+        }
     }
 }
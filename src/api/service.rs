@@ -1,15 +1,163 @@
 //! Communication gateway meant to mediate access to storage.
 
-use super::types::{Request, Response};
-use crate::storage::Store;
-use anyhow::Result;
-use futures::{Sink, SinkExt, Stream, StreamExt};
+use super::{
+    audit::AuditLog,
+    base64,
+    clients::Registration,
+    codec::{MalformedRequest, ValueEncoding},
+    events::{Events, ServerEvent},
+    namespaces::Namespaces,
+    settings::Settings,
+    types::{Request, Response},
+};
+use crate::storage::{
+    types::{Capabilities, Operation, Value},
+    Busy, Full, Invalid, Store, Unsupported,
+};
+use anyhow::{bail, Result};
+use futures::{Future, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use std::{collections::HashMap, fmt, io, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::time::{self, Instant};
 use tracing::info;
 
+#[cfg(feature = "admin")]
+use anyhow::{anyhow, Context};
+#[cfg(feature = "admin")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "extended")]
+use crate::storage::types::{Extremum, LIST_DELIMITER};
+
+#[cfg(feature = "expiry")]
+use crate::storage::types::Expiry;
+
+#[cfg(feature = "pubsub")]
+use super::codec::wire_len;
+#[cfg(feature = "pubsub")]
+use crate::storage::types::Mutation;
+#[cfg(feature = "pubsub")]
+use futures::future;
+#[cfg(feature = "pubsub")]
+use std::collections::VecDeque;
+#[cfg(feature = "pubsub")]
+use tokio::sync::broadcast::error::RecvError;
+
+#[cfg(feature = "session")]
+use super::codec::PROTOCOL_VERSION;
+
+#[cfg(feature = "transactions")]
+use crate::storage::types::Outcome;
+#[cfg(feature = "transactions")]
+use std::mem;
+
+/// Upper bound on the bytes of chunks a connection holds back via `SETCHUNK`, unless configured.
+pub const DEFAULT_MAX_CHUNK_BYTES: usize = 64 * 1024 * 1024;
+
+/// Upper bound on how many keys a connection holds chunks back under, unless configured.
+pub const DEFAULT_MAX_CHUNKED_KEYS: usize = 16;
+
+/// Error of a subscriber whose notifications yet to be sent exceed the output buffer limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SlowConsumer;
+
+impl fmt::Display for SlowConsumer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("output buffer limit exceeded")
+    }
+}
+
+impl std::error::Error for SlowConsumer {}
+
+/// Error of a store operation outlasting the command timeout, or the deadline of the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("store operation timed out")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
 #[derive(Debug)]
 pub struct StoreService<F, S> {
     frames: F,
     store: S,
+    config: Config,
+    /// Operations queued since `MULTI`, if in a transaction.
+    queued: Option<Vec<Operation>>,
+    /// Keys watched since `WATCH`, along with their version then.
+    #[cfg(feature = "transactions")]
+    watched: Vec<(String, u64)>,
+    /// Chunks held back under each key since `SETCHUNK`, in order.
+    #[cfg(feature = "extended")]
+    chunks: HashMap<String, Vec<Value>>,
+    /// Bytes of every chunk held back.
+    #[cfg(feature = "extended")]
+    chunk_bytes: usize,
+    /// Whether the client has negotiated a supported protocol version via `HELLO`.
+    greeted: bool,
+    /// Prefix of the keys the client may access, as granted by `AUTH`.
+    prefix: Option<String>,
+    /// Address of the client, if known.
+    peer_addr: Option<SocketAddr>,
+    /// Stores the client may switch to via `USE`, if any.
+    namespaces: Option<Namespaces<S>>,
+    /// Membership of the client in the registry of connected clients, if any.
+    registration: Option<Registration>,
+    /// When the request being handled must be answered by, as set by `DEADLINE`, if ever.
+    deadline: Option<Instant>,
+    /// Since when uptime is counted.
+    #[cfg(feature = "admin")]
+    started: Instant,
+    /// How values are written and read back, as negotiated via `ENCODING`.
+    encoding: ValueEncoding,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Upper bound on how long a single store operation may take, unbounded if unset.
+    pub command_timeout: Option<Duration>,
+    /// Whether clients must negotiate the protocol version via `HELLO` before any other command.
+    pub require_hello: bool,
+    /// Whether malformed requests are answered with an `ERR` line instead of closing the connection.
+    pub reply_errors: bool,
+    /// Whether a request that cannot be decoded is answered with a final `ERR` line before the
+    /// connection closes, unless `reply_errors` keeps it open.
+    pub error_before_close: bool,
+    /// Whether requests writing to the store are refused.
+    pub read_only: bool,
+    /// Key prefix granted by each `AUTH` password, any key being accessible without `AUTH` if empty.
+    pub credentials: Arc<HashMap<String, String>>,
+    /// Log recording every mutation applied on behalf of clients, if any.
+    pub audit: Option<AuditLog>,
+    /// Application-level keepalive probing idle clients, if any.
+    pub heartbeat: Option<Heartbeat>,
+    /// Effective settings of the server, whose tunable ones override the above, if any.
+    pub settings: Option<Arc<Settings>>,
+    /// Channel every command received is reported to, if any.
+    pub events: Option<Events>,
+    /// When the server started, uptime being counted from the start of the service otherwise.
+    pub started: Option<Instant>,
+    /// Upper bound on the bytes of notifications buffered for a subscriber slow to read them,
+    /// in which case they are held back instead, if unset.
+    pub max_output_buffer: Option<usize>,
+    /// Upper bound on the bytes of any key a request reads or writes, unbounded if unset.
+    pub max_key_bytes: Option<usize>,
+    /// Upper bound on the bytes of chunks held back, [`DEFAULT_MAX_CHUNK_BYTES`] if unset.
+    pub max_chunk_bytes: Option<usize>,
+    /// Upper bound on how many keys chunks are held back under, [`DEFAULT_MAX_CHUNKED_KEYS`]
+    /// if unset.
+    pub max_chunked_keys: Option<usize>,
+}
+
+/// Keepalive sending `PING` to a client idle for `interval`, closing the
+/// connection unless the client sends anything within `deadline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub deadline: Duration,
 }
 
 impl<F, S> StoreService<F, S>
@@ -18,37 +166,3424 @@ where
     S: Store<Err = anyhow::Error>,
 {
     pub fn new(frames: F, store: S) -> Self {
-        Self { frames, store }
+        Self::with_config(frames, store, Config::default())
+    }
+
+    pub fn with_config(frames: F, store: S, config: Config) -> Self {
+        #[cfg(feature = "admin")]
+        let started = config.started.unwrap_or_else(Instant::now);
+        Self {
+            frames,
+            store,
+            config,
+            queued: None,
+            #[cfg(feature = "transactions")]
+            watched: Vec::new(),
+            #[cfg(feature = "extended")]
+            chunks: HashMap::new(),
+            #[cfg(feature = "extended")]
+            chunk_bytes: 0,
+            greeted: false,
+            prefix: None,
+            peer_addr: None,
+            namespaces: None,
+            registration: None,
+            deadline: None,
+            #[cfg(feature = "admin")]
+            started,
+            encoding: ValueEncoding::default(),
+        }
+    }
+
+    /// Identifies the client as `peer_addr`, e.g. in the audit log.
+    pub fn with_peer_addr(mut self, peer_addr: SocketAddr) -> Self {
+        self.peer_addr = Some(peer_addr);
+        self
+    }
+
+    /// Lets the client switch to any of `namespaces` via `USE`.
+    pub fn with_namespaces(mut self, namespaces: Namespaces<S>) -> Self {
+        self.namespaces = Some(namespaces);
+        self
+    }
+
+    /// Lists the client in the registry of `registration`, through which it may be terminated.
+    pub fn with_registration(mut self, registration: Registration) -> Self {
+        self.registration = Some(registration);
+        self
     }
 
+    /// Serves requests until the client disconnects.
+    ///
+    /// The client hanging up abruptly, e.g. without reading pending responses,
+    /// is a disconnect rather than an error.
     pub async fn start(mut self) -> Result<()> {
-        while let Some(req) = self.frames.next().await {
-            let res = self.handle(req?).await?;
-            self.frames.send(res).await?;
+        let served = match self.registration.as_ref().map(Registration::kill_signal) {
+            Some(kill) => tokio::select! {
+                served = self.serve() => served,
+                _ = kill.notified() => {
+                    info!("killed");
+                    Ok(())
+                }
+            },
+            None => self.serve().await,
+        };
+        let flushed = match &served {
+            // Flushing would await a client that does not read forever.
+            Err(e) if e.is::<SlowConsumer>() => Ok(()),
+            _ => self.frames.flush().await,
+        };
+
+        match served.and(flushed) {
+            Err(e) if is_disconnect(&e) => {
+                info!(reason = %e, "client disconnected abruptly");
+                Ok(())
+            }
+            outcome => outcome,
+        }
+    }
+
+    /// Responses to pipelined requests are buffered, being flushed together once the client has
+    /// no further request ready, or before blocking on its behalf, e.g. for `BGET`.
+    async fn serve(&mut self) -> Result<()> {
+        let mut pinged = false;
+        loop {
+            let ready = self.frames.next().now_or_never();
+            let req = match (ready, self.config.heartbeat) {
+                (Some(req), _) => req,
+                (None, Some(heartbeat)) => {
+                    self.frames.flush().await?;
+                    let idle = if pinged {
+                        heartbeat.deadline
+                    } else {
+                        heartbeat.interval
+                    };
+                    match time::timeout(idle, self.frames.next()).await {
+                        Ok(req) => req,
+                        Err(_) if pinged => {
+                            info!("client missed heartbeat");
+                            return Ok(());
+                        }
+                        Err(_) => {
+                            self.frames.send(Response::Ping).await?;
+                            pinged = true;
+                            continue;
+                        }
+                    }
+                }
+                (None, None) => {
+                    self.frames.flush().await?;
+                    self.frames.next().await
+                }
+            };
+            pinged = false;
+
+            let res = match req {
+                None => return Ok(()),
+                Some(Ok(Request::Pong)) => continue,
+                Some(Ok(req)) => {
+                    if req.blocks() {
+                        self.frames.flush().await?;
+                    }
+                    match self.handle(self.emit_command(req)).await {
+                        Err(e) if is_busy(&e) => {
+                            info!("store busy");
+                            Response::Busy
+                        }
+                        Err(e) if is_timed_out(&e) => {
+                            info!("store operation timed out");
+                            Response::Error {
+                                message: TimedOut.to_string(),
+                            }
+                        }
+                        Err(e) if is_full(&e) => {
+                            info!("store full");
+                            Response::Error {
+                                message: Full.to_string(),
+                            }
+                        }
+                        Err(e) if is_invalid(&e) => {
+                            let message = e.root_cause().to_string();
+                            info!(reason = %message, "invalid value");
+                            Response::Error { message }
+                        }
+                        Err(e) if is_unsupported(&e) => {
+                            let message = e.root_cause().to_string();
+                            info!(reason = %message, "unsupported command");
+                            Response::Error { message }
+                        }
+                        res => res?,
+                    }
+                }
+                Some(Err(e)) if self.config.reply_errors && e.is::<MalformedRequest>() => {
+                    let message = reason_of_malformed(&e);
+                    info!(reason = %message, "malformed request");
+                    Response::Error { message }
+                }
+                Some(Err(e)) if self.config.error_before_close && !is_disconnect(&e) => {
+                    let message = if e.is::<MalformedRequest>() {
+                        reason_of_malformed(&e)
+                    } else {
+                        e.to_string()
+                    };
+                    info!(reason = %message, "closing on undecodable request");
+                    self.frames.feed(Response::Error { message }).await?;
+                    return Err(e);
+                }
+                Some(Err(e)) => return Err(e),
+            };
+            self.frames.feed(encode_values(self.encoding, res)).await?;
         }
-        Ok(())
     }
 
     async fn handle(&mut self, req: Request) -> Result<Response> {
+        if let Some(settings) = &self.config.settings {
+            let tunable = settings.tunable();
+            self.config.command_timeout = tunable.command_timeout;
+        }
+
+        let greeting = matches!(req, Request::Hello { version: _ });
+        if self.config.require_hello && !self.greeted && !greeting {
+            bail!("expected HELLO before {:?}", req);
+        }
+
+        if let Some((bytes, max)) = self.oversized_key(&req) {
+            info!("denied: key of {} bytes", bytes);
+            return Ok(Response::Error {
+                message: format!("key of {} bytes exceeds limit of {} bytes", bytes, max),
+            });
+        }
+
+        if let Some(key) = self.forbidden_key(&req) {
+            info!("denied: key: {}", key);
+            return Ok(Response::Error {
+                message: format!("access denied to key {}", key),
+            });
+        }
+
+        if req.administers() && self.forbids_admin() {
+            info!("denied: unauthenticated: {:?}", req);
+            return Ok(Response::Error {
+                message: "authentication required".into(),
+            });
+        }
+
+        if let Some(command) = lacking_capability(self.store.capabilities(), &req) {
+            return Err(Unsupported(command).into());
+        }
+
+        if self.config.read_only && req.mutates() {
+            info!("denied: read-only: {:?}", req);
+            return Ok(Response::Error {
+                message: "read-only server".into(),
+            });
+        }
+
+        let encoding = self.encoding;
+        let req = match &mut self.queued {
+            Some(queued) => match into_operation(req) {
+                Ok(op) => {
+                    let op = decode_operation(encoding, op)?;
+                    info!("queue: {:?}", op);
+                    queued.push(op);
+                    return Ok(Response::Queued);
+                }
+                // Applying it right away would escape the transaction.
+                Err(req) if req.mutates() => {
+                    info!("denied: not queueable: {:?}", req);
+                    return Ok(Response::Error {
+                        message: "command cannot be queued within a transaction".into(),
+                    });
+                }
+                Err(req) => req,
+            },
+            None => req,
+        };
+
         match req {
             Request::Get { key } => {
                 info!("get: key: {}", key);
                 let value = self.get_from_store(&key).await?;
                 Ok(Response::Get { key, value })
             }
+            #[cfg(feature = "extended")]
+            Request::BGet { key, timeout_ms } => {
+                info!("bget: key: {} timeout_ms: {}", key, timeout_ms);
+                // Waiting is the point, hence not bounded by the command timeout.
+                let value = self
+                    .store
+                    .get_or_wait(&key, Duration::from_millis(timeout_ms))
+                    .await?;
+                Ok(Response::Get { key, value })
+            }
+            #[cfg(feature = "extended")]
+            Request::GetRange { key, start, end } => {
+                info!("getrange: key: {} start: {} end: {}", key, start, end);
+                let value = timed(self.timeout(), self.store.get_range(&key, start, end)).await?;
+                Ok(Response::GetRange { key, value })
+            }
+            #[cfg(feature = "extended")]
+            Request::GetDel { key } => {
+                info!("getdel: key: {}", key);
+                let value = self.get_del_from_store(key.clone()).await?;
+                Ok(Response::GetDel { key, value })
+            }
+            #[cfg(feature = "extended")]
+            Request::GetOr { key, default } => {
+                info!("getor: key: {} default: {}", key, default);
+                let value = match self.get_from_store(&key).await? {
+                    Some(value) => value,
+                    None => self.incoming(&key, default)?,
+                };
+                Ok(Response::Get {
+                    key,
+                    value: Some(value),
+                })
+            }
             Request::Set { key, value } => {
                 info!("set: key: {} value: {}", key, value);
+                let value = self.incoming(&key, value)?;
                 self.set_into_store(key.clone(), value).await?;
                 Ok(Response::Set { key })
             }
+            Request::SetGet { key, value } => {
+                info!("set: key: {} value: {} get", key, value);
+                let value = self.incoming(&key, value)?;
+                let value = self.set_get_into_store(key.clone(), value).await?;
+                Ok(Response::SetGet { key, value })
+            }
+            #[cfg(feature = "extended")]
+            Request::SetNx { key, value } => {
+                info!("setnx: key: {} value: {}", key, value);
+                let value = self.incoming(&key, value)?;
+                let set = self.set_nx_into_store(key.clone(), value).await?;
+                Ok(Response::SetNx { key, set })
+            }
+            #[cfg(feature = "extended")]
+            Request::SetIfMatch {
+                key,
+                pattern,
+                value,
+            } => {
+                info!(
+                    "setifmatch: key: {} pattern: {} value: {}",
+                    key, pattern, value
+                );
+                let (pattern, value) = (self.incoming(&key, pattern)?, self.incoming(&key, value)?);
+                let set = self
+                    .set_if_match_in_store(key.clone(), pattern, value)
+                    .await?;
+                Ok(Response::SetIfMatch { key, set })
+            }
+            #[cfg(feature = "extended")]
+            Request::DelIfEquals { key, expected } => {
+                info!("delifeq: key: {} expected: {}", key, expected);
+                let expected = self.incoming(&key, expected)?;
+                let deleted = self.del_if_equals_in_store(key.clone(), expected).await?;
+                Ok(Response::DelIfEquals { key, deleted })
+            }
+            #[cfg(feature = "extended")]
+            Request::SetIdem { key, token, value } => {
+                info!("setidem: key: {} token: {} value: {}", key, token, value);
+                let value = self.incoming(&key, value)?;
+                let applied = self.set_idem_in_store(key.clone(), token, value).await?;
+                Ok(Response::SetIdem { key, applied })
+            }
+            #[cfg(feature = "extended")]
+            Request::MSetNx { pairs } => {
+                info!("msetnx: pairs: {:?}", pairs);
+                let pairs = pairs
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let value = self.incoming(&key, value)?;
+                        Ok((key, value))
+                    })
+                    .collect::<Result<_, Invalid>>()?;
+                let set = self.mset_nx_into_store(pairs).await?;
+                Ok(Response::MSetNx { set })
+            }
+            #[cfg(feature = "extended")]
+            Request::Rename { src, dst } => {
+                info!("rename: src: {} dst: {}", src, dst);
+                let ok = self.rename_in_store(src, dst).await?;
+                Ok(Response::Rename { ok })
+            }
+            #[cfg(feature = "transactions")]
+            Request::Multi => {
+                info!("multi");
+                let ok = self.queued.is_none();
+                if ok {
+                    self.queued = Some(Vec::new());
+                }
+                Ok(Response::Multi { ok })
+            }
+            #[cfg(feature = "transactions")]
+            Request::Exec => {
+                info!("exec");
+                let watched = mem::take(&mut self.watched);
+                let responses = match self.queued.take() {
+                    Some(ops) => match self.transaction_in_store(watched, ops).await? {
+                        Some(responses) => Some(responses),
+                        None => {
+                            info!("exec aborted");
+                            return Ok(Response::Aborted);
+                        }
+                    },
+                    None => None,
+                };
+                Ok(Response::Exec { responses })
+            }
+            #[cfg(feature = "transactions")]
+            Request::Discard => {
+                info!("discard");
+                self.watched.clear();
+                let ok = self.queued.take().is_some();
+                Ok(Response::Discard { ok })
+            }
+            #[cfg(feature = "transactions")]
+            Request::Watch { keys } => {
+                info!("watch: keys: {:?}", keys);
+                // Watching from within a transaction would come too late to guard it.
+                let ok = self.queued.is_none();
+                if ok {
+                    for key in keys {
+                        let version = timed(self.timeout(), self.store.version(&key)).await?;
+                        self.watched.push((key, version));
+                    }
+                }
+                Ok(Response::Watch { ok })
+            }
+            #[cfg(feature = "transactions")]
+            Request::Unwatch => {
+                info!("unwatch");
+                self.watched.clear();
+                Ok(Response::Unwatch)
+            }
+            #[cfg(feature = "admin")]
+            Request::Dump => {
+                info!("dump");
+                self.dump().await?;
+                Ok(Response::DumpEnd)
+            }
+            #[cfg(feature = "extended")]
+            Request::GetPrefix { prefix } => {
+                info!("getprefix: prefix: {}", prefix);
+                let pairs = timed(self.timeout(), self.store.get_prefix(&prefix))
+                    .await?
+                    .into_iter()
+                    .filter(|(key, _)| !self.forbids(key))
+                    .collect();
+                Ok(Response::GetPrefix { pairs })
+            }
+            #[cfg(feature = "admin")]
+            Request::MemUsage { prefix } => {
+                info!("memusage: prefix: {}", prefix);
+                let sizes = timed(self.timeout(), self.store.mem_usage(&prefix))
+                    .await?
+                    .into_iter()
+                    .filter(|(key, _)| !self.forbids(key))
+                    .collect();
+                Ok(Response::MemUsage { sizes })
+            }
+            #[cfg(feature = "extended")]
+            Request::Children { prefix, separator } => {
+                info!("children: prefix: {}, separator: {}", prefix, separator);
+                let segments = timed(self.timeout(), self.store.children(&prefix, &separator))
+                    .await?
+                    .into_iter()
+                    .filter(|segment| !self.forbids(&format!("{}{}", prefix, segment)))
+                    .collect();
+                Ok(Response::Children { segments })
+            }
+            #[cfg(feature = "admin")]
+            Request::FindVal { substring } => {
+                info!("findval: substring: {}", substring);
+                let keys = timed(self.timeout(), self.store.find_value(substring.as_bytes()))
+                    .await?
+                    .into_iter()
+                    .filter(|key| !self.forbids(key))
+                    .collect();
+                Ok(Response::FindVal { keys })
+            }
+            #[cfg(feature = "pubsub")]
+            Request::PSubscribe { prefix } => {
+                info!("psubscribe: prefix: {}", prefix);
+                let ack = Response::PSubscribe {
+                    prefix: prefix.clone(),
+                };
+                self.stream_mutations(ack, |mutation| {
+                    let mutation = match mutation {
+                        // Removed all the same, which is all subscribers such as followers need.
+                        Mutation::Expired { key } => Mutation::Del { key },
+                        mutation => mutation,
+                    };
+                    Some(mutation).filter(|mutation| mutation.key().starts_with(&prefix))
+                })
+                .await?;
+                Ok(Response::SubscriptionEnd)
+            }
+            #[cfg(feature = "pubsub")]
+            Request::SubscribeExpired => {
+                info!("subscribe-expired");
+                self.stream_mutations(Response::SubscribeExpired, |mutation| {
+                    Some(mutation)
+                        .filter(|mutation| matches!(mutation, Mutation::Expired { key: _ }))
+                })
+                .await?;
+                Ok(Response::SubscriptionEnd)
+            }
+            #[cfg(feature = "admin")]
+            Request::Compact => {
+                info!("compact");
+                timed(self.timeout(), self.store.compact()).await?;
+                Ok(Response::Compact)
+            }
+            #[cfg(feature = "admin")]
+            Request::Sync => {
+                info!("sync");
+                timed(self.timeout(), self.store.sync()).await?;
+                Ok(Response::Sync)
+            }
+            #[cfg(feature = "extended")]
+            Request::Checksum { key } => {
+                info!("checksum: key: {}", key);
+                let hash = timed(self.timeout(), self.store.checksum(&key)).await?;
+                Ok(Response::Checksum { key, hash })
+            }
+            #[cfg(feature = "expiry")]
+            Request::IdleTime { key } => {
+                info!("idletime: key: {}", key);
+                let seconds = self.idle_time_from_store(&key).await?;
+                Ok(Response::IdleTime { key, seconds })
+            }
+            #[cfg(feature = "expiry")]
+            Request::HotKeys { count } => {
+                info!("hotkeys: count: {}", count);
+                let keys = timed(self.timeout(), self.store.hot_keys(count))
+                    .await?
+                    .into_iter()
+                    .filter(|key| !self.forbids(key))
+                    .collect();
+                Ok(Response::HotKeys { keys })
+            }
+            #[cfg(feature = "expiry")]
+            Request::Expire { key, seconds } => {
+                info!("expire: key: {} seconds: {}", key, seconds);
+                let ok = self.expire_in_store(key.clone(), seconds).await?;
+                Ok(Response::Expire { key, ok })
+            }
+            #[cfg(feature = "expiry")]
+            Request::SetEx {
+                key,
+                seconds,
+                value,
+            } => {
+                info!("setex: key: {} seconds: {} value: {}", key, seconds, value);
+                let value = self.incoming(&key, value)?;
+                self.set_ex_into_store(key.clone(), seconds, value).await?;
+                Ok(Response::Set { key })
+            }
+            #[cfg(feature = "extended")]
+            Request::IncrBy { key, delta } => {
+                info!("incrby: key: {} delta: {}", key, delta);
+                let value = self.incr_by_in_store(key.clone(), delta).await?;
+                Ok(Response::IncrBy { key, value })
+            }
+            #[cfg(feature = "extended")]
+            Request::LPush { key, item } => {
+                info!("lpush: key: {} item: {}", key, item);
+                let item = self.incoming(&key, item)?;
+                // An item spanning several, or none, would miscount the list.
+                if item.is_empty() || item.contains(&LIST_DELIMITER) {
+                    return Err(Invalid(key).into());
+                }
+                let len = self.lpush_into_store(key.clone(), item).await?;
+                Ok(Response::ListLen { key, len })
+            }
+            #[cfg(feature = "extended")]
+            Request::SetChunk { key, seq, data } => {
+                info!("setchunk: key: {} seq: {}", key, seq);
+                let data = self.incoming(&key, data)?;
+                let next = self.chunks.get(&key).map_or(0, Vec::len) as u64;
+                if seq != next {
+                    return Ok(Response::SetChunk {
+                        key,
+                        expected: Some(next),
+                    });
+                }
+                let max_keys = self
+                    .config
+                    .max_chunked_keys
+                    .unwrap_or(DEFAULT_MAX_CHUNKED_KEYS);
+                if next == 0 && self.chunks.len() >= max_keys {
+                    info!("denied: chunks held back under {} keys", max_keys);
+                    return Ok(Response::Error {
+                        message: format!("chunks held back under more than {} keys", max_keys),
+                    });
+                }
+                let max_bytes = self
+                    .config
+                    .max_chunk_bytes
+                    .unwrap_or(DEFAULT_MAX_CHUNK_BYTES);
+                if self.chunk_bytes + data.len() > max_bytes {
+                    info!("denied: chunks held back beyond {} bytes", max_bytes);
+                    self.drop_chunks(&key);
+                    return Ok(Response::Error {
+                        message: format!("chunks held back exceed {} bytes", max_bytes),
+                    });
+                }
+                self.chunk_bytes += data.len();
+                self.chunks.entry(key.clone()).or_default().push(data);
+                Ok(Response::SetChunk {
+                    key,
+                    expected: None,
+                })
+            }
+            #[cfg(feature = "extended")]
+            Request::SetChunkEnd { key } => {
+                info!("setchunkend: key: {}", key);
+                let ok = match self.drop_chunks(&key) {
+                    Some(chunks) => {
+                        self.set_into_store(key.clone(), chunks.concat().into())
+                            .await?;
+                        true
+                    }
+                    None => false,
+                };
+                Ok(Response::SetChunkEnd { key, ok })
+            }
+            #[cfg(feature = "extended")]
+            Request::LLen { key } => {
+                info!("llen: key: {}", key);
+                let len = timed(self.timeout(), self.store.llen(&key)).await?;
+                Ok(Response::ListLen { key, len })
+            }
+            #[cfg(feature = "extended")]
+            Request::SetExtremum {
+                key,
+                extremum,
+                candidate,
+            } => {
+                info!(
+                    "setextremum: key: {} extremum: {:?} candidate: {}",
+                    key, extremum, candidate
+                );
+                let value = self
+                    .set_extremum_in_store(key.clone(), extremum, candidate)
+                    .await?;
+                Ok(Response::SetExtremum { key, value })
+            }
+            #[cfg(feature = "admin")]
+            Request::Fill { prefix, count } => {
+                info!("fill: prefix: {} count: {}", prefix, count);
+                let created = self.fill_store(prefix, count).await?;
+                Ok(Response::Fill { created })
+            }
+            #[cfg(feature = "admin")]
+            Request::DbSize => {
+                info!("dbsize");
+                let size = timed(self.timeout(), self.store.db_size()).await?;
+                Ok(Response::DbSize { size })
+            }
+            #[cfg(feature = "admin")]
+            Request::Info => {
+                info!("info");
+                let queue = timed(self.timeout(), self.store.queue_depth()).await?;
+                let fields = vec![
+                    ("queue_depth".into(), queue.pending.to_string()),
+                    ("queue_capacity".into(), queue.capacity.to_string()),
+                ];
+                Ok(Response::Info { fields })
+            }
+            #[cfg(feature = "admin")]
+            Request::Time => {
+                info!("time");
+                let unix_secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .context("system clock is set before the Unix epoch")?
+                    .as_secs();
+                let uptime_secs = self.started.elapsed().as_secs();
+                Ok(Response::Time {
+                    unix_secs,
+                    uptime_secs,
+                })
+            }
+            #[cfg(feature = "admin")]
+            Request::Capabilities => {
+                info!("capabilities");
+                let capabilities = self.store.capabilities();
+                Ok(Response::Capabilities { capabilities })
+            }
+            #[cfg(feature = "admin")]
+            Request::LastSeq => {
+                info!("lastseq");
+                let seq = timed(self.timeout(), self.store.last_seq()).await?;
+                Ok(Response::LastSeq { seq })
+            }
+            #[cfg(feature = "admin")]
+            Request::GetLog { count } => {
+                info!("getlog: count: {}", count);
+                let entries = timed(self.timeout(), self.store.get_log(count))
+                    .await?
+                    .into_iter()
+                    .filter(|entry| !self.forbids(entry.mutation.key()))
+                    .collect();
+                Ok(Response::GetLog { entries })
+            }
+            #[cfg(feature = "expiry")]
+            Request::Ttl { key } => {
+                info!("ttl: key: {}", key);
+                let seconds = self.ttl_from_store(&key).await?;
+                Ok(Response::Ttl { key, seconds })
+            }
+            #[cfg(feature = "expiry")]
+            Request::GetWithTtl { key } => {
+                info!("getttl: key: {}", key);
+                let found = timed(self.timeout(), self.store.get_with_ttl(&key)).await?;
+                let (value, ttl_secs) = match found {
+                    Some((value, expiry)) => (Some(value), Some(seconds_left(expiry))),
+                    None => (None, None),
+                };
+                Ok(Response::GetWithTtl {
+                    key,
+                    value,
+                    ttl_secs,
+                })
+            }
+            #[cfg(feature = "session")]
+            Request::Deadline {
+                timeout_ms,
+                request,
+            } => {
+                info!("deadline: timeout_ms: {}", timeout_ms);
+                let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+                self.deadline = Some(deadline);
+                let res = Box::pin(self.handle(*request)).await;
+                self.deadline = None;
+                match res {
+                    Err(e) if is_timed_out(&e) && Instant::now() >= deadline => {
+                        info!("deadline exceeded");
+                        Ok(Response::Error {
+                            message: "deadline exceeded".into(),
+                        })
+                    }
+                    res => res,
+                }
+            }
+            #[cfg(feature = "session")]
+            Request::Pong => {
+                bail!("unexpected PONG");
+            }
+            #[cfg(feature = "admin")]
+            Request::Use { name } => {
+                info!("use: name: {}", name);
+                // Watched versions only tell changes apart within the store they were taken in.
+                let store = match &self.namespaces {
+                    Some(namespaces) if self.queued.is_none() && !self.watching() => {
+                        Some(namespaces.open(&name))
+                    }
+                    _ => None,
+                };
+                let ok = store.is_some();
+                if let Some(store) = store {
+                    self.store = store;
+                }
+                Ok(Response::Use { ok })
+            }
+            #[cfg(feature = "admin")]
+            Request::DbList => {
+                info!("dblist");
+                let stores = self
+                    .namespaces
+                    .as_ref()
+                    .map(Namespaces::list)
+                    .unwrap_or_default();
+                let mut sizes = Vec::with_capacity(stores.len());
+                for (name, store) in stores {
+                    let size = timed(self.timeout(), store.db_size()).await?;
+                    sizes.push((name, size));
+                }
+                Ok(Response::DbList { sizes })
+            }
+            #[cfg(feature = "admin")]
+            Request::ConfigGet { param } => {
+                info!("config get: param: {}", param);
+                let value = self
+                    .config
+                    .settings
+                    .as_ref()
+                    .and_then(|settings| settings.get(&param));
+                Ok(Response::ConfigGet { param, value })
+            }
+            #[cfg(feature = "admin")]
+            Request::ConfigSet { param, value } => {
+                info!("config set: param: {} value: {}", param, value);
+                let set = match &self.config.settings {
+                    Some(settings) => settings.set(&param, &value),
+                    None => Err(anyhow!("unknown setting {}", param)),
+                };
+                match set {
+                    Ok(()) => Ok(Response::ConfigSet { param }),
+                    Err(e) => Ok(Response::Error {
+                        message: e.to_string(),
+                    }),
+                }
+            }
+            #[cfg(feature = "admin")]
+            Request::ClientList => {
+                info!("client list");
+                let clients = self
+                    .registration
+                    .as_ref()
+                    .map(|registration| registration.clients().list());
+                Ok(Response::ClientList { clients })
+            }
+            #[cfg(feature = "admin")]
+            Request::ClientKill { peer_addr } => {
+                info!("client kill: peer_addr: {}", peer_addr);
+                let ok = self
+                    .registration
+                    .as_ref()
+                    .is_some_and(|registration| registration.clients().kill(peer_addr));
+                Ok(Response::ClientKill { ok })
+            }
+            #[cfg(feature = "session")]
+            Request::Encoding { encoding } => {
+                info!("encoding: {}", encoding);
+                self.encoding = encoding;
+                Ok(Response::Encoding { encoding })
+            }
+            #[cfg(feature = "session")]
+            Request::Auth { password } => {
+                info!("auth");
+                let prefix = self.config.credentials.get(&password);
+                let ok = prefix.is_some();
+                if ok {
+                    self.prefix = prefix.cloned();
+                }
+                Ok(Response::Auth { ok })
+            }
+            #[cfg(feature = "session")]
+            Request::Hello { version } => {
+                info!("hello: version: {}", version);
+                let ok = version == PROTOCOL_VERSION;
+                self.greeted |= ok;
+                Ok(Response::Hello {
+                    version: PROTOCOL_VERSION,
+                    ok,
+                })
+            }
+            // Commands of families left out of the build, which the codec rejects already.
+            #[allow(unreachable_patterns)]
+            req => bail!("command not supported in this build: {:?}", req),
+        }
+    }
+
+    #[cfg(feature = "admin")]
+    /// Whether keys are watched for the next `EXEC`.
+    fn watching(&self) -> bool {
+        #[cfg(feature = "transactions")]
+        return !self.watched.is_empty();
+        #[cfg(not(feature = "transactions"))]
+        false
+    }
+
+    /// Finds a key of `req` outside of the prefix granted to the client, if authentication is required.
+    fn forbidden_key(&self, req: &Request) -> Option<String> {
+        req.keys()
+            .into_iter()
+            .find(|key| self.forbids(key))
+            .map(ToOwned::to_owned)
+    }
+
+    #[cfg(feature = "extended")]
+    /// Stops holding back the chunks of `key`, returning them, if any.
+    fn drop_chunks(&mut self, key: &str) -> Option<Vec<Value>> {
+        let chunks = self.chunks.remove(key)?;
+        self.chunk_bytes -= chunks.iter().map(Value::len).sum::<usize>();
+        Some(chunks)
+    }
+
+    /// Finds the size of a key of `req` beyond the limit, along with the limit, if any.
+    fn oversized_key(&self, req: &Request) -> Option<(usize, usize)> {
+        let max = self.config.max_key_bytes?;
+        req.keys()
+            .into_iter()
+            .map(str::len)
+            .find(|bytes| *bytes > max)
+            .map(|bytes| (bytes, max))
+    }
+
+    /// Converts `value`, as written by the client under `key`, into what gets stored.
+    fn incoming(&self, key: &str, value: String) -> Result<Value, Invalid> {
+        decode_value(self.encoding, key, value.into())
+    }
+
+    /// Whether `key` is outside of the prefix granted to the client, if authentication is required.
+    fn forbids(&self, key: &str) -> bool {
+        forbidden(&self.config.credentials, self.prefix.as_deref(), key)
+    }
+
+    /// Whether the client may not administer the server, having not authenticated although
+    /// authentication is required.
+    fn forbids_admin(&self) -> bool {
+        !self.config.credentials.is_empty() && self.prefix.is_none()
+    }
+
+    /// Bounds the next store operation by the command timeout, shortened to the deadline of
+    /// the request, if any.
+    fn timeout(&self) -> Option<Duration> {
+        let left = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match (self.config.command_timeout, left) {
+            (Some(timeout), Some(left)) => Some(timeout.min(left)),
+            (timeout, left) => timeout.or(left),
+        }
+    }
+
+    async fn get_from_store(&mut self, key: &str) -> Result<Option<Value>> {
+        timed(self.timeout(), self.store.get(key)).await
+    }
+
+    #[cfg(feature = "extended")]
+    async fn get_del_from_store(&mut self, key: String) -> Result<Option<Value>> {
+        let command = format!("GETDEL {}", key);
+        let value = timed(self.timeout(), self.store.get_del(key)).await?;
+        if value.is_some() {
+            self.audit(&command).await?;
+        }
+        Ok(value)
+    }
+
+    async fn set_into_store(&mut self, key: String, value: Value) -> Result<()> {
+        let command = format!("SET {}", key);
+        timed(self.timeout(), self.store.set(key, value)).await?;
+        self.audit(&command).await
+    }
+
+    async fn set_get_into_store(&mut self, key: String, value: Value) -> Result<Option<Value>> {
+        let command = format!("SET {}", key);
+        let old = timed(self.timeout(), self.store.set_get(key, value)).await?;
+        self.audit(&command).await?;
+        Ok(old)
+    }
+
+    #[cfg(feature = "extended")]
+    async fn set_nx_into_store(&mut self, key: String, value: Value) -> Result<bool> {
+        let command = format!("SETNX {}", key);
+        let set = timed(self.timeout(), self.store.set_nx(key, value)).await?;
+        if set {
+            self.audit(&command).await?;
+        }
+        Ok(set)
+    }
+
+    #[cfg(feature = "extended")]
+    async fn set_if_match_in_store(
+        &mut self,
+        key: String,
+        pattern: Value,
+        value: Value,
+    ) -> Result<bool> {
+        let command = format!("SETIFMATCH {}", key);
+        let set = timed(self.timeout(), self.store.set_if_match(key, pattern, value)).await?;
+        if set {
+            self.audit(&command).await?;
+        }
+        Ok(set)
+    }
+
+    #[cfg(feature = "extended")]
+    async fn del_if_equals_in_store(&mut self, key: String, expected: Value) -> Result<bool> {
+        let command = format!("DELIFEQ {}", key);
+        let deleted = timed(self.timeout(), self.store.del_if_equals(key, expected)).await?;
+        if deleted {
+            self.audit(&command).await?;
+        }
+        Ok(deleted)
+    }
+
+    #[cfg(feature = "extended")]
+    async fn set_idem_in_store(
+        &mut self,
+        key: String,
+        token: String,
+        value: Value,
+    ) -> Result<bool> {
+        let command = format!("SETIDEM {} {}", key, token);
+        let applied = timed(self.timeout(), self.store.set_idem(key, token, value)).await?;
+        if applied {
+            self.audit(&command).await?;
+        }
+        Ok(applied)
+    }
+
+    #[cfg(feature = "extended")]
+    async fn mset_nx_into_store(&mut self, pairs: Vec<(String, Value)>) -> Result<bool> {
+        let keys: Vec<_> = pairs.iter().map(|(key, _)| key.as_str()).collect();
+        let command = format!("MSETNX {}", keys.join(" "));
+        let set = timed(self.timeout(), self.store.mset_nx(pairs)).await?;
+        if set {
+            self.audit(&command).await?;
+        }
+        Ok(set)
+    }
+
+    #[cfg(feature = "extended")]
+    async fn rename_in_store(&mut self, src: String, dst: String) -> Result<bool> {
+        let command = format!("RENAME {} {}", src, dst);
+        let ok = timed(self.timeout(), self.store.rename(src, dst)).await?;
+        if ok {
+            self.audit(&command).await?;
+        }
+        Ok(ok)
+    }
+
+    #[cfg(feature = "expiry")]
+    async fn idle_time_from_store(&mut self, key: &str) -> Result<Option<u64>> {
+        let idle = timed(self.timeout(), self.store.idle_time(key)).await?;
+        Ok(idle.map(|idle| idle.as_secs()))
+    }
+
+    #[cfg(feature = "expiry")]
+    async fn expire_in_store(&mut self, key: String, seconds: u64) -> Result<bool> {
+        let command = format!("EXPIRE {} {}", key, seconds);
+        let ttl = Duration::from_secs(seconds);
+        let ok = timed(self.timeout(), self.store.expire(key, ttl)).await?;
+        if ok {
+            self.audit(&command).await?;
+        }
+        Ok(ok)
+    }
+
+    #[cfg(feature = "expiry")]
+    async fn set_ex_into_store(&mut self, key: String, seconds: u64, value: Value) -> Result<()> {
+        let command = format!("SETEX {} {}", key, seconds);
+        let ttl = Duration::from_secs(seconds);
+        timed(self.timeout(), self.store.set_ex(key, value, ttl)).await?;
+        self.audit(&command).await
+    }
+
+    #[cfg(feature = "admin")]
+    async fn fill_store(&mut self, prefix: String, count: usize) -> Result<usize> {
+        let command = format!("FILL {} {}", prefix, count);
+        let created = timed(self.timeout(), self.store.fill(prefix, count)).await?;
+        self.audit(&command).await?;
+        Ok(created)
+    }
+
+    #[cfg(feature = "extended")]
+    async fn incr_by_in_store(&mut self, key: String, delta: i64) -> Result<Option<i64>> {
+        let command = format!("INCRBY {} {}", key, delta);
+        let value = timed(self.timeout(), self.store.incr_by(key, delta)).await?;
+        if value.is_some() {
+            self.audit(&command).await?;
+        }
+        Ok(value)
+    }
+
+    #[cfg(feature = "extended")]
+    async fn lpush_into_store(&mut self, key: String, item: Value) -> Result<usize> {
+        let command = format!("LPUSH {}", key);
+        let len = timed(self.timeout(), self.store.lpush(key, item)).await?;
+        self.audit(&command).await?;
+        Ok(len)
+    }
+
+    #[cfg(feature = "extended")]
+    async fn set_extremum_in_store(
+        &mut self,
+        key: String,
+        extremum: Extremum,
+        candidate: i64,
+    ) -> Result<Option<i64>> {
+        let name = match extremum {
+            Extremum::Max => "MAX",
+            Extremum::Min => "MIN",
+        };
+        let command = format!("{} {} {}", name, key, candidate);
+        let value = timed(
+            self.timeout(),
+            self.store.set_extremum(key, extremum, candidate),
+        )
+        .await?;
+        if value.is_some() {
+            self.audit(&command).await?;
+        }
+        Ok(value)
+    }
+
+    #[cfg(feature = "expiry")]
+    async fn ttl_from_store(&mut self, key: &str) -> Result<Option<i64>> {
+        let expiry = timed(self.timeout(), self.store.ttl(key)).await?;
+        Ok(expiry.map(seconds_left))
+    }
+
+    #[cfg(feature = "admin")]
+    /// Streams every pair the client may access, the connection being busy meanwhile.
+    async fn dump(&mut self) -> Result<()> {
+        let pairs = timed(self.timeout(), self.store.snapshot()).await?;
+        for (key, value) in pairs {
+            if !self.forbids(&key) {
+                let value = Some(value);
+                let res = encode_values(self.encoding, Response::Get { key, value });
+                self.frames.feed(res).await?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "pubsub")]
+    /// Acknowledges with `ack`, then streams every mutation of a key the client may access, as
+    /// turned by `notified` into what the client is notified of, if anything, until the client sends anything else, which is otherwise ignored, or disconnects.
+    ///
+    /// A client slow to read holds further mutations back, missing the oldest ones once too far
+    /// behind. With an output buffer limit, mutations are buffered instead, the connection
+    /// being closed once those yet to be sent take more than the limit.
+    async fn stream_mutations(
+        &mut self,
+        ack: Response,
+        notified: impl Fn(Mutation) -> Option<Mutation>,
+    ) -> Result<()> {
+        let mut mutations = timed(self.timeout(), self.store.subscribe()).await?;
+        self.frames.send(ack).await?;
+
+        let limit = self.config.max_output_buffer;
+        let credentials = Arc::clone(&self.config.credentials);
+        let prefix = self.prefix.clone();
+        let (mut sink, mut requests) = (&mut self.frames).split();
+        let mut backlog = VecDeque::new();
+        let (mut backlog_bytes, mut unflushed) = (0, false);
+
+        loop {
+            let mutation = tokio::select! {
+                // Mutations already published are streamed before the client gets to end it.
+                biased;
+                fed = feed_or_flush(&mut sink, backlog.front().cloned(), unflushed) => {
+                    unflushed = fed?;
+                    if unflushed {
+                        let sent = backlog.pop_front().map_or(0, |sent| wire_len(&sent));
+                        backlog_bytes -= sent;
+                    }
+                    continue;
+                }
+                mutation = mutations.recv(), if limit.is_some() || backlog.is_empty() => mutation,
+                _ = requests.next() => break,
+            };
+            match mutation {
+                Ok(mutation) => {
+                    let mutation = notified(mutation).filter(|mutation| {
+                        !forbidden(&credentials, prefix.as_deref(), mutation.key())
+                    });
+                    if let Some(mutation) = mutation {
+                        let notification = Response::Notify { mutation };
+                        backlog_bytes += wire_len(&notification);
+                        backlog.push_back(notification);
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => info!(missed, "subscriber missed mutations"),
+                Err(RecvError::Closed) => break,
+            }
+            if let Some(limit) = limit.filter(|limit| backlog_bytes > *limit) {
+                info!(limit, "output buffer limit exceeded");
+                let message = SlowConsumer.to_string();
+                // Telling the client why only if it has room for it, lest the connection hang.
+                let _ = sink.send(Response::Error { message }).now_or_never();
+                return Err(SlowConsumer.into());
+            }
+        }
+
+        for notification in backlog {
+            sink.feed(notification).await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "transactions")]
+    /// Applies `ops` at once, unless any key of `watched` changed version, in which case nothing
+    /// is applied and `None` is returned.
+    async fn transaction_in_store(
+        &mut self,
+        watched: Vec<(String, u64)>,
+        ops: Vec<Operation>,
+    ) -> Result<Option<Vec<Response>>> {
+        let outcomes = if watched.is_empty() {
+            timed(self.timeout(), self.store.transaction(ops.clone())).await?
+        } else {
+            let timeout = self.timeout();
+            let applied = self.store.transaction_if_unchanged(watched, ops.clone());
+            match timed(timeout, applied).await? {
+                Some(outcomes) => outcomes,
+                None => return Ok(None),
+            }
+        };
+
+        let commands: Vec<_> = ops
+            .iter()
+            .zip(&outcomes)
+            .filter_map(|(op, outcome)| mutation_command(op, outcome))
+            .collect();
+        for command in commands {
+            self.audit(&command).await?;
+        }
+
+        ops.into_iter()
+            .zip(outcomes)
+            .map(|(op, outcome)| into_response(op, outcome))
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+
+    /// Reports `req` as received, passing it through.
+    fn emit_command(&self, req: Request) -> Request {
+        if let Some(events) = &self.config.events {
+            events.emit(ServerEvent::Command {
+                peer_addr: self.peer_addr,
+                request: req.clone(),
+            });
+        }
+        req
+    }
+
+    /// Records that `command` was applied in the audit log, if any.
+    async fn audit(&mut self, command: &str) -> Result<()> {
+        match &self.config.audit {
+            Some(log) => log.record(self.peer_addr, command).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "expiry")]
+/// Returns the seconds left before expiring, `-1` if never.
+fn seconds_left(expiry: Expiry) -> i64 {
+    match expiry {
+        Expiry::Never => -1,
+        Expiry::In(ttl) => ttl.as_secs() as i64,
+    }
+}
+
+#[cfg(feature = "transactions")]
+/// Describes `op` as it is recorded in the audit log, if it is a mutation that `outcome` tells
+/// was applied.
+fn mutation_command(op: &Operation, outcome: &Outcome) -> Option<String> {
+    if matches!(outcome, Outcome::Applied(false) | Outcome::Value(None)) {
+        return None;
+    }
+    match op {
+        Operation::Get { key: _ } => None,
+        Operation::Set { key, value: _ } => Some(format!("SET {}", key)),
+        Operation::SetNx { key, value: _ } => Some(format!("SETNX {}", key)),
+        Operation::Rename { src, dst } => Some(format!("RENAME {} {}", src, dst)),
+        Operation::GetDel { key } => Some(format!("GETDEL {}", key)),
+    }
+}
+
+/// Whether a client granted `prefix`, if any, may not access `key`, given the `credentials`
+/// of the server.
+fn forbidden(credentials: &HashMap<String, String>, prefix: Option<&str>, key: &str) -> bool {
+    if credentials.is_empty() {
+        return false;
+    }
+
+    match prefix {
+        Some(prefix) => !key.starts_with(prefix),
+        None => true,
+    }
+}
+
+#[cfg(feature = "pubsub")]
+/// Feeds `next` to `sink`, returning whether it did, or flushes `sink` if `unflushed`,
+/// waiting forever otherwise.
+async fn feed_or_flush<Si>(sink: &mut Si, next: Option<Response>, unflushed: bool) -> Result<bool>
+where
+    Si: Sink<Response, Error = anyhow::Error> + Unpin,
+{
+    match next {
+        Some(response) => sink.feed(response).await.map(|()| true),
+        None if unflushed => sink.flush().await.map(|()| false),
+        None => future::pending().await,
+    }
+}
+
+fn is_busy(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<Busy>())
+}
+
+fn is_timed_out(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<TimedOut>())
+}
+
+fn is_full(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<Full>())
+}
+
+fn is_invalid(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<Invalid>())
+}
+
+fn is_unsupported(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<Unsupported>())
+}
+
+/// Returns the command `req` is, if it requires a feature missing from `capabilities`.
+fn lacking_capability(capabilities: Capabilities, req: &Request) -> Option<&'static str> {
+    match req {
+        Request::Deadline {
+            timeout_ms: _,
+            request,
+        } => lacking_capability(capabilities, request),
+        Request::Expire { key: _, seconds: _ } if !capabilities.expiry => Some("EXPIRE"),
+        Request::SetEx {
+            key: _,
+            seconds: _,
+            value: _,
+        } if !capabilities.expiry => Some("SETEX"),
+        Request::GetPrefix { prefix: _ } if !capabilities.scan => Some("GETPREFIX"),
+        Request::MemUsage { prefix: _ } if !capabilities.scan => Some("MEMUSAGE"),
+        Request::Children {
+            prefix: _,
+            separator: _,
+        } if !capabilities.scan => Some("CHILDREN"),
+        Request::FindVal { substring: _ } if !capabilities.scan => Some("FINDVAL"),
+        Request::PSubscribe { prefix: _ } if !capabilities.subscribe => Some("PSUBSCRIBE"),
+        Request::SubscribeExpired if !capabilities.subscribe => Some("SUBSCRIBE-EXPIRED"),
+        _ => None,
+    }
+}
+
+fn is_disconnect(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|e| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+            )
+        })
+}
+
+/// Describes why a request is malformed, skipping the [`MalformedRequest`] context itself.
+fn reason_of_malformed(e: &anyhow::Error) -> String {
+    e.chain()
+        .skip(1)
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+/// Decodes `value`, as written by the client under `key`, from `encoding`.
+fn decode_value(encoding: ValueEncoding, key: &str, value: Value) -> Result<Value, Invalid> {
+    match encoding {
+        ValueEncoding::Raw => Ok(value),
+        ValueEncoding::Base64 => base64::decode(&value)
+            .map(Value::from)
+            .ok_or_else(|| Invalid(key.into())),
+    }
+}
+
+fn decode_operation(encoding: ValueEncoding, op: Operation) -> Result<Operation, Invalid> {
+    let op = match op {
+        Operation::Set { key, value } => {
+            let value = decode_value(encoding, &key, value)?;
+            Operation::Set { key, value }
         }
+        Operation::SetNx { key, value } => {
+            let value = decode_value(encoding, &key, value)?;
+            Operation::SetNx { key, value }
+        }
+        op => op,
+    };
+    Ok(op)
+}
+
+/// Encodes the values `response` carries to `encoding`, for the client to read them back.
+///
+/// Raw values that would break the response line, e.g. written by another client in base64,
+/// are answered with an error instead, lest they be taken for further responses.
+fn encode_values(encoding: ValueEncoding, response: Response) -> Response {
+    if encoding == ValueEncoding::Raw && !values_of(&response).all(|value| representable_raw(value))
+    {
+        info!("value not representable raw");
+        return Response::Error {
+            message: "value not representable, use ENCODING base64".into(),
+        };
+    }
+    let encode = |value: Value| match encoding {
+        ValueEncoding::Raw => value,
+        ValueEncoding::Base64 => base64::encode(&value).into(),
+    };
+    match response {
+        Response::Get { key, value } => Response::Get {
+            key,
+            value: value.map(encode),
+        },
+        Response::GetDel { key, value } => Response::GetDel {
+            key,
+            value: value.map(encode),
+        },
+        Response::GetRange { key, value } => Response::GetRange {
+            key,
+            value: value.map(encode),
+        },
+        Response::SetGet { key, value } => Response::SetGet {
+            key,
+            value: value.map(encode),
+        },
+        Response::GetWithTtl {
+            key,
+            value,
+            ttl_secs,
+        } => Response::GetWithTtl {
+            key,
+            value: value.map(encode),
+            ttl_secs,
+        },
+        Response::GetPrefix { pairs } => Response::GetPrefix {
+            pairs: pairs
+                .into_iter()
+                .map(|(key, value)| (key, encode(value)))
+                .collect(),
+        },
+        Response::Exec {
+            responses: Some(responses),
+        } => Response::Exec {
+            responses: Some(
+                responses
+                    .into_iter()
+                    .map(|response| encode_values(encoding, response))
+                    .collect(),
+            ),
+        },
+        response => response,
+    }
+}
+
+/// Values `response` carries, those of the responses to a transaction aside.
+fn values_of(response: &Response) -> Box<dyn Iterator<Item = &Value> + '_> {
+    match response {
+        Response::Get { key: _, value }
+        | Response::GetDel { key: _, value }
+        | Response::GetRange { key: _, value }
+        | Response::SetGet { key: _, value }
+        | Response::GetWithTtl {
+            key: _,
+            value,
+            ttl_secs: _,
+        } => Box::new(value.iter()),
+        Response::GetPrefix { pairs } => Box::new(pairs.iter().map(|(_, value)| value)),
+        _ => Box::new(std::iter::empty()),
     }
+}
+
+/// Whether `value` may be written as is within a response line, i.e. holds no line feed and
+/// does not end with a carriage return.
+fn representable_raw(value: &[u8]) -> bool {
+    !value.contains(&b'\n') && value.last() != Some(&b'\r')
+}
 
-    async fn get_from_store(&mut self, key: &str) -> Result<Option<String>> {
-        self.store.get(key).await
+/// Converts a request that can be part of a transaction into its operation.
+fn into_operation(req: Request) -> Result<Operation, Request> {
+    match req {
+        Request::Get { key } => Ok(Operation::Get { key }),
+        Request::Set { key, value } => Ok(Operation::Set {
+            key,
+            value: value.into(),
+        }),
+        Request::SetNx { key, value } => Ok(Operation::SetNx {
+            key,
+            value: value.into(),
+        }),
+        Request::Rename { src, dst } => Ok(Operation::Rename { src, dst }),
+        Request::GetDel { key } => Ok(Operation::GetDel { key }),
+        req => Err(req),
     }
+}
+
+#[cfg(feature = "transactions")]
+fn into_response(op: Operation, outcome: Outcome) -> Result<Response> {
+    let response = match (op, outcome) {
+        (Operation::Get { key }, Outcome::Value(value)) => Response::Get { key, value },
+        (Operation::Set { key, value: _ }, Outcome::Done) => Response::Set { key },
+        (Operation::SetNx { key, value: _ }, Outcome::Applied(set)) => Response::SetNx { key, set },
+        (Operation::Rename { src: _, dst: _ }, Outcome::Applied(ok)) => Response::Rename { ok },
+        (Operation::GetDel { key }, Outcome::Value(value)) => Response::GetDel { key, value },
+        (op, outcome) => bail!("unexpected outcome {:?} of {:?}", outcome, op),
+    };
+    Ok(response)
+}
 
-    async fn set_into_store(&mut self, key: String, value: String) -> Result<()> {
-        self.store.set(key, value).await
+async fn timed<T>(
+    timeout: Option<Duration>,
+    operation: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => time::timeout(timeout, operation)
+            .await
+            .map_err(|_| TimedOut)?,
+        None => operation.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::framed,
+        storage::{
+            inmemory::{self, InMemoryConfig},
+            types::{Key, KeyRef, Outcome, Value},
+            validating::{self, ValidatingStore},
+            NoopObserver,
+        },
+    };
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use std::{
+        pin::Pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        task::Poll,
+    };
+    use tokio::{
+        io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+        task,
+    };
+
+    #[tokio::test]
+    #[cfg(all(feature = "transactions", feature = "extended"))]
+    async fn exec_applies_queued_commands() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store.clone(), b"MULTI\nSET k a\nRENAME k j\nGET j\nEXEC\n").await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY\nOKAY QUEUED\nOKAY QUEUED\nOKAY QUEUED\nOKAY 3\nOKAY k\nOKAY\nOKAY j a\n"
+        );
+        assert_eq!(store.get("j").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "transactions")]
+    async fn discard_drops_queued_commands() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store.clone(), b"MULTI\nSET k a\nDISCARD\nEXEC\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY\nOKAY QUEUED\nOKAY\nFAIL\nFAIL k\n");
+        assert_eq!(store.get("k").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "transactions", feature = "extended"))]
+    async fn mutation_that_cannot_be_queued_is_refused_within_transaction() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "1".into()).await.unwrap();
+
+        // Action.
+        let output = exchange(store.clone(), b"MULTI\nINCRBY k 1\nDISCARD\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY\nERR command cannot be queued within a transaction\nOKAY\nOKAY k 1\n"
+        );
+        assert_eq!(store.get("k").await.unwrap(), Some("1".into()));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "transactions")]
+    async fn multi_within_transaction_fails() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store, b"MULTI\nMULTI\nEXEC\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY\nFAIL\nOKAY 0\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "transactions")]
+    async fn exec_aborts_once_watched_key_changes_concurrently() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        let (client, server) = io::duplex(1024);
+        let mut client = io::BufReader::new(client);
+        let service = tokio::spawn(StoreService::new(framed(server), store.clone()).start());
+
+        client.write_all(b"WATCH k j\n").await.unwrap();
+        let mut watched = String::new();
+        client.read_line(&mut watched).await.unwrap();
+
+        // Action.
+        store.set("k".into(), "b".into()).await.unwrap();
+        client
+            .write_all(b"MULTI\nSET j c\nEXEC\nMULTI\nSET j c\nEXEC\n")
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        // Post-condition.
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        assert_eq!(watched, "OKAY\n");
+        assert_eq!(
+            output,
+            "OKAY\nOKAY QUEUED\nFAIL ABORTED\nOKAY\nOKAY QUEUED\nOKAY 1\nOKAY j\n"
+        );
+        assert_eq!(store.get("j").await.unwrap(), Some("c".into()));
+        service.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "transactions")]
+    async fn exec_aborts_once_watched_key_is_set_to_expire_concurrently() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        let (client, server) = io::duplex(1024);
+        let mut client = io::BufReader::new(client);
+        let service = tokio::spawn(StoreService::new(framed(server), store.clone()).start());
+
+        client.write_all(b"WATCH k\n").await.unwrap();
+        let mut watched = String::new();
+        client.read_line(&mut watched).await.unwrap();
+
+        // Action.
+        store
+            .expire("k".into(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        client.write_all(b"MULTI\nSET j c\nEXEC\n").await.unwrap();
+        client.shutdown().await.unwrap();
+
+        // Post-condition.
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        assert_eq!(watched, "OKAY\n");
+        assert_eq!(output, "OKAY\nOKAY QUEUED\nFAIL ABORTED\n");
+        assert_eq!(store.get("j").await.unwrap(), None);
+        service.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "transactions")]
+    async fn exec_applies_queued_commands_while_watched_keys_are_unchanged() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        let (client, server) = io::duplex(1024);
+        let mut client = io::BufReader::new(client);
+        let service = tokio::spawn(StoreService::new(framed(server), store.clone()).start());
+
+        client.write_all(b"WATCH k j\n").await.unwrap();
+        let mut watched = String::new();
+        client.read_line(&mut watched).await.unwrap();
+
+        // Action.
+        store.set("i".into(), "b".into()).await.unwrap();
+        client
+            .write_all(b"MULTI\nGET k\nSET j c\nEXEC\n")
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        // Post-condition.
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        assert_eq!(watched, "OKAY\n");
+        assert_eq!(
+            output,
+            "OKAY\nOKAY QUEUED\nOKAY QUEUED\nOKAY 2\nOKAY k a\nOKAY j\n"
+        );
+        service.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "transactions")]
+    async fn unwatch_and_discard_forget_watched_keys() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store,
+            b"WATCH k\nSET k a\nUNWATCH\nMULTI\nGET k\nEXEC\nWATCH k\nSET k b\nMULTI\nDISCARD\nMULTI\nWATCH k\nEXEC\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY\nOKAY k\nOKAY\nOKAY\nOKAY QUEUED\nOKAY 1\nOKAY k a\nOKAY\nOKAY k\nOKAY\nOKAY\nOKAY\nFAIL\nOKAY 0\n"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn use_switches_between_isolated_stores() {
+        // Pre-condition.
+        let namespaces = Namespaces::new(inmemory::start);
+        let serve = |input: &'static [u8]| {
+            let (mut client, server) = io::duplex(1024);
+            let service = StoreService::new(framed(server), inmemory::start())
+                .with_namespaces(namespaces.clone());
+            async move {
+                let service = tokio::spawn(service.start());
+                client.write_all(input).await.unwrap();
+                client.shutdown().await.unwrap();
+                let mut output = String::new();
+                client.read_to_string(&mut output).await.unwrap();
+                service.await.unwrap().unwrap();
+                output
+            }
+        };
+
+        // Action.
+        let first = serve(b"USE app1\nSET k a\nUSE app2\nGET k\nSET k b\n").await;
+        let second = serve(b"GET k\nUSE app1\nGET k\nUSE app2\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(first, "OKAY\nOKAY k\nOKAY\nFAIL k\nOKAY k\n");
+        assert_eq!(second, "FAIL k\nOKAY\nOKAY k a\nOKAY\nOKAY k b\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn dblist_counts_keys_of_every_named_store() {
+        // Pre-condition.
+        let (mut client, server) = io::duplex(1024);
+        let service = StoreService::new(framed(server), inmemory::start())
+            .with_namespaces(Namespaces::new(inmemory::start));
+        let service = tokio::spawn(service.start());
+
+        // Action.
+        let without = exchange(inmemory::start(), b"DBLIST\n").await;
+        client
+            .write_all(b"USE app2\nSET k c\nUSE app0\nSET k a\nSET j b\nDBLIST\n")
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(without, "OKAY 0\n");
+        assert_eq!(
+            output,
+            "OKAY\nOKAY k\nOKAY\nOKAY k\nOKAY j\nOKAY 2\napp0 2\napp2 1\n"
+        );
+        service.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "transactions", feature = "admin"))]
+    async fn use_fails_without_namespaces_or_within_transaction() {
+        // Pre-condition.
+        let (mut client, server) = io::duplex(1024);
+        let service = StoreService::new(framed(server), inmemory::start())
+            .with_namespaces(Namespaces::new(inmemory::start));
+        let service = tokio::spawn(service.start());
+
+        // Action.
+        let without = exchange(inmemory::start(), b"USE app1\n").await;
+        client.write_all(b"MULTI\nUSE app1\n").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut within = String::new();
+        client.read_to_string(&mut within).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(without, "FAIL\n");
+        assert_eq!(within, "OKAY\nFAIL\n");
+        service.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "transactions", feature = "admin"))]
+    async fn use_fails_while_keys_are_watched() {
+        // Pre-condition.
+        let (mut client, server) = io::duplex(1024);
+        let service = StoreService::new(framed(server), inmemory::start())
+            .with_namespaces(Namespaces::new(inmemory::start));
+        let service = tokio::spawn(service.start());
+
+        // Action.
+        client
+            .write_all(b"WATCH k\nUSE app1\nUNWATCH\nUSE app1\n")
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(output, "OKAY\nFAIL\nOKAY\nOKAY\n");
+        service.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn get_or_on_existing_key_returns_stored_value() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let output = exchange(store, b"GETOR k b\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k a\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn get_or_on_missing_key_returns_default() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store.clone(), b"GETOR k b\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k b\n");
+        assert_eq!(store.get("k").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn get_range_returns_slice_of_value() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "toy-storage".into()).await.unwrap();
+
+        // Action.
+        let output = exchange(store, b"GETRANGE k -7 -1\nGETRANGE j 0 1\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k storage\nFAIL j\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn get_del_returns_value_only_once() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let output = exchange(store.clone(), b"GETDEL k\nGETDEL k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k a\nFAIL k\n");
+        assert_eq!(store.get("k").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn empty_value_round_trips() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store.clone(), b"SET k \nGET k\nSET j\nGETDEL j\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k\nOKAY k \nOKAY j\nOKAY j \n");
+        assert_eq!(store.get("k").await.unwrap(), Some("".into()));
+    }
+
+    #[tokio::test]
+    async fn store_at_max_keys_refuses_new_keys_only() {
+        // Pre-condition.
+        let config = InMemoryConfig {
+            max_keys: Some(2),
+            ..InMemoryConfig::default()
+        };
+        let store = inmemory::start_with_config(config, Arc::new(NoopObserver));
+
+        // Action.
+        let output = exchange(store, b"SET a 1\nSET b 2\nSET c 3\nSET a 4\nGET c\n").await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY a\nOKAY b\nERR store is full\nOKAY a\nFAIL c\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn value_violating_rule_of_its_key_is_answered_with_error() {
+        // Pre-condition.
+        let store = ValidatingStore::new(inmemory::start()).rule("count:", validating::integer());
+
+        // Action.
+        let output = exchange(store, b"SET count:a ten\nSET count:a 10\nSET name ten\n").await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "ERR invalid value for key count:a\nOKAY count:a\nOKAY name\n"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn checksum_changes_with_value_only() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store,
+            b"SET k 123456789\nCHECKSUM k\nCHECKSUM k\nSET k 12345678\nCHECKSUM k\nCHECKSUM j\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY k\nOKAY k cbf43926\nOKAY k cbf43926\nOKAY k\nOKAY k 9ae0daaf\nFAIL j\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn set_with_get_returns_old_value_unlike_plain_set() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store.clone(), b"SET k a GET\nSET k b GET\nSET k c\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "FAIL k\nOKAY k a\nOKAY k\nOKAY k c\n");
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "admin", feature = "extended"))]
+    async fn last_seq_counts_mutating_commands_only() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store,
+            b"LASTSEQ\nSET k a\nGET k\nLASTSEQ\nGETDEL k\nLASTSEQ\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY 0\nOKAY k\nOKAY k a\nOKAY 1\nOKAY k a\nOKAY 2\n"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "pubsub", feature = "expiry", feature = "admin"))]
+    async fn capabilities_are_listed_and_lacking_ones_answered_with_error() {
+        use crate::storage::dual::DualStore;
+
+        // Pre-condition.
+        let store = DualStore::new(inmemory::start(), inmemory::start());
+
+        // Action.
+        let output = exchange(
+            store,
+            b"CAPABILITIES\nEXPIRE k 10\nPSUBSCRIBE user:\nSET k a\nGET k\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY scan\n\
+             ERR EXPIRE is not supported by this store\n\
+             ERR PSUBSCRIBE is not supported by this store\n\
+             OKAY k\nOKAY k a\n"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "admin", feature = "extended"))]
+    async fn getlog_replays_mutations_in_order_with_their_seq() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store,
+            b"SET k a\nSET j b\nGET k\nGETDEL k\nSET k c\nGETLOG 10\nGETLOG 2\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY k\nOKAY j\nOKAY k a\nOKAY k a\nOKAY k\n\
+             OKAY 4\n1 SET k\n2 SET j\n3 DEL k\n4 SET k\n\
+             OKAY 2\n3 DEL k\n4 SET k\n"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn set_if_match_sets_only_values_starting_with_pattern() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "v1:a".into()).await.unwrap();
+
+        // Action.
+        let output = exchange(
+            store.clone(),
+            b"SETIFMATCH k v2 b\nSETIFMATCH k v1 v2:b\nSETIFMATCH j v1 c\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(output, "FAIL k\nOKAY k\nFAIL j\n");
+        assert_eq!(store.get("k").await.unwrap(), Some("v2:b".into()));
+        assert_eq!(store.get("j").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn info_reports_queue_of_idle_store_as_empty() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store, b"INFO\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY 2\nqueue_depth 0\nqueue_capacity 32\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn max_and_min_answer_resulting_integer() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store,
+            b"MAX k 5\nMAX k 3\nMAX k 9\nMIN k 4\nSET j one\nMIN j 1\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY k 5\nOKAY k 5\nOKAY k 9\nOKAY k 4\nOKAY j\nFAIL j\n"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn time_reports_clock_of_server_and_uptime() {
+        // Pre-condition.
+        let store = inmemory::start();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        // Action.
+        let output = exchange(store, b"TIME\n").await;
+
+        // Post-condition.
+        let fields: Vec<u64> = output
+            .strip_prefix("OKAY ")
+            .and_then(|fields| fields.strip_suffix('\n'))
+            .unwrap_or_else(|| panic!("unexpected response: {:?}", output))
+            .split(' ')
+            .map(|field| field.parse().unwrap())
+            .collect();
+        match fields.as_slice() {
+            [unix_secs, uptime_secs] => {
+                assert!(unix_secs.abs_diff(now) <= 2, "{} vs {}", unix_secs, now);
+                assert!(*uptime_secs <= 2, "{}", uptime_secs);
+            }
+            _ => panic!("unexpected response: {:?}", output),
+        }
+    }
+
+    #[tokio::test]
+    async fn comment_and_blank_lines_are_skipped_without_response() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store, b"# Seed.\nSET k a\n\n  \n# GET k\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k\nOKAY k a\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn sync_on_volatile_store_is_answered_right_away() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store, b"SET k a\nSYNC\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k\nOKAY\nOKAY k a\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn del_if_equals_deletes_only_matching_values() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let output = exchange(
+            store.clone(),
+            b"DELIFEQ k b\nDELIFEQ k a\nDELIFEQ k a\nDELIFEQ j a\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(output, "FAIL k\nOKAY k\nFAIL k\nFAIL j\n");
+        assert_eq!(store.get("k").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn set_idem_applies_each_token_once() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store.clone(),
+            b"SETIDEM k t1 1\nINCRBY k 1\nSETIDEM k t1 1\nGET k\nSETIDEM k t2 1\nGET k\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY k APPLIED\nOKAY k 2\nOKAY k DUPLICATE\nOKAY k 2\nOKAY k APPLIED\nOKAY k 1\n"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn msetnx_sets_all_keys_or_none() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store.clone(),
+            b"MSETNX k1 a k2 b\nMSETNX k3 c k2 d\nGET k1\nGET k2\nGET k3\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY\nFAIL\nOKAY k1 a\nOKAY k2 b\nFAIL k3\n");
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "session", feature = "transactions"))]
+    async fn base64_encoding_round_trips_binary_values() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("r".into(), "plain".into()).await.unwrap();
+        let binary: &[u8] = b"a\nb\r\n\0\xff";
+        let encoded = base64::encode(binary);
+
+        // Action.
+        let input = format!(
+            "ENCODING base64\nSET k {0}\nGET k\nMULTI\nSET t {0}\nGET t\nEXEC\nENCODING raw\nGET r\n",
+            encoded
+        );
+        let output = exchange(store.clone(), input.as_bytes()).await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            format!(
+                "OKAY base64\nOKAY k\nOKAY k {0}\nOKAY\nOKAY QUEUED\nOKAY QUEUED\nOKAY 2\nOKAY t\nOKAY t {0}\nOKAY raw\nOKAY r plain\n",
+                encoded
+            )
+        );
+        assert_eq!(store.get("k").await.unwrap(), Some(Value::from(binary)));
+        assert_eq!(store.get("t").await.unwrap(), Some(Value::from(binary)));
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "session", feature = "extended"))]
+    async fn raw_value_breaking_response_line_is_answered_with_error() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "a\nOKAY j b".into()).await.unwrap();
+        store.set("r".into(), "a\rb".into()).await.unwrap();
+
+        // Action.
+        let output = exchange(store, b"GET k\nGETPREFIX \nGET r\nENCODING base64\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            format!(
+                "ERR value not representable, use ENCODING base64\n\
+                 ERR value not representable, use ENCODING base64\n\
+                 OKAY r a\rb\n\
+                 OKAY base64\n\
+                 OKAY k {}\n",
+                base64::encode(b"a\nOKAY j b")
+            )
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "session")]
+    async fn value_that_is_not_base64_is_answered_with_error() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store, b"ENCODING base64\nSET k a\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY base64\nERR invalid value for key k\nFAIL k\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn lpush_answers_list_length_and_llen_counts_items() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store.clone(),
+            b"LLEN k\nLPUSH k a\nLPUSH k b\nLPUSH k c\nLLEN k\nGET k\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY k 0\nOKAY k 1\nOKAY k 2\nOKAY k 3\nOKAY k 3\nOKAY k a,b,c\n"
+        );
+        assert_eq!(store.get("k").await.unwrap(), Some("a,b,c".into()));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn lpush_of_item_holding_delimiter_is_answered_with_error() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store, b"LPUSH k a,b\nLLEN k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "ERR invalid value for key k\nOKAY k 0\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn setchunkend_sets_value_assembled_from_chunks() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store.clone(),
+            b"SETCHUNK k 0 abc\nSETCHUNK j 0 x\nSETCHUNK k 1 def\nGET k\nSETCHUNK k 2 ghi\nSETCHUNKEND k\nGET k\nSETCHUNKEND k\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY k\nOKAY j\nOKAY k\nFAIL k\nOKAY k\nOKAY k\nOKAY k abcdefghi\nFAIL k\n"
+        );
+        assert_eq!(store.get("k").await.unwrap(), Some("abcdefghi".into()));
+        assert_eq!(store.get("j").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn setchunk_beyond_limits_is_refused_and_drops_chunks_held_back() {
+        // Pre-condition.
+        let store = inmemory::start();
+        let config = Config {
+            max_chunk_bytes: Some(8),
+            max_chunked_keys: Some(2),
+            ..Config::default()
+        };
+
+        // Action.
+        let (output, _) = exchange_with_config(
+            store.clone(),
+            config,
+            b"SETCHUNK k 0 abc\nSETCHUNK j 0 de\nSETCHUNK i 0 f\nSETCHUNK k 1 ghij\n\
+              SETCHUNKEND k\nSETCHUNK i 0 fgh\nSETCHUNKEND i\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY k\nOKAY j\n\
+             ERR chunks held back under more than 2 keys\n\
+             ERR chunks held back exceed 8 bytes\n\
+             FAIL k\nOKAY i\nOKAY i\n"
+        );
+        assert_eq!(store.get("k").await.unwrap(), None);
+        assert_eq!(store.get("i").await.unwrap(), Some("fgh".into()));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn setchunk_out_of_order_is_rejected_with_expected_seq() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store,
+            b"SETCHUNK k 1 def\nSETCHUNKEND k\nSETCHUNK k 0 abc\nSETCHUNK k 2 ghi\nSETCHUNK k 0 xyz\nSETCHUNK k 1 def\nSETCHUNKEND k\nGET k\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "FAIL k 0\nFAIL k\nOKAY k\nFAIL k 1\nFAIL k 1\nOKAY k\nOKAY k\nOKAY k abcdef\n"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "expiry")]
+    async fn set_ex_sets_value_with_ttl() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store.clone(), b"SETEX k 10 a\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k\nOKAY k a\n");
+        assert!(matches!(
+            store.ttl("k").await.unwrap(),
+            Some(Expiry::In(ttl)) if ttl <= Duration::from_secs(10)
+        ));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "expiry")]
+    async fn set_ex_beyond_representable_instant_keeps_store_serving() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store.clone(),
+            b"SETEX k 18446744073709551615 a\nTTL k\nGET k\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k\nOKAY k -1\nOKAY k a\n");
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn fill_creates_keys_counted_by_db_size() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store, b"FILL k 100\nDBSIZE\nGET k:42\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY 100\nOKAY 100\nOKAY k:42 42\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn dump_streams_every_pair_then_end_marker() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+        store.set("i".into(), "c".into()).await.unwrap();
+
+        // Action.
+        let output = exchange(store, b"DUMP\nGET k\n").await;
+
+        // Post-condition.
+        let mut lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.pop(), Some("OKAY k a"));
+        assert_eq!(lines.pop(), Some("OKAY END"));
+        lines.sort_unstable();
+        assert_eq!(lines, vec!["OKAY i c", "OKAY j b", "OKAY k a"]);
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "session", feature = "admin"))]
+    async fn dump_streams_only_pairs_under_granted_prefix() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("a:k".into(), "1".into()).await.unwrap();
+        store.set("b:k".into(), "2".into()).await.unwrap();
+
+        // Action.
+        let (output, _) = exchange_with_config(store, tenants(), b"AUTH secret-a\nDUMP\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY\nOKAY a:k 1\nOKAY END\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn getprefix_returns_pairs_under_prefix_only() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("user:2".into(), "b c".into()).await.unwrap();
+        store.set("user:1".into(), "a".into()).await.unwrap();
+        store.set("group:1".into(), "d".into()).await.unwrap();
+
+        // Action.
+        let output = exchange(store, b"GETPREFIX user:\nGETPREFIX session:\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY 2\nuser:1 a\nuser:2 b c\nOKAY 0\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn memusage_lists_sizes_under_prefix_largest_first() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("user:1".into(), "abc".into()).await.unwrap();
+        store
+            .set("user:2".into(), "a".repeat(100).into())
+            .await
+            .unwrap();
+        store.set("user:3".into(), "".into()).await.unwrap();
+        store.set("user:4".into(), "xyz".into()).await.unwrap();
+        store
+            .set("group:1".into(), "a".repeat(1000).into())
+            .await
+            .unwrap();
+
+        // Action.
+        let output = exchange(store, b"MEMUSAGE user:\nMEMUSAGE session:\n").await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY 4\nuser:2 100\nuser:1 3\nuser:4 3\nuser:3 0\nOKAY 0\n"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "extended")]
+    async fn children_lists_distinct_direct_segments_only() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        for key in [
+            "user:1:name",
+            "user:1:address:city",
+            "user:1:address:zip",
+            "user:1:",
+            "user:10:name",
+            "user:2:name",
+            "group:1:name",
+        ] {
+            store.set(key.into(), "a".into()).await.unwrap();
+        }
+
+        // Action.
+        let output = exchange(
+            store,
+            b"CHILDREN user:1: :\nCHILDREN user: :\nCHILDREN session: :\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY 2\naddress\nname\nOKAY 3\n1\n10\n2\nOKAY 0\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "admin")]
+    async fn findval_lists_keys_whose_value_contains_substring() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        for (key, value) in [
+            ("user:1", "alice@example.com"),
+            ("user:2", "bob@example.org"),
+            ("user:3", "carol@example.com"),
+            ("count", "42"),
+        ] {
+            store.set(key.into(), value.into()).await.unwrap();
+        }
+
+        // Action.
+        let output = exchange(store, b"FINDVAL example.com\nFINDVAL nobody\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY 2\nuser:1\nuser:3\nOKAY 0\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg(feature = "expiry")]
+    async fn hotkeys_lists_keys_most_recently_accessed_first() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        for key in ["a", "b", "c"].iter() {
+            store.set((*key).into(), "v".into()).await.unwrap();
+            time::advance(Duration::from_secs(1)).await;
+        }
+
+        // Action.
+        let output = exchange(store, b"GET b\nHOTKEYS 2\nHOTKEYS 5\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY b v\nOKAY 2\nb\nc\nOKAY 3\nb\nc\na\n");
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "session", feature = "extended"))]
+    async fn getprefix_returns_only_pairs_under_granted_prefix() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("a:k".into(), "1".into()).await.unwrap();
+        store.set("b:k".into(), "2".into()).await.unwrap();
+
+        // Action.
+        let (output, _) =
+            exchange_with_config(store, tenants(), b"AUTH secret-a\nGETPREFIX \n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY\nOKAY 1\na:k 1\n");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "pubsub")]
+    async fn psubscribe_streams_only_mutations_under_prefix() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        let (client, server) = io::duplex(1024);
+        let mut client = io::BufReader::new(client);
+        tokio::spawn(StoreService::new(framed(server), store.clone()).start());
+
+        client.write_all(b"PSUBSCRIBE user:\n").await.unwrap();
+        let mut subscribed = String::new();
+        client.read_line(&mut subscribed).await.unwrap();
+
+        // Action.
+        store.set("user:1".into(), "a".into()).await.unwrap();
+        store.set("other:1".into(), "b".into()).await.unwrap();
+        store
+            .rename("user:1".into(), "other:2".into())
+            .await
+            .unwrap();
+        store.get_del("other:2".into()).await.unwrap();
+        store.set_nx("user:2".into(), "c".into()).await.unwrap();
+
+        client.write_all(b"GET user:2\nGET user:2\n").await.unwrap();
+        client.shutdown().await.unwrap();
+
+        // Post-condition.
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        assert_eq!(subscribed, "OKAY user:\n");
+        assert_eq!(
+            output,
+            "NOTIFY SET user:1\nNOTIFY DEL user:1\nNOTIFY SET user:2\nOKAY END\nOKAY user:2 c\n"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg(all(feature = "pubsub", feature = "expiry"))]
+    async fn psubscribe_notifies_expiry_as_removal() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        let (client, server) = io::duplex(1024);
+        let mut client = io::BufReader::new(client);
+        tokio::spawn(StoreService::new(framed(server), store.clone()).start());
+
+        client.write_all(b"PSUBSCRIBE user:\n").await.unwrap();
+        client.read_line(&mut String::new()).await.unwrap();
+
+        // Action.
+        store
+            .set_ex("user:1".into(), "a".into(), Duration::from_secs(1))
+            .await
+            .unwrap();
+        time::sleep(Duration::from_secs(2)).await;
+
+        client.write_all(b"\n").await.unwrap();
+        client.shutdown().await.unwrap();
+
+        // Post-condition.
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "NOTIFY SET user:1\nNOTIFY DEL user:1\nOKAY END\n");
+    }
+
+    #[tokio::test]
+    async fn key_beyond_max_key_bytes_is_refused_without_touching_store() {
+        // Pre-condition.
+        let store = inmemory::start();
+        let config = Config {
+            max_key_bytes: Some(4),
+            ..Config::default()
+        };
+
+        // Action.
+        let (output, _) = exchange_with_config(
+            store.clone(),
+            config,
+            b"SET abcd 1\nSET abcde 2\nGET abcd\nGET abcde\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY abcd\n\
+             ERR key of 5 bytes exceeds limit of 4 bytes\n\
+             OKAY abcd 1\n\
+             ERR key of 5 bytes exceeds limit of 4 bytes\n"
+        );
+        assert_eq!(store.get("abcde").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "pubsub")]
+    async fn subscriber_never_reading_is_disconnected_at_output_buffer_limit() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        let config = Config {
+            max_output_buffer: Some(256),
+            ..Config::default()
+        };
+        let (client, server) = io::duplex(64);
+        let mut client = io::BufReader::new(client);
+        let service =
+            tokio::spawn(StoreService::with_config(framed(server), store.clone(), config).start());
+
+        client.write_all(b"PSUBSCRIBE user:\n").await.unwrap();
+        let mut subscribed = String::new();
+        client.read_line(&mut subscribed).await.unwrap();
+
+        // Action.
+        for i in 0..2000 {
+            store.set(format!("user:{}", i), "a".into()).await.unwrap();
+        }
+
+        // Post-condition.
+        let outcome = time::timeout(Duration::from_secs(5), service)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(outcome.unwrap_err().is::<SlowConsumer>());
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        let notified = output
+            .lines()
+            .filter(|line| line.starts_with("NOTIFY"))
+            .count();
+        assert!(notified < 2000, "{}", notified);
+        assert!(!output.ends_with("OKAY END\n"));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "pubsub")]
+    async fn subscribe_expired_streams_keys_once_their_ttl_elapses() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        let (client, server) = io::duplex(1024);
+        let mut client = io::BufReader::new(client);
+        tokio::spawn(StoreService::new(framed(server), store.clone()).start());
+
+        client.write_all(b"SUBSCRIBE-EXPIRED\n").await.unwrap();
+        let mut subscribed = String::new();
+        client.read_line(&mut subscribed).await.unwrap();
+
+        // Action.
+        store
+            .set_ex("k".into(), "a".into(), Duration::from_millis(50))
+            .await
+            .unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+
+        // Post-condition.
+        let mut notification = String::new();
+        time::timeout(Duration::from_secs(5), client.read_line(&mut notification))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(subscribed, "OKAY\n");
+        assert_eq!(notification, "EXPIRED k\n");
+        assert_eq!(store.get("k").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "transactions", feature = "extended"))]
+    async fn read_only_server_refuses_mutations_only() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        let config = Config {
+            read_only: true,
+            ..Config::default()
+        };
+
+        // Action.
+        let (output, outcome) = exchange_with_config(
+            store.clone(),
+            config,
+            b"GET k\nSET k b\nGETDEL k\nMULTI\nSET j b\nEXEC\nGET k\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY k a\nERR read-only server\nERR read-only server\nOKAY\nERR read-only server\nOKAY 0\nOKAY k a\n"
+        );
+        assert!(outcome.is_ok(), "{:?}", outcome);
+        assert_eq!(store.get("j").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "session")]
+    async fn hello_with_supported_version_allows_commands() {
+        // Pre-condition.
+        let config = Config {
+            require_hello: true,
+            ..Config::default()
+        };
+
+        // Action.
+        let (output, outcome) =
+            exchange_with_config(inmemory::start(), config, b"HELLO 1\nSET k a\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY 1\nOKAY k\nOKAY k a\n");
+        assert!(outcome.is_ok(), "{:?}", outcome);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "session")]
+    async fn hello_with_unsupported_version_is_rejected() {
+        // Pre-condition.
+        let config = Config {
+            require_hello: true,
+            ..Config::default()
+        };
+
+        // Action.
+        let (output, outcome) =
+            exchange_with_config(inmemory::start(), config, b"HELLO 2\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "FAIL 1\n");
+        let err = outcome.unwrap_err();
+        assert!(err.to_string().contains("expected HELLO"), "{}", err);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "session")]
+    async fn commands_without_hello_are_served_by_default() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store, b"GET k\nHELLO 1\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "FAIL k\nOKAY 1\n");
+    }
+
+    #[tokio::test]
+    async fn malformed_request_is_answered_with_error_when_enabled() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        let config = Config {
+            reply_errors: true,
+            ..Config::default()
+        };
+
+        // Action.
+        let (output, outcome) = exchange_with_config(store, config, b"FOO\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "ERR unrecognized command: FOO\nOKAY k a\n");
+        assert!(outcome.is_ok(), "{:?}", outcome);
+    }
+
+    #[tokio::test]
+    async fn malformed_request_closes_connection_by_default() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let (output, outcome) =
+            exchange_with_config(store, Config::default(), b"FOO\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "");
+        assert!(outcome.is_err());
+    }
+
+    #[tokio::test]
+    async fn malformed_request_is_answered_with_error_before_closing_when_enabled() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        let config = Config {
+            error_before_close: true,
+            ..Config::default()
+        };
+
+        // Action.
+        let (output, outcome) = exchange_with_config(store, config, b"SET k a\nFOO\nGET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k\nERR unrecognized command: FOO\n");
+        assert!(outcome.is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "session", feature = "extended"))]
+    async fn authenticated_tenant_accesses_keys_within_its_prefix() {
+        // Pre-condition.
+        let config = tenants();
+
+        // Action.
+        let (output, outcome) = exchange_with_config(
+            inmemory::start(),
+            config,
+            b"AUTH secret-a\nSET a:k v\nGET a:k\nRENAME a:k a:j\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY\nOKAY a:k\nOKAY a:k v\nOKAY\n");
+        assert!(outcome.is_ok(), "{:?}", outcome);
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "session", feature = "extended"))]
+    async fn authenticated_tenant_is_denied_keys_outside_its_prefix() {
+        // Pre-condition.
+        let mut store = inmemory::start();
+        store.set("b:k".into(), "v".into()).await.unwrap();
+
+        // Action.
+        let (output, _) = exchange_with_config(
+            store.clone(),
+            tenants(),
+            b"AUTH secret-a\nGET b:k\nSET b:k w\nRENAME a:k b:k\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "OKAY\nERR access denied to key b:k\nERR access denied to key b:k\nERR access denied to key b:k\n"
+        );
+        assert_eq!(store.get("b:k").await.unwrap(), Some("v".into()));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "session")]
+    async fn unauthenticated_client_is_denied_every_key() {
+        // Pre-condition.
+        let config = tenants();
+
+        // Action.
+        let (output, _) =
+            exchange_with_config(inmemory::start(), config, b"AUTH wrong\nGET a:k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "FAIL\nERR access denied to key a:k\n");
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "session", feature = "admin"))]
+    async fn unauthenticated_client_is_denied_admin_commands() {
+        // Pre-condition.
+        let config = tenants();
+
+        // Action.
+        let (output, outcome) = exchange_with_config(
+            inmemory::start(),
+            config,
+            b"CONFIG SET command-timeout-ms 5\nDEADLINE 100 DBSIZE\nAUTH secret-a\nDBSIZE\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "ERR authentication required\nERR authentication required\nOKAY\nOKAY 0\n"
+        );
+        assert!(outcome.is_ok(), "{:?}", outcome);
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "transactions", feature = "expiry", feature = "extended"))]
+    async fn mutations_are_recorded_in_audit_log() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let config = Config {
+            audit: Some(AuditLog::open(&path, u64::MAX).await.unwrap()),
+            ..Config::default()
+        };
+
+        // Action.
+        exchange_with_config(
+            inmemory::start(),
+            config,
+            b"SET k a\nSETNX k b\nGET k\nMULTI\nRENAME k j\nSETNX j c\nEXEC\nRENAME k i\nEXPIRE k 60\n",
+        )
+        .await
+        .1
+        .unwrap();
+
+        // Post-condition.
+        let audit = tokio::fs::read_to_string(&path).await.unwrap();
+        let commands: Vec<_> = audit
+            .lines()
+            .map(|line| line.splitn(3, ' ').nth(2).unwrap())
+            .collect();
+        assert_eq!(commands, vec!["SET k", "RENAME k j"]);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "expiry")]
+    async fn expire_beyond_representable_instant_keeps_store_serving() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store.clone(),
+            b"SET k a\nEXPIRE k 18446744073709551615\nTTL k\nGET k\n",
+        )
+        .await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k\nOKAY k\nOKAY k -1\nOKAY k a\n");
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "expiry")]
+    async fn ttl_reports_remaining_seconds_or_persistence() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store,
+            b"SET k a\nEXPIRE k 60\nTTL k\nSET j b\nTTL j\nTTL i\n",
+        )
+        .await;
+
+        // Post-condition.
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[..2], ["OKAY k", "OKAY k"]);
+        assert!(
+            lines[2] == "OKAY k 60" || lines[2] == "OKAY k 59",
+            "{}",
+            lines[2]
+        );
+        assert_eq!(lines[3..], ["OKAY j", "OKAY j -1", "FAIL i"]);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "expiry")]
+    async fn get_with_ttl_reports_value_along_with_remaining_seconds() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(
+            store,
+            b"SETEX k 60 a\nGETTTL k\nSET j b\nGETTTL j\nGETTTL i\n",
+        )
+        .await;
+
+        // Post-condition.
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "OKAY k");
+        assert!(
+            lines[1] == "OKAY k 60 a" || lines[1] == "OKAY k 59 a",
+            "{}",
+            lines[1]
+        );
+        assert_eq!(lines[2..], ["OKAY j", "OKAY j -1 b", "FAIL i"]);
+    }
+
+    #[tokio::test]
+    async fn client_closing_before_reading_responses_is_a_disconnect() {
+        // Pre-condition.
+        let (mut client, server) = io::duplex(64);
+        client.write_all(b"SET k a\nGET k\n").await.unwrap();
+        drop(client);
+
+        let service = StoreService::new(framed(server), inmemory::start());
+
+        // Action.
+        let outcome = service.start().await;
+
+        // Post-condition.
+        assert!(outcome.is_ok(), "{:?}", outcome);
+    }
+
+    #[tokio::test]
+    async fn slow_store_operation_fails_once_timeout_elapses() {
+        // Pre-condition.
+        let config = Config {
+            command_timeout: Some(Duration::from_millis(10)),
+            ..Config::default()
+        };
+
+        // Action.
+        let (output, outcome) = time::timeout(
+            Duration::from_secs(5),
+            exchange_with_config(
+                SlowStore(Duration::from_secs(60)),
+                config,
+                b"GET k\nSET k a\n",
+            ),
+        )
+        .await
+        .expect("service should not block on a slow store");
+
+        // Post-condition.
+        assert_eq!(
+            output,
+            "ERR store operation timed out\nERR store operation timed out\n"
+        );
+        assert!(outcome.is_ok(), "{:?}", outcome);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "session")]
+    async fn request_within_deadline_is_answered_as_usual() {
+        // Pre-condition.
+        let store = inmemory::start();
+
+        // Action.
+        let output = exchange(store, b"DEADLINE 1000 SET k a\nDEADLINE 1000 GET k\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "OKAY k\nOKAY k a\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg(feature = "session")]
+    async fn request_exceeding_deadline_is_answered_with_error() {
+        // Pre-condition.
+        let store = SlowStore(Duration::from_secs(60));
+
+        // Action.
+        let output = exchange(store, b"DEADLINE 10 GET k\nDEADLINE 10 SET k a\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "ERR deadline exceeded\nERR deadline exceeded\n");
+    }
+
+    /// Configuration granting `secret-a` access to keys prefixed by `a:` and `secret-b` to `b:`.
+    #[cfg(feature = "session")]
+    fn tenants() -> Config {
+        let credentials = [("secret-a", "a:"), ("secret-b", "b:")]
+            .iter()
+            .map(|(password, prefix)| (password.to_string(), prefix.to_string()))
+            .collect();
+
+        Config {
+            credentials: Arc::new(credentials),
+            ..Config::default()
+        }
+    }
+
+    /// Sends `input` to a service backed by `store`, returning all it responds until EOF.
+    async fn exchange<S>(store: S, input: &[u8]) -> String
+    where
+        S: Store<Err = anyhow::Error> + Send + 'static,
+    {
+        let (output, outcome) = exchange_with_config(store, Config::default(), input).await;
+        outcome.unwrap();
+        output
+    }
+
+    /// Like [`exchange`], but configuring the service and returning how it finished as well.
+    async fn exchange_with_config<S>(store: S, config: Config, input: &[u8]) -> (String, Result<()>)
+    where
+        S: Store<Err = anyhow::Error> + Send + 'static,
+    {
+        let (mut client, server) = io::duplex(1024);
+        let service =
+            tokio::spawn(StoreService::with_config(framed(server), store, config).start());
+
+        client.write_all(input).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+
+        (output, service.await.unwrap())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn client_ignoring_ping_is_disconnected_after_deadline() {
+        // Pre-condition.
+        let (mut client, server) = io::duplex(1024);
+        let config = Config {
+            heartbeat: Some(Heartbeat {
+                interval: Duration::from_secs(10),
+                deadline: Duration::from_secs(5),
+            }),
+            ..Config::default()
+        };
+        let service = tokio::spawn(
+            StoreService::with_config(framed(server), inmemory::start(), config).start(),
+        );
+        let started = time::Instant::now();
+
+        // Action.
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(output, "PING\n");
+        assert_eq!(started.elapsed(), Duration::from_secs(15));
+        service.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg(feature = "session")]
+    async fn client_answering_ping_stays_connected() {
+        // Pre-condition.
+        let (client, server) = io::duplex(1024);
+        let mut client = io::BufReader::new(client);
+        let config = Config {
+            heartbeat: Some(Heartbeat {
+                interval: Duration::from_secs(10),
+                deadline: Duration::from_secs(5),
+            }),
+            ..Config::default()
+        };
+        tokio::spawn(StoreService::with_config(framed(server), inmemory::start(), config).start());
+
+        let mut ping = String::new();
+        client.read_line(&mut ping).await.unwrap();
+
+        // Action.
+        client.write_all(b"PONG\n").await.unwrap();
+        time::sleep(Duration::from_secs(8)).await;
+        client.write_all(b"GET k\n").await.unwrap();
+
+        // Post-condition.
+        let mut response = String::new();
+        client.read_line(&mut response).await.unwrap();
+        assert_eq!(ping, "PING\n");
+        assert_eq!(response, "FAIL k\n");
+    }
+
+    #[tokio::test]
+    async fn busy_store_is_answered_with_fail() {
+        // Pre-condition.
+        let store = BusyStore;
+
+        // Action.
+        let output = exchange(store, b"GET k\nSET k a\n").await;
+
+        // Post-condition.
+        assert_eq!(output, "FAIL BUSY\nFAIL BUSY\n");
+    }
+
+    #[tokio::test]
+    async fn pipelined_requests_are_handled_one_at_a_time() {
+        // Pre-condition.
+        let store = TrackedStore::default();
+        let peak = Arc::clone(&store.peak);
+        let input: String = (0..50)
+            .map(|i| format!("SET k{0} {0}\nGET k{0}\n", i))
+            .collect();
+
+        // Action.
+        let output = exchange(store, input.as_bytes()).await;
+
+        // Post-condition.
+        let expected: String = (0..50)
+            .map(|i| format!("OKAY k{0}\nOKAY k{0} {0}\n", i))
+            .collect();
+        assert_eq!(output, expected);
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn pipelined_responses_are_written_together() {
+        // Pre-condition.
+        let (mut client, server) = io::duplex(64 * 1024);
+        let server = CountingWriter::new(server);
+        let writes = Arc::clone(&server.writes);
+        let input: String = (0..50)
+            .map(|i| format!("SET k{0} {0}\nGET k{0}\n", i))
+            .collect();
+
+        // Action.
+        client.write_all(input.as_bytes()).await.unwrap();
+        client.shutdown().await.unwrap();
+        StoreService::new(framed(server), inmemory::start())
+            .start()
+            .await
+            .unwrap();
+
+        // Post-condition.
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        let expected: String = (0..50)
+            .map(|i| format!("OKAY k{0}\nOKAY k{0} {0}\n", i))
+            .collect();
+        assert_eq!(output, expected);
+        let writes = writes.load(Ordering::SeqCst);
+        assert!(writes < 10, "{} writes for 100 responses", writes);
+    }
+
+    #[tokio::test]
+    async fn response_is_written_once_client_pauses() {
+        // Pre-condition.
+        let (client, server) = io::duplex(1024);
+        let mut client = io::BufReader::new(client);
+        tokio::spawn(StoreService::new(framed(server), inmemory::start()).start());
+
+        // Action.
+        client.write_all(b"SET k a\nGET k\n").await.unwrap();
+
+        // Post-condition.
+        let mut line = String::new();
+        client.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "OKAY k\n");
+        line.clear();
+        client.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "OKAY k a\n");
+    }
+
+    /// Connection counting how many times it is written to.
+    struct CountingWriter<C> {
+        inner: C,
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl<C> CountingWriter<C> {
+        fn new(inner: C) -> Self {
+            Self {
+                inner,
+                writes: Arc::default(),
+            }
+        }
+    }
+
+    impl<C: AsyncRead + Unpin> AsyncRead for CountingWriter<C> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl<C: AsyncWrite + Unpin> AsyncWrite for CountingWriter<C> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(
+            mut self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    /// Store recording the most operations it ever had in flight at once.
+    #[derive(Debug, Default)]
+    struct TrackedStore {
+        inner: Mutex<HashMap<Key, Value>>,
+        in_flight: AtomicUsize,
+        peak: Arc<AtomicUsize>,
+    }
+
+    impl TrackedStore {
+        async fn tracked<T>(&self, operation: impl FnOnce() -> T) -> T {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(in_flight, Ordering::SeqCst);
+            // Gives any other operation a chance to overlap.
+            task::yield_now().await;
+            let output = operation();
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            output
+        }
+    }
+
+    #[async_trait]
+    impl Store for TrackedStore {
+        type Err = anyhow::Error;
+
+        async fn get<'k>(&self, key: KeyRef<'k>) -> Result<Option<Value>> {
+            Ok(self
+                .tracked(|| self.inner.lock().unwrap().get(key).cloned())
+                .await)
+        }
+
+        async fn set(&mut self, key: Key, value: Value) -> Result<()> {
+            self.tracked(|| self.inner.lock().unwrap().insert(key, value))
+                .await;
+            Ok(())
+        }
+
+        async fn set_nx(&mut self, _: Key, _: Value) -> Result<bool> {
+            Err(anyhow!("unsupported by tracked store"))
+        }
+
+        async fn rename(&mut self, _: Key, _: Key) -> Result<bool> {
+            Err(anyhow!("unsupported by tracked store"))
+        }
+
+        async fn get_del(&mut self, _: Key) -> Result<Option<Value>> {
+            Err(anyhow!("unsupported by tracked store"))
+        }
+
+        async fn transaction(&mut self, _: Vec<Operation>) -> Result<Vec<Outcome>> {
+            Err(anyhow!("unsupported by tracked store"))
+        }
+
+        async fn snapshot(&self) -> Result<Vec<(Key, Value)>> {
+            Err(anyhow!("unsupported by tracked store"))
+        }
+    }
+
+    #[derive(Debug)]
+    struct SlowStore(Duration);
+
+    #[async_trait]
+    impl Store for SlowStore {
+        type Err = anyhow::Error;
+
+        async fn get<'k>(&self, _: KeyRef<'k>) -> Result<Option<Value>> {
+            time::sleep(self.0).await;
+            Ok(None)
+        }
+
+        async fn set(&mut self, _: Key, _: Value) -> Result<()> {
+            time::sleep(self.0).await;
+            Ok(())
+        }
+
+        async fn set_nx(&mut self, _: Key, _: Value) -> Result<bool> {
+            time::sleep(self.0).await;
+            Ok(false)
+        }
+
+        async fn rename(&mut self, _: Key, _: Key) -> Result<bool> {
+            time::sleep(self.0).await;
+            Ok(false)
+        }
+
+        async fn get_del(&mut self, _: Key) -> Result<Option<Value>> {
+            time::sleep(self.0).await;
+            Ok(None)
+        }
+
+        async fn transaction(&mut self, _: Vec<Operation>) -> Result<Vec<Outcome>> {
+            time::sleep(self.0).await;
+            Ok(Vec::new())
+        }
+
+        async fn snapshot(&self) -> Result<Vec<(Key, Value)>> {
+            time::sleep(self.0).await;
+            Ok(Vec::new())
+        }
+    }
+
+    #[derive(Debug)]
+    struct BusyStore;
+
+    #[async_trait]
+    impl Store for BusyStore {
+        type Err = anyhow::Error;
+
+        async fn get<'k>(&self, _: KeyRef<'k>) -> Result<Option<Value>> {
+            Err(Busy.into())
+        }
+
+        async fn set(&mut self, _: Key, _: Value) -> Result<()> {
+            Err(Busy.into())
+        }
+
+        async fn set_nx(&mut self, _: Key, _: Value) -> Result<bool> {
+            Err(Busy.into())
+        }
+
+        async fn rename(&mut self, _: Key, _: Key) -> Result<bool> {
+            Err(Busy.into())
+        }
+
+        async fn get_del(&mut self, _: Key) -> Result<Option<Value>> {
+            Err(Busy.into())
+        }
+
+        async fn transaction(&mut self, _: Vec<Operation>) -> Result<Vec<Outcome>> {
+            Err(Busy.into())
+        }
+
+        async fn snapshot(&self) -> Result<Vec<(Key, Value)>> {
+            Err(Busy.into())
+        }
     }
 }
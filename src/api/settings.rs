@@ -0,0 +1,133 @@
+//! Effective settings of a server, as queried by `CONFIG GET`, some of which
+//! may be adjusted while it runs by `CONFIG SET`.
+
+use anyhow::{anyhow, bail, Result};
+use std::{collections::BTreeMap, fmt::Display, str::FromStr, sync::RwLock, time::Duration};
+
+/// Settings by name, shared between the server and every connection.
+#[derive(Debug)]
+pub struct Settings {
+    /// Settings fixed for as long as the server runs.
+    fixed: BTreeMap<&'static str, String>,
+    tunable: RwLock<Tunable>,
+}
+
+/// Settings that are safe to adjust while the server runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tunable {
+    /// Upper bound on how long a single store operation may take, unbounded if unset.
+    pub command_timeout: Option<Duration>,
+    /// Upper bound on how many connections a single peer IP may have open, unbounded if unset.
+    pub max_connections_per_ip: Option<usize>,
+}
+
+impl Settings {
+    /// Names every setting after the matching command-line option, an unset one being `none`.
+    pub fn new(fixed: BTreeMap<&'static str, String>, tunable: Tunable) -> Self {
+        Self {
+            fixed,
+            tunable: RwLock::new(tunable),
+        }
+    }
+
+    /// Returns the current value of the tunable settings.
+    pub fn tunable(&self) -> Tunable {
+        *self.tunable.read().unwrap()
+    }
+
+    /// Returns the value of the setting named `param`, if any.
+    pub fn get(&self, param: &str) -> Option<String> {
+        let tunable = self.tunable();
+        match param {
+            "command-timeout-ms" => Some(or_none(tunable.command_timeout.map(|t| t.as_millis()))),
+            "max-connections-per-ip" => Some(or_none(tunable.max_connections_per_ip)),
+            _ => self.fixed.get(param).cloned(),
+        }
+    }
+
+    /// Sets the tunable setting named `param` to `value`, `none` unsetting it.
+    pub fn set(&self, param: &str, value: &str) -> Result<()> {
+        let mut tunable = self.tunable.write().unwrap();
+        match param {
+            "command-timeout-ms" => {
+                tunable.command_timeout = parse_or_none(param, value)?.map(Duration::from_millis)
+            }
+            "max-connections-per-ip" => {
+                tunable.max_connections_per_ip = parse_or_none(param, value)?
+            }
+            _ if self.fixed.contains_key(param) => bail!("{} cannot be set at runtime", param),
+            _ => bail!("unknown setting {}", param),
+        }
+        Ok(())
+    }
+}
+
+/// Formats `setting`, or `none` if unset.
+pub fn or_none<T: ToString>(setting: Option<T>) -> String {
+    setting.map_or_else(|| "none".into(), |setting| setting.to_string())
+}
+
+fn parse_or_none<T>(param: &str, value: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match value {
+        "none" => Ok(None),
+        value => parse(param, value).map(Some),
+    }
+}
+
+fn parse<T>(param: &str, value: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| anyhow!("malformed value {} for {}: {}", value, param, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_adjusts_tunable_setting_only() {
+        // Pre-condition.
+        let settings = Settings::new(
+            BTreeMap::from([
+                ("validation", "lenient".to_owned()),
+                ("read-only", "true".to_owned()),
+            ]),
+            Tunable::default(),
+        );
+
+        // Action.
+        let timeout = settings.set("command-timeout-ms", "250");
+        let read_only = settings.set("read-only", "false");
+        let fixed = settings.set("validation", "strict");
+        let unknown = settings.set("unknown", "1");
+        let malformed = settings.set("max-connections-per-ip", "many");
+
+        // Post-condition.
+        assert!(timeout.is_ok());
+        assert!(read_only.is_err());
+        assert!(fixed.is_err());
+        assert!(unknown.is_err());
+        assert!(malformed.is_err());
+
+        assert_eq!(
+            settings.tunable(),
+            Tunable {
+                command_timeout: Some(Duration::from_millis(250)),
+                max_connections_per_ip: None,
+            }
+        );
+        assert_eq!(settings.get("command-timeout-ms"), Some("250".into()));
+        assert_eq!(settings.get("validation"), Some("lenient".into()));
+        assert_eq!(settings.get("read-only"), Some("true".into()));
+        assert_eq!(settings.get("max-connections-per-ip"), Some("none".into()));
+        assert_eq!(settings.get("unknown"), None);
+    }
+}
@@ -1,28 +1,760 @@
 //! Request/Response for API interaction.
 
+use super::{clients::ClientInfo, codec::ValueEncoding};
+use crate::storage::types::{Capabilities, Extremum, LogEntry, Mutation, Value};
+use std::net::SocketAddr;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Request {
-    Get { key: String },
-    Set { key: String, value: String },
+    Get {
+        key: String,
+    },
+    GetDel {
+        key: String,
+    },
+    GetRange {
+        key: String,
+        start: i64,
+        end: i64,
+    },
+    /// Waits for `key` to be set unless it exists, giving up after `timeout_ms`.
+    BGet {
+        key: String,
+        timeout_ms: u64,
+    },
+    GetOr {
+        key: String,
+        default: String,
+    },
+    Set {
+        key: String,
+        value: String,
+    },
+    /// `SET` answered with the value displaced, as requested by a trailing `GET`.
+    SetGet {
+        key: String,
+        value: String,
+    },
+    SetNx {
+        key: String,
+        value: String,
+    },
+    /// Sets `value` only if the current value starts with `pattern`.
+    SetIfMatch {
+        key: String,
+        pattern: String,
+        value: String,
+    },
+    /// Removes `key` only if its value equals `expected`.
+    DelIfEquals {
+        key: String,
+        expected: String,
+    },
+    /// Sets `value` unless a `SETIDEM` carrying `token` was applied recently.
+    SetIdem {
+        key: String,
+        token: String,
+        value: String,
+    },
+    /// Sets every key of `pairs` to its value only if none of them exists.
+    MSetNx {
+        pairs: Vec<(String, String)>,
+    },
+    Rename {
+        src: String,
+        dst: String,
+    },
+    Multi,
+    Exec,
+    Discard,
+    /// Aborts the next `EXEC` if any of `keys` changes in the meantime.
+    Watch {
+        keys: Vec<String>,
+    },
+    Unwatch,
+    IdleTime {
+        key: String,
+    },
+    /// Lists up to `count` keys, those read or written most recently first.
+    HotKeys {
+        count: usize,
+    },
+    /// Fetches the CRC32 of the value under `key`, e.g. to tell whether it changed.
+    Checksum {
+        key: String,
+    },
+    Hello {
+        version: u32,
+    },
+    Auth {
+        password: String,
+    },
+    /// Switches how values are written and read back on this connection.
+    Encoding {
+        encoding: ValueEncoding,
+    },
+    Expire {
+        key: String,
+        seconds: u64,
+    },
+    Ttl {
+        key: String,
+    },
+    GetWithTtl {
+        key: String,
+    },
+    SetEx {
+        key: String,
+        seconds: u64,
+        value: String,
+    },
+    IncrBy {
+        key: String,
+        delta: i64,
+    },
+    LPush {
+        key: String,
+        item: String,
+    },
+    LLen {
+        key: String,
+    },
+    /// Holds back `data` as chunk `seq` of the value to set under `key` on `SETCHUNKEND`.
+    SetChunk {
+        key: String,
+        seq: u64,
+        data: String,
+    },
+    SetChunkEnd {
+        key: String,
+    },
+    /// Sets `key` to `candidate` if it is the `extremum` of both, as `MAX` or `MIN`.
+    SetExtremum {
+        key: String,
+        extremum: Extremum,
+        candidate: i64,
+    },
+    Dump,
+    /// Fetches every pair whose key starts with `prefix`.
+    GetPrefix {
+        prefix: String,
+    },
+    /// Lists the size of the value under every key starting with `prefix`, largest first.
+    MemUsage {
+        prefix: String,
+    },
+    /// Lists the distinct segments following `prefix` up to `separator` among keys under it.
+    Children {
+        prefix: String,
+        separator: String,
+    },
+    /// Lists every key whose value contains `substring`, scanning every value.
+    FindVal {
+        substring: String,
+    },
+    /// Streams every mutation of a key under `prefix` until the client sends anything else.
+    PSubscribe {
+        prefix: String,
+    },
+    /// Streams the key of every item removed once expired until the client sends anything else.
+    SubscribeExpired,
+    Compact,
+    /// Makes every write applied so far durable before answering.
+    Sync,
+    Fill {
+        prefix: String,
+        count: usize,
+    },
+    DbSize,
+    /// Lists the number of keys in every named store selected so far with `USE`, sorted by name.
+    DbList,
+    /// Lists the optional features the store supports.
+    Capabilities,
+    LastSeq,
+    /// Fetches the last `count` mutations recorded in the operation log, oldest first.
+    GetLog {
+        count: usize,
+    },
+    Info,
+    Time,
+    Use {
+        name: String,
+    },
+    ClientList,
+    ClientKill {
+        peer_addr: SocketAddr,
+    },
+    ConfigGet {
+        param: String,
+    },
+    ConfigSet {
+        param: String,
+        value: String,
+    },
+    /// Answer to a `PING` sent by the server.
+    Pong,
+    /// Answers `request` with an error unless its store operations complete within `timeout_ms`.
+    Deadline {
+        timeout_ms: u64,
+        request: Box<Request>,
+    },
+}
+
+impl Request {
+    /// Keys this request reads or writes.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Request::Get { key }
+            | Request::GetDel { key }
+            | Request::GetRange {
+                key,
+                start: _,
+                end: _,
+            }
+            | Request::GetOr { key, default: _ }
+            | Request::BGet { key, timeout_ms: _ }
+            | Request::Set { key, value: _ }
+            | Request::SetGet { key, value: _ }
+            | Request::SetNx { key, value: _ }
+            | Request::SetIfMatch {
+                key,
+                pattern: _,
+                value: _,
+            }
+            | Request::SetIdem {
+                key,
+                token: _,
+                value: _,
+            }
+            | Request::DelIfEquals { key, expected: _ }
+            | Request::IdleTime { key }
+            | Request::Checksum { key }
+            | Request::Expire { key, seconds: _ }
+            | Request::SetEx {
+                key,
+                seconds: _,
+                value: _,
+            }
+            | Request::Ttl { key }
+            | Request::GetWithTtl { key }
+            | Request::IncrBy { key, delta: _ }
+            | Request::LPush { key, item: _ }
+            | Request::LLen { key }
+            | Request::SetChunk {
+                key,
+                seq: _,
+                data: _,
+            }
+            | Request::SetChunkEnd { key }
+            | Request::SetExtremum {
+                key,
+                extremum: _,
+                candidate: _,
+            }
+            | Request::Fill {
+                prefix: key,
+                count: _,
+            } => vec![key],
+            Request::Rename { src, dst } => vec![src, dst],
+            Request::MSetNx { pairs } => pairs.iter().map(|(key, _)| key.as_str()).collect(),
+            Request::Watch { keys } => keys.iter().map(String::as_str).collect(),
+            Request::Deadline {
+                timeout_ms: _,
+                request,
+            } => request.keys(),
+            Request::Multi
+            | Request::Exec
+            | Request::Discard
+            | Request::Unwatch
+            | Request::Dump
+            | Request::GetPrefix { prefix: _ }
+            | Request::MemUsage { prefix: _ }
+            | Request::Children {
+                prefix: _,
+                separator: _,
+            }
+            | Request::FindVal { substring: _ }
+            | Request::PSubscribe { prefix: _ }
+            | Request::SubscribeExpired
+            | Request::Compact
+            | Request::Sync
+            | Request::DbSize
+            | Request::DbList
+            | Request::Capabilities
+            | Request::LastSeq
+            | Request::GetLog { count: _ }
+            | Request::HotKeys { count: _ }
+            | Request::Info
+            | Request::Time
+            | Request::Use { name: _ }
+            | Request::ClientList
+            | Request::ClientKill { peer_addr: _ }
+            | Request::ConfigGet { param: _ }
+            | Request::ConfigSet { param: _, value: _ }
+            | Request::Pong
+            | Request::Hello { version: _ }
+            | Request::Auth { password: _ }
+            | Request::Encoding { encoding: _ } => vec![],
+        }
+    }
+
+    /// Whether this request may wait on something other than the store, e.g. `BGET`.
+    pub fn blocks(&self) -> bool {
+        match self {
+            Request::Deadline {
+                timeout_ms: _,
+                request,
+            } => request.blocks(),
+            Request::BGet {
+                key: _,
+                timeout_ms: _,
+            }
+            | Request::PSubscribe { prefix: _ }
+            | Request::SubscribeExpired => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this request acts on the server or the whole store rather than on given keys,
+    /// e.g. `CONFIG SET`.
+    pub fn administers(&self) -> bool {
+        if let Request::Deadline {
+            timeout_ms: _,
+            request,
+        } = self
+        {
+            return request.administers();
+        }
+        matches!(
+            self,
+            Request::Dump
+                | Request::Compact
+                | Request::Sync
+                | Request::DbSize
+                | Request::DbList
+                | Request::LastSeq
+                | Request::Info
+                | Request::Time
+                | Request::ClientList
+                | Request::ClientKill { peer_addr: _ }
+                | Request::ConfigGet { param: _ }
+                | Request::ConfigSet { param: _, value: _ }
+                | Request::Use { name: _ }
+                | Request::MemUsage { prefix: _ }
+                | Request::FindVal { substring: _ }
+                | Request::GetLog { count: _ }
+                | Request::Capabilities
+        )
+    }
+
+    /// Whether this request writes to the store.
+    pub fn mutates(&self) -> bool {
+        if let Request::Deadline {
+            timeout_ms: _,
+            request,
+        } = self
+        {
+            return request.mutates();
+        }
+        matches!(
+            self,
+            Request::GetDel { key: _ }
+                | Request::Set { key: _, value: _ }
+                | Request::SetGet { key: _, value: _ }
+                | Request::SetNx { key: _, value: _ }
+                | Request::SetIfMatch {
+                    key: _,
+                    pattern: _,
+                    value: _,
+                }
+                | Request::SetIdem {
+                    key: _,
+                    token: _,
+                    value: _,
+                }
+                | Request::MSetNx { pairs: _ }
+                | Request::DelIfEquals {
+                    key: _,
+                    expected: _,
+                }
+                | Request::Rename { src: _, dst: _ }
+                | Request::Expire { key: _, seconds: _ }
+                | Request::IncrBy { key: _, delta: _ }
+                | Request::LPush { key: _, item: _ }
+                | Request::SetChunkEnd { key: _ }
+                | Request::SetExtremum {
+                    key: _,
+                    extremum: _,
+                    candidate: _,
+                }
+                | Request::SetEx {
+                    key: _,
+                    seconds: _,
+                    value: _,
+                }
+                | Request::Fill {
+                    prefix: _,
+                    count: _,
+                }
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Response {
-    Get { key: String, value: Option<String> },
-    Set { key: String },
+    Get {
+        key: String,
+        value: Option<Value>,
+    },
+    GetDel {
+        key: String,
+        value: Option<Value>,
+    },
+    GetRange {
+        key: String,
+        value: Option<Value>,
+    },
+    Set {
+        key: String,
+    },
+    /// Value displaced by a `SET`, if any.
+    SetGet {
+        key: String,
+        value: Option<Value>,
+    },
+    SetNx {
+        key: String,
+        set: bool,
+    },
+    SetIfMatch {
+        key: String,
+        set: bool,
+    },
+    DelIfEquals {
+        key: String,
+        deleted: bool,
+    },
+    /// Acknowledges a `SETIDEM`, whether `applied` or deduplicated.
+    SetIdem {
+        key: String,
+        applied: bool,
+    },
+    /// Tells whether every value was set, none being set otherwise.
+    MSetNx {
+        set: bool,
+    },
+    Rename {
+        ok: bool,
+    },
+    Multi {
+        ok: bool,
+    },
+    Queued,
+    /// Probe sent by the server to a client that has been idle.
+    Ping,
+    /// Marks the end of the pairs streamed by `DUMP`.
+    DumpEnd,
+    /// Pairs whose key starts with the prefix requested, sorted by key.
+    GetPrefix {
+        pairs: Vec<(String, Value)>,
+    },
+    /// Size of the value under every key starting with the prefix requested, largest first.
+    MemUsage {
+        sizes: Vec<(String, usize)>,
+    },
+    /// Segments following the prefix requested among keys under it, sorted.
+    Children {
+        segments: Vec<String>,
+    },
+    /// Keys whose value contains the substring requested, sorted.
+    FindVal {
+        keys: Vec<String>,
+    },
+    /// Acknowledges a subscription to mutations of keys under `prefix`.
+    PSubscribe {
+        prefix: String,
+    },
+    /// Mutation streamed to a subscriber.
+    Notify {
+        mutation: Mutation,
+    },
+    /// Acknowledges a subscription to expiries.
+    SubscribeExpired,
+    /// Marks the end of the mutations streamed by `PSUBSCRIBE` or `SUBSCRIBE-EXPIRED`.
+    SubscriptionEnd,
+    Exec {
+        responses: Option<Vec<Response>>,
+    },
+    Discard {
+        ok: bool,
+    },
+    Watch {
+        ok: bool,
+    },
+    Unwatch,
+    /// A watched key changed before `EXEC`, hence none of the queued commands was applied.
+    Aborted,
+    IdleTime {
+        key: String,
+        seconds: Option<u64>,
+    },
+    /// Keys read or written most recently, most recent first.
+    HotKeys {
+        keys: Vec<String>,
+    },
+    Checksum {
+        key: String,
+        hash: Option<u32>,
+    },
+    Hello {
+        version: u32,
+        ok: bool,
+    },
+    Auth {
+        ok: bool,
+    },
+    Encoding {
+        encoding: ValueEncoding,
+    },
+    Use {
+        ok: bool,
+    },
+    /// Connected clients, unless the server keeps no registry of them.
+    ClientList {
+        clients: Option<Vec<ClientInfo>>,
+    },
+    ClientKill {
+        ok: bool,
+    },
+    /// Effective value of `param`, unless there is no such setting.
+    ConfigGet {
+        param: String,
+        value: Option<String>,
+    },
+    ConfigSet {
+        param: String,
+    },
+    Expire {
+        key: String,
+        ok: bool,
+    },
+    /// Remaining seconds before `key` expires, `-1` if it never does.
+    Ttl {
+        key: String,
+        seconds: Option<i64>,
+    },
+    /// Value along with its remaining seconds before expiring, `-1` if it never does.
+    GetWithTtl {
+        key: String,
+        value: Option<Value>,
+        ttl_secs: Option<i64>,
+    },
+    /// Resulting integer, unless the value is not one or the result would overflow.
+    IncrBy {
+        key: String,
+        value: Option<i64>,
+    },
+    /// Resulting integer, unless the value is not one.
+    SetExtremum {
+        key: String,
+        value: Option<i64>,
+    },
+    /// Number of items of the list under `key`, as answered to `LPUSH` and `LLEN`.
+    ListLen {
+        key: String,
+        len: usize,
+    },
+    /// Sequence number of the chunk expected instead, unless the chunk was held back.
+    SetChunk {
+        key: String,
+        expected: Option<u64>,
+    },
+    /// Whether any chunk was held back, to be set as the value under `key`.
+    SetChunkEnd {
+        key: String,
+        ok: bool,
+    },
+    Compact,
+    Sync,
+    Fill {
+        created: usize,
+    },
+    DbSize {
+        size: usize,
+    },
+    /// Number of keys in every named store, sorted by name.
+    DbList {
+        sizes: Vec<(String, usize)>,
+    },
+    /// Optional features the store supports.
+    Capabilities {
+        capabilities: Capabilities,
+    },
+    LastSeq {
+        seq: u64,
+    },
+    /// Mutations recorded in the operation log, oldest first.
+    GetLog {
+        entries: Vec<LogEntry>,
+    },
+    /// Diagnostics of the server, as named fields.
+    Info {
+        fields: Vec<(String, String)>,
+    },
+    /// Clock of the server, as seconds since the Unix epoch, and how long it has been up.
+    Time {
+        unix_secs: u64,
+        uptime_secs: u64,
+    },
+    /// The store could not accept the command in time.
+    Busy,
+    Error {
+        message: String,
+    },
 }
 
 impl Response {
     pub(super) fn status(&self) -> Status {
         match self {
-            Response::Get { key: _, value } => {
+            Response::Get { key: _, value }
+            | Response::GetDel { key: _, value }
+            | Response::GetRange { key: _, value }
+            | Response::SetGet { key: _, value } => {
                 if value.is_some() {
                     Status::Okay
                 } else {
                     Status::Fail
                 }
             }
-            Response::Set { key: _ } => Status::Okay,
+            Response::Set { key: _ }
+            | Response::SetIdem { key: _, applied: _ }
+            | Response::ConfigSet { param: _ }
+            | Response::Encoding { encoding: _ }
+            | Response::ListLen { key: _, len: _ }
+            | Response::Unwatch => Status::Okay,
+            Response::SetNx { key: _, set }
+            | Response::SetIfMatch { key: _, set }
+            | Response::DelIfEquals {
+                key: _,
+                deleted: set,
+            }
+            | Response::Expire { key: _, ok: set } => {
+                if *set {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
+            Response::Rename { ok }
+            | Response::Multi { ok }
+            | Response::Discard { ok }
+            | Response::Watch { ok }
+            | Response::Hello { version: _, ok }
+            | Response::Auth { ok }
+            | Response::MSetNx { set: ok }
+            | Response::SetChunkEnd { key: _, ok }
+            | Response::Use { ok }
+            | Response::ClientKill { ok } => {
+                if *ok {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
+            Response::Queued
+            | Response::DumpEnd
+            | Response::GetPrefix { pairs: _ }
+            | Response::Children { segments: _ }
+            | Response::FindVal { keys: _ }
+            | Response::HotKeys { keys: _ }
+            | Response::MemUsage { sizes: _ }
+            | Response::PSubscribe { prefix: _ }
+            | Response::Notify { mutation: _ }
+            | Response::SubscriptionEnd
+            | Response::SubscribeExpired
+            | Response::Ping
+            | Response::Compact
+            | Response::Sync
+            | Response::Fill { created: _ }
+            | Response::DbSize { size: _ }
+            | Response::DbList { sizes: _ }
+            | Response::Capabilities { capabilities: _ }
+            | Response::LastSeq { seq: _ }
+            | Response::GetLog { entries: _ }
+            | Response::Info { fields: _ }
+            | Response::Time {
+                unix_secs: _,
+                uptime_secs: _,
+            } => Status::Okay,
+            Response::Busy | Response::Aborted => Status::Fail,
+            Response::Error { message: _ } => Status::Error,
+            Response::IdleTime { key: _, seconds } => {
+                if seconds.is_some() {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
+            Response::SetChunk { key: _, expected } => {
+                if expected.is_none() {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
+            Response::Checksum { key: _, hash } => {
+                if hash.is_some() {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
+            Response::IncrBy { key: _, value } | Response::SetExtremum { key: _, value } => {
+                if value.is_some() {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
+            Response::GetWithTtl {
+                key: _,
+                value,
+                ttl_secs: _,
+            } => {
+                if value.is_some() {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
+            Response::Ttl { key: _, seconds } => {
+                if seconds.is_some() {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
+            Response::ConfigGet { param: _, value } => {
+                if value.is_some() {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
+            Response::ClientList { clients } => {
+                if clients.is_some() {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
+            Response::Exec { responses } => {
+                if responses.is_some() {
+                    Status::Okay
+                } else {
+                    Status::Fail
+                }
+            }
         }
     }
 }
@@ -31,4 +763,7 @@ impl Response {
 pub(super) enum Status {
     Okay,
     Fail,
+    Error,
+    /// Missing key, in place of `Fail` when the codec answers so.
+    Nil,
 }
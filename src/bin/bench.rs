@@ -0,0 +1,55 @@
+use anyhow::Result;
+use std::{net::SocketAddr, time::Duration};
+use structopt::StructOpt;
+use toy_storage::{
+    api::bench::{self, Options},
+    config::parse_address,
+};
+
+#[derive(StructOpt)]
+struct Opts {
+    /// Address of the server to benchmark, e.g. `127.0.0.1:8080` or `[::1]:8080`.
+    #[structopt(short, long, default_value = "127.0.0.1:8080", parse(try_from_str = parse_address))]
+    address: SocketAddr,
+
+    /// Connections issuing requests concurrently.
+    #[structopt(short, long, default_value = "8")]
+    connections: usize,
+
+    /// Issue requests for this many seconds.
+    #[structopt(short, long, default_value = "10")]
+    duration_secs: u64,
+
+    /// Target of requests per second across every connection, as fast as possible if absent.
+    #[structopt(short, long)]
+    rate: Option<u64>,
+
+    /// Fraction of requests that are SETs, the others being GETs.
+    #[structopt(long, default_value = "0.2")]
+    set_ratio: f64,
+
+    /// Number of distinct keys requested.
+    #[structopt(long, default_value = "1000")]
+    keys: u64,
+
+    /// Size of the values set, in bytes.
+    #[structopt(long, default_value = "64")]
+    value_bytes: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opts = Opts::from_args();
+    let options = Options {
+        connections: opts.connections,
+        duration: Duration::from_secs(opts.duration_secs),
+        rate: opts.rate,
+        set_ratio: opts.set_ratio,
+        keys: opts.keys,
+        value_bytes: opts.value_bytes,
+    };
+
+    let report = bench::run(opts.address, &options).await?;
+    println!("{}", report);
+    Ok(())
+}
@@ -0,0 +1,424 @@
+//! Server configuration, as loaded from a TOML file.
+//!
+//! Every field mirrors the command-line option of the same name, which
+//! overrides it, e.g.:
+//!
+//! ```toml
+//! addresses = ["127.0.0.1:8080", "[::1]:8080"]
+//! command-timeout-ms = 500
+//! validation = "strict"
+//! wal = "/var/lib/toy-storage/wal"
+//!
+//! [credentials]
+//! secret-a = "a:"
+//! ```
+
+use crate::{
+    api::{
+        audit::AuditLog,
+        codec::{LineEnding, MissingResponse, Validation},
+        follower::FollowerTask,
+        health::HealthServer,
+        Server,
+    },
+    storage::{
+        inmemory::{self, InMemoryConfig},
+        NoopObserver, Store,
+    },
+};
+use anyhow::{anyhow, Context, Result};
+use serde::{de, Deserialize, Deserializer};
+use std::{
+    collections::HashMap, fmt::Display, fs, io, net::SocketAddr, path::Path, path::PathBuf,
+    str::FromStr, sync::Arc, time::Duration,
+};
+use tokio::net::TcpListener;
+use tracing::info;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Addresses to listen at.
+    pub addresses: Vec<SocketAddr>,
+    /// Whether to serve on the socket passed by systemd via socket activation instead of
+    /// listening at `addresses`.
+    pub systemd: bool,
+    /// Address to answer liveness and readiness probes over HTTP at.
+    pub health_address: Option<SocketAddr>,
+    /// Upper bound on how long a single store operation may take, in milliseconds.
+    pub command_timeout_ms: Option<u64>,
+    /// Whether clients must negotiate the protocol version via `HELLO` before any other command.
+    pub require_hello: bool,
+    /// Whether malformed requests are answered with an `ERR` line instead of closing the connection.
+    pub reply_errors: bool,
+    /// Whether a request that cannot be decoded is answered with an `ERR` line before closing.
+    pub error_before_close: bool,
+    /// Whether requests writing to the store are refused.
+    pub read_only: bool,
+    /// Address of a leader whose writes are applied to the store, which is then read-only.
+    pub follow: Option<SocketAddr>,
+    /// Upper bound on how long connections may take to finish on shutdown, in seconds.
+    pub shutdown_grace_secs: Option<u64>,
+    /// Upper bound on how many connections a single peer IP may have open.
+    pub max_connections_per_ip: Option<usize>,
+    /// Size beyond which a response is replaced by an error, in bytes.
+    pub max_response_bytes: Option<usize>,
+    /// Size beyond which a subscriber slow to read is disconnected, in bytes.
+    pub max_output_buffer_bytes: Option<usize>,
+    /// Size beyond which a key is refused, in bytes.
+    pub max_key_bytes: Option<usize>,
+    /// Number of accept loops run concurrently on each listener.
+    pub accept_workers: usize,
+    /// Key prefix granted by each `AUTH` password.
+    pub credentials: HashMap<String, String>,
+    /// File recording every mutation applied on behalf of clients.
+    pub audit_log: Option<PathBuf>,
+    /// Size beyond which the audit log is rotated, in bytes.
+    pub audit_log_max_bytes: u64,
+    /// File the final metrics are written to as JSON on shutdown.
+    pub metrics_dump: Option<PathBuf>,
+    /// Which characters keys and values may contain.
+    #[serde(deserialize_with = "from_str")]
+    pub validation: Validation,
+    /// Terminator of response lines.
+    #[serde(deserialize_with = "from_str")]
+    pub line_ending: LineEnding,
+    /// Status answering a `GET` of a missing key.
+    #[serde(deserialize_with = "from_str")]
+    pub missing_response: MissingResponse,
+    /// Upper bound on how many keys the in-memory store may hold.
+    pub max_keys: Option<usize>,
+    /// Longest time the in-memory store may hold back a `SET` to coalesce it with later ones, in
+    /// milliseconds.
+    pub coalesce_window_ms: Option<u64>,
+    /// Number of latest mutations the in-memory store keeps for `GETLOG`.
+    pub op_log_capacity: usize,
+    /// File logging every mutation to make in-memory data durable.
+    pub wal: Option<PathBuf>,
+    /// Time between periodic compactions of the write-ahead log, in seconds.
+    pub wal_compact_interval_secs: Option<u64>,
+    /// Directory of a sled database persisting data instead of memory.
+    #[cfg(feature = "sled")]
+    pub data_dir: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addresses: vec![SocketAddr::from(([127, 0, 0, 1], 8080))],
+            systemd: false,
+            health_address: None,
+            command_timeout_ms: None,
+            require_hello: false,
+            reply_errors: false,
+            error_before_close: false,
+            read_only: false,
+            follow: None,
+            shutdown_grace_secs: None,
+            max_connections_per_ip: None,
+            max_response_bytes: None,
+            max_output_buffer_bytes: None,
+            max_key_bytes: None,
+            accept_workers: 1,
+            credentials: HashMap::new(),
+            audit_log: None,
+            audit_log_max_bytes: 10 * 1024 * 1024,
+            metrics_dump: None,
+            validation: Validation::default(),
+            line_ending: LineEnding::default(),
+            missing_response: MissingResponse::default(),
+            max_keys: None,
+            coalesce_window_ms: None,
+            op_log_capacity: InMemoryConfig::default().op_log_capacity,
+            wal: None,
+            wal_compact_interval_secs: None,
+            #[cfg(feature = "sled")]
+            data_dir: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration from the TOML file at `path`, defaulting every missing field.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("unable to read config at {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("unable to parse config at {}", path.display()))
+    }
+
+    /// Binds every address, unless adopting the socket passed by systemd, and sets up a server
+    /// for `store` accordingly.
+    pub async fn server<S>(&self, store: S) -> Result<Server<S>>
+    where
+        S: Store<Err = anyhow::Error> + Clone + Send + Sync + 'static,
+    {
+        let mut server = if self.systemd {
+            info!("listening on socket passed by systemd");
+            Server::from_std(systemd_listener()?, store)
+                .context("unable to listen on socket passed by systemd")?
+        } else {
+            let (first, rest) = self
+                .addresses
+                .split_first()
+                .context("missing address to listen at")?;
+
+            let mut server = Server::new(bind(*first).await?, store);
+            for addr in rest {
+                server = server.listen_on(bind(*addr).await?);
+            }
+            server
+        };
+
+        server = server
+            .validation(self.validation)
+            .line_ending(self.line_ending)
+            .missing_response(self.missing_response)
+            .require_hello(self.require_hello)
+            .reply_errors(self.reply_errors)
+            .error_before_close(self.error_before_close)
+            .read_only(self.read_only || self.follow.is_some())
+            .credentials(self.credentials.clone());
+
+        if let Some(path) = &self.audit_log {
+            info!("auditing mutations to {}", path.display());
+            server = server.audit_log(AuditLog::open(path, self.audit_log_max_bytes).await?);
+        }
+
+        if let Some(path) = &self.metrics_dump {
+            server = server.metrics_dump(path);
+        }
+
+        if let Some(timeout) = self.command_timeout_ms {
+            server = server.command_timeout(Duration::from_millis(timeout));
+        }
+
+        if let Some(grace) = self.shutdown_grace_secs {
+            server = server.shutdown_grace(Duration::from_secs(grace));
+        }
+
+        if let Some(max) = self.max_connections_per_ip {
+            server = server.max_connections_per_ip(max);
+        }
+
+        if let Some(max) = self.max_response_bytes {
+            server = server.max_response_bytes(max);
+        }
+
+        if let Some(max) = self.max_output_buffer_bytes {
+            server = server.max_output_buffer(max);
+        }
+
+        if let Some(max) = self.max_key_bytes {
+            server = server.max_key_bytes(max);
+        }
+
+        server = server.accept_workers(self.accept_workers);
+
+        Ok(server)
+    }
+
+    /// Starts an in-memory store accordingly.
+    pub fn inmemory(&self) -> inmemory::Store {
+        let config = InMemoryConfig {
+            max_keys: self.max_keys,
+            coalesce_window: self.coalesce_window_ms.map(Duration::from_millis),
+            op_log_capacity: self.op_log_capacity,
+            ..InMemoryConfig::default()
+        };
+        inmemory::start_with_config(config, Arc::new(NoopObserver))
+    }
+
+    /// Sets up the application of the writes of the leader to follow, if any, to `store`.
+    pub fn follower<S>(&self, store: S) -> Option<FollowerTask<S>>
+    where
+        S: Store<Err = anyhow::Error>,
+    {
+        self.follow.map(|leader| {
+            info!("following leader at {}", leader);
+            FollowerTask::new(leader, store)
+        })
+    }
+
+    /// Binds the health address, if any, and sets up probes of `store` there.
+    pub async fn health_server<S>(&self, store: S) -> Result<Option<HealthServer<S>>>
+    where
+        S: Store<Err = anyhow::Error> + Clone + Send + Sync + 'static,
+    {
+        match self.health_address {
+            Some(addr) => Ok(Some(HealthServer::new(bind(addr).await?, store))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Parses an address to listen at, telling what is expected instead of a malformed one.
+pub fn parse_address(s: &str) -> Result<SocketAddr> {
+    s.parse().map_err(|_| {
+        anyhow!(
+            "invalid address '{}': expected host:port, e.g. 127.0.0.1:8080 or [::1]:8080",
+            s
+        )
+    })
+}
+
+async fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    info!("listening at {}", addr);
+
+    TcpListener::bind(addr).await.map_err(|e| match e.kind() {
+        io::ErrorKind::AddrInUse => anyhow!(
+            "unable to listen at {}: port {} already in use",
+            addr,
+            addr.port()
+        ),
+        _ => anyhow::Error::new(e).context(format!("unable to listen at {}", addr)),
+    })
+}
+
+/// Adopts the socket passed by systemd via socket activation, i.e. file descriptor 3.
+#[cfg(unix)]
+fn systemd_listener() -> Result<std::net::TcpListener> {
+    use anyhow::ensure;
+    use std::{
+        env,
+        os::unix::io::{FromRawFd, RawFd},
+        process,
+    };
+
+    const LISTEN_FDS_START: RawFd = 3;
+
+    let pid = env::var("LISTEN_PID").context("missing LISTEN_PID from systemd")?;
+    ensure!(
+        pid == process::id().to_string(),
+        "sockets passed by systemd are meant for process {}",
+        pid
+    );
+    let fds = env::var("LISTEN_FDS").context("missing LISTEN_FDS from systemd")?;
+    ensure!(fds == "1", "expected one socket from systemd, got {}", fds);
+
+    // SAFETY: systemd passes the socket as the first descriptor past stdio, owned by nothing else.
+    Ok(unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) })
+}
+
+#[cfg(not(unix))]
+fn systemd_listener() -> Result<std::net::TcpListener> {
+    anyhow::bail!("socket activation is only supported on unix")
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::inmemory;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpStream,
+    };
+
+    #[tokio::test]
+    async fn server_listens_at_address_from_file() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "addresses = [\"127.0.0.1:0\"]\ncommand-timeout-ms = 250\nvalidation = \"strict\"\n",
+        )
+        .unwrap();
+
+        // Action.
+        let config = Config::load(&path).unwrap();
+        let server = config.server(inmemory::start()).await.unwrap();
+        let addrs = server.local_addrs().unwrap();
+        tokio::spawn(server.start());
+
+        // Post-condition.
+        assert_eq!(config.command_timeout_ms, Some(250));
+        assert_eq!(config.validation, Validation::Strict);
+        assert_eq!(
+            config.audit_log_max_bytes,
+            Config::default().audit_log_max_bytes
+        );
+
+        assert_eq!(addrs.len(), 1);
+        assert!(addrs[0].ip().is_loopback());
+
+        let mut client = BufReader::new(TcpStream::connect(addrs[0]).await.unwrap());
+        client.write_all(b"GET k\n").await.unwrap();
+        let mut response = String::new();
+        client.read_line(&mut response).await.unwrap();
+        assert_eq!(response, "FAIL k\n");
+    }
+
+    #[test]
+    fn unknown_field_is_rejected() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "adresses = [\"127.0.0.1:0\"]\n").unwrap();
+
+        // Action.
+        let config = Config::load(&path);
+
+        // Post-condition.
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn malformed_address_is_rejected_telling_expected_format() {
+        // Action.
+        let cases = vec!["foo", "127.0.0.1", "localhost:8080", "127.0.0.1:http"];
+
+        // Post-condition.
+        for address in cases {
+            let e = parse_address(address).unwrap_err();
+            assert!(
+                e.to_string().starts_with(&format!(
+                    "invalid address '{}': expected host:port",
+                    address
+                )),
+                "{}",
+                e
+            );
+        }
+        assert_eq!(
+            parse_address("[::1]:8080").unwrap(),
+            SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 8080))
+        );
+    }
+
+    #[tokio::test]
+    async fn address_in_use_is_reported_as_such() {
+        // Pre-condition.
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+        let config = Config {
+            addresses: vec![addr],
+            ..Config::default()
+        };
+
+        // Action.
+        let e = config.server(inmemory::start()).await.err().unwrap();
+
+        // Post-condition.
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "unable to listen at {}: port {} already in use",
+                addr,
+                addr.port()
+            )
+        );
+    }
+}
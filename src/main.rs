@@ -1,13 +1,216 @@
-use anyhow::Result;
+#[cfg(any(feature = "sled", not(feature = "session")))]
+use anyhow::ensure;
+use anyhow::{Context, Result};
+use futures::future;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 use structopt::StructOpt;
-use tokio::net::TcpListener;
-use toy_storage::{api::Server, storage::inmemory};
-use tracing::info;
+use tokio::{fs::File, signal};
+#[cfg(feature = "sled")]
+use toy_storage::storage::sled_store::SledStore;
+use toy_storage::{
+    api::{
+        bulk,
+        codec::{LineEnding, MissingResponse, Validation},
+    },
+    config::{parse_address, Config},
+    storage::{
+        wal::{WalConfig, WalStore},
+        Store,
+    },
+};
+use tracing::{error, info};
 
 #[derive(StructOpt)]
 struct Opts {
-    #[structopt(short, long, default_value = "127.0.0.1:8080")]
-    address: String,
+    /// Read options from this TOML file, which the options below override.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Address to listen at, e.g. `127.0.0.1:8080` or `[::1]:8080`; repeat to listen at several
+    /// [default: 127.0.0.1:8080].
+    #[structopt(short, long = "address", parse(try_from_str = parse_address))]
+    addresses: Vec<SocketAddr>,
+
+    /// Serve on the socket passed by systemd via socket activation (file descriptor 3) instead of
+    /// listening at any address.
+    #[structopt(long)]
+    systemd: bool,
+
+    /// Answer liveness (`/livez`) and readiness (`/readyz`) probes over HTTP at this address.
+    #[structopt(long, parse(try_from_str = parse_address))]
+    health_address: Option<SocketAddr>,
+
+    /// Fail store operations that take longer than this many milliseconds.
+    #[structopt(long)]
+    command_timeout_ms: Option<u64>,
+
+    /// Require clients to negotiate the protocol version via HELLO before any other command.
+    #[structopt(long)]
+    require_hello: bool,
+
+    /// Answer malformed requests with an ERR line instead of closing the connection.
+    #[structopt(long)]
+    reply_errors: bool,
+
+    /// Answer a request that cannot be decoded with an ERR line before closing the connection.
+    #[structopt(long)]
+    error_before_close: bool,
+
+    /// Refuse requests writing to the store, e.g. SET, GETDEL, or RENAME.
+    #[structopt(long)]
+    read_only: bool,
+
+    /// Serve as a read-only replica of the leader at this address, applying its writes locally.
+    #[structopt(long)]
+    follow: Option<SocketAddr>,
+
+    /// On shutdown (Ctrl-C), abort connections still open after this many seconds instead of
+    /// awaiting them forever.
+    #[structopt(long)]
+    shutdown_grace_secs: Option<u64>,
+
+    /// Refuse connections from a peer IP that already has this many open.
+    #[structopt(long)]
+    max_connections_per_ip: Option<usize>,
+
+    /// Answer with an error instead of any response larger than this many bytes, e.g. a
+    /// GETPREFIX matching a huge number of keys.
+    #[structopt(long)]
+    max_response_bytes: Option<usize>,
+
+    /// Disconnect a subscriber slow to read once the notifications yet to be sent to it take
+    /// more than this many bytes, instead of holding further ones back.
+    #[structopt(long)]
+    max_output_buffer_bytes: Option<usize>,
+
+    /// Answer requests reading or writing a key longer than this many bytes with an error.
+    #[structopt(long)]
+    max_key_bytes: Option<usize>,
+
+    /// Run this many accept loops concurrently on each listener [default: 1].
+    #[structopt(long)]
+    accept_workers: Option<usize>,
+
+    /// Require clients to AUTH with this password to access keys starting with this prefix, as
+    /// `password=prefix`; repeat to admit several tenants.
+    #[structopt(long = "credential", parse(try_from_str = parse_credential))]
+    credentials: Vec<(String, String)>,
+
+    /// Record every mutation applied on behalf of clients in this file.
+    #[structopt(long, parse(from_os_str))]
+    audit_log: Option<PathBuf>,
+
+    /// Rotate the audit log once it would grow beyond this many bytes [default: 10485760].
+    #[structopt(long)]
+    audit_log_max_bytes: Option<u64>,
+
+    /// On shutdown, write the final metrics, e.g. command counts, as JSON to this file.
+    #[structopt(long, parse(from_os_str))]
+    metrics_dump: Option<PathBuf>,
+
+    /// Which characters keys and values may contain: `lenient` or `strict` [default: lenient].
+    #[structopt(long)]
+    validation: Option<Validation>,
+
+    /// Terminator of response lines: `lf` or `crlf` [default: lf].
+    #[structopt(long)]
+    line_ending: Option<LineEnding>,
+
+    /// Status answering a `GET` of a missing key: `fail` or `nil` [default: fail].
+    #[structopt(long)]
+    missing_response: Option<MissingResponse>,
+
+    /// Refuse writes creating keys once the in-memory store holds this many.
+    #[structopt(long)]
+    max_keys: Option<usize>,
+
+    /// Hold SET commands back for up to this many milliseconds, applying only the latest to each key.
+    #[structopt(long)]
+    coalesce_window_ms: Option<u64>,
+
+    /// Keep this many of the latest mutations for GETLOG [default: 1024].
+    #[structopt(long)]
+    op_log_capacity: Option<usize>,
+
+    /// Apply the SET commands in this file to the store, then exit.
+    #[structopt(long, parse(from_os_str), conflicts_with = "export")]
+    import: Option<PathBuf>,
+
+    /// Write every key in the store as a SET command to this file, then exit.
+    #[structopt(long, parse(from_os_str))]
+    export: Option<PathBuf>,
+
+    /// Copy every key in the store into a sled database at this directory, then exit.
+    #[cfg(feature = "sled")]
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["import", "export"])]
+    migrate_to: Option<PathBuf>,
+
+    /// Make in-memory data durable by logging every mutation to this file.
+    #[structopt(long, parse(from_os_str))]
+    wal: Option<PathBuf>,
+
+    /// Compact the write-ahead log every this many seconds, discarding superseded records.
+    #[structopt(long)]
+    wal_compact_interval_secs: Option<u64>,
+
+    /// Persist data in a sled database at this directory instead of in memory.
+    #[cfg(feature = "sled")]
+    #[structopt(long, parse(from_os_str), conflicts_with = "wal")]
+    data_dir: Option<PathBuf>,
+}
+
+impl Opts {
+    /// Loads the configuration file, if any, overriding it with the options given.
+    fn config(&self) -> Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        if !self.addresses.is_empty() {
+            config.addresses = self.addresses.clone();
+        }
+        config.systemd |= self.systemd;
+        config.health_address = self.health_address.or(config.health_address);
+        config.command_timeout_ms = self.command_timeout_ms.or(config.command_timeout_ms);
+        config.require_hello |= self.require_hello;
+        config.reply_errors |= self.reply_errors;
+        config.error_before_close |= self.error_before_close;
+        config.read_only |= self.read_only;
+        config.follow = self.follow.or(config.follow);
+        config.shutdown_grace_secs = self.shutdown_grace_secs.or(config.shutdown_grace_secs);
+        config.max_connections_per_ip = self
+            .max_connections_per_ip
+            .or(config.max_connections_per_ip);
+        config.max_response_bytes = self.max_response_bytes.or(config.max_response_bytes);
+        config.max_output_buffer_bytes = self
+            .max_output_buffer_bytes
+            .or(config.max_output_buffer_bytes);
+        config.max_key_bytes = self.max_key_bytes.or(config.max_key_bytes);
+        config.accept_workers = self.accept_workers.unwrap_or(config.accept_workers);
+        config.credentials.extend(self.credentials.iter().cloned());
+        config.audit_log = self.audit_log.clone().or(config.audit_log);
+        config.audit_log_max_bytes = self
+            .audit_log_max_bytes
+            .unwrap_or(config.audit_log_max_bytes);
+        config.metrics_dump = self.metrics_dump.clone().or(config.metrics_dump);
+        config.validation = self.validation.unwrap_or(config.validation);
+        config.line_ending = self.line_ending.unwrap_or(config.line_ending);
+        config.missing_response = self.missing_response.unwrap_or(config.missing_response);
+        config.max_keys = self.max_keys.or(config.max_keys);
+        config.coalesce_window_ms = self.coalesce_window_ms.or(config.coalesce_window_ms);
+        config.op_log_capacity = self.op_log_capacity.unwrap_or(config.op_log_capacity);
+        config.wal = self.wal.clone().or(config.wal);
+        config.wal_compact_interval_secs = self
+            .wal_compact_interval_secs
+            .or(config.wal_compact_interval_secs);
+        #[cfg(feature = "sled")]
+        {
+            config.data_dir = self.data_dir.clone().or(config.data_dir);
+        }
+
+        Ok(config)
+    }
 }
 
 #[tokio::main]
@@ -20,17 +223,91 @@ async fn main() -> Result<()> {
 }
 
 async fn run_with(opts: Opts) -> Result<()> {
-    info!("listening at {}", opts.address);
+    let config = opts.config()?;
+
+    // Clients could never satisfy either without HELLO and AUTH.
+    #[cfg(not(feature = "session"))]
+    ensure!(
+        !config.require_hello && config.credentials.is_empty(),
+        "requiring HELLO or AUTH needs a build with the session feature"
+    );
+
+    #[cfg(feature = "sled")]
+    if let Some(dir) = &config.data_dir {
+        ensure!(
+            config.wal.is_none(),
+            "a write-ahead log and a sled database are mutually exclusive"
+        );
+        info!("persisting data at {}", dir.display());
+        return run(SledStore::open(dir)?, config, opts).await;
+    }
+
+    if let Some(path) = &config.wal {
+        info!("logging mutations to {}", path.display());
+        let wal_config = WalConfig {
+            compact_interval: config.wal_compact_interval_secs.map(Duration::from_secs),
+            ..WalConfig::default()
+        };
+        let store = WalStore::open(path, config.inmemory(), wal_config).await?;
+        return run(store, config, opts).await;
+    }
+
+    let store = config.inmemory();
+    run(store, config, opts).await
+}
 
-    let listener = TcpListener::bind(opts.address).await?;
+async fn run<S>(mut store: S, config: Config, opts: Opts) -> Result<()>
+where
+    S: Store<Err = anyhow::Error> + Clone + Send + Sync + 'static,
+{
+    if let Some(path) = &opts.import {
+        let imported = bulk::import(File::open(path).await?, &mut store).await?;
+        info!("imported {} keys from {}", imported, path.display());
+        return Ok(());
+    }
 
-    let store = inmemory::start();
+    if let Some(path) = &opts.export {
+        let exported = bulk::export(File::create(path).await?, &store).await?;
+        info!("exported {} keys to {}", exported, path.display());
+        return Ok(());
+    }
 
-    Server::new(listener, store).start().await;
+    #[cfg(feature = "sled")]
+    if let Some(dir) = &opts.migrate_to {
+        let migrated = bulk::migrate(&store, &mut SledStore::open(dir)?).await?;
+        info!("migrated {} keys to {}", migrated, dir.display());
+        return Ok(());
+    }
+
+    if let Some(follower) = config.follower(store.clone()) {
+        tokio::spawn(follower.start());
+    }
+
+    if let Some(health) = config.health_server(store.clone()).await? {
+        tokio::spawn(health.start());
+    }
+
+    config
+        .server(store)
+        .await?
+        .start_until(async {
+            if let Err(e) = signal::ctrl_c().await {
+                error!(reason = %e, "unable to listen for shutdown signal");
+                future::pending::<()>().await;
+            }
+        })
+        .await;
 
     Ok(())
 }
 
+fn parse_credential(s: &str) -> Result<(String, String)> {
+    let (password, prefix) = s
+        .split_once('=')
+        .context("credential should be formatted as password=prefix")?;
+    Ok((password.into(), prefix.into()))
+}
+
 fn init_logger() {
     tracing_subscriber::fmt().init()
 }
@@ -0,0 +1,346 @@
+//! Transparent compression of large values for an inner store.
+//!
+//! Every value is stored behind a one-byte tag telling how it was encoded, so
+//! that values remain readable after the threshold or codec change.
+//!
+//! - RAW (value below the threshold)
+//!     - `0x00 $value`
+//! - DEFLATE
+//!     - `0x01 $deflated_value`
+//! - GZIP
+//!     - `0x02 $gzipped_value`
+
+use super::{
+    types::{
+        Capabilities, Expiry, Key, KeyRef, LogEntry, Mutation, Operation, Outcome, QueueDepth,
+        Value,
+    },
+    Store,
+};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use flate2::{
+    write::{DeflateDecoder, DeflateEncoder, GzDecoder, GzEncoder},
+    Compression,
+};
+use std::{io::Write, time::Duration};
+use tokio::sync::broadcast;
+
+const RAW: u8 = 0x00;
+const DEFLATE: u8 = 0x01;
+const GZIP: u8 = 0x02;
+
+/// Algorithm compressing values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Deflate,
+    Gzip,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionConfig {
+    /// Size from which values are compressed, in bytes.
+    pub threshold: usize,
+    pub codec: Codec,
+    /// Compression level, from 0 (fastest) to 9 (smallest).
+    pub level: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 1024,
+            codec: Codec::Deflate,
+            level: 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompressingStore<S> {
+    inner: S,
+    config: CompressionConfig,
+}
+
+impl<S> CompressingStore<S> {
+    pub fn new(inner: S, config: CompressionConfig) -> Self {
+        Self { inner, config }
+    }
+
+    fn encode(&self, value: Value) -> Result<Value> {
+        if value.len() < self.config.threshold {
+            return Ok([&[RAW], value.as_ref()].concat().into());
+        }
+
+        let level = Compression::new(self.config.level);
+        let encoded = match self.config.codec {
+            Codec::Deflate => {
+                let mut encoder = DeflateEncoder::new(vec![DEFLATE], level);
+                encoder.write_all(&value)?;
+                encoder.finish()?
+            }
+            Codec::Gzip => {
+                let mut encoder = GzEncoder::new(vec![GZIP], level);
+                encoder.write_all(&value)?;
+                encoder.finish()?
+            }
+        };
+        Ok(encoded.into())
+    }
+
+    fn encode_op(&self, op: Operation) -> Result<Operation> {
+        Ok(match op {
+            Operation::Set { key, value } => Operation::Set {
+                key,
+                value: self.encode(value)?,
+            },
+            Operation::SetNx { key, value } => Operation::SetNx {
+                key,
+                value: self.encode(value)?,
+            },
+            op => op,
+        })
+    }
+}
+
+fn decode(value: Value) -> Result<Value> {
+    let (tag, payload) = value.split_first().context("missing compression tag")?;
+    let decoded = match *tag {
+        RAW => return Ok(value.slice(1..)),
+        DEFLATE => {
+            let mut decoder = DeflateDecoder::new(Vec::new());
+            decoder.write_all(payload)?;
+            decoder.finish()?
+        }
+        GZIP => {
+            let mut decoder = GzDecoder::new(Vec::new());
+            decoder.write_all(payload)?;
+            decoder.finish()?
+        }
+        tag => bail!("unknown compression tag: {:#04x}", tag),
+    };
+    Ok(decoded.into())
+}
+
+fn decode_outcome(outcome: Outcome) -> Result<Outcome> {
+    Ok(match outcome {
+        Outcome::Value(value) => Outcome::Value(value.map(decode).transpose()?),
+        outcome => outcome,
+    })
+}
+
+#[async_trait]
+impl<S> Store for CompressingStore<S>
+where
+    S: Store<Err = anyhow::Error>,
+{
+    type Err = anyhow::Error;
+
+    async fn get<'k>(&self, key: KeyRef<'k>) -> Result<Option<Value>, Self::Err> {
+        self.inner.get(key).await?.map(decode).transpose()
+    }
+
+    async fn set(&mut self, key: Key, value: Value) -> Result<(), Self::Err> {
+        let value = self.encode(value)?;
+        self.inner.set(key, value).await
+    }
+
+    async fn set_nx(&mut self, key: Key, value: Value) -> Result<bool, Self::Err> {
+        let value = self.encode(value)?;
+        self.inner.set_nx(key, value).await
+    }
+
+    async fn mset_nx(&mut self, pairs: Vec<(Key, Value)>) -> Result<bool, Self::Err> {
+        let pairs = pairs
+            .into_iter()
+            .map(|(key, value)| Ok((key, self.encode(value)?)))
+            .collect::<Result<_>>()?;
+        self.inner.mset_nx(pairs).await
+    }
+
+    async fn rename(&mut self, src: Key, dst: Key) -> Result<bool, Self::Err> {
+        self.inner.rename(src, dst).await
+    }
+
+    async fn get_del(&mut self, key: Key) -> Result<Option<Value>, Self::Err> {
+        self.inner.get_del(key).await?.map(decode).transpose()
+    }
+
+    async fn transaction(&mut self, ops: Vec<Operation>) -> Result<Vec<Outcome>, Self::Err> {
+        let ops = ops
+            .into_iter()
+            .map(|op| self.encode_op(op))
+            .collect::<Result<_>>()?;
+        self.inner
+            .transaction(ops)
+            .await?
+            .into_iter()
+            .map(decode_outcome)
+            .collect()
+    }
+
+    async fn version<'k>(&self, key: KeyRef<'k>) -> Result<u64, Self::Err> {
+        self.inner.version(key).await
+    }
+
+    async fn transaction_if_unchanged(
+        &mut self,
+        watched: Vec<(Key, u64)>,
+        ops: Vec<Operation>,
+    ) -> Result<Option<Vec<Outcome>>, Self::Err> {
+        let ops = ops
+            .into_iter()
+            .map(|op| self.encode_op(op))
+            .collect::<Result<_>>()?;
+        self.inner
+            .transaction_if_unchanged(watched, ops)
+            .await?
+            .map(|outcomes| outcomes.into_iter().map(decode_outcome).collect())
+            .transpose()
+    }
+
+    async fn snapshot(&self) -> Result<Vec<(Key, Value)>, Self::Err> {
+        self.inner
+            .snapshot()
+            .await?
+            .into_iter()
+            .map(|(key, value)| Ok((key, decode(value)?)))
+            .collect()
+    }
+
+    async fn get_or_wait<'k>(
+        &self,
+        key: KeyRef<'k>,
+        timeout: Duration,
+    ) -> Result<Option<Value>, Self::Err> {
+        self.inner
+            .get_or_wait(key, timeout)
+            .await?
+            .map(decode)
+            .transpose()
+    }
+
+    async fn queue_depth(&self) -> Result<QueueDepth, Self::Err> {
+        self.inner.queue_depth().await
+    }
+
+    async fn db_size(&self) -> Result<usize, Self::Err> {
+        self.inner.db_size().await
+    }
+
+    async fn last_seq(&self) -> Result<u64, Self::Err> {
+        self.inner.last_seq().await
+    }
+
+    async fn get_log(&self, count: usize) -> Result<Vec<LogEntry>, Self::Err> {
+        self.inner.get_log(count).await
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn ping(&self) -> Result<(), Self::Err> {
+        self.inner.ping().await
+    }
+
+    async fn compact(&mut self) -> Result<(), Self::Err> {
+        self.inner.compact().await
+    }
+
+    async fn sync(&self) -> Result<(), Self::Err> {
+        self.inner.sync().await
+    }
+
+    /// Hashes the value as written, rather than as stored.
+    async fn checksum<'k>(&self, key: KeyRef<'k>) -> Result<Option<u32>, Self::Err> {
+        let value = self.get(key).await?;
+        Ok(value.map(|value| crc32fast::hash(&value)))
+    }
+
+    /// Sizes values as stored, i.e. compressed, rather than as written.
+    async fn mem_usage<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, usize)>, Self::Err> {
+        self.inner.mem_usage(prefix).await
+    }
+
+    async fn idle_time<'k>(&self, key: KeyRef<'k>) -> Result<Option<Duration>, Self::Err> {
+        self.inner.idle_time(key).await
+    }
+
+    async fn hot_keys(&self, count: usize) -> Result<Vec<Key>, Self::Err> {
+        self.inner.hot_keys(count).await
+    }
+
+    async fn expire(&mut self, key: Key, ttl: Duration) -> Result<bool, Self::Err> {
+        self.inner.expire(key, ttl).await
+    }
+
+    async fn set_ex(&mut self, key: Key, value: Value, ttl: Duration) -> Result<(), Self::Err> {
+        let value = self.encode(value)?;
+        self.inner.set_ex(key, value, ttl).await
+    }
+
+    async fn ttl<'k>(&self, key: KeyRef<'k>) -> Result<Option<Expiry>, Self::Err> {
+        self.inner.ttl(key).await
+    }
+
+    async fn subscribe(&self) -> Result<broadcast::Receiver<Mutation>, Self::Err> {
+        self.inner.subscribe().await
+    }
+
+    async fn get_with_ttl<'k>(
+        &self,
+        key: KeyRef<'k>,
+    ) -> Result<Option<(Value, Expiry)>, Self::Err> {
+        self.inner
+            .get_with_ttl(key)
+            .await?
+            .map(|(value, expiry)| Ok((decode(value)?, expiry)))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::inmemory;
+
+    #[tokio::test]
+    async fn large_value_round_trips_compressed() {
+        for codec in [Codec::Deflate, Codec::Gzip] {
+            // Pre-condition.
+            let inner = inmemory::start();
+            let config = CompressionConfig {
+                codec,
+                ..CompressionConfig::default()
+            };
+            let mut store = CompressingStore::new(inner.clone(), config);
+            let value = Value::from("toy storage ".repeat(1024));
+
+            // Action.
+            store.set("k".into(), value.clone()).await.unwrap();
+
+            // Post-condition.
+            assert_eq!(store.get("k").await.unwrap(), Some(value.clone()));
+            let stored = inner.get("k").await.unwrap().unwrap();
+            assert!(stored.len() < value.len(), "{:?}", codec);
+        }
+    }
+
+    #[tokio::test]
+    async fn small_value_is_stored_uncompressed() {
+        // Pre-condition.
+        let inner = inmemory::start();
+        let mut store = CompressingStore::new(inner.clone(), CompressionConfig::default());
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+        assert_eq!(
+            inner.get("k").await.unwrap(),
+            Some(b"\x00a".as_ref().into())
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! Writes mirrored into two stores, e.g. to migrate from one to the other
+//! without downtime.
+//!
+//! Reads are served by the primary store only. Every write is applied to the
+//! primary first, then its effect is replayed onto the secondary, e.g. a
+//! `SETNX` rejected by the primary is not applied to the secondary at all.
+//! Writes are not serialized across both stores, hence concurrent writes to
+//! the same key may land in a different order on the secondary.
+//!
+//! Operations beyond reads, sets, renames, removals, and transactions are
+//! unsupported.
+
+use super::{
+    types::{Capabilities, Key, KeyRef, Operation, Outcome, Value},
+    Store,
+};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use tracing::error;
+
+/// What becomes of a write applied to the primary store but failing on the secondary one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SecondaryFailure {
+    /// The write fails, despite having been applied to the primary store.
+    #[default]
+    Fail,
+    /// The failure is logged, the write succeeding all the same.
+    Ignore,
+}
+
+#[derive(Debug, Clone)]
+pub struct DualStore<A, B> {
+    primary: A,
+    secondary: B,
+    on_secondary_failure: SecondaryFailure,
+}
+
+impl<A, B> DualStore<A, B> {
+    /// Reads from `primary`, mirroring writes into `secondary`.
+    pub fn new(primary: A, secondary: B) -> Self {
+        Self {
+            primary,
+            secondary,
+            on_secondary_failure: SecondaryFailure::default(),
+        }
+    }
+
+    /// Handles writes failing on the secondary store according to `policy`.
+    pub fn on_secondary_failure(mut self, policy: SecondaryFailure) -> Self {
+        self.on_secondary_failure = policy;
+        self
+    }
+
+    /// Settles the outcome of mirroring a write onto the secondary store.
+    fn mirrored(&self, outcome: Result<()>) -> Result<()> {
+        match (outcome, self.on_secondary_failure) {
+            (Ok(()), _) => Ok(()),
+            (Err(e), SecondaryFailure::Fail) => {
+                Err(e.context("unable to mirror write to secondary store"))
+            }
+            (Err(e), SecondaryFailure::Ignore) => {
+                error!(reason = %e, "unable to mirror write to secondary store");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Returns the operations replaying the effect `outcomes` of `ops` had, skipping the rest.
+fn effective(ops: Vec<Operation>, outcomes: &[Outcome]) -> Result<Vec<Operation>> {
+    if ops.len() != outcomes.len() {
+        bail!("expected {} outcomes, got {}", ops.len(), outcomes.len());
+    }
+
+    let effective = ops
+        .into_iter()
+        .zip(outcomes)
+        .filter_map(|(op, outcome)| match (op, outcome) {
+            (Operation::Set { key, value }, _)
+            | (Operation::SetNx { key, value }, Outcome::Applied(true)) => {
+                Some(Operation::Set { key, value })
+            }
+            (op @ Operation::Rename { src: _, dst: _ }, Outcome::Applied(true))
+            | (op @ Operation::GetDel { key: _ }, Outcome::Value(Some(_))) => Some(op),
+            _ => None,
+        })
+        .collect();
+    Ok(effective)
+}
+
+#[async_trait]
+impl<A, B> Store for DualStore<A, B>
+where
+    A: Store<Err = anyhow::Error>,
+    B: Store<Err = anyhow::Error>,
+{
+    type Err = anyhow::Error;
+
+    async fn get<'k>(&self, key: KeyRef<'k>) -> Result<Option<Value>, Self::Err> {
+        self.primary.get(key).await
+    }
+
+    async fn set(&mut self, key: Key, value: Value) -> Result<(), Self::Err> {
+        self.primary.set(key.clone(), value.clone()).await?;
+        let mirrored = self.secondary.set(key, value).await;
+        self.mirrored(mirrored)
+    }
+
+    async fn get_range<'k>(
+        &self,
+        key: KeyRef<'k>,
+        start: i64,
+        end: i64,
+    ) -> Result<Option<Value>, Self::Err> {
+        self.primary.get_range(key, start, end).await
+    }
+
+    async fn set_nx(&mut self, key: Key, value: Value) -> Result<bool, Self::Err> {
+        let set = self.primary.set_nx(key.clone(), value.clone()).await?;
+        if set {
+            let mirrored = self.secondary.set(key, value).await;
+            self.mirrored(mirrored)?;
+        }
+        Ok(set)
+    }
+
+    async fn rename(&mut self, src: Key, dst: Key) -> Result<bool, Self::Err> {
+        let renamed = self.primary.rename(src.clone(), dst.clone()).await?;
+        if renamed {
+            let mirrored = self.secondary.rename(src, dst).await.map(drop);
+            self.mirrored(mirrored)?;
+        }
+        Ok(renamed)
+    }
+
+    async fn get_del(&mut self, key: Key) -> Result<Option<Value>, Self::Err> {
+        let value = self.primary.get_del(key.clone()).await?;
+        if value.is_some() {
+            let mirrored = self.secondary.get_del(key).await.map(drop);
+            self.mirrored(mirrored)?;
+        }
+        Ok(value)
+    }
+
+    async fn transaction(&mut self, ops: Vec<Operation>) -> Result<Vec<Outcome>, Self::Err> {
+        let outcomes = self.primary.transaction(ops.clone()).await?;
+        let ops = effective(ops, &outcomes).context("unable to mirror transaction")?;
+        if !ops.is_empty() {
+            let mirrored = self.secondary.transaction(ops).await.map(drop);
+            self.mirrored(mirrored)?;
+        }
+        Ok(outcomes)
+    }
+
+    async fn checksum<'k>(&self, key: KeyRef<'k>) -> Result<Option<u32>, Self::Err> {
+        self.primary.checksum(key).await
+    }
+
+    async fn snapshot(&self) -> Result<Vec<(Key, Value)>, Self::Err> {
+        self.primary.snapshot().await
+    }
+
+    async fn get_prefix<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, Value)>, Self::Err> {
+        self.primary.get_prefix(prefix).await
+    }
+
+    async fn mem_usage<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, usize)>, Self::Err> {
+        self.primary.mem_usage(prefix).await
+    }
+
+    async fn children<'p>(
+        &self,
+        prefix: KeyRef<'p>,
+        separator: &'p str,
+    ) -> Result<Vec<String>, Self::Err> {
+        self.primary.children(prefix, separator).await
+    }
+
+    async fn find_value<'n>(&self, needle: &'n [u8]) -> Result<Vec<Key>, Self::Err> {
+        self.primary.find_value(needle).await
+    }
+
+    async fn db_size(&self) -> Result<usize, Self::Err> {
+        self.primary.db_size().await
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // Writes land in both stores, hence are durable as soon as either store makes them so.
+        let (primary, secondary) = (self.primary.capabilities(), self.secondary.capabilities());
+        Capabilities {
+            sync: primary.sync || secondary.sync,
+            scan: primary.scan,
+            ..Capabilities::default()
+        }
+    }
+
+    async fn ping(&self) -> Result<(), Self::Err> {
+        self.primary.ping().await?;
+        self.secondary.ping().await
+    }
+
+    async fn sync(&self) -> Result<(), Self::Err> {
+        self.primary.sync().await?;
+        self.secondary.sync().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{
+        inmemory::{self, InMemoryConfig},
+        NoopObserver,
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn set_lands_in_both_stores() {
+        // Pre-condition.
+        let (primary, secondary) = (inmemory::start(), inmemory::start());
+        let mut store = DualStore::new(primary.clone(), secondary.clone());
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+        let renamed = store.rename("j".into(), "i".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(renamed);
+        for backend in [primary, secondary] {
+            let mut pairs = backend.snapshot().await.unwrap();
+            pairs.sort();
+            assert_eq!(
+                pairs,
+                vec![("i".into(), "b".into()), ("k".into(), "a".into())]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn get_reads_from_primary_even_when_secondary_differs() {
+        // Pre-condition.
+        let (mut primary, mut secondary) = (inmemory::start(), inmemory::start());
+        primary.set("k".into(), "a".into()).await.unwrap();
+        secondary.set("k".into(), "stale".into()).await.unwrap();
+        secondary.set("j".into(), "b".into()).await.unwrap();
+        let store = DualStore::new(primary, secondary);
+
+        // Action.
+        let k = store.get("k").await.unwrap();
+        let j = store.get("j").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(k, Some("a".into()));
+        assert_eq!(j, None);
+    }
+
+    #[tokio::test]
+    async fn transaction_mirrors_only_effective_writes() {
+        // Pre-condition.
+        let (mut primary, secondary) = (inmemory::start(), inmemory::start());
+        primary.set("k".into(), "a".into()).await.unwrap();
+        let mut store = DualStore::new(primary, secondary.clone());
+
+        // Action.
+        let outcomes = store
+            .transaction(vec![
+                Operation::SetNx {
+                    key: "k".into(),
+                    value: "b".into(),
+                },
+                Operation::SetNx {
+                    key: "j".into(),
+                    value: "c".into(),
+                },
+                Operation::GetDel { key: "i".into() },
+            ])
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Applied(false),
+                Outcome::Applied(true),
+                Outcome::Value(None)
+            ]
+        );
+        assert_eq!(
+            secondary.snapshot().await.unwrap(),
+            vec![("j".into(), "c".into())]
+        );
+    }
+
+    #[tokio::test]
+    async fn secondary_failure_fails_write_unless_ignored() {
+        let cases = vec![
+            (SecondaryFailure::Fail, false, "fail"),
+            (SecondaryFailure::Ignore, true, "ignore"),
+        ];
+
+        for (policy, expected_ok, reason) in cases {
+            // Pre-condition.
+            let config = InMemoryConfig {
+                max_keys: Some(0),
+                ..InMemoryConfig::default()
+            };
+            let full = inmemory::start_with_config(config, Arc::new(NoopObserver));
+            let primary = inmemory::start();
+            let mut store = DualStore::new(primary.clone(), full).on_secondary_failure(policy);
+
+            // Action.
+            let set = store.set("k".into(), "a".into()).await;
+
+            // Post-condition.
+            assert_eq!(set.is_ok(), expected_ok, "{}", reason);
+            assert_eq!(
+                primary.get("k").await.unwrap(),
+                Some("a".into()),
+                "{}",
+                reason
+            );
+        }
+    }
+}
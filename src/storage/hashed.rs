@@ -0,0 +1,354 @@
+//! Fixed-size keys for an inner store, capping the memory long keys take.
+//!
+//! Every key is replaced by the 32 hex digits of its 128-bit FNV-1a hash, so
+//! the inner store only ever holds keys of that size.
+//!
+//! Collisions are not detected: keys sharing a hash share a single entry, the
+//! latest write winning. With 128 bits, the odds of any collision among `n`
+//! keys are about `n² / 2¹²⁹`, i.e. negligible for realistic workloads.
+//! Original keys cannot be recovered, hence snapshots (and so `DUMP` or an
+//! export) list hashes instead, and `FINDVAL` and `HOTKEYS` are unsupported.
+//! Nor can keys be matched by prefix, hence `GETPREFIX`, `CHILDREN` and
+//! `MEMUSAGE` are unsupported.
+
+use super::{
+    types::{Capabilities, Expiry, Extremum, Key, KeyRef, Operation, Outcome, QueueDepth, Value},
+    Store, Unsupported,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::time::Duration;
+
+const FNV_OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+/// Hashes keys into the ones the inner store holds.
+pub type KeyHasher = fn(KeyRef) -> u128;
+
+#[derive(Debug, Clone)]
+pub struct HashedKeyStore<S> {
+    inner: S,
+    hasher: KeyHasher,
+}
+
+impl<S> HashedKeyStore<S> {
+    /// Hashes keys with 128-bit FNV-1a.
+    pub fn new(inner: S) -> Self {
+        Self::with_hasher(inner, fnv1a)
+    }
+
+    /// Hashes keys with `hasher`, which must be stable for data to outlive the process.
+    pub fn with_hasher(inner: S, hasher: KeyHasher) -> Self {
+        Self { inner, hasher }
+    }
+
+    fn hash(&self, key: KeyRef) -> Key {
+        format!("{:032x}", (self.hasher)(key))
+    }
+
+    fn hash_op(&self, op: Operation) -> Operation {
+        match op {
+            Operation::Get { key } => Operation::Get {
+                key: self.hash(&key),
+            },
+            Operation::Set { key, value } => Operation::Set {
+                key: self.hash(&key),
+                value,
+            },
+            Operation::SetNx { key, value } => Operation::SetNx {
+                key: self.hash(&key),
+                value,
+            },
+            Operation::Rename { src, dst } => Operation::Rename {
+                src: self.hash(&src),
+                dst: self.hash(&dst),
+            },
+            Operation::GetDel { key } => Operation::GetDel {
+                key: self.hash(&key),
+            },
+        }
+    }
+}
+
+/// Returns the 128-bit FNV-1a hash of `key`, stable across processes and platforms.
+pub fn fnv1a(key: KeyRef) -> u128 {
+    key.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u128::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[async_trait]
+impl<S> Store for HashedKeyStore<S>
+where
+    S: Store<Err = anyhow::Error>,
+{
+    type Err = anyhow::Error;
+
+    async fn get<'k>(&self, key: KeyRef<'k>) -> Result<Option<Value>, Self::Err> {
+        self.inner.get(&self.hash(key)).await
+    }
+
+    async fn set(&mut self, key: Key, value: Value) -> Result<(), Self::Err> {
+        let key = self.hash(&key);
+        self.inner.set(key, value).await
+    }
+
+    async fn get_range<'k>(
+        &self,
+        key: KeyRef<'k>,
+        start: i64,
+        end: i64,
+    ) -> Result<Option<Value>, Self::Err> {
+        self.inner.get_range(&self.hash(key), start, end).await
+    }
+
+    async fn set_get(&mut self, key: Key, value: Value) -> Result<Option<Value>, Self::Err> {
+        let key = self.hash(&key);
+        self.inner.set_get(key, value).await
+    }
+
+    async fn set_nx(&mut self, key: Key, value: Value) -> Result<bool, Self::Err> {
+        let key = self.hash(&key);
+        self.inner.set_nx(key, value).await
+    }
+
+    async fn rename(&mut self, src: Key, dst: Key) -> Result<bool, Self::Err> {
+        let (src, dst) = (self.hash(&src), self.hash(&dst));
+        self.inner.rename(src, dst).await
+    }
+
+    async fn get_del(&mut self, key: Key) -> Result<Option<Value>, Self::Err> {
+        let key = self.hash(&key);
+        self.inner.get_del(key).await
+    }
+
+    async fn transaction(&mut self, ops: Vec<Operation>) -> Result<Vec<Outcome>, Self::Err> {
+        let ops = ops.into_iter().map(|op| self.hash_op(op)).collect();
+        self.inner.transaction(ops).await
+    }
+
+    async fn version<'k>(&self, key: KeyRef<'k>) -> Result<u64, Self::Err> {
+        self.inner.version(&self.hash(key)).await
+    }
+
+    async fn transaction_if_unchanged(
+        &mut self,
+        watched: Vec<(Key, u64)>,
+        ops: Vec<Operation>,
+    ) -> Result<Option<Vec<Outcome>>, Self::Err> {
+        let watched = watched
+            .into_iter()
+            .map(|(key, version)| (self.hash(&key), version))
+            .collect();
+        let ops = ops.into_iter().map(|op| self.hash_op(op)).collect();
+        self.inner.transaction_if_unchanged(watched, ops).await
+    }
+
+    async fn snapshot(&self) -> Result<Vec<(Key, Value)>, Self::Err> {
+        self.inner.snapshot().await
+    }
+
+    async fn get_prefix<'p>(&self, _: KeyRef<'p>) -> Result<Vec<(Key, Value)>, Self::Err> {
+        // Hashes do not preserve prefixes.
+        Err(Unsupported("GETPREFIX").into())
+    }
+
+    async fn mem_usage<'p>(&self, _: KeyRef<'p>) -> Result<Vec<(Key, usize)>, Self::Err> {
+        // Hashes do not preserve prefixes.
+        Err(Unsupported("MEMUSAGE").into())
+    }
+
+    async fn children<'p>(&self, _: KeyRef<'p>, _: &'p str) -> Result<Vec<String>, Self::Err> {
+        // Hashes do not preserve prefixes.
+        Err(Unsupported("CHILDREN").into())
+    }
+
+    async fn find_value<'n>(&self, _: &'n [u8]) -> Result<Vec<Key>, Self::Err> {
+        // Hashes cannot be told back as the keys they stand for.
+        Err(Unsupported("FINDVAL").into())
+    }
+
+    async fn hot_keys(&self, _: usize) -> Result<Vec<Key>, Self::Err> {
+        // Hashes cannot be told back as the keys they stand for.
+        Err(Unsupported("HOTKEYS").into())
+    }
+
+    async fn queue_depth(&self) -> Result<QueueDepth, Self::Err> {
+        self.inner.queue_depth().await
+    }
+
+    async fn db_size(&self) -> Result<usize, Self::Err> {
+        self.inner.db_size().await
+    }
+
+    async fn last_seq(&self) -> Result<u64, Self::Err> {
+        self.inner.last_seq().await
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            scan: false,
+            subscribe: false,
+            ..self.inner.capabilities()
+        }
+    }
+
+    async fn ping(&self) -> Result<(), Self::Err> {
+        self.inner.ping().await
+    }
+
+    async fn get_or_wait<'k>(
+        &self,
+        key: KeyRef<'k>,
+        timeout: Duration,
+    ) -> Result<Option<Value>, Self::Err> {
+        self.inner.get_or_wait(&self.hash(key), timeout).await
+    }
+
+    async fn checksum<'k>(&self, key: KeyRef<'k>) -> Result<Option<u32>, Self::Err> {
+        self.inner.checksum(&self.hash(key)).await
+    }
+
+    async fn idle_time<'k>(&self, key: KeyRef<'k>) -> Result<Option<Duration>, Self::Err> {
+        self.inner.idle_time(&self.hash(key)).await
+    }
+
+    async fn expire(&mut self, key: Key, ttl: Duration) -> Result<bool, Self::Err> {
+        let key = self.hash(&key);
+        self.inner.expire(key, ttl).await
+    }
+
+    async fn set_ex(&mut self, key: Key, value: Value, ttl: Duration) -> Result<(), Self::Err> {
+        let key = self.hash(&key);
+        self.inner.set_ex(key, value, ttl).await
+    }
+
+    async fn incr_by(&mut self, key: Key, delta: i64) -> Result<Option<i64>, Self::Err> {
+        let key = self.hash(&key);
+        self.inner.incr_by(key, delta).await
+    }
+
+    async fn set_extremum(
+        &mut self,
+        key: Key,
+        extremum: Extremum,
+        candidate: i64,
+    ) -> Result<Option<i64>, Self::Err> {
+        let key = self.hash(&key);
+        self.inner.set_extremum(key, extremum, candidate).await
+    }
+
+    async fn set_if_match(
+        &mut self,
+        key: Key,
+        pattern: Value,
+        value: Value,
+    ) -> Result<bool, Self::Err> {
+        let key = self.hash(&key);
+        self.inner.set_if_match(key, pattern, value).await
+    }
+
+    async fn del_if_equals(&mut self, key: Key, expected: Value) -> Result<bool, Self::Err> {
+        let key = self.hash(&key);
+        self.inner.del_if_equals(key, expected).await
+    }
+
+    async fn set_idem(&mut self, key: Key, token: String, value: Value) -> Result<bool, Self::Err> {
+        let key = self.hash(&key);
+        self.inner.set_idem(key, token, value).await
+    }
+
+    async fn mset_nx(&mut self, pairs: Vec<(Key, Value)>) -> Result<bool, Self::Err> {
+        let pairs = pairs
+            .into_iter()
+            .map(|(key, value)| (self.hash(&key), value))
+            .collect();
+        self.inner.mset_nx(pairs).await
+    }
+
+    async fn lpush(&mut self, key: Key, item: Value) -> Result<usize, Self::Err> {
+        let key = self.hash(&key);
+        self.inner.lpush(key, item).await
+    }
+
+    async fn llen<'k>(&self, key: KeyRef<'k>) -> Result<usize, Self::Err> {
+        self.inner.llen(&self.hash(key)).await
+    }
+
+    async fn compact(&mut self) -> Result<(), Self::Err> {
+        self.inner.compact().await
+    }
+
+    async fn sync(&self) -> Result<(), Self::Err> {
+        self.inner.sync().await
+    }
+
+    async fn ttl<'k>(&self, key: KeyRef<'k>) -> Result<Option<Expiry>, Self::Err> {
+        self.inner.ttl(&self.hash(key)).await
+    }
+
+    async fn get_with_ttl<'k>(
+        &self,
+        key: KeyRef<'k>,
+    ) -> Result<Option<(Value, Expiry)>, Self::Err> {
+        self.inner.get_with_ttl(&self.hash(key)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::inmemory;
+
+    #[tokio::test]
+    async fn long_keys_are_stored_under_fixed_size_hashes() {
+        // Pre-condition.
+        let inner = inmemory::start();
+        let mut store = HashedKeyStore::new(inner.clone());
+        let long = "k".repeat(1024);
+
+        // Action.
+        store.set(long.clone(), "a".into()).await.unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+        let renamed = store.rename("j".into(), "i".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(renamed);
+        assert_eq!(store.get(&long).await.unwrap(), Some("a".into()));
+        assert_eq!(store.get("j").await.unwrap(), None);
+        assert_eq!(store.get("i").await.unwrap(), Some("b".into()));
+
+        let keys: Vec<_> = inner
+            .snapshot()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys.len(), 2);
+        assert!(keys.iter().all(|key| key.len() == 32), "{:?}", keys);
+        assert!(keys.contains(&format!("{:032x}", fnv1a(&long))));
+    }
+
+    #[tokio::test]
+    async fn colliding_keys_share_one_entry() {
+        // Pre-condition.
+        let mut store = HashedKeyStore::with_hasher(inmemory::start(), |key| key.len() as u128);
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        store.set("j".into(), "b".into()).await.unwrap();
+        let set = store.set_nx("i".into(), "c".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(!set);
+        assert_eq!(store.get("k").await.unwrap(), Some("b".into()));
+        assert_eq!(store.db_size().await.unwrap(), 1);
+    }
+
+    #[test]
+    fn fnv1a_matches_reference_vectors() {
+        assert_eq!(fnv1a(""), 0x6c62272e07bb014262b821756295c58d);
+        assert_eq!(fnv1a("a"), 0xd228cb696f1a8caf78912b704e4a8964);
+    }
+}
@@ -1,33 +1,179 @@
 //! In-memory key-value storage.
+//!
+//! Writes may be coalesced: with a `coalesce_window`, a `SET` is only
+//! recorded as pending, superseding any pending one to the same key, until
+//! the window elapses since the first pending write. A `GET` flushes the
+//! pending write to its key first, while any other command flushes every
+//! pending write first, hence reads always observe the latest write. Only
+//! the latest write to a key is then applied, so observers, waiters, and
+//! subscribers are told about it once flushed, possibly out of order with
+//! writes to other keys. Likewise, the operation log records it once flushed,
+//! under the sequence number of the command flushing it.
+//!
+//! Expired items are swept as soon as they expire, subscribers being told
+//! about each via [`Mutation::Expired`], unless their key is accessed first,
+//! which removes them all the same.
 
-use super::types::{Command, Key, KeyRef, Value};
+use super::{
+    types::{
+        Capabilities, Command, Expiry, Extremum, Key, KeyRef, LogEntry, Mutation, Operation,
+        Outcome, QueueDepth, Value, LIST_DELIMITER,
+    },
+    Busy, Full, NoopObserver, Observer,
+};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use std::collections::HashMap;
-use tokio::sync::{mpsc, oneshot};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    fmt, mem,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc::{self, error::SendTimeoutError},
+        oneshot,
+    },
+    time::{self, Instant},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct InMemoryConfig {
+    /// Number of commands that may be pending before the backend applies them.
+    pub capacity: usize,
+    /// Longest time to wait for the backend to accept a command, unbounded if unset.
+    ///
+    /// Operations fail with [`Busy`] once it elapses.
+    pub send_timeout: Option<Duration>,
+    /// Number of mutations a subscriber may lag behind before missing the oldest ones.
+    pub mutations_capacity: usize,
+    /// Number of keys beyond which creating another one fails with [`Full`], unbounded if unset.
+    ///
+    /// Unlike an eviction policy, existing keys are never dropped to make room.
+    pub max_keys: Option<usize>,
+    /// Number of `SETIDEM` tokens remembered, beyond which the least recently used is forgotten.
+    pub idempotency_tokens: usize,
+    /// Longest time a `SET` may be held back to be superseded by a later one, if coalescing.
+    pub coalesce_window: Option<Duration>,
+    /// Number of mutations recorded in the operation log, beyond which the oldest is dropped.
+    pub op_log_capacity: usize,
+}
+
+impl Default for InMemoryConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 32,
+            send_timeout: None,
+            mutations_capacity: 1024,
+            max_keys: None,
+            idempotency_tokens: 1024,
+            coalesce_window: None,
+            op_log_capacity: 1024,
+        }
+    }
+}
 
-#[derive(Debug)]
 pub struct Backend {
-    data: HashMap<Key, Value>,
+    data: HashMap<Key, Item>,
     commands: mpsc::Receiver<Command>,
+    observer: Arc<dyn Observer>,
+    /// Callbacks awaiting a value to be written under a key.
+    waiters: HashMap<Key, Vec<oneshot::Sender<Value>>>,
+    /// Publishes every mutation to subscribers, if any.
+    mutations: broadcast::Sender<Mutation>,
+    /// Sequence number of the last command that mutated data, `0` if none did.
+    seq: u64,
+    /// Whether the command being applied has mutated data so far.
+    mutated: bool,
+    max_keys: Option<usize>,
+    /// Tokens of the `SETIDEM` commands applied recently.
+    tokens: Tokens,
+    coalesce_window: Option<Duration>,
+    /// Latest value set under each key, yet to be written, if coalescing, along with the
+    /// sequence number of the command setting it.
+    pending: HashMap<Key, (Value, u64)>,
+    /// Sequence number of the held-back write being flushed, if any, which it is logged under.
+    flushing: Option<u64>,
+    /// When the pending writes are flushed at the latest.
+    flush_at: Instant,
+    /// Keys by when their item expires, soonest first, including outdated entries of items
+    /// since rewritten or removed.
+    expirations: BTreeSet<(Instant, Key)>,
+    /// Most recent mutations, oldest first.
+    op_log: VecDeque<LogEntry>,
+    op_log_capacity: usize,
+    /// Version of each existing key, as told apart by `WATCH`, given by whatever last wrote it.
+    versions: HashMap<Key, u64>,
+    /// Version last given, bumped by every write, removal and expiry applied.
+    last_version: u64,
+    /// Version given by the latest removal or expiry of any key, which every missing key is at.
+    ///
+    /// Removed keys thus need no tombstone, at the cost of a missing key changing version
+    /// whenever any other key is removed.
+    last_removal: u64,
+}
+
+#[derive(Debug)]
+struct Item {
+    value: Value,
+    last_access: Instant,
+    /// When the item expires, if ever.
+    ///
+    /// Expired items are removed once swept or their key is next accessed, whichever comes first.
+    expires_at: Option<Instant>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Store {
     commands: mpsc::Sender<Command>,
+    send_timeout: Option<Duration>,
+    mutations: broadcast::Sender<Mutation>,
 }
 
 pub fn start() -> Store {
-    let (tx, rx) = mpsc::channel(32);
+    start_with_observer(Arc::new(NoopObserver))
+}
+
+/// Starts a store notifying `observer` of the operations it applies.
+pub fn start_with_observer(observer: Arc<dyn Observer>) -> Store {
+    start_with_config(InMemoryConfig::default(), observer)
+}
+
+/// Starts a store configured by `config`, notifying `observer` of the operations it applies.
+pub fn start_with_config(config: InMemoryConfig, observer: Arc<dyn Observer>) -> Store {
+    let (tx, rx) = mpsc::channel(config.capacity);
+    let (mutations, _) = broadcast::channel(config.mutations_capacity);
 
     let backend = Backend {
         data: HashMap::new(),
         commands: rx,
+        observer,
+        waiters: HashMap::new(),
+        mutations: mutations.clone(),
+        seq: 0,
+        mutated: false,
+        max_keys: config.max_keys,
+        tokens: Tokens::new(config.idempotency_tokens),
+        coalesce_window: config.coalesce_window,
+        pending: HashMap::new(),
+        flushing: None,
+        flush_at: Instant::now(),
+        expirations: BTreeSet::new(),
+        op_log: VecDeque::new(),
+        op_log_capacity: config.op_log_capacity,
+        versions: HashMap::new(),
+        last_version: 0,
+        last_removal: 0,
     };
 
     tokio::spawn(backend.start());
 
-    Store { commands: tx }
+    Store {
+        commands: tx,
+        send_timeout: config.send_timeout,
+        mutations,
+    }
 }
 
 #[async_trait]
@@ -35,103 +181,2706 @@ impl super::Store for Store {
     type Err = anyhow::Error;
 
     async fn get<'k>(&self, key: KeyRef<'k>) -> Result<Option<Value>, Self::Err> {
-        let (tx, rx) = oneshot::channel();
-        self.commands
-            .send(Command::Get {
-                key: key.to_owned(),
-                cb: tx,
+        self.request("get", |cb| Command::Get {
+            key: key.to_owned(),
+            cb,
+        })
+        .await
+    }
+
+    async fn get_range<'k>(
+        &self,
+        key: KeyRef<'k>,
+        start: i64,
+        end: i64,
+    ) -> Result<Option<Value>, Self::Err> {
+        self.request("getrange", |cb| Command::GetRange {
+            key: key.to_owned(),
+            start,
+            end,
+            cb,
+        })
+        .await
+    }
+
+    async fn set(&mut self, key: Key, value: Value) -> Result<(), Self::Err> {
+        Ok(self
+            .request("set", |cb| Command::Set { key, value, cb })
+            .await??)
+    }
+
+    async fn set_get(&mut self, key: Key, value: Value) -> Result<Option<Value>, Self::Err> {
+        Ok(self
+            .request("setget", |cb| Command::SetGet { key, value, cb })
+            .await??)
+    }
+
+    async fn set_nx(&mut self, key: Key, value: Value) -> Result<bool, Self::Err> {
+        Ok(self
+            .request("setnx", |cb| Command::SetNx { key, value, cb })
+            .await??)
+    }
+
+    async fn rename(&mut self, src: Key, dst: Key) -> Result<bool, Self::Err> {
+        self.request("rename", |cb| Command::Rename { src, dst, cb })
+            .await
+    }
+
+    async fn get_del(&mut self, key: Key) -> Result<Option<Value>, Self::Err> {
+        self.request("getdel", |cb| Command::GetDel { key, cb })
+            .await
+    }
+
+    async fn transaction(&mut self, ops: Vec<Operation>) -> Result<Vec<Outcome>, Self::Err> {
+        Ok(self
+            .request("transaction", |cb| Command::Transaction { ops, cb })
+            .await??)
+    }
+
+    async fn version<'k>(&self, key: KeyRef<'k>) -> Result<u64, Self::Err> {
+        self.request("version", |cb| Command::Version {
+            key: key.to_owned(),
+            cb,
+        })
+        .await
+    }
+
+    async fn transaction_if_unchanged(
+        &mut self,
+        watched: Vec<(Key, u64)>,
+        ops: Vec<Operation>,
+    ) -> Result<Option<Vec<Outcome>>, Self::Err> {
+        Ok(self
+            .request("transactionifunchanged", |cb| {
+                Command::TransactionIfUnchanged { watched, ops, cb }
             })
+            .await??)
+    }
+
+    async fn snapshot(&self) -> Result<Vec<(Key, Value)>, Self::Err> {
+        self.request("snapshot", |cb| Command::Snapshot { cb })
             .await
-            .context("unable to send get command")?;
-        rx.await.context("unable to access result of get command")
     }
 
-    async fn set(&mut self, key: Key, value: Value) -> Result<(), Self::Err> {
-        self.commands
-            .send(Command::Set {
-                key: key.to_owned(),
+    async fn get_prefix<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, Value)>, Self::Err> {
+        let prefix = prefix.to_owned();
+        self.request("getprefix", |cb| Command::GetPrefix { prefix, cb })
+            .await
+    }
+
+    async fn mem_usage<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, usize)>, Self::Err> {
+        let prefix = prefix.to_owned();
+        self.request("memusage", |cb| Command::MemUsage { prefix, cb })
+            .await
+    }
+
+    async fn children<'p>(
+        &self,
+        prefix: KeyRef<'p>,
+        separator: &'p str,
+    ) -> Result<Vec<String>, Self::Err> {
+        let (prefix, separator) = (prefix.to_owned(), separator.to_owned());
+        self.request("children", |cb| Command::Children {
+            prefix,
+            separator,
+            cb,
+        })
+        .await
+    }
+
+    async fn find_value<'n>(&self, needle: &'n [u8]) -> Result<Vec<Key>, Self::Err> {
+        let needle = Value::copy_from_slice(needle);
+        self.request("findvalue", |cb| Command::FindValue { needle, cb })
+            .await
+    }
+
+    async fn fill(&mut self, prefix: Key, count: usize) -> Result<usize, Self::Err> {
+        self.request("fill", |cb| Command::Fill { prefix, count, cb })
+            .await
+    }
+
+    async fn queue_depth(&self) -> Result<QueueDepth, Self::Err> {
+        // Computed here rather than by the backend, lest the command wait in the very queue.
+        let capacity = self.commands.max_capacity();
+        Ok(QueueDepth {
+            pending: capacity - self.commands.capacity(),
+            capacity,
+        })
+    }
+
+    async fn db_size(&self) -> Result<usize, Self::Err> {
+        self.request("dbsize", |cb| Command::DbSize { cb }).await
+    }
+
+    async fn last_seq(&self) -> Result<u64, Self::Err> {
+        self.request("lastseq", |cb| Command::LastSeq { cb }).await
+    }
+
+    async fn get_log(&self, count: usize) -> Result<Vec<LogEntry>, Self::Err> {
+        self.request("getlog", |cb| Command::GetLog { count, cb })
+            .await
+    }
+
+    async fn get_or_wait<'k>(
+        &self,
+        key: KeyRef<'k>,
+        timeout: Duration,
+    ) -> Result<Option<Value>, Self::Err> {
+        let value = self.request("bget", |cb| Command::GetOrWait {
+            key: key.to_owned(),
+            cb,
+        });
+        match time::timeout(timeout, value).await {
+            Ok(value) => value.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            expiry: true,
+            sync: false,
+            scan: true,
+            subscribe: true,
+        }
+    }
+
+    async fn ping(&self) -> Result<(), Self::Err> {
+        self.request("ping", |cb| Command::Ping { cb }).await
+    }
+
+    async fn checksum<'k>(&self, key: KeyRef<'k>) -> Result<Option<u32>, Self::Err> {
+        self.request("checksum", |cb| Command::Checksum {
+            key: key.to_owned(),
+            cb,
+        })
+        .await
+    }
+
+    async fn idle_time<'k>(&self, key: KeyRef<'k>) -> Result<Option<Duration>, Self::Err> {
+        self.request("idletime", |cb| Command::IdleTime {
+            key: key.to_owned(),
+            cb,
+        })
+        .await
+    }
+
+    async fn hot_keys(&self, count: usize) -> Result<Vec<Key>, Self::Err> {
+        self.request("hotkeys", |cb| Command::HotKeys { count, cb })
+            .await
+    }
+
+    async fn expire(&mut self, key: Key, ttl: Duration) -> Result<bool, Self::Err> {
+        self.request("expire", |cb| Command::Expire { key, ttl, cb })
+            .await
+    }
+
+    async fn set_ex(&mut self, key: Key, value: Value, ttl: Duration) -> Result<(), Self::Err> {
+        Ok(self
+            .request("setex", |cb| Command::SetEx {
+                key,
                 value,
+                ttl,
+                cb,
+            })
+            .await??)
+    }
+
+    async fn incr_by(&mut self, key: Key, delta: i64) -> Result<Option<i64>, Self::Err> {
+        Ok(self
+            .request("incrby", |cb| Command::IncrBy { key, delta, cb })
+            .await??)
+    }
+
+    async fn set_extremum(
+        &mut self,
+        key: Key,
+        extremum: Extremum,
+        candidate: i64,
+    ) -> Result<Option<i64>, Self::Err> {
+        Ok(self
+            .request("setextremum", |cb| Command::SetExtremum {
+                key,
+                extremum,
+                candidate,
+                cb,
             })
+            .await??)
+    }
+
+    async fn set_if_match(
+        &mut self,
+        key: Key,
+        pattern: Value,
+        value: Value,
+    ) -> Result<bool, Self::Err> {
+        self.request("setifmatch", |cb| Command::SetIfMatch {
+            key,
+            pattern,
+            value,
+            cb,
+        })
+        .await
+    }
+
+    async fn del_if_equals(&mut self, key: Key, expected: Value) -> Result<bool, Self::Err> {
+        self.request("delifeq", |cb| Command::DelIfEquals { key, expected, cb })
             .await
-            .context("unable to send set command")
+    }
+
+    async fn set_idem(&mut self, key: Key, token: String, value: Value) -> Result<bool, Self::Err> {
+        Ok(self
+            .request("setidem", |cb| Command::SetIdem {
+                key,
+                token,
+                value,
+                cb,
+            })
+            .await??)
+    }
+
+    async fn mset_nx(&mut self, pairs: Vec<(Key, Value)>) -> Result<bool, Self::Err> {
+        Ok(self
+            .request("msetnx", |cb| Command::MSetNx { pairs, cb })
+            .await??)
+    }
+
+    async fn lpush(&mut self, key: Key, item: Value) -> Result<usize, Self::Err> {
+        Ok(self
+            .request("lpush", |cb| Command::LPush { key, item, cb })
+            .await??)
+    }
+
+    async fn llen<'k>(&self, key: KeyRef<'k>) -> Result<usize, Self::Err> {
+        self.request("llen", |cb| Command::LLen {
+            key: key.to_owned(),
+            cb,
+        })
+        .await
+    }
+
+    async fn ttl<'k>(&self, key: KeyRef<'k>) -> Result<Option<Expiry>, Self::Err> {
+        self.request("ttl", |cb| Command::Ttl {
+            key: key.to_owned(),
+            cb,
+        })
+        .await
+    }
+
+    async fn subscribe(&self) -> Result<broadcast::Receiver<Mutation>, Self::Err> {
+        Ok(self.mutations.subscribe())
+    }
+
+    async fn get_with_ttl<'k>(
+        &self,
+        key: KeyRef<'k>,
+    ) -> Result<Option<(Value, Expiry)>, Self::Err> {
+        self.request("getttl", |cb| Command::GetWithTtl {
+            key: key.to_owned(),
+            cb,
+        })
+        .await
+    }
+}
+
+impl Store {
+    async fn request<T>(
+        &self,
+        name: &str,
+        command: impl FnOnce(oneshot::Sender<T>) -> Command,
+    ) -> Result<T> {
+        let (tx, rx) = oneshot::channel();
+        self.send(name, command(tx)).await?;
+        rx.await
+            .with_context(|| format!("unable to access result of {} command", name))
+    }
+
+    /// Hands `command` over to the backend, failing with [`Busy`] if it cannot take it in time.
+    async fn send(&self, name: &str, command: Command) -> Result<()> {
+        match self.send_timeout {
+            Some(timeout) => match self.commands.send_timeout(command, timeout).await {
+                Err(SendTimeoutError::Timeout(_)) => Err(Busy.into()),
+                sent => sent.with_context(|| format!("unable to send {} command", name)),
+            },
+            None => self
+                .commands
+                .send(command)
+                .await
+                .with_context(|| format!("unable to send {} command", name)),
+        }
+    }
+}
+
+impl fmt::Debug for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Backend")
+            .field("data", &self.data)
+            .field("commands", &self.commands)
+            .field("waiters", &self.waiters)
+            .finish_non_exhaustive()
     }
 }
 
 impl Backend {
     pub async fn start(mut self) {
-        while let Some(command) = self.commands.recv().await {
+        loop {
+            let next_expiry = self.expirations.first().map(|(expires_at, _)| *expires_at);
+            let command = tokio::select! {
+                command = self.commands.recv() => match command {
+                    Some(command) => command,
+                    None => break,
+                },
+                _ = time::sleep_until(self.flush_at), if !self.pending.is_empty() => {
+                    self.flush_pending();
+                    continue;
+                }
+                _ = time::sleep_until(next_expiry.unwrap_or_else(Instant::now)), if next_expiry.is_some() => {
+                    self.sweep();
+                    continue;
+                }
+            };
+
+            match &command {
+                Command::Set {
+                    key: _,
+                    value: _,
+                    cb: _,
+                } => {}
+                Command::Get { key, cb: _ } => self.flush_pending_key(key),
+                _ => self.flush_pending(),
+            }
+
             match command {
                 Command::Get { key, cb } => {
-                    let value = self.data.get(&key).map(Value::clone);
-                    let _ = cb.send(value);
+                    let _ = cb.send(self.get(&key));
+                }
+                Command::GetRange {
+                    key,
+                    start,
+                    end,
+                    cb,
+                } => {
+                    let range = self.get(&key).map(|value| super::slice(&value, start, end));
+                    let _ = cb.send(range);
+                }
+                Command::Set { key, value, cb } => {
+                    let set = match self.coalesce_window {
+                        Some(window) => self.coalesce(key, value, window),
+                        None => self.admit(&key).map(|()| drop(self.set(key, value))),
+                    };
+                    let _ = cb.send(set);
+                }
+                Command::SetNx { key, value, cb } => {
+                    let _ = cb.send(self.admit(&key).map(|()| self.set_nx(key, value)));
+                }
+                Command::SetGet { key, value, cb } => {
+                    let _ = cb.send(self.admit(&key).map(|()| self.set(key, value)));
+                }
+                Command::Rename { src, dst, cb } => {
+                    let _ = cb.send(self.rename(src, dst));
+                }
+                Command::GetDel { key, cb } => {
+                    let _ = cb.send(self.get_del(&key));
+                }
+                Command::Transaction { ops, cb } => {
+                    let outcomes = self
+                        .admit_all(&ops)
+                        .map(|()| ops.into_iter().map(|op| self.apply(op)).collect());
+                    let _ = cb.send(outcomes);
                 }
-                Command::Set { key, value } => {
-                    self.data.insert(key, value);
+                Command::Version { key, cb } => {
+                    let _ = cb.send(self.version(&key));
                 }
+                Command::TransactionIfUnchanged { watched, ops, cb } => {
+                    let outcomes = if watched
+                        .iter()
+                        .any(|(key, version)| self.version(key) != *version)
+                    {
+                        Ok(None)
+                    } else {
+                        self.admit_all(&ops)
+                            .map(|()| Some(ops.into_iter().map(|op| self.apply(op)).collect()))
+                    };
+                    let _ = cb.send(outcomes);
+                }
+                Command::Snapshot { cb } => {
+                    let now = Instant::now();
+                    let pairs = self
+                        .data
+                        .iter()
+                        .filter(|(_, item)| !item.is_expired(now))
+                        .map(|(key, item)| (key.clone(), item.value.clone()))
+                        .collect();
+                    let _ = cb.send(pairs);
+                }
+                Command::Checksum { key, cb } => {
+                    let checksum = self.live(&key).map(|item| crc32fast::hash(&item.value));
+                    let _ = cb.send(checksum);
+                }
+                Command::IdleTime { key, cb } => {
+                    let _ = cb.send(self.idle_time(&key));
+                }
+                Command::HotKeys { count, cb } => {
+                    let _ = cb.send(self.hot_keys(count));
+                }
+                Command::Expire { key, ttl, cb } => {
+                    let _ = cb.send(self.expire(&key, ttl));
+                }
+                Command::Ttl { key, cb } => {
+                    let _ = cb.send(self.ttl(&key));
+                }
+                Command::GetWithTtl { key, cb } => {
+                    let _ = cb.send(self.get_with_ttl(&key));
+                }
+                Command::SetEx {
+                    key,
+                    value,
+                    ttl,
+                    cb,
+                } => {
+                    let _ = cb.send(self.admit(&key).map(|()| self.set_ex(key, value, ttl)));
+                }
+                Command::Fill { prefix, count, cb } => {
+                    let mut created = 0;
+                    for i in 0..count {
+                        let key = format!("{}:{}", prefix, i);
+                        if self.admit(&key).is_err() {
+                            break;
+                        }
+                        self.set(key, i.to_string().into());
+                        created += 1;
+                    }
+                    let _ = cb.send(created);
+                }
+                Command::IncrBy { key, delta, cb } => {
+                    let _ = cb.send(self.admit(&key).map(|()| self.incr_by(key, delta)));
+                }
+                Command::SetExtremum {
+                    key,
+                    extremum,
+                    candidate,
+                    cb,
+                } => {
+                    let extreme = self
+                        .admit(&key)
+                        .map(|()| self.set_extremum(key, extremum, candidate));
+                    let _ = cb.send(extreme);
+                }
+                Command::SetIfMatch {
+                    key,
+                    pattern,
+                    value,
+                    cb,
+                } => {
+                    let _ = cb.send(self.set_if_match(key, &pattern, value));
+                }
+                Command::DelIfEquals { key, expected, cb } => {
+                    let _ = cb.send(self.del_if_equals(&key, &expected));
+                }
+                Command::SetIdem {
+                    key,
+                    token,
+                    value,
+                    cb,
+                } => {
+                    let _ = cb.send(self.set_idem(key, token, value));
+                }
+                Command::MSetNx { pairs, cb } => {
+                    let _ = cb.send(self.mset_nx(pairs));
+                }
+                Command::LPush { key, item, cb } => {
+                    let _ = cb.send(self.admit(&key).map(|()| self.lpush(key, item)));
+                }
+                Command::LLen { key, cb } => {
+                    let _ = cb.send(self.llen(&key));
+                }
+                Command::GetOrWait { key, cb } => match self.get(&key) {
+                    Some(value) => {
+                        let _ = cb.send(value);
+                    }
+                    None => {
+                        // Waiters that gave up are only dropped now, lest they pile up.
+                        self.waiters.retain(|_, waiters| {
+                            waiters.retain(|waiter| !waiter.is_closed());
+                            !waiters.is_empty()
+                        });
+                        self.waiters.entry(key).or_default().push(cb);
+                    }
+                },
+                Command::Ping { cb } => {
+                    let _ = cb.send(());
+                }
+                Command::GetPrefix { prefix, cb } => {
+                    let now = Instant::now();
+                    let mut pairs: Vec<_> = self
+                        .data
+                        .iter()
+                        .filter(|(key, item)| key.starts_with(&prefix) && !item.is_expired(now))
+                        .map(|(key, item)| (key.clone(), item.value.clone()))
+                        .collect();
+                    pairs.sort();
+                    let _ = cb.send(pairs);
+                }
+                Command::MemUsage { prefix, cb } => {
+                    let now = Instant::now();
+                    let sizes = self
+                        .data
+                        .iter()
+                        .filter(|(key, item)| key.starts_with(&prefix) && !item.is_expired(now))
+                        .map(|(key, item)| (key.clone(), item.value.len()));
+                    let _ = cb.send(super::by_size(sizes));
+                }
+                Command::Children {
+                    prefix,
+                    separator,
+                    cb,
+                } => {
+                    let now = Instant::now();
+                    let keys = self
+                        .data
+                        .iter()
+                        .filter(|(_, item)| !item.is_expired(now))
+                        .map(|(key, _)| key.as_str());
+                    let _ = cb.send(super::children(keys, &prefix, &separator));
+                }
+                Command::FindValue { needle, cb } => {
+                    let now = Instant::now();
+                    let pairs = self
+                        .data
+                        .iter()
+                        .filter(|(_, item)| !item.is_expired(now))
+                        .map(|(key, item)| (key.as_str(), &item.value));
+                    let _ = cb.send(super::containing(pairs, &needle));
+                }
+                Command::DbSize { cb } => {
+                    let now = Instant::now();
+                    let size = self
+                        .data
+                        .values()
+                        .filter(|item| !item.is_expired(now))
+                        .count();
+                    let _ = cb.send(size);
+                }
+                Command::LastSeq { cb } => {
+                    let _ = cb.send(self.seq);
+                }
+                Command::GetLog { count, cb } => {
+                    let skipped = self.op_log.len().saturating_sub(count);
+                    let _ = cb.send(self.op_log.iter().skip(skipped).cloned().collect());
+                }
+            }
+
+            if mem::take(&mut self.mutated) {
+                self.seq += 1;
             }
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::storage::Store;
+    /// Holds back writing `value` under `key`, superseding any write to it held back already.
+    fn coalesce(&mut self, key: Key, value: Value, window: Duration) -> Result<(), Full> {
+        if !self.pending.contains_key(&key) {
+            self.admit(&key)?;
+        }
+        if self.pending.is_empty() {
+            self.flush_at = Instant::now() + window;
+        }
+        self.pending.insert(key, (value, self.seq + 1));
+        // Applied as far as clients can tell, hence counted right away.
+        self.mutated = true;
+        Ok(())
+    }
 
-    #[tokio::test]
-    async fn get_with_no_prior_set_returns_none() {
-        // Pre-condition.
-        let store = start();
+    /// Writes every value held back, oldest first.
+    fn flush_pending(&mut self) {
+        let mut pending: Vec<_> = mem::take(&mut self.pending).into_iter().collect();
+        pending.sort_unstable_by_key(|(_, (_, seq))| *seq);
+        for (key, (value, seq)) in pending {
+            self.flush(key, value, seq);
+        }
+    }
 
-        // Action.
-        let value = store.get("k").await.unwrap();
+    /// Writes the value held back under `key`, if any.
+    fn flush_pending_key(&mut self, key: KeyRef) {
+        if let Some((key, (value, seq))) = self.pending.remove_entry(key) {
+            self.flush(key, value, seq);
+        }
+    }
 
-        // Post-condition.
-        assert_eq!(value, None);
+    /// Writes `value` under `key` as set by the command numbered `seq`, without counting it as
+    /// a mutation of the command being applied.
+    fn flush(&mut self, key: Key, value: Value, seq: u64) {
+        let mutated = self.mutated;
+        self.flushing = Some(seq);
+        self.set(key, value);
+        self.flushing = None;
+        self.mutated = mutated;
     }
 
-    #[tokio::test]
-    async fn get_after_set_returns_set_value() {
-        // Pre-condition.
-        let mut store = start();
+    fn apply(&mut self, op: Operation) -> Outcome {
+        match op {
+            Operation::Get { key } => Outcome::Value(self.get(&key)),
+            Operation::Set { key, value } => {
+                self.set(key, value);
+                Outcome::Done
+            }
+            Operation::SetNx { key, value } => Outcome::Applied(self.set_nx(key, value)),
+            Operation::Rename { src, dst } => Outcome::Applied(self.rename(src, dst)),
+            Operation::GetDel { key } => Outcome::Value(self.get_del(&key)),
+        }
+    }
 
-        // Action.
-        store.set("k".into(), "a".into()).await.unwrap();
+    fn get(&mut self, key: KeyRef) -> Option<Value> {
+        let value = self.live(key).map(|item| {
+            item.last_access = Instant::now();
+            item.value.clone()
+        });
+        self.observer.on_get(key, value.is_some());
+        value
+    }
 
-        let value = store.get("k").await.unwrap();
+    /// Returns the value displaced, if any.
+    fn set(&mut self, key: Key, value: Value) -> Option<Value> {
+        self.observer.on_set(&key);
+        self.written(&key, &value);
+        let now = Instant::now();
+        self.data
+            .insert(key, Item::new(value))
+            .filter(|item| !item.is_expired(now))
+            .map(|item| item.value)
+    }
 
-        // Post-condition.
-        assert_eq!(value, Some("a".into()));
+    fn set_nx(&mut self, key: Key, value: Value) -> bool {
+        if self.live(&key).is_some() {
+            return false;
+        }
+        self.observer.on_set(&key);
+        self.written(&key, &value);
+        self.data.insert(key, Item::new(value));
+        true
     }
 
-    #[tokio::test]
-    async fn get_twice_with_no_set_in_between_returns_same_value() {
-        // Pre-condition.
-        let mut store = start();
+    fn rename(&mut self, src: Key, dst: Key) -> bool {
+        self.live(&src);
+        match self.data.remove(&src) {
+            Some(item) => {
+                // The item expires under its new key as it would have under the old one.
+                if let Some(expires_at) = item.expires_at {
+                    self.expirations.remove(&(expires_at, src.clone()));
+                    self.expirations.insert((expires_at, dst.clone()));
+                }
+                self.publish(Mutation::Del { key: src });
+                self.written(&dst, &item.value);
+                self.data.insert(dst, item);
+                true
+            }
+            None => false,
+        }
+    }
 
-        // Action.
-        store.set("k".into(), "a".into()).await.unwrap();
+    fn get_del(&mut self, key: KeyRef) -> Option<Value> {
+        self.live(key)?;
+        let item = self.data.remove(key)?;
+        self.publish(Mutation::Del { key: key.into() });
+        Some(item.value)
+    }
 
-        let value_first = store.get("k").await.unwrap();
-        let value_second = store.get("k").await.unwrap();
+    fn idle_time(&mut self, key: KeyRef) -> Option<Duration> {
+        self.live(key)
+            .map(|item| Instant::now().duration_since(item.last_access))
+    }
 
-        // Post-condition.
-        assert_eq!(value_first, Some("a".into()));
-        assert_eq!(value_second, Some("a".into()));
+    /// Returns up to `count` live keys by descending access time, ties broken by key.
+    fn hot_keys(&self, count: usize) -> Vec<Key> {
+        let now = Instant::now();
+        let mut accesses: Vec<_> = self
+            .data
+            .iter()
+            .filter(|(_, item)| !item.is_expired(now))
+            .map(|(key, item)| (item.last_access, key))
+            .collect();
+        accesses.sort_unstable_by(|(a_at, a_key), (b_at, b_key)| {
+            b_at.cmp(a_at).then_with(|| a_key.cmp(b_key))
+        });
+        accesses
+            .into_iter()
+            .take(count)
+            .map(|(_, key)| key.clone())
+            .collect()
     }
 
-    #[tokio::test]
-    async fn set_overrides_prior_set_value() {
-        // Pre-condition.
-        let mut store = start();
+    /// Sets `key` to expire after `ttl`, or never if that is too far ahead to tell when.
+    fn expire(&mut self, key: KeyRef, ttl: Duration) -> bool {
+        let expires_at = Instant::now().checked_add(ttl);
+        match self.live(key) {
+            Some(item) => {
+                item.expires_at = expires_at;
+                if let Some(expires_at) = expires_at {
+                    self.expirations.insert((expires_at, key.into()));
+                }
+                self.publish(Mutation::Expire { key: key.into() });
+                true
+            }
+            None => false,
+        }
+    }
 
-        // Action.
-        store.set("k".into(), "a".into()).await.unwrap();
-        store.set("k".into(), "b".into()).await.unwrap();
+    /// Sets `value` under `key` to expire after `ttl`, or never if that is too far ahead to tell
+    /// when.
+    fn set_ex(&mut self, key: Key, value: Value, ttl: Duration) {
+        self.observer.on_set(&key);
+        self.written(&key, &value);
+        let expires_at = Instant::now().checked_add(ttl);
+        let item = Item {
+            expires_at,
+            ..Item::new(value)
+        };
+        if let Some(expires_at) = expires_at {
+            self.expirations.insert((expires_at, key.clone()));
+        }
+        self.data.insert(key, item);
+    }
 
-        let value = store.get("k").await.unwrap();
+    /// Removes every item expired by now, skipping entries outdated since.
+    fn sweep(&mut self) {
+        let now = Instant::now();
+        while let Some((expires_at, key)) = self.expirations.pop_first() {
+            if expires_at > now {
+                self.expirations.insert((expires_at, key));
+                break;
+            }
+            let current = self.data.get(&key).and_then(|item| item.expires_at);
+            if current == Some(expires_at) {
+                self.data.remove(&key);
+                self.publish_expired(key);
+            }
+        }
+    }
 
-        // Post-condition.
-        assert_eq!(value, Some("b".into()));
+    /// Overflows fail rather than saturate, so that a counter never silently stops counting.
+    fn incr_by(&mut self, key: Key, delta: i64) -> Option<i64> {
+        let current: i64 = match self.live(&key) {
+            Some(item) => std::str::from_utf8(&item.value).ok()?.parse().ok()?,
+            None => 0,
+        };
+        let value = current.checked_add(delta)?;
+        self.write_in_place(key, value.to_string().into());
+        Some(value)
+    }
+
+    fn set_extremum(&mut self, key: Key, extremum: Extremum, candidate: i64) -> Option<i64> {
+        let current: Option<i64> = match self.live(&key) {
+            Some(item) => Some(std::str::from_utf8(&item.value).ok()?.parse().ok()?),
+            None => None,
+        };
+        match current {
+            Some(current) if extremum.of(current, candidate) == current => Some(current),
+            _ => {
+                self.write_in_place(key, candidate.to_string().into());
+                Some(candidate)
+            }
+        }
+    }
+
+    /// Writes `value` under `key`, keeping the expiry of the value it replaces, if any.
+    fn write_in_place(&mut self, key: Key, value: Value) {
+        self.observer.on_set(&key);
+        self.written(&key, &value);
+        match self.live(&key) {
+            Some(item) => {
+                item.value = value;
+                item.last_access = Instant::now();
+            }
+            None => {
+                self.data.insert(key, Item::new(value));
+            }
+        }
+    }
+
+    fn set_if_match(&mut self, key: Key, pattern: &[u8], value: Value) -> bool {
+        match self.live(&key) {
+            Some(item) if item.value.starts_with(pattern) => {
+                self.set(key, value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn del_if_equals(&mut self, key: KeyRef, expected: &[u8]) -> bool {
+        match self.live(key) {
+            Some(item) if item.value == expected => self.get_del(key).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Skips tokens applied recently, so that retrying leaves the value as is.
+    fn set_idem(&mut self, key: Key, token: String, value: Value) -> Result<bool, Full> {
+        if self.tokens.touch(&token) {
+            return Ok(false);
+        }
+        self.admit(&key)?;
+        self.tokens.insert(token);
+        self.set(key, value);
+        Ok(true)
+    }
+
+    /// Writes nothing unless every key is missing and there is room for all of them.
+    fn mset_nx(&mut self, pairs: Vec<(Key, Value)>) -> Result<bool, Full> {
+        if pairs.iter().any(|(key, _)| self.live(key).is_some()) {
+            return Ok(false);
+        }
+        if let Some(max) = self.max_keys {
+            let keys: HashSet<_> = pairs.iter().map(|(key, _)| key).collect();
+            self.make_room(max, keys.len())?;
+        }
+        for (key, value) in pairs {
+            self.set(key, value);
+        }
+        Ok(true)
+    }
+
+    fn lpush(&mut self, key: Key, item: Value) -> usize {
+        let mut list = self
+            .live(&key)
+            .map(|existing| existing.value.to_vec())
+            .unwrap_or_default();
+        if !list.is_empty() {
+            list.push(LIST_DELIMITER);
+        }
+        list.extend_from_slice(&item);
+        let len = list_len(&list);
+        self.write_in_place(key, list.into());
+        len
+    }
+
+    fn llen(&mut self, key: KeyRef) -> usize {
+        self.live(key).map_or(0, |item| list_len(&item.value))
+    }
+
+    fn ttl(&mut self, key: KeyRef) -> Option<Expiry> {
+        self.live(key).map(|item| match item.expires_at {
+            Some(expires_at) => Expiry::In(expires_at.duration_since(Instant::now())),
+            None => Expiry::Never,
+        })
+    }
+
+    fn get_with_ttl(&mut self, key: KeyRef) -> Option<(Value, Expiry)> {
+        let expiry = self.ttl(key)?;
+        let value = self.get(key)?;
+        Some((value, expiry))
+    }
+
+    /// Answers whoever awaits `value` to be written under `key`, and publishes the write.
+    fn written(&mut self, key: KeyRef, value: &Value) {
+        if let Some(waiters) = self.waiters.remove(key) {
+            notify(waiters, value);
+        }
+        self.publish(Mutation::Set { key: key.into() });
+    }
+
+    /// Publishes `mutation` and records it in the operation log under the sequence number of
+    /// the command being applied, or of the held-back write being flushed.
+    fn publish(&mut self, mutation: Mutation) {
+        self.mutated = true;
+        match &mutation {
+            Mutation::Set { key } | Mutation::Expire { key } => self.bump_version(key),
+            Mutation::Del { key } | Mutation::Expired { key } => self.drop_version(key),
+        }
+        if self.op_log_capacity > 0 {
+            if self.op_log.len() == self.op_log_capacity {
+                self.op_log.pop_front();
+            }
+            self.op_log.push_back(LogEntry {
+                seq: self.flushing.unwrap_or(self.seq + 1),
+                mutation: mutation.clone(),
+            });
+        }
+        // Failing only when nobody is subscribed.
+        let _ = self.mutations.send(mutation);
+    }
+
+    /// Publishes the expiry of `key`, which no client requested, hence not a mutation.
+    fn publish_expired(&mut self, key: Key) {
+        self.drop_version(&key);
+        let _ = self.mutations.send(Mutation::Expired { key });
+    }
+
+    fn bump_version(&mut self, key: KeyRef) {
+        self.last_version += 1;
+        self.versions.insert(key.into(), self.last_version);
+    }
+
+    /// Forgets the version of `key`, now missing, hence at that of this removal.
+    fn drop_version(&mut self, key: KeyRef) {
+        self.last_version += 1;
+        self.last_removal = self.last_version;
+        self.versions.remove(key);
+    }
+
+    /// A key is at the version given by whatever last wrote it, or by the latest removal of
+    /// any key if missing, `0` if no key was ever removed.
+    fn version(&mut self, key: KeyRef) -> u64 {
+        match self.live(key) {
+            Some(_) => self.versions.get(key).copied().unwrap_or_default(),
+            None => self.last_removal,
+        }
+    }
+
+    /// Fails unless `key` exists or there is room for one more key.
+    fn admit(&mut self, key: KeyRef) -> Result<(), Full> {
+        let max = match self.max_keys {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        if self.live(key).is_some() {
+            return Ok(());
+        }
+        self.make_room(max, 1)
+    }
+
+    /// Fails unless there is room for every key `ops` may create.
+    ///
+    /// Keys that other operations may remove are not accounted for, erring on
+    /// the side of refusing.
+    fn admit_all(&mut self, ops: &[Operation]) -> Result<(), Full> {
+        let max = match self.max_keys {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        let mut created = HashSet::new();
+        for op in ops {
+            if let Operation::Set { key, value: _ } | Operation::SetNx { key, value: _ } = op {
+                if self.live(key).is_none() {
+                    created.insert(key);
+                }
+            }
+        }
+        self.make_room(max, created.len())
+    }
+
+    /// Fails unless `count` keys more would fit within `max`, once expired ones are dropped.
+    ///
+    /// Keys that writes held back would create count as taken.
+    fn make_room(&mut self, max: usize, count: usize) -> Result<(), Full> {
+        if self.keys_taken() + count > max {
+            self.sweep();
+        }
+        if self.keys_taken() + count > max {
+            return Err(Full);
+        }
+        Ok(())
+    }
+
+    fn keys_taken(&self) -> usize {
+        let held_back = self
+            .pending
+            .keys()
+            .filter(|key| !self.data.contains_key(*key))
+            .count();
+        self.data.len() + held_back
+    }
+
+    /// Returns the item under `key` unless it has expired, removing it if so.
+    fn live(&mut self, key: KeyRef) -> Option<&mut Item> {
+        let now = Instant::now();
+        if self.data.get(key)?.is_expired(now) {
+            self.data.remove(key);
+            self.publish_expired(key.into());
+            return None;
+        }
+        self.data.get_mut(key)
+    }
+}
+
+/// Tokens forgetting the least recently used beyond `capacity`.
+#[derive(Debug)]
+struct Tokens {
+    capacity: usize,
+    /// Tick at which each token was last used.
+    last_used: HashMap<String, u64>,
+    /// Tokens by the tick at which they were last used, oldest first.
+    by_use: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl Tokens {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            last_used: HashMap::new(),
+            by_use: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Marks `token` as used just now, returning whether it is remembered at all.
+    fn touch(&mut self, token: &str) -> bool {
+        let tick = match self.last_used.get_mut(token) {
+            Some(tick) => tick,
+            None => return false,
+        };
+        let token = self
+            .by_use
+            .remove(tick)
+            .expect("token is indexed by its tick");
+        self.tick += 1;
+        *tick = self.tick;
+        self.by_use.insert(self.tick, token);
+        true
+    }
+
+    fn insert(&mut self, token: String) {
+        self.tick += 1;
+        self.last_used.insert(token.clone(), self.tick);
+        self.by_use.insert(self.tick, token);
+        while self.by_use.len() > self.capacity {
+            if let Some((_, oldest)) = self.by_use.pop_first() {
+                self.last_used.remove(&oldest);
+            }
+        }
+    }
+}
+
+fn notify(waiters: Vec<oneshot::Sender<Value>>, value: &Value) {
+    for waiter in waiters {
+        let _ = waiter.send(value.clone());
+    }
+}
+
+/// An empty value holds no items rather than a single empty one.
+fn list_len(list: &[u8]) -> usize {
+    if list.is_empty() {
+        0
+    } else {
+        list.split(|byte| *byte == LIST_DELIMITER).count()
+    }
+}
+
+impl Item {
+    fn new(value: Value) -> Self {
+        Self {
+            value,
+            last_access: Instant::now(),
+            expires_at: None,
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Store;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::{task, time};
+
+    #[tokio::test]
+    async fn get_with_no_prior_set_returns_none() {
+        // Pre-condition.
+        let store = start();
+
+        // Action.
+        let value = store.get("k").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(value, None);
+    }
+
+    #[tokio::test]
+    async fn get_after_set_returns_set_value() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        let value = store.get("k").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(value, Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn get_twice_with_no_set_in_between_returns_same_value() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        let value_first = store.get("k").await.unwrap();
+        let value_second = store.get("k").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(value_first, Some("a".into()));
+        assert_eq!(value_second, Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn set_overrides_prior_set_value() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.set("k".into(), "b".into()).await.unwrap();
+
+        let value = store.get("k").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(value, Some("b".into()));
+    }
+
+    #[tokio::test]
+    async fn set_get_returns_displaced_value() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        let first = store.set_get("k".into(), "a".into()).await.unwrap();
+        let second = store.set_get("k".into(), "b".into()).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(first, None);
+        assert_eq!(second, Some("a".into()));
+        assert_eq!(store.get("k").await.unwrap(), Some("b".into()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn set_get_does_not_return_expired_value() {
+        // Pre-condition.
+        let mut store = start();
+        store
+            .set_ex("k".into(), "a".into(), Duration::from_secs(10))
+            .await
+            .unwrap();
+        time::advance(Duration::from_secs(11)).await;
+
+        // Action.
+        let displaced = store.set_get("k".into(), "b".into()).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(displaced, None);
+        assert_eq!(store.get("k").await.unwrap(), Some("b".into()));
+    }
+
+    #[tokio::test]
+    async fn del_if_equals_removes_matching_value() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let deleted = store.del_if_equals("k".into(), "a".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(deleted);
+        assert_eq!(store.get("k").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn del_if_equals_leaves_mismatching_value_as_is() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "ab".into()).await.unwrap();
+
+        // Action.
+        let deleted = store.del_if_equals("k".into(), "a".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(!deleted);
+        assert_eq!(store.get("k").await.unwrap(), Some("ab".into()));
+    }
+
+    #[tokio::test]
+    async fn del_if_equals_fails_on_missing_key() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        let deleted = store.del_if_equals("k".into(), "".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(!deleted);
+    }
+
+    #[tokio::test]
+    async fn set_idem_with_repeated_token_leaves_value_as_is() {
+        // Pre-condition.
+        let mut store = start();
+        store
+            .set_idem("k".into(), "t1".into(), "a".into())
+            .await
+            .unwrap();
+
+        // Action.
+        let repeated = store
+            .set_idem("k".into(), "t1".into(), "b".into())
+            .await
+            .unwrap();
+        let fresh = store
+            .set_idem("k".into(), "t2".into(), "c".into())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert!(!repeated);
+        assert!(fresh);
+        assert_eq!(store.get("k").await.unwrap(), Some("c".into()));
+    }
+
+    #[tokio::test]
+    async fn set_idem_forgets_least_recently_used_tokens() {
+        // Pre-condition.
+        let config = InMemoryConfig {
+            idempotency_tokens: 2,
+            ..InMemoryConfig::default()
+        };
+        let mut store = start_with_config(config, Arc::new(NoopObserver));
+        for token in ["t1", "t2", "t1", "t3"] {
+            store
+                .set_idem("k".into(), token.into(), token.into())
+                .await
+                .unwrap();
+        }
+
+        // Action.
+        let t1 = store
+            .set_idem("k".into(), "t1".into(), "a".into())
+            .await
+            .unwrap();
+        let t2 = store
+            .set_idem("k".into(), "t2".into(), "b".into())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert!(!t1, "t1 was used more recently than t2");
+        assert!(t2, "t2 was forgotten to make room for t3");
+        assert_eq!(store.get("k").await.unwrap(), Some("b".into()));
+    }
+
+    #[tokio::test]
+    async fn mset_nx_sets_every_key_when_all_are_missing() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        let set = store
+            .mset_nx(vec![("k".into(), "a".into()), ("j".into(), "b".into())])
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert!(set);
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+        assert_eq!(store.get("j").await.unwrap(), Some("b".into()));
+        assert_eq!(store.last_seq().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn mset_nx_writes_nothing_when_some_key_exists() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("j".into(), "old".into()).await.unwrap();
+
+        // Action.
+        let set = store
+            .mset_nx(vec![
+                ("k".into(), "a".into()),
+                ("j".into(), "b".into()),
+                ("i".into(), "c".into()),
+            ])
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert!(!set);
+        assert_eq!(store.get("k").await.unwrap(), None);
+        assert_eq!(store.get("j").await.unwrap(), Some("old".into()));
+        assert_eq!(store.get("i").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn mset_nx_beyond_max_keys_writes_nothing() {
+        // Pre-condition.
+        let mut store = start_capped(2);
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let set = store
+            .mset_nx(vec![("j".into(), "b".into()), ("i".into(), "c".into())])
+            .await;
+
+        // Post-condition.
+        assert!(set.unwrap_err().is::<Full>());
+        assert_eq!(store.db_size().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn find_value_returns_keys_of_live_values_containing_needle_sorted() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("c".into(), "haystack".into()).await.unwrap();
+        store.set("a".into(), "needle".into()).await.unwrap();
+        store.set("b".into(), "needlework".into()).await.unwrap();
+        store.set("d".into(), "nee".into()).await.unwrap();
+        store
+            .set_ex("e".into(), "needle".into(), Duration::ZERO)
+            .await
+            .unwrap();
+
+        // Action.
+        let keys = store.find_value(b"needle").await.unwrap();
+        let none = store.find_value(b"thread").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(keys, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(none, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn get_prefix_returns_matching_pairs_sorted_by_key() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("user:2".into(), "b".into()).await.unwrap();
+        store.set("user:1".into(), "a".into()).await.unwrap();
+        store.set("users".into(), "c".into()).await.unwrap();
+        store.set("group:1".into(), "d".into()).await.unwrap();
+        store
+            .set_ex("user:3".into(), "e".into(), Duration::ZERO)
+            .await
+            .unwrap();
+
+        // Action.
+        let pairs = store.get_prefix("user:").await.unwrap();
+        let none = store.get_prefix("session:").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(
+            pairs,
+            vec![("user:1".into(), "a".into()), ("user:2".into(), "b".into())]
+        );
+        assert_eq!(none, vec![]);
+    }
+
+    #[tokio::test]
+    async fn store_at_max_keys_refuses_new_keys_only() {
+        // Pre-condition.
+        let mut store = start_capped(2);
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+
+        // Action.
+        let refused = store.set("i".into(), "c".into()).await;
+        let overwritten = store.set("k".into(), "d".into()).await;
+
+        // Post-condition.
+        assert!(refused.unwrap_err().is::<Full>());
+        assert!(overwritten.is_ok());
+        assert_eq!(store.get("i").await.unwrap(), None);
+        assert_eq!(store.get("k").await.unwrap(), Some("d".into()));
+        assert_eq!(store.db_size().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn store_at_max_keys_refuses_every_command_creating_keys() {
+        // Pre-condition.
+        let mut store = start_capped(1);
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let set_nx = store.set_nx("j".into(), "b".into()).await;
+        let set_get = store.set_get("j".into(), "b".into()).await;
+        let incr_by = store.incr_by("j".into(), 1).await;
+        let transaction = store
+            .transaction(vec![
+                Operation::Set {
+                    key: "k".into(),
+                    value: "b".into(),
+                },
+                Operation::Set {
+                    key: "j".into(),
+                    value: "b".into(),
+                },
+            ])
+            .await;
+        let filled = store.fill("f".into(), 3).await.unwrap();
+
+        // Post-condition.
+        assert!(set_nx.unwrap_err().is::<Full>());
+        assert!(set_get.unwrap_err().is::<Full>());
+        assert!(incr_by.unwrap_err().is::<Full>());
+        assert!(transaction.unwrap_err().is::<Full>());
+        assert_eq!(filled, 0);
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+        assert_eq!(store.db_size().await.unwrap(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_keys_make_room_below_max_keys() {
+        // Pre-condition.
+        let mut store = start_capped(1);
+        store
+            .set_ex("k".into(), "a".into(), Duration::from_secs(10))
+            .await
+            .unwrap();
+        time::advance(Duration::from_secs(11)).await;
+
+        // Action.
+        let set = store.set("j".into(), "b".into()).await;
+
+        // Post-condition.
+        assert!(set.is_ok());
+        assert_eq!(store.get("j").await.unwrap(), Some("b".into()));
+    }
+
+    #[tokio::test]
+    async fn last_seq_is_bumped_once_per_mutating_command() {
+        // Pre-condition.
+        let mut store = start();
+        let initial = store.last_seq().await.unwrap();
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+        let after_set = store.last_seq().await.unwrap();
+
+        store.get("k").await.unwrap();
+        let after_get = store.last_seq().await.unwrap();
+
+        store.rename("k".into(), "j".into()).await.unwrap();
+        let after_rename = store.last_seq().await.unwrap();
+
+        store.get_del("j".into()).await.unwrap();
+        let after_get_del = store.last_seq().await.unwrap();
+
+        store.get_del("j".into()).await.unwrap();
+        let after_missed_get_del = store.last_seq().await.unwrap();
+
+        // Post-condition.
+        assert_eq!(initial, 0);
+        assert_eq!(after_set, 1);
+        assert_eq!(after_get, 1);
+        assert_eq!(after_rename, 2);
+        assert_eq!(after_get_del, 3);
+        assert_eq!(after_missed_get_del, 3);
+    }
+
+    #[tokio::test]
+    async fn op_log_keeps_latest_mutations_with_their_seq() {
+        // Pre-condition.
+        let config = InMemoryConfig {
+            op_log_capacity: 3,
+            ..InMemoryConfig::default()
+        };
+        let mut store = start_with_config(config, Arc::new(NoopObserver));
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+        store.get("k").await.unwrap();
+        store.rename("k".into(), "i".into()).await.unwrap();
+        store.get_del("j".into()).await.unwrap();
+
+        // Post-condition.
+        let entry = |seq, mutation| LogEntry { seq, mutation };
+        assert_eq!(
+            store.get_log(10).await.unwrap(),
+            vec![
+                entry(3, Mutation::Del { key: "k".into() }),
+                entry(3, Mutation::Set { key: "i".into() }),
+                entry(4, Mutation::Del { key: "j".into() }),
+            ]
+        );
+        assert_eq!(
+            store.get_log(1).await.unwrap(),
+            vec![entry(4, Mutation::Del { key: "j".into() })]
+        );
+    }
+
+    #[tokio::test]
+    async fn expire_is_logged_and_changes_version() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        let before = store.version("k").await.unwrap();
+
+        // Action.
+        store
+            .expire("k".into(), Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_ne!(store.version("k").await.unwrap(), before);
+        assert_eq!(
+            store.get_log(1).await.unwrap(),
+            vec![LogEntry {
+                seq: 2,
+                mutation: Mutation::Expire { key: "k".into() },
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn expire_beyond_representable_instant_never_expires() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let set = store
+            .expire("k".into(), Duration::from_secs(u64::MAX))
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert!(set);
+        assert_eq!(store.ttl("k").await.unwrap(), Some(Expiry::Never));
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn set_nx_on_missing_key_sets_value() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        let set = store.set_nx("k".into(), "a".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(set);
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn set_nx_on_existing_key_preserves_prior_value() {
+        // Pre-condition.
+        let mut store = start();
+        store.set_nx("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let set = store.set_nx("k".into(), "b".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(!set);
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn rename_moves_value_to_destination() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("src".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let renamed = store.rename("src".into(), "dst".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(renamed);
+        assert_eq!(store.get("src").await.unwrap(), None);
+        assert_eq!(store.get("dst").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn rename_overrides_existing_destination() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("src".into(), "a".into()).await.unwrap();
+        store.set("dst".into(), "b".into()).await.unwrap();
+
+        // Action.
+        let renamed = store.rename("src".into(), "dst".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(renamed);
+        assert_eq!(store.get("src").await.unwrap(), None);
+        assert_eq!(store.get("dst").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn rename_with_missing_source_fails_and_keeps_destination() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("dst".into(), "b".into()).await.unwrap();
+
+        // Action.
+        let renamed = store.rename("src".into(), "dst".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(!renamed);
+        assert_eq!(store.get("dst").await.unwrap(), Some("b".into()));
+    }
+
+    #[tokio::test]
+    async fn snapshot_returns_every_pair() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+
+        // Action.
+        let mut pairs = store.snapshot().await.unwrap();
+
+        // Post-condition.
+        pairs.sort();
+        assert_eq!(
+            pairs,
+            vec![("j".into(), "b".into()), ("k".into(), "a".into())]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_shares_stored_value_instead_of_copying_it() {
+        // Pre-condition.
+        let mut store = start();
+        let value = Value::from(vec![b'a'; 1 << 20]);
+        store.set("k".into(), value.clone()).await.unwrap();
+
+        // Action.
+        let mut reads = Vec::new();
+        for _ in 0..1_000 {
+            reads.push(store.get("k").await.unwrap().unwrap());
+        }
+
+        // Post-condition.
+        assert!(reads.iter().all(|read| read.as_ptr() == value.as_ptr()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_time_grows_until_next_access() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let idle_after_set = store.idle_time("k").await.unwrap();
+
+        time::advance(Duration::from_secs(3)).await;
+        let idle_before_get = store.idle_time("k").await.unwrap();
+
+        store.get("k").await.unwrap();
+        let idle_after_get = store.idle_time("k").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(idle_after_set, Some(Duration::ZERO));
+        assert_eq!(idle_before_get, Some(Duration::from_secs(3)));
+        assert_eq!(idle_after_get, Some(Duration::ZERO));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hot_keys_lists_most_recently_accessed_first() {
+        // Pre-condition.
+        let mut store = start();
+        for key in ["a", "b", "c", "d"].iter() {
+            store.set((*key).into(), "v".into()).await.unwrap();
+            time::advance(Duration::from_secs(1)).await;
+        }
+
+        // Action.
+        store.get("a").await.unwrap();
+        let hot = store.hot_keys(3).await.unwrap();
+        let all = store.hot_keys(10).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(hot, vec!["a", "d", "c"]);
+        assert_eq!(all, vec!["a", "d", "c", "b"]);
+    }
+
+    #[tokio::test]
+    async fn idle_time_of_missing_key_is_none() {
+        // Pre-condition.
+        let store = start();
+
+        // Action.
+        let idle = store.idle_time("k").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(idle, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn set_is_visible_to_next_get_under_concurrent_load() {
+        let cases = vec![
+            (InMemoryConfig::default(), "immediate"),
+            (
+                InMemoryConfig {
+                    coalesce_window: Some(Duration::from_millis(5)),
+                    ..InMemoryConfig::default()
+                },
+                "coalescing",
+            ),
+        ];
+
+        for (config, reason) in cases {
+            // Pre-condition.
+            let store = start_with_config(config, Arc::new(NoopObserver));
+
+            // Action.
+            let writers: Vec<_> = (0..16)
+                .map(|writer| {
+                    let mut store = store.clone();
+                    tokio::spawn(async move {
+                        let key = format!("k{}", writer);
+                        for i in 0..200 {
+                            let value: Value = i.to_string().into();
+                            store.set(key.clone(), value.clone()).await.unwrap();
+                            assert_eq!(store.get(&key).await.unwrap(), Some(value));
+                        }
+                    })
+                })
+                .collect();
+
+            // Post-condition.
+            for writer in writers {
+                writer.await.expect(reason);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn checksum_tells_values_apart_and_is_stable() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.set("j".into(), "a".into()).await.unwrap();
+        store.set("i".into(), "b".into()).await.unwrap();
+
+        // Action.
+        let k = store.checksum("k").await.unwrap();
+        let j = store.checksum("j").await.unwrap();
+        let i = store.checksum("i").await.unwrap();
+        let k_again = store.checksum("k").await.unwrap();
+        let missing = store.checksum("h").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(k, Some(crc32fast::hash(b"a")));
+        assert_eq!(k, j);
+        assert_ne!(k, i);
+        assert_eq!(k, k_again);
+        assert_eq!(missing, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ttl_of_expiring_key_counts_down() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        store
+            .expire("k".into(), Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        // Action.
+        time::advance(Duration::from_secs(3)).await;
+        let ttl = store.ttl("k").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(ttl, Some(Expiry::In(Duration::from_secs(7))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn get_with_ttl_returns_value_along_with_expiry() {
+        // Pre-condition.
+        let mut store = start();
+        store
+            .set_ex("k".into(), "a".into(), Duration::from_secs(10))
+            .await
+            .unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+
+        // Action.
+        time::advance(Duration::from_secs(3)).await;
+        let expiring = store.get_with_ttl("k").await.unwrap();
+        let persistent = store.get_with_ttl("j").await.unwrap();
+        let missing = store.get_with_ttl("i").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(
+            expiring,
+            Some(("a".into(), Expiry::In(Duration::from_secs(7))))
+        );
+        assert_eq!(persistent, Some(("b".into(), Expiry::Never)));
+        assert_eq!(missing, None);
+    }
+
+    #[tokio::test]
+    async fn ttl_of_key_without_expiry_is_never() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let ttl = store.ttl("k").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(ttl, Some(Expiry::Never));
+    }
+
+    #[tokio::test]
+    async fn ttl_of_missing_key_is_none() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        let expired = store
+            .expire("k".into(), Duration::from_secs(10))
+            .await
+            .unwrap();
+        let ttl = store.ttl("k").await.unwrap();
+
+        // Post-condition.
+        assert!(!expired);
+        assert_eq!(ttl, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_key_is_gone() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        store
+            .expire("k".into(), Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        // Action.
+        time::advance(Duration::from_secs(10)).await;
+
+        // Post-condition.
+        assert_eq!(store.get("k").await.unwrap(), None);
+        assert_eq!(store.ttl("k").await.unwrap(), None);
+        assert!(store.snapshot().await.unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn renamed_key_is_swept_and_published_once_expired() {
+        // Pre-condition.
+        let mut store = start();
+        let mut mutations = store.subscribe().await.unwrap();
+        store
+            .set_ex("k".into(), "a".into(), Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        // Action.
+        store.rename("k".into(), "j".into()).await.unwrap();
+        time::advance(Duration::from_secs(30)).await;
+
+        // Post-condition.
+        let mut published = vec![];
+        for _ in 0..4 {
+            published.push(mutations.recv().await.unwrap());
+        }
+        assert_eq!(
+            published,
+            vec![
+                Mutation::Set { key: "k".into() },
+                Mutation::Del { key: "k".into() },
+                Mutation::Set { key: "j".into() },
+                Mutation::Expired { key: "j".into() },
+            ]
+        );
+        assert_eq!(store.db_size().await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_key_is_swept_and_published_without_being_accessed() {
+        // Pre-condition.
+        let mut store = start();
+        let mut mutations = store.subscribe().await.unwrap();
+        store
+            .set_ex("k".into(), "a".into(), Duration::from_secs(10))
+            .await
+            .unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+        store
+            .expire("j".into(), Duration::from_secs(20))
+            .await
+            .unwrap();
+        store.set("j".into(), "c".into()).await.unwrap();
+
+        // Action.
+        time::advance(Duration::from_secs(30)).await;
+
+        // Post-condition.
+        let mut published = vec![];
+        for _ in 0..5 {
+            published.push(mutations.recv().await.unwrap());
+        }
+        assert_eq!(
+            published,
+            vec![
+                Mutation::Set { key: "k".into() },
+                Mutation::Set { key: "j".into() },
+                Mutation::Expire { key: "j".into() },
+                Mutation::Set { key: "j".into() },
+                Mutation::Expired { key: "k".into() },
+            ]
+        );
+        assert_eq!(store.db_size().await.unwrap(), 1);
+        assert_eq!(store.last_seq().await.unwrap(), 4);
+        assert!(mutations.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn get_or_wait_is_answered_once_key_is_set() {
+        // Pre-condition.
+        let store = start();
+        let mut writer = store.clone();
+
+        // Action.
+        let (awaited, _) = tokio::join!(
+            store.get_or_wait("k", Duration::from_secs(5)),
+            writer.set("k".into(), "v".into())
+        );
+        let existing = store.get_or_wait("k", Duration::from_secs(5)).await;
+        let missing = store.get_or_wait("other", Duration::from_millis(10)).await;
+
+        // Post-condition.
+        assert_eq!(awaited.unwrap(), Some("v".into()));
+        assert_eq!(existing.unwrap(), Some("v".into()));
+        assert_eq!(missing.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn lpush_appends_items_counted_by_llen() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("e".into(), "".into()).await.unwrap();
+
+        // Action.
+        let lens = vec![
+            store.lpush("k".into(), "a".into()).await.unwrap(),
+            store.lpush("k".into(), "b".into()).await.unwrap(),
+            store.lpush("k".into(), "c".into()).await.unwrap(),
+        ];
+
+        // Post-condition.
+        assert_eq!(lens, vec![1, 2, 3]);
+        assert_eq!(store.llen("k").await.unwrap(), 3);
+        assert_eq!(store.get("k").await.unwrap(), Some("a,b,c".into()));
+        assert_eq!(store.llen("e").await.unwrap(), 0);
+        assert_eq!(store.llen("missing").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn lpush_keeps_expiry_of_list() {
+        // Pre-condition.
+        let mut store = start();
+        store
+            .set_ex("k".into(), "a".into(), Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        // Action.
+        store.lpush("k".into(), "b".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(matches!(
+            store.ttl("k").await.unwrap(),
+            Some(Expiry::In(ttl)) if ttl <= Duration::from_secs(10)
+        ));
+    }
+
+    #[tokio::test]
+    async fn incr_by_adds_delta_to_integer() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        let created = store.incr_by("k".into(), 5).await.unwrap();
+        let incremented = store.incr_by("k".into(), 3).await.unwrap();
+        let decremented = store.incr_by("k".into(), -10).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(created, Some(5));
+        assert_eq!(incremented, Some(8));
+        assert_eq!(decremented, Some(-2));
+        assert_eq!(store.get("k").await.unwrap(), Some("-2".into()));
+    }
+
+    #[tokio::test]
+    async fn incr_by_fails_on_overflow_leaving_value_as_is() {
+        // Pre-condition.
+        let mut store = start();
+        store
+            .set("k".into(), i64::MAX.to_string().into())
+            .await
+            .unwrap();
+
+        // Action.
+        let overflowed = store.incr_by("k".into(), 1).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(overflowed, None);
+        assert_eq!(
+            store.get("k").await.unwrap(),
+            Some(i64::MAX.to_string().into())
+        );
+    }
+
+    #[tokio::test]
+    async fn incr_by_fails_on_non_integer_value() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "one".into()).await.unwrap();
+
+        // Action.
+        let incremented = store.incr_by("k".into(), 1).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(incremented, None);
+        assert_eq!(store.get("k").await.unwrap(), Some("one".into()));
+    }
+
+    #[tokio::test]
+    async fn max_keeps_high_water_mark() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        let created = store
+            .set_extremum("k".into(), Extremum::Max, 5)
+            .await
+            .unwrap();
+        let smaller = store
+            .set_extremum("k".into(), Extremum::Max, 3)
+            .await
+            .unwrap();
+        let again = store
+            .set_extremum("k".into(), Extremum::Max, -10)
+            .await
+            .unwrap();
+        let larger = store
+            .set_extremum("k".into(), Extremum::Max, 8)
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(created, Some(5));
+        assert_eq!(smaller, Some(5));
+        assert_eq!(again, Some(5));
+        assert_eq!(larger, Some(8));
+        assert_eq!(store.get("k").await.unwrap(), Some("8".into()));
+    }
+
+    #[tokio::test]
+    async fn min_keeps_low_water_mark() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "5".into()).await.unwrap();
+
+        // Action.
+        let larger = store
+            .set_extremum("k".into(), Extremum::Min, 7)
+            .await
+            .unwrap();
+        let smaller = store
+            .set_extremum("k".into(), Extremum::Min, -3)
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(larger, Some(5));
+        assert_eq!(smaller, Some(-3));
+        assert_eq!(store.get("k").await.unwrap(), Some("-3".into()));
+    }
+
+    #[tokio::test]
+    async fn extremum_fails_on_non_integer_value() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "one".into()).await.unwrap();
+
+        // Action.
+        let max = store
+            .set_extremum("k".into(), Extremum::Max, 1)
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(max, None);
+        assert_eq!(store.get("k").await.unwrap(), Some("one".into()));
+    }
+
+    #[tokio::test]
+    async fn set_if_match_sets_value_starting_with_pattern() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "v1:a".into()).await.unwrap();
+
+        // Action.
+        let set = store
+            .set_if_match("k".into(), "v1".into(), "v2:b".into())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert!(set);
+        assert_eq!(store.get("k").await.unwrap(), Some("v2:b".into()));
+    }
+
+    #[tokio::test]
+    async fn set_if_match_leaves_value_not_starting_with_pattern_as_is() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "v1:a".into()).await.unwrap();
+
+        // Action.
+        let set = store
+            .set_if_match("k".into(), "a".into(), "v2:b".into())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert!(!set);
+        assert_eq!(store.get("k").await.unwrap(), Some("v1:a".into()));
+    }
+
+    #[tokio::test]
+    async fn set_if_match_does_not_create_missing_key() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        let set = store
+            .set_if_match("k".into(), "".into(), "v".into())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert!(!set);
+        assert_eq!(store.get("k").await.unwrap(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn set_ex_value_is_readable_until_it_expires() {
+        // Pre-condition.
+        let mut store = start();
+        store
+            .set_ex("k".into(), "a".into(), Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        // Action.
+        time::advance(Duration::from_secs(9)).await;
+        let before = store.get("k").await.unwrap();
+        time::advance(Duration::from_secs(1)).await;
+        let after = store.get("k").await.unwrap();
+
+        // Post-condition.
+        assert_eq!(before, Some("a".into()));
+        assert_eq!(after, None);
+    }
+
+    #[tokio::test]
+    async fn set_ex_beyond_representable_instant_never_expires() {
+        // Pre-condition.
+        let mut store = start();
+
+        // Action.
+        store
+            .set_ex("k".into(), "a".into(), Duration::from_secs(u64::MAX))
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(store.ttl("k").await.unwrap(), Some(Expiry::Never));
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn operation_fails_busy_when_backend_stalls() {
+        // Pre-condition.
+        let (commands, _stalled) = mpsc::channel(1);
+        let mut store = super::Store {
+            commands,
+            send_timeout: Some(Duration::from_millis(100)),
+            mutations: broadcast::channel(1).0,
+        };
+        let (cb, _) = oneshot::channel();
+        store.commands.send(Command::Ping { cb }).await.unwrap();
+
+        // Action.
+        let set = store.set("j".into(), "b".into()).await;
+        let get = store.get("k").await;
+
+        // Post-condition.
+        assert!(set.unwrap_err().is::<Busy>());
+        assert!(get.unwrap_err().is::<Busy>());
+    }
+
+    #[tokio::test]
+    async fn queue_depth_grows_while_backend_stalls_until_drained() {
+        // Pre-condition.
+        let (commands, mut stalled) = mpsc::channel(4);
+        let store = super::Store {
+            commands,
+            send_timeout: None,
+            mutations: broadcast::channel(1).0,
+        };
+        let idle = store.queue_depth().await.unwrap();
+
+        // Action.
+        for i in 0..3 {
+            let mut store = store.clone();
+            tokio::spawn(async move { store.set(i.to_string(), "a".into()).await });
+        }
+        while store.queue_depth().await.unwrap().pending < 3 {
+            task::yield_now().await;
+        }
+        let backlogged = store.queue_depth().await.unwrap();
+
+        while stalled.try_recv().is_ok() {}
+        let drained = store.queue_depth().await.unwrap();
+
+        // Post-condition.
+        assert_eq!(
+            idle,
+            QueueDepth {
+                pending: 0,
+                capacity: 4
+            }
+        );
+        assert_eq!(backlogged.pending, 3);
+        assert_eq!(drained.pending, 0);
+    }
+
+    #[tokio::test]
+    async fn get_range_slices_value() {
+        let cases = vec![
+            (0, 3, Some("toy "), "leading range"),
+            (4, 10, Some("storage"), "inner range"),
+            (-7, -1, Some("storage"), "negative indices"),
+            (-100, 2, Some("toy"), "start before beginning"),
+            (4, 100, Some("storage"), "end beyond end"),
+            (5, 2, Some(""), "start after end"),
+            (100, 200, Some(""), "start beyond end"),
+        ];
+
+        for (from, to, expected, reason) in cases {
+            // Pre-condition.
+            let mut store = start();
+            store.set("k".into(), "toy storage".into()).await.unwrap();
+
+            // Action.
+            let range = store.get_range("k", from, to).await.unwrap();
+
+            // Post-condition.
+            assert_eq!(range, expected.map(Value::from), "{}", reason);
+        }
+    }
+
+    #[tokio::test]
+    async fn get_range_of_missing_key_is_none() {
+        // Pre-condition.
+        let store = start();
+
+        // Action.
+        let range = store.get_range("k", 0, -1).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(range, None);
+    }
+
+    #[tokio::test]
+    async fn ping_fails_once_backend_is_gone() {
+        // Pre-condition.
+        let running = start();
+        let (commands, backend) = mpsc::channel(1);
+        let gone = super::Store {
+            commands,
+            send_timeout: None,
+            mutations: broadcast::channel(1).0,
+        };
+        drop(backend);
+
+        // Action.
+        let running = running.ping().await;
+        let gone = gone.ping().await;
+
+        // Post-condition.
+        assert!(running.is_ok());
+        assert!(gone.is_err());
+    }
+
+    #[tokio::test]
+    async fn fill_sets_keys_to_their_index() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("other".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let created = store.fill("k".into(), 100).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(created, 100);
+        assert_eq!(store.db_size().await.unwrap(), 101);
+        assert_eq!(store.get("k:0").await.unwrap(), Some("0".into()));
+        assert_eq!(store.get("k:99").await.unwrap(), Some("99".into()));
+        assert_eq!(store.get("k:100").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn get_del_removes_key_returning_its_value_once() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let first = store.get_del("k".into()).await.unwrap();
+        let second = store.get_del("k".into()).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(first, Some("a".into()));
+        assert_eq!(second, None);
+        assert_eq!(store.get("k").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn observer_is_notified_of_gets_and_sets() {
+        // Pre-condition.
+        let observer = Arc::new(CountingObserver::default());
+        let mut store = start_with_observer(observer.clone());
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.set_nx("k".into(), "b".into()).await.unwrap();
+        store.set_nx("j".into(), "b".into()).await.unwrap();
+        store.get("k").await.unwrap();
+        store.get("i").await.unwrap();
+        store
+            .transaction(vec![Operation::Get { key: "j".into() }])
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(observer.sets.load(Ordering::SeqCst), 2);
+        assert_eq!(observer.hits.load(Ordering::SeqCst), 2);
+        assert_eq!(observer.misses.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn transaction_applies_operations_in_order() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let outcomes = store
+            .transaction(vec![
+                Operation::Get { key: "k".into() },
+                Operation::Set {
+                    key: "k".into(),
+                    value: "b".into(),
+                },
+                Operation::SetNx {
+                    key: "k".into(),
+                    value: "c".into(),
+                },
+                Operation::Rename {
+                    src: "k".into(),
+                    dst: "j".into(),
+                },
+                Operation::Get { key: "j".into() },
+            ])
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Value(Some("a".into())),
+                Outcome::Done,
+                Outcome::Applied(false),
+                Outcome::Applied(true),
+                Outcome::Value(Some("b".into())),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn transaction_if_unchanged_applies_nothing_once_watched_key_changed_version() {
+        // Pre-condition.
+        let mut store = start();
+        let missing = store.version("k").await.unwrap();
+        store.set("k".into(), "a".into()).await.unwrap();
+        let written = store.version("k").await.unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+
+        // Action.
+        let applied = store
+            .transaction_if_unchanged(
+                vec![("k".into(), written)],
+                vec![Operation::Set {
+                    key: "i".into(),
+                    value: "c".into(),
+                }],
+            )
+            .await
+            .unwrap();
+        store.get_del("k".into()).await.unwrap();
+        let aborted = store
+            .transaction_if_unchanged(
+                vec![("k".into(), written)],
+                vec![Operation::Set {
+                    key: "i".into(),
+                    value: "d".into(),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(missing, 0);
+        assert_ne!(written, 0);
+        assert_eq!(applied, Some(vec![Outcome::Done]));
+        assert_eq!(aborted, None);
+        assert_ne!(store.version("k").await.unwrap(), written);
+        assert_eq!(store.get("i").await.unwrap(), Some("c".into()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn version_changes_with_every_write_once_coalesced_writes_are_flushed() {
+        // Pre-condition.
+        let mut store = start_coalescing(Duration::from_millis(10), None);
+        store.set("k".into(), "1".into()).await.unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+        let flushed = store.version("k").await.unwrap();
+
+        // Action.
+        store.incr_by("k".into(), 1).await.unwrap();
+        let aborted = store
+            .transaction_if_unchanged(
+                vec![("k".into(), flushed)],
+                vec![Operation::Set {
+                    key: "j".into(),
+                    value: "a".into(),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_ne!(store.version("k").await.unwrap(), flushed);
+        assert_eq!(aborted, None);
+        assert_eq!(store.get("j").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn removed_key_keeps_version_apart_from_never_written_one() {
+        // Pre-condition.
+        let mut store = start();
+        let missing = store.version("k").await.unwrap();
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.get_del("k".into()).await.unwrap();
+        let aborted = store
+            .transaction_if_unchanged(
+                vec![("k".into(), missing)],
+                vec![Operation::Set {
+                    key: "j".into(),
+                    value: "a".into(),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(missing, 0);
+        assert_ne!(store.version("k").await.unwrap(), 0);
+        assert_eq!(aborted, None);
+    }
+
+    #[tokio::test]
+    async fn removed_keys_share_version_of_latest_removal() {
+        // Pre-condition.
+        let mut store = start();
+        store.set("k".into(), "a".into()).await.unwrap();
+        let missing = store.version("j").await.unwrap();
+
+        // Action.
+        store.set("i".into(), "b".into()).await.unwrap();
+        store.get_del("i".into()).await.unwrap();
+        let aborted = store
+            .transaction_if_unchanged(
+                vec![("j".into(), missing)],
+                vec![Operation::Set {
+                    key: "h".into(),
+                    value: "c".into(),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(aborted, None);
+        assert_eq!(
+            store.version("i").await.unwrap(),
+            store.version("j").await.unwrap()
+        );
+        assert_ne!(
+            store.version("k").await.unwrap(),
+            store.version("i").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn transaction_is_not_interleaved_with_concurrent_writes() {
+        // Pre-condition.
+        let store = start();
+
+        let mut writer = store.clone();
+        let writes = tokio::spawn(async move {
+            for _ in 0..1000 {
+                writer.set("k".into(), "concurrent".into()).await.unwrap();
+            }
+        });
+
+        // Action.
+        let mut transactions = store.clone();
+        let mut outcomes = Vec::new();
+        for _ in 0..100 {
+            let mut run = transactions
+                .transaction(vec![
+                    Operation::Set {
+                        key: "k".into(),
+                        value: "tx".into(),
+                    },
+                    Operation::Get { key: "k".into() },
+                ])
+                .await
+                .unwrap();
+            outcomes.push(run.pop().unwrap());
+        }
+        writes.await.unwrap();
+
+        // Post-condition.
+        assert!(outcomes
+            .into_iter()
+            .all(|outcome| outcome == Outcome::Value(Some("tx".into()))));
+    }
+
+    #[tokio::test]
+    async fn coalesced_sets_are_read_back_right_away() {
+        // Pre-condition.
+        let mut store = start_coalescing(Duration::from_secs(3600), None);
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.set("k".into(), "b".into()).await.unwrap();
+        store.set("j".into(), "c".into()).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(store.get("k").await.unwrap(), Some("b".into()));
+        assert_eq!(store.get("j").await.unwrap(), Some("c".into()));
+        assert_eq!(store.get_del("j".into()).await.unwrap(), Some("c".into()));
+        assert_eq!(store.db_size().await.unwrap(), 1);
+        assert_eq!(store.last_seq().await.unwrap(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_latest_of_coalesced_sets_is_applied_once_window_elapses() {
+        // Pre-condition.
+        let observer = Arc::new(CountingObserver::default());
+        let config = InMemoryConfig {
+            coalesce_window: Some(Duration::from_millis(10)),
+            ..InMemoryConfig::default()
+        };
+        let mut store = start_with_config(config, Arc::clone(&observer) as Arc<dyn Observer>);
+        let mut mutations = store.subscribe().await.unwrap();
+
+        // Action.
+        for value in ["a", "b", "c"] {
+            store.set("k".into(), value.into()).await.unwrap();
+        }
+        let mutation = mutations.recv().await.unwrap();
+
+        // Post-condition.
+        assert_eq!(mutation, Mutation::Set { key: "k".into() });
+        assert_eq!(observer.sets.load(Ordering::SeqCst), 1);
+        assert_eq!(store.get("k").await.unwrap(), Some("c".into()));
+        assert!(mutations.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesced_sets_are_logged_under_seq_of_command_setting_them() {
+        // Pre-condition.
+        let mut store = start_coalescing(Duration::from_millis(10), None);
+
+        // Action.
+        store.set("k".into(), "a".into()).await.unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+        store.set("i".into(), "c".into()).await.unwrap();
+        let log = store.get_log(10).await.unwrap();
+
+        // Post-condition.
+        let entry = |seq, key: &str| LogEntry {
+            seq,
+            mutation: Mutation::Set { key: key.into() },
+        };
+        assert_eq!(log, vec![entry(1, "k"), entry(2, "j"), entry(3, "i")]);
+        assert_eq!(store.last_seq().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn coalesced_sets_count_towards_key_limit() {
+        // Pre-condition.
+        let mut store = start_coalescing(Duration::from_secs(3600), Some(1));
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let other = store.set("j".into(), "b".into()).await;
+        let same = store.set("k".into(), "b".into()).await;
+
+        // Post-condition.
+        assert!(other.unwrap_err().is::<Full>());
+        assert!(same.is_ok());
+        assert_eq!(
+            store.snapshot().await.unwrap(),
+            vec![("k".into(), "b".into())]
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingObserver {
+        hits: AtomicUsize,
+        misses: AtomicUsize,
+        sets: AtomicUsize,
+    }
+
+    impl Observer for CountingObserver {
+        fn on_get(&self, _: KeyRef, hit: bool) {
+            let counter = if hit { &self.hits } else { &self.misses };
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_set(&self, _: KeyRef) {
+            self.sets.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn start_coalescing(window: Duration, max_keys: Option<usize>) -> super::Store {
+        let config = InMemoryConfig {
+            coalesce_window: Some(window),
+            max_keys,
+            ..InMemoryConfig::default()
+        };
+        start_with_config(config, Arc::new(NoopObserver))
+    }
+
+    fn start_capped(max_keys: usize) -> super::Store {
+        let config = InMemoryConfig {
+            max_keys: Some(max_keys),
+            ..InMemoryConfig::default()
+        };
+        start_with_config(config, Arc::new(NoopObserver))
     }
 }
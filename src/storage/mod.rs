@@ -1,14 +1,624 @@
-use self::types::{Key, KeyRef, Value};
+use self::types::{
+    Capabilities, Expiry, Extremum, Key, KeyRef, LogEntry, Mutation, Operation, Outcome,
+    QueueDepth, Value,
+};
+use anyhow::anyhow;
 use async_trait::async_trait;
+use std::{collections::BTreeSet, error::Error, fmt, time::Duration};
+use tokio::sync::broadcast;
 
+#[cfg(feature = "compression")]
+pub mod compressing;
+pub mod dual;
+#[cfg(feature = "hashed-keys")]
+pub mod hashed;
 pub mod inmemory;
+#[cfg(feature = "sled")]
+pub mod sled_store;
 pub mod types;
+pub mod validating;
+pub mod wal;
 
+/// Key-value store.
+///
+/// Operations with a default implementation are optional, failing with
+/// [`Unsupported`] unless a store overrides them.
 #[async_trait]
-pub trait Store {
-    type Err;
+pub trait Store: Send + Sync {
+    type Err: From<Unsupported> + From<anyhow::Error>;
 
     async fn get<'k>(&self, key: KeyRef<'k>) -> Result<Option<Value>, Self::Err>;
 
+    /// Returns once `value` is visible to any later `get` of `key`.
     async fn set(&mut self, key: Key, value: Value) -> Result<(), Self::Err>;
+
+    /// Returns the bytes from `start` up to `end` (inclusive) of the value under `key`, if it exists.
+    ///
+    /// See [`slice`] for how indices are interpreted.
+    async fn get_range<'k>(
+        &self,
+        key: KeyRef<'k>,
+        start: i64,
+        end: i64,
+    ) -> Result<Option<Value>, Self::Err> {
+        Ok(self.get(key).await?.map(|value| slice(&value, start, end)))
+    }
+
+    /// Sets `key` to `value`, returning the value it held before, if any.
+    async fn set_get(&mut self, key: Key, value: Value) -> Result<Option<Value>, Self::Err> {
+        let ops = vec![
+            Operation::Get { key: key.clone() },
+            Operation::Set { key, value },
+        ];
+        match self.transaction(ops).await?.into_iter().next() {
+            Some(Outcome::Value(old)) => Ok(old),
+            outcome => Err(anyhow!("unexpected outcome {:?} of GET", outcome).into()),
+        }
+    }
+
+    /// Sets `key` to `value` only if `key` does not exist yet.
+    ///
+    /// Returns `false`, leaving the prior value untouched, when `key` exists.
+    async fn set_nx(&mut self, key: Key, value: Value) -> Result<bool, Self::Err>;
+
+    /// Moves the value stored under `src` to `dst`, overriding any value
+    /// previously stored under `dst`.
+    ///
+    /// Returns `false` when `src` does not exist.
+    async fn rename(&mut self, src: Key, dst: Key) -> Result<bool, Self::Err>;
+
+    /// Removes `key`, returning the value it held, if any.
+    async fn get_del(&mut self, key: Key) -> Result<Option<Value>, Self::Err>;
+
+    /// Applies all `ops` in order without interleaving them with any other operation.
+    ///
+    /// Returns one [`Outcome`] per operation.
+    async fn transaction(&mut self, ops: Vec<Operation>) -> Result<Vec<Outcome>, Self::Err>;
+
+    /// Returns the version of `key`, which changes whenever it is written, set to expire or
+    /// removed. Missing keys share the version of the latest removal, `0` until any.
+    async fn version<'k>(&self, _key: KeyRef<'k>) -> Result<u64, Self::Err> {
+        Err(Unsupported("WATCH").into())
+    }
+
+    /// Applies all `ops` as per `transaction`, unless any key of `watched` is no longer at the
+    /// version paired with it, checking so without interleaving any other operation.
+    ///
+    /// Returns one [`Outcome`] per operation, or `None` if nothing was applied.
+    async fn transaction_if_unchanged(
+        &mut self,
+        _watched: Vec<(Key, u64)>,
+        _ops: Vec<Operation>,
+    ) -> Result<Option<Vec<Outcome>>, Self::Err> {
+        Err(Unsupported("WATCH").into())
+    }
+
+    /// Returns every key-value pair, in no particular order.
+    async fn snapshot(&self) -> Result<Vec<(Key, Value)>, Self::Err>;
+
+    /// Returns every key-value pair whose key starts with `prefix`, sorted by key.
+    async fn get_prefix<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, Value)>, Self::Err> {
+        let mut pairs: Vec<_> = self
+            .snapshot()
+            .await?
+            .into_iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .collect();
+        pairs.sort();
+        Ok(pairs)
+    }
+
+    /// Returns the size in bytes of the value under every key starting with `prefix`, largest
+    /// first, ties being sorted by key.
+    async fn mem_usage<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, usize)>, Self::Err> {
+        let pairs = self.get_prefix(prefix).await?;
+        Ok(by_size(
+            pairs.into_iter().map(|(key, value)| (key, value.len())),
+        ))
+    }
+
+    /// Returns the distinct segments following `prefix` up to `separator` among the keys
+    /// starting with `prefix`, sorted, e.g. `name` and `address` for the keys `user:1:name`
+    /// and `user:1:address:city` under `user:1:` with `:`.
+    async fn children<'p>(
+        &self,
+        prefix: KeyRef<'p>,
+        separator: &'p str,
+    ) -> Result<Vec<String>, Self::Err> {
+        let pairs = self.snapshot().await?;
+        Ok(children(
+            pairs.iter().map(|(key, _)| key.as_str()),
+            prefix,
+            separator,
+        ))
+    }
+
+    /// Returns every key whose value contains `needle`, sorted.
+    ///
+    /// Every value is scanned, hence this is meant for debugging rather than regular use.
+    async fn find_value<'n>(&self, needle: &'n [u8]) -> Result<Vec<Key>, Self::Err> {
+        let pairs = self.snapshot().await?;
+        Ok(containing(
+            pairs.iter().map(|(key, value)| (key.as_str(), value)),
+            needle,
+        ))
+    }
+
+    /// Sets every key from `$prefix:0` up to `$prefix:$count` (exclusive) to its index.
+    ///
+    /// Returns how many keys were set.
+    async fn fill(&mut self, prefix: Key, count: usize) -> Result<usize, Self::Err> {
+        let ops = (0..count)
+            .map(|i| Operation::Set {
+                key: format!("{}:{}", prefix, i),
+                value: i.to_string().into(),
+            })
+            .collect();
+        self.transaction(ops).await?;
+        Ok(count)
+    }
+
+    /// Returns how many keys exist.
+    async fn db_size(&self) -> Result<usize, Self::Err> {
+        Ok(self.snapshot().await?.len())
+    }
+
+    /// Returns the sequence number of the last command that mutated data, `0` if none did.
+    ///
+    /// Every such command bumps it by exactly one, e.g. so that a follower knows how far
+    /// behind it is.
+    async fn last_seq(&self) -> Result<u64, Self::Err> {
+        Err(Unsupported("LASTSEQ").into())
+    }
+
+    /// Returns the last `count` mutations recorded in the operation log, oldest first.
+    ///
+    /// Only a bounded number of the most recent mutations is recorded, expiries excluded.
+    async fn get_log(&self, _count: usize) -> Result<Vec<LogEntry>, Self::Err> {
+        Err(Unsupported("GETLOG").into())
+    }
+
+    /// Returns which optional features the store supports, only scanning by default as it
+    /// builds on snapshots.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            scan: true,
+            ..Capabilities::default()
+        }
+    }
+
+    /// Checks that the store is able to serve operations.
+    async fn ping(&self) -> Result<(), Self::Err> {
+        Ok(())
+    }
+
+    /// Returns the value of `key`, waiting up to `timeout` for it to be written unless it exists.
+    async fn get_or_wait<'k>(
+        &self,
+        _key: KeyRef<'k>,
+        _timeout: Duration,
+    ) -> Result<Option<Value>, Self::Err> {
+        Err(Unsupported("BGET").into())
+    }
+
+    /// Returns the CRC32 of the value under `key`, if any.
+    async fn checksum<'k>(&self, _key: KeyRef<'k>) -> Result<Option<u32>, Self::Err> {
+        Err(Unsupported("CHECKSUM").into())
+    }
+
+    /// Returns how long ago `key` was last read or written, if it exists.
+    async fn idle_time<'k>(&self, _key: KeyRef<'k>) -> Result<Option<Duration>, Self::Err> {
+        Err(Unsupported("IDLETIME").into())
+    }
+
+    /// Returns up to `count` keys, those read or written most recently first.
+    async fn hot_keys(&self, _count: usize) -> Result<Vec<Key>, Self::Err> {
+        Err(Unsupported("HOTKEYS").into())
+    }
+
+    /// Makes `key` expire once `ttl` has elapsed, until it is set again.
+    ///
+    /// Returns `false` when `key` does not exist.
+    async fn expire(&mut self, _key: Key, _ttl: Duration) -> Result<bool, Self::Err> {
+        Err(Unsupported("EXPIRE").into())
+    }
+
+    /// Sets `key` to `value`, making it expire once `ttl` has elapsed.
+    async fn set_ex(&mut self, _key: Key, _value: Value, _ttl: Duration) -> Result<(), Self::Err> {
+        Err(Unsupported("SETEX").into())
+    }
+
+    /// Adds `delta` to the integer under `key`, a missing key counting as `0`.
+    ///
+    /// Returns the resulting integer, or `None` if the value under `key` is not
+    /// an integer or the result would overflow, the value being left as is.
+    async fn incr_by(&mut self, _key: Key, _delta: i64) -> Result<Option<i64>, Self::Err> {
+        Err(Unsupported("INCRBY").into())
+    }
+
+    /// Sets `key` to `candidate` if it is the `extremum` of both, a missing key always being set.
+    ///
+    /// Returns the resulting integer, or `None` if the value under `key` is not
+    /// an integer, the value being left as is.
+    async fn set_extremum(
+        &mut self,
+        _key: Key,
+        extremum: Extremum,
+        _candidate: i64,
+    ) -> Result<Option<i64>, Self::Err> {
+        let command = match extremum {
+            Extremum::Max => "MAX",
+            Extremum::Min => "MIN",
+        };
+        Err(Unsupported(command).into())
+    }
+
+    /// Sets `key` to `value` only if its current value starts with `pattern`.
+    ///
+    /// Returns whether `value` was set, a missing key never matching.
+    async fn set_if_match(
+        &mut self,
+        _key: Key,
+        _pattern: Value,
+        _value: Value,
+    ) -> Result<bool, Self::Err> {
+        Err(Unsupported("SETIFMATCH").into())
+    }
+
+    /// Removes `key` only if its current value equals `expected`.
+    ///
+    /// Returns whether `key` was removed, a missing key never matching.
+    async fn del_if_equals(&mut self, _key: Key, _expected: Value) -> Result<bool, Self::Err> {
+        Err(Unsupported("DELIFEQ").into())
+    }
+
+    /// Sets `key` to `value` unless a write carrying `token` was applied recently.
+    ///
+    /// Returns whether `value` was set, so that a retried write is applied at most once.
+    async fn set_idem(
+        &mut self,
+        _key: Key,
+        _token: String,
+        _value: Value,
+    ) -> Result<bool, Self::Err> {
+        Err(Unsupported("SETIDEM").into())
+    }
+
+    /// Sets every key of `pairs` to its value only if none of them exists, all at once.
+    ///
+    /// Returns whether the values were set, nothing being written otherwise.
+    async fn mset_nx(&mut self, _pairs: Vec<(Key, Value)>) -> Result<bool, Self::Err> {
+        Err(Unsupported("MSETNX").into())
+    }
+
+    /// Appends `item` to the list held by the value under `key`, its items being separated by
+    /// [`LIST_DELIMITER`](types::LIST_DELIMITER), a missing key holding an empty list.
+    ///
+    /// Returns the number of items once `item` is appended.
+    async fn lpush(&mut self, _key: Key, _item: Value) -> Result<usize, Self::Err> {
+        Err(Unsupported("LPUSH").into())
+    }
+
+    /// Returns the number of items of the list held by the value under `key`, as per `lpush`.
+    async fn llen<'k>(&self, _key: KeyRef<'k>) -> Result<usize, Self::Err> {
+        Err(Unsupported("LLEN").into())
+    }
+
+    /// Discards whatever the store keeps that no longer affects its data, e.g. superseded
+    /// records of a log.
+    async fn compact(&mut self) -> Result<(), Self::Err> {
+        Err(Unsupported("COMPACT").into())
+    }
+
+    /// Returns once every write applied so far is durable, right away for volatile stores.
+    async fn sync(&self) -> Result<(), Self::Err> {
+        Ok(())
+    }
+
+    /// Returns how long `key` has left before expiring, if it exists.
+    async fn ttl<'k>(&self, _key: KeyRef<'k>) -> Result<Option<Expiry>, Self::Err> {
+        Err(Unsupported("TTL").into())
+    }
+
+    /// Returns a receiver of every mutation applied from now on.
+    ///
+    /// A receiver lagging too far behind misses the oldest mutations it has yet to receive.
+    async fn subscribe(&self) -> Result<broadcast::Receiver<Mutation>, Self::Err> {
+        Err(Unsupported("PSUBSCRIBE").into())
+    }
+
+    /// Returns the value of `key` along with how long it has left before expiring, if it exists.
+    async fn get_with_ttl<'k>(
+        &self,
+        _key: KeyRef<'k>,
+    ) -> Result<Option<(Value, Expiry)>, Self::Err> {
+        Err(Unsupported("GETTTL").into())
+    }
+
+    /// Returns how full the queue of operations awaiting the backend is, e.g. to tell
+    /// backpressure apart from slow operations.
+    async fn queue_depth(&self) -> Result<QueueDepth, Self::Err> {
+        Err(Unsupported("INFO").into())
+    }
+}
+
+/// Returns `sizes` largest first, ties being sorted by key.
+pub fn by_size(sizes: impl Iterator<Item = (Key, usize)>) -> Vec<(Key, usize)> {
+    let mut sizes: Vec<_> = sizes.collect();
+    sizes.sort_by(|(a, a_size), (b, b_size)| b_size.cmp(a_size).then_with(|| a.cmp(b)));
+    sizes
+}
+
+/// Returns the distinct segments following `prefix` up to `separator` among `keys`, sorted.
+///
+/// Keys equal to `prefix` have no segment following it, hence are skipped.
+pub fn children<'k>(
+    keys: impl Iterator<Item = KeyRef<'k>>,
+    prefix: KeyRef,
+    separator: &str,
+) -> Vec<String> {
+    let segments: BTreeSet<_> = keys
+        .filter_map(|key| key.strip_prefix(prefix))
+        .filter_map(|rest| rest.split(separator).next())
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    segments.into_iter().map(str::to_owned).collect()
+}
+
+/// Returns the keys among `pairs` whose value contains `needle`, sorted.
+pub fn containing<'k>(
+    pairs: impl Iterator<Item = (KeyRef<'k>, &'k Value)>,
+    needle: &[u8],
+) -> Vec<Key> {
+    let mut keys: Vec<_> = pairs
+        .filter(|(_, value)| needle.is_empty() || value.windows(needle.len()).any(|w| w == needle))
+        .map(|(key, _)| key.to_owned())
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// Returns the bytes of `value` from `start` up to `end` (inclusive).
+///
+/// Negative indices count from the end, `-1` being the last byte, and indices
+/// beyond either end are clamped, such that an empty range yields no bytes.
+pub fn slice(value: &Value, start: i64, end: i64) -> Value {
+    let len = value.len() as i64;
+    let resolve = |i: i64| if i < 0 { len + i } else { i };
+    let (start, end) = (resolve(start).max(0), resolve(end).min(len - 1));
+    if start > end {
+        return Value::new();
+    }
+    value.slice(start as usize..=end as usize)
+}
+
+/// Hook notified of operations as a store applies them, e.g. for instrumentation.
+///
+/// Every callback does nothing by default. Callbacks run while the store
+/// applies operations, so they should return promptly.
+pub trait Observer: Send + Sync {
+    /// Called once `key` is read, `hit` telling whether it exists.
+    fn on_get(&self, _key: KeyRef, _hit: bool) {}
+
+    /// Called once a value is written under `key`.
+    fn on_set(&self, _key: KeyRef) {}
+}
+
+/// Observer ignoring every operation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl Observer for NoopObserver {}
+
+/// Error of a store lacking support for an optional operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsupported(pub &'static str);
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not supported by this store", self.0)
+    }
+}
+
+impl Error for Unsupported {}
+
+/// Error of a store too busy to accept an operation in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Busy;
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("store is busy")
+    }
+}
+
+impl Error for Busy {}
+
+/// Error of a store holding as many keys as it may, hence refusing to create another one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Full;
+
+impl fmt::Display for Full {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("store is full")
+    }
+}
+
+impl Error for Full {}
+
+/// Error of a value violating a rule of the key it is written under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invalid(pub Key);
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid value for key {}", self.0)
+    }
+}
+
+impl Error for Invalid {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{dual::DualStore, validating::ValidatingStore, wal::WalStore};
+    use anyhow::Result;
+
+    /// Store whose transactions answer every operation as if it were a `SET`.
+    struct Misbehaving;
+
+    #[async_trait]
+    impl Store for Misbehaving {
+        type Err = anyhow::Error;
+
+        async fn get<'k>(&self, _: KeyRef<'k>) -> Result<Option<Value>> {
+            Ok(None)
+        }
+
+        async fn set(&mut self, _: Key, _: Value) -> Result<()> {
+            Ok(())
+        }
+
+        async fn set_nx(&mut self, _: Key, _: Value) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn rename(&mut self, _: Key, _: Key) -> Result<bool> {
+            Ok(false)
+        }
+
+        async fn get_del(&mut self, _: Key) -> Result<Option<Value>> {
+            Ok(None)
+        }
+
+        async fn transaction(&mut self, ops: Vec<Operation>) -> Result<Vec<Outcome>> {
+            Ok(ops.iter().map(|_| Outcome::Done).collect())
+        }
+
+        async fn snapshot(&self) -> Result<Vec<(Key, Value)>> {
+            Ok(vec![])
+        }
+    }
+
+    /// Whether `outcome` is that of an operation the store supports.
+    fn supported<T>(outcome: Result<T>) -> bool {
+        match outcome {
+            Ok(_) => true,
+            Err(e) if e.is::<Unsupported>() => false,
+            Err(e) => panic!("unexpected failure: {:#}", e),
+        }
+    }
+
+    /// Tells the capabilities of `store` apart by trying them, `SYNC` aside as volatile stores
+    /// answer it all the same.
+    async fn probe<S: Store<Err = anyhow::Error>>(mut store: S) -> Capabilities {
+        Capabilities {
+            expiry: supported(store.expire("k".into(), Duration::from_secs(60)).await),
+            sync: store.capabilities().sync,
+            scan: supported(store.get_prefix("").await),
+            subscribe: supported(store.subscribe().await),
+        }
+    }
+
+    /// Returns the capabilities `store` reports, along with those it actually has.
+    async fn observe<S: Store<Err = anyhow::Error>>(store: S) -> (Capabilities, Capabilities) {
+        (store.capabilities(), probe(store).await)
+    }
+
+    #[tokio::test]
+    async fn set_get_fails_on_unexpected_outcome_instead_of_panicking() {
+        // Pre-condition.
+        let mut store = Misbehaving;
+
+        // Action.
+        let set_get = store.set_get("k".into(), "a".into()).await;
+
+        // Post-condition.
+        let reason = format!("{:#}", set_get.unwrap_err());
+        assert!(reason.contains("unexpected outcome"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn reported_capabilities_match_actual_support() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let wal = WalStore::open(
+            dir.path().join("wal"),
+            inmemory::start(),
+            wal::WalConfig::default(),
+        )
+        .await
+        .unwrap();
+        let all = Capabilities {
+            expiry: true,
+            sync: false,
+            scan: true,
+            subscribe: true,
+        };
+
+        // Action.
+        let cases = vec![
+            ("inmemory", observe(inmemory::start()).await, all),
+            (
+                "wal",
+                observe(wal.clone()).await,
+                Capabilities {
+                    expiry: false,
+                    sync: true,
+                    ..all
+                },
+            ),
+            (
+                "validating",
+                observe(ValidatingStore::new(inmemory::start())).await,
+                all,
+            ),
+            (
+                "dual",
+                observe(DualStore::new(inmemory::start(), wal)).await,
+                Capabilities {
+                    expiry: false,
+                    sync: true,
+                    scan: true,
+                    subscribe: false,
+                },
+            ),
+            #[cfg(feature = "hashed-keys")]
+            (
+                "hashed",
+                observe(hashed::HashedKeyStore::new(inmemory::start())).await,
+                Capabilities {
+                    scan: false,
+                    subscribe: false,
+                    ..all
+                },
+            ),
+            #[cfg(feature = "compression")]
+            (
+                "compressing",
+                observe(compressing::CompressingStore::new(
+                    inmemory::start(),
+                    Default::default(),
+                ))
+                .await,
+                all,
+            ),
+            #[cfg(feature = "sled")]
+            (
+                "sled",
+                observe(sled_store::SledStore::open(dir.path().join("sled")).unwrap()).await,
+                Capabilities {
+                    sync: true,
+                    scan: true,
+                    ..Capabilities::default()
+                },
+            ),
+        ];
+
+        // Post-condition.
+        for (name, (reported, actual), expected) in cases {
+            assert_eq!(reported, expected, "{}", name);
+            assert_eq!(actual, expected, "{}", name);
+        }
+    }
 }
@@ -0,0 +1,258 @@
+//! Persistent key-value storage backed by [sled](https://docs.rs/sled).
+
+use super::types::{Capabilities, Key, KeyRef, Operation, Outcome, Value};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use sled::{transaction::ConflictableTransactionResult, Db, IVec};
+use std::path::Path;
+
+/// Handle to a sled database, cheap to clone and share between connections.
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    db: Db,
+}
+
+impl SledStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let db = sled::open(path)
+            .with_context(|| format!("unable to open sled database at {}", path.display()))?;
+        Ok(Self { db })
+    }
+}
+
+#[async_trait]
+impl super::Store for SledStore {
+    type Err = anyhow::Error;
+
+    async fn get<'k>(&self, key: KeyRef<'k>) -> Result<Option<Value>, Self::Err> {
+        self.db
+            .get(key)
+            .context("unable to read from sled")
+            .map(|value| value.map(into_value))
+    }
+
+    async fn set(&mut self, key: Key, value: Value) -> Result<(), Self::Err> {
+        self.db
+            .insert(key, value.as_ref())
+            .context("unable to write to sled")?;
+        Ok(())
+    }
+
+    async fn set_nx(&mut self, key: Key, value: Value) -> Result<bool, Self::Err> {
+        let swapped = self
+            .db
+            .compare_and_swap(key, None::<&[u8]>, Some(value.as_ref()))
+            .context("unable to write to sled")?;
+        Ok(swapped.is_ok())
+    }
+
+    async fn rename(&mut self, src: Key, dst: Key) -> Result<bool, Self::Err> {
+        self.db
+            .transaction(|tx| -> ConflictableTransactionResult<bool> {
+                match tx.remove(src.as_bytes())? {
+                    Some(value) => {
+                        tx.insert(dst.as_bytes(), value)?;
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                }
+            })
+            .map_err(|e| anyhow!("unable to rename in sled: {:?}", e))
+    }
+
+    async fn get_del(&mut self, key: Key) -> Result<Option<Value>, Self::Err> {
+        self.db
+            .remove(key)
+            .context("unable to remove from sled")
+            .map(|value| value.map(into_value))
+    }
+
+    async fn transaction(&mut self, ops: Vec<Operation>) -> Result<Vec<Outcome>, Self::Err> {
+        self.db
+            .transaction(|tx| -> ConflictableTransactionResult<Vec<Outcome>> {
+                let mut outcomes = Vec::with_capacity(ops.len());
+                for op in &ops {
+                    let outcome = match op {
+                        Operation::Get { key } => {
+                            Outcome::Value(tx.get(key.as_bytes())?.map(into_value))
+                        }
+                        Operation::Set { key, value } => {
+                            tx.insert(key.as_bytes(), value.as_ref())?;
+                            Outcome::Done
+                        }
+                        Operation::SetNx { key, value } => {
+                            let absent = tx.get(key.as_bytes())?.is_none();
+                            if absent {
+                                tx.insert(key.as_bytes(), value.as_ref())?;
+                            }
+                            Outcome::Applied(absent)
+                        }
+                        Operation::Rename { src, dst } => match tx.remove(src.as_bytes())? {
+                            Some(value) => {
+                                tx.insert(dst.as_bytes(), value)?;
+                                Outcome::Applied(true)
+                            }
+                            None => Outcome::Applied(false),
+                        },
+                        Operation::GetDel { key } => {
+                            Outcome::Value(tx.remove(key.as_bytes())?.map(into_value))
+                        }
+                    };
+                    outcomes.push(outcome);
+                }
+                Ok(outcomes)
+            })
+            .map_err(|e| anyhow!("unable to apply transaction in sled: {:?}", e))
+    }
+
+    async fn snapshot(&self) -> Result<Vec<(Key, Value)>, Self::Err> {
+        self.db
+            .iter()
+            .map(|pair| {
+                let (key, value) = pair.context("unable to read from sled")?;
+                let key =
+                    String::from_utf8(key.to_vec()).context("stored key is not valid UTF-8")?;
+                Ok((key, into_value(value)))
+            })
+            .collect()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sync: true,
+            scan: true,
+            ..Capabilities::default()
+        }
+    }
+
+    async fn sync(&self) -> Result<(), Self::Err> {
+        self.db
+            .flush_async()
+            .await
+            .context("unable to flush sled")?;
+        Ok(())
+    }
+}
+
+fn into_value(bytes: IVec) -> Value {
+    Value::copy_from_slice(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Store;
+
+    #[tokio::test]
+    async fn get_after_reopen_returns_value_set_before() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut store = SledStore::open(dir.path()).unwrap();
+            store.set("k".into(), "a".into()).await.unwrap();
+            store.set("j".into(), "b".into()).await.unwrap();
+        }
+
+        // Action.
+        let store = SledStore::open(dir.path()).unwrap();
+
+        // Post-condition.
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+        assert_eq!(store.get("j").await.unwrap(), Some("b".into()));
+        assert_eq!(store.get("i").await.unwrap(), None);
+        assert_eq!(
+            store.snapshot().await.unwrap(),
+            vec![("j".into(), "b".into()), ("k".into(), "a".into())]
+        );
+    }
+
+    #[tokio::test]
+    async fn set_nx_on_existing_key_preserves_prior_value() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SledStore::open(dir.path()).unwrap();
+
+        // Action.
+        let first = store.set_nx("k".into(), "a".into()).await.unwrap();
+        let second = store.set_nx("k".into(), "b".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(first);
+        assert!(!second);
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn rename_moves_value_to_destination() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SledStore::open(dir.path()).unwrap();
+        store.set("src".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let renamed = store.rename("src".into(), "dst".into()).await.unwrap();
+        let renamed_again = store.rename("src".into(), "dst".into()).await.unwrap();
+
+        // Post-condition.
+        assert!(renamed);
+        assert!(!renamed_again);
+        assert_eq!(store.get("src").await.unwrap(), None);
+        assert_eq!(store.get("dst").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn get_del_removes_key_returning_its_value_once() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SledStore::open(dir.path()).unwrap();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let first = store.get_del("k".into()).await.unwrap();
+        let second = store.get_del("k".into()).await.unwrap();
+
+        // Post-condition.
+        assert_eq!(first, Some("a".into()));
+        assert_eq!(second, None);
+    }
+
+    #[tokio::test]
+    async fn transaction_applies_operations_in_order() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SledStore::open(dir.path()).unwrap();
+
+        // Action.
+        let outcomes = store
+            .transaction(vec![
+                Operation::Set {
+                    key: "k".into(),
+                    value: "a".into(),
+                },
+                Operation::SetNx {
+                    key: "k".into(),
+                    value: "b".into(),
+                },
+                Operation::Rename {
+                    src: "k".into(),
+                    dst: "j".into(),
+                },
+                Operation::Get { key: "j".into() },
+            ])
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Done,
+                Outcome::Applied(false),
+                Outcome::Applied(true),
+                Outcome::Value(Some("a".into())),
+            ]
+        );
+        assert_eq!(store.get("k").await.unwrap(), None);
+    }
+}
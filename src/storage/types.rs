@@ -1,3 +1,6 @@
+use super::Full;
+use bytes::Bytes;
+use std::time::Duration;
 use tokio::sync::oneshot;
 
 #[derive(Debug)]
@@ -9,9 +12,279 @@ pub enum Command {
     Set {
         key: Key,
         value: Value,
+        cb: oneshot::Sender<Result<(), Full>>,
+    },
+    SetNx {
+        key: Key,
+        value: Value,
+        cb: oneshot::Sender<Result<bool, Full>>,
+    },
+    SetGet {
+        key: Key,
+        value: Value,
+        cb: oneshot::Sender<Result<Option<Value>, Full>>,
+    },
+    Rename {
+        src: Key,
+        dst: Key,
+        cb: oneshot::Sender<bool>,
+    },
+    Transaction {
+        ops: Vec<Operation>,
+        cb: oneshot::Sender<Result<Vec<Outcome>, Full>>,
+    },
+    Version {
+        key: Key,
+        cb: oneshot::Sender<u64>,
+    },
+    /// Answered with `None`, applying nothing, if any key of `watched` changed version.
+    TransactionIfUnchanged {
+        watched: Vec<(Key, u64)>,
+        ops: Vec<Operation>,
+        cb: oneshot::Sender<Result<Option<Vec<Outcome>>, Full>>,
+    },
+    Snapshot {
+        cb: oneshot::Sender<Vec<(Key, Value)>>,
+    },
+    GetPrefix {
+        prefix: Key,
+        cb: oneshot::Sender<Vec<(Key, Value)>>,
+    },
+    MemUsage {
+        prefix: Key,
+        cb: oneshot::Sender<Vec<(Key, usize)>>,
+    },
+    Children {
+        prefix: Key,
+        separator: String,
+        cb: oneshot::Sender<Vec<String>>,
+    },
+    FindValue {
+        needle: Value,
+        cb: oneshot::Sender<Vec<Key>>,
+    },
+    Checksum {
+        key: Key,
+        cb: oneshot::Sender<Option<u32>>,
+    },
+    IdleTime {
+        key: Key,
+        cb: oneshot::Sender<Option<Duration>>,
+    },
+    HotKeys {
+        count: usize,
+        cb: oneshot::Sender<Vec<Key>>,
+    },
+    Expire {
+        key: Key,
+        ttl: Duration,
+        cb: oneshot::Sender<bool>,
+    },
+    Ttl {
+        key: Key,
+        cb: oneshot::Sender<Option<Expiry>>,
+    },
+    GetWithTtl {
+        key: Key,
+        cb: oneshot::Sender<Option<(Value, Expiry)>>,
+    },
+    GetDel {
+        key: Key,
+        cb: oneshot::Sender<Option<Value>>,
+    },
+    SetEx {
+        key: Key,
+        value: Value,
+        ttl: Duration,
+        cb: oneshot::Sender<Result<(), Full>>,
+    },
+    Fill {
+        prefix: Key,
+        count: usize,
+        cb: oneshot::Sender<usize>,
+    },
+    DbSize {
+        cb: oneshot::Sender<usize>,
+    },
+    LastSeq {
+        cb: oneshot::Sender<u64>,
+    },
+    GetLog {
+        count: usize,
+        cb: oneshot::Sender<Vec<LogEntry>>,
+    },
+    GetRange {
+        key: Key,
+        start: i64,
+        end: i64,
+        cb: oneshot::Sender<Option<Value>>,
+    },
+    Ping {
+        cb: oneshot::Sender<()>,
+    },
+    IncrBy {
+        key: Key,
+        delta: i64,
+        cb: oneshot::Sender<Result<Option<i64>, Full>>,
+    },
+    SetExtremum {
+        key: Key,
+        extremum: Extremum,
+        candidate: i64,
+        cb: oneshot::Sender<Result<Option<i64>, Full>>,
+    },
+    SetIfMatch {
+        key: Key,
+        pattern: Value,
+        value: Value,
+        cb: oneshot::Sender<bool>,
+    },
+    DelIfEquals {
+        key: Key,
+        expected: Value,
+        cb: oneshot::Sender<bool>,
+    },
+    SetIdem {
+        key: Key,
+        token: String,
+        value: Value,
+        cb: oneshot::Sender<Result<bool, Full>>,
+    },
+    MSetNx {
+        pairs: Vec<(Key, Value)>,
+        cb: oneshot::Sender<Result<bool, Full>>,
+    },
+    LPush {
+        key: Key,
+        item: Value,
+        cb: oneshot::Sender<Result<usize, Full>>,
+    },
+    LLen {
+        key: Key,
+        cb: oneshot::Sender<usize>,
+    },
+    /// Answered once `key` exists, which may be right away.
+    GetOrWait {
+        key: Key,
+        cb: oneshot::Sender<Value>,
     },
 }
 
+/// Operation applied as part of a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Get { key: Key },
+    Set { key: Key, value: Value },
+    SetNx { key: Key, value: Value },
+    Rename { src: Key, dst: Key },
+    GetDel { key: Key },
+}
+
+/// Outcome of an [`Operation`], mirroring what the equivalent `Store` method returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Value(Option<Value>),
+    Done,
+    Applied(bool),
+}
+
+/// Change applied to a key, as published to subscribers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Mutation {
+    /// A value was written under `key`.
+    Set { key: Key },
+    /// `key` was removed.
+    Del { key: Key },
+    /// `key` was set to expire.
+    Expire { key: Key },
+    /// `key` was removed once its item expired.
+    Expired { key: Key },
+}
+
+impl Mutation {
+    pub fn key(&self) -> KeyRef<'_> {
+        match self {
+            Mutation::Set { key }
+            | Mutation::Del { key }
+            | Mutation::Expire { key }
+            | Mutation::Expired { key } => key,
+        }
+    }
+}
+
+/// Mutation recorded in the operation log, along with the sequence number of the command
+/// applying it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LogEntry {
+    pub seq: u64,
+    pub mutation: Mutation,
+}
+
+/// Optional features a store supports.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+    /// Items may be made to expire, i.e. via `EXPIRE` and `SETEX`.
+    pub expiry: bool,
+    /// Writes are made durable, at the latest once `SYNC` answers.
+    pub sync: bool,
+    /// Keys may be listed by prefix, e.g. via `GETPREFIX`.
+    pub scan: bool,
+    /// Mutations are streamed to subscribers, i.e. via `PSUBSCRIBE` and `SUBSCRIBE-EXPIRED`.
+    pub subscribe: bool,
+}
+
+impl Capabilities {
+    /// Names of the features supported, in declaration order.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            ("expiry", self.expiry),
+            ("sync", self.sync),
+            ("scan", self.scan),
+            ("subscribe", self.subscribe),
+        ]
+        .iter()
+        .filter_map(|&(name, supported)| supported.then_some(name))
+        .collect()
+    }
+}
+
+/// Which of its current integer and a candidate one a key keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Extremum {
+    Max,
+    Min,
+}
+
+impl Extremum {
+    pub fn of(self, a: i64, b: i64) -> i64 {
+        match self {
+            Extremum::Max => a.max(b),
+            Extremum::Min => a.min(b),
+        }
+    }
+}
+
+/// Fill level of the queue of operations awaiting the backend of a store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueDepth {
+    /// Operations queued, yet to be taken by the backend.
+    pub pending: usize,
+    /// Operations that may be queued before further ones must wait.
+    pub capacity: usize,
+}
+
+/// Remaining lifetime of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// The key lives until removed.
+    Never,
+    /// The key expires once this long has elapsed.
+    In(Duration),
+}
+
 pub type Key = String;
 pub type KeyRef<'a> = &'a str;
-pub type Value = String;
+pub type Value = Bytes;
+
+/// Separates the items of a list-like value, as appended to via `LPUSH`.
+pub const LIST_DELIMITER: u8 = b',';
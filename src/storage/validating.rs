@@ -0,0 +1,449 @@
+//! Values checked against rules before reaching an inner store.
+//!
+//! Each rule applies to the keys starting with its prefix, a value written
+//! under a key having to satisfy every rule applying to it, whereas keys no
+//! rule applies to are unrestricted. Values that are not UTF-8 violate any
+//! rule applying to them.
+//!
+//! Values computed by the store cannot be checked before they are written,
+//! hence `INCRBY` and `LPUSH` are refused under keys any rule applies to,
+//! while `MAX` and `MIN` check their candidate, which is all they may write.
+//! A rename is refused unless every rule applying to its destination applies
+//! to its source as well, whose value thus already satisfies them.
+
+use super::{
+    types::{
+        Capabilities, Expiry, Extremum, Key, KeyRef, LogEntry, Mutation, Operation, Outcome,
+        QueueDepth, Value,
+    },
+    Invalid, Store,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{fmt, sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+
+/// Tells whether a value is acceptable.
+pub type Validator = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+pub struct ValidatingStore<S> {
+    inner: S,
+    rules: Vec<(String, Validator)>,
+}
+
+impl<S: fmt::Debug> fmt::Debug for ValidatingStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefixes: Vec<_> = self.rules.iter().map(|(prefix, _)| prefix).collect();
+        f.debug_struct("ValidatingStore")
+            .field("inner", &self.inner)
+            .field("prefixes", &prefixes)
+            .finish()
+    }
+}
+
+impl<S> ValidatingStore<S> {
+    /// Checks values against no rule until some are added.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Checks values written under keys starting with `prefix` with `validator`.
+    pub fn rule(mut self, prefix: impl Into<String>, validator: Validator) -> Self {
+        self.rules.push((prefix.into(), validator));
+        self
+    }
+
+    /// Rules applying to `key`.
+    fn rules_of<'a>(&'a self, key: KeyRef<'a>) -> impl Iterator<Item = &'a (String, Validator)> {
+        self.rules
+            .iter()
+            .filter(move |(prefix, _)| key.starts_with(prefix.as_str()))
+    }
+
+    /// Fails unless `value` satisfies every rule applying to `key`.
+    fn check(&self, key: KeyRef, value: &[u8]) -> Result<(), Invalid> {
+        let mut rules = self.rules_of(key);
+        let valid = match std::str::from_utf8(value) {
+            Ok(value) => rules.all(|(_, validator)| validator(value)),
+            Err(_) => rules.next().is_none(),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(Invalid(key.into()))
+        }
+    }
+
+    /// Fails if any rule applies to `key`, whose value the store computes.
+    fn check_unruled(&self, key: KeyRef) -> Result<(), Invalid> {
+        match self.rules_of(key).next() {
+            Some(_) => Err(Invalid(key.into())),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails unless every rule applying to `dst` applies to `src` as well.
+    fn check_rename(&self, src: KeyRef, dst: KeyRef) -> Result<(), Invalid> {
+        if self
+            .rules_of(dst)
+            .all(|(prefix, _)| src.starts_with(prefix.as_str()))
+        {
+            Ok(())
+        } else {
+            Err(Invalid(dst.into()))
+        }
+    }
+
+    fn check_op(&self, op: &Operation) -> Result<(), Invalid> {
+        match op {
+            Operation::Set { key, value } | Operation::SetNx { key, value } => {
+                self.check(key, value)
+            }
+            Operation::Rename { src, dst } => self.check_rename(src, dst),
+            Operation::Get { key: _ } | Operation::GetDel { key: _ } => Ok(()),
+        }
+    }
+}
+
+/// Accepts integers only, e.g. for counters.
+pub fn integer() -> Validator {
+    Arc::new(|value| value.parse::<i64>().is_ok())
+}
+
+#[async_trait]
+impl<S> Store for ValidatingStore<S>
+where
+    S: Store<Err = anyhow::Error>,
+{
+    type Err = anyhow::Error;
+
+    async fn get<'k>(&self, key: KeyRef<'k>) -> Result<Option<Value>, Self::Err> {
+        self.inner.get(key).await
+    }
+
+    async fn set(&mut self, key: Key, value: Value) -> Result<(), Self::Err> {
+        self.check(&key, &value)?;
+        self.inner.set(key, value).await
+    }
+
+    async fn get_range<'k>(
+        &self,
+        key: KeyRef<'k>,
+        start: i64,
+        end: i64,
+    ) -> Result<Option<Value>, Self::Err> {
+        self.inner.get_range(key, start, end).await
+    }
+
+    async fn set_get(&mut self, key: Key, value: Value) -> Result<Option<Value>, Self::Err> {
+        self.check(&key, &value)?;
+        self.inner.set_get(key, value).await
+    }
+
+    async fn set_nx(&mut self, key: Key, value: Value) -> Result<bool, Self::Err> {
+        self.check(&key, &value)?;
+        self.inner.set_nx(key, value).await
+    }
+
+    async fn rename(&mut self, src: Key, dst: Key) -> Result<bool, Self::Err> {
+        self.check_rename(&src, &dst)?;
+        self.inner.rename(src, dst).await
+    }
+
+    async fn get_del(&mut self, key: Key) -> Result<Option<Value>, Self::Err> {
+        self.inner.get_del(key).await
+    }
+
+    async fn transaction(&mut self, ops: Vec<Operation>) -> Result<Vec<Outcome>, Self::Err> {
+        for op in &ops {
+            self.check_op(op)?;
+        }
+        self.inner.transaction(ops).await
+    }
+
+    async fn version<'k>(&self, key: KeyRef<'k>) -> Result<u64, Self::Err> {
+        self.inner.version(key).await
+    }
+
+    async fn transaction_if_unchanged(
+        &mut self,
+        watched: Vec<(Key, u64)>,
+        ops: Vec<Operation>,
+    ) -> Result<Option<Vec<Outcome>>, Self::Err> {
+        for op in &ops {
+            self.check_op(op)?;
+        }
+        self.inner.transaction_if_unchanged(watched, ops).await
+    }
+
+    async fn snapshot(&self) -> Result<Vec<(Key, Value)>, Self::Err> {
+        self.inner.snapshot().await
+    }
+
+    async fn get_prefix<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, Value)>, Self::Err> {
+        self.inner.get_prefix(prefix).await
+    }
+
+    async fn mem_usage<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, usize)>, Self::Err> {
+        self.inner.mem_usage(prefix).await
+    }
+
+    async fn children<'p>(
+        &self,
+        prefix: KeyRef<'p>,
+        separator: &'p str,
+    ) -> Result<Vec<String>, Self::Err> {
+        self.inner.children(prefix, separator).await
+    }
+
+    async fn find_value<'n>(&self, needle: &'n [u8]) -> Result<Vec<Key>, Self::Err> {
+        self.inner.find_value(needle).await
+    }
+
+    async fn queue_depth(&self) -> Result<QueueDepth, Self::Err> {
+        self.inner.queue_depth().await
+    }
+
+    async fn db_size(&self) -> Result<usize, Self::Err> {
+        self.inner.db_size().await
+    }
+
+    async fn last_seq(&self) -> Result<u64, Self::Err> {
+        self.inner.last_seq().await
+    }
+
+    async fn get_log(&self, count: usize) -> Result<Vec<LogEntry>, Self::Err> {
+        self.inner.get_log(count).await
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn ping(&self) -> Result<(), Self::Err> {
+        self.inner.ping().await
+    }
+
+    async fn get_or_wait<'k>(
+        &self,
+        key: KeyRef<'k>,
+        timeout: Duration,
+    ) -> Result<Option<Value>, Self::Err> {
+        self.inner.get_or_wait(key, timeout).await
+    }
+
+    async fn checksum<'k>(&self, key: KeyRef<'k>) -> Result<Option<u32>, Self::Err> {
+        self.inner.checksum(key).await
+    }
+
+    async fn idle_time<'k>(&self, key: KeyRef<'k>) -> Result<Option<Duration>, Self::Err> {
+        self.inner.idle_time(key).await
+    }
+
+    async fn hot_keys(&self, count: usize) -> Result<Vec<Key>, Self::Err> {
+        self.inner.hot_keys(count).await
+    }
+
+    async fn expire(&mut self, key: Key, ttl: Duration) -> Result<bool, Self::Err> {
+        self.inner.expire(key, ttl).await
+    }
+
+    async fn set_ex(&mut self, key: Key, value: Value, ttl: Duration) -> Result<(), Self::Err> {
+        self.check(&key, &value)?;
+        self.inner.set_ex(key, value, ttl).await
+    }
+
+    async fn incr_by(&mut self, key: Key, delta: i64) -> Result<Option<i64>, Self::Err> {
+        self.check_unruled(&key)?;
+        self.inner.incr_by(key, delta).await
+    }
+
+    async fn set_extremum(
+        &mut self,
+        key: Key,
+        extremum: Extremum,
+        candidate: i64,
+    ) -> Result<Option<i64>, Self::Err> {
+        self.check(&key, candidate.to_string().as_bytes())?;
+        self.inner.set_extremum(key, extremum, candidate).await
+    }
+
+    async fn set_if_match(
+        &mut self,
+        key: Key,
+        pattern: Value,
+        value: Value,
+    ) -> Result<bool, Self::Err> {
+        self.check(&key, &value)?;
+        self.inner.set_if_match(key, pattern, value).await
+    }
+
+    async fn del_if_equals(&mut self, key: Key, expected: Value) -> Result<bool, Self::Err> {
+        self.inner.del_if_equals(key, expected).await
+    }
+
+    async fn set_idem(&mut self, key: Key, token: String, value: Value) -> Result<bool, Self::Err> {
+        self.check(&key, &value)?;
+        self.inner.set_idem(key, token, value).await
+    }
+
+    async fn mset_nx(&mut self, pairs: Vec<(Key, Value)>) -> Result<bool, Self::Err> {
+        for (key, value) in &pairs {
+            self.check(key, value)?;
+        }
+        self.inner.mset_nx(pairs).await
+    }
+
+    async fn lpush(&mut self, key: Key, item: Value) -> Result<usize, Self::Err> {
+        self.check_unruled(&key)?;
+        self.inner.lpush(key, item).await
+    }
+
+    async fn llen<'k>(&self, key: KeyRef<'k>) -> Result<usize, Self::Err> {
+        self.inner.llen(key).await
+    }
+
+    async fn compact(&mut self) -> Result<(), Self::Err> {
+        self.inner.compact().await
+    }
+
+    async fn sync(&self) -> Result<(), Self::Err> {
+        self.inner.sync().await
+    }
+
+    async fn ttl<'k>(&self, key: KeyRef<'k>) -> Result<Option<Expiry>, Self::Err> {
+        self.inner.ttl(key).await
+    }
+
+    async fn subscribe(&self) -> Result<broadcast::Receiver<Mutation>, Self::Err> {
+        self.inner.subscribe().await
+    }
+
+    async fn get_with_ttl<'k>(
+        &self,
+        key: KeyRef<'k>,
+    ) -> Result<Option<(Value, Expiry)>, Self::Err> {
+        self.inner.get_with_ttl(key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::inmemory;
+
+    #[tokio::test]
+    async fn non_integer_under_numeric_prefix_is_rejected() {
+        // Pre-condition.
+        let inner = inmemory::start();
+        let mut store = ValidatingStore::new(inner.clone()).rule("count:", integer());
+
+        // Action.
+        let set = store.set("count:a".into(), "ten".into()).await;
+        let set_nx = store.set_nx("count:b".into(), "1.5".into()).await;
+        let transaction = store
+            .transaction(vec![
+                Operation::Set {
+                    key: "count:c".into(),
+                    value: "1".into(),
+                },
+                Operation::Set {
+                    key: "count:d".into(),
+                    value: "x".into(),
+                },
+            ])
+            .await;
+
+        // Post-condition.
+        for outcome in [set.map(drop), set_nx.map(drop), transaction.map(drop)] {
+            let e = outcome.unwrap_err();
+            assert!(e.is::<Invalid>(), "{}", e);
+        }
+        assert!(inner.snapshot().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn valid_values_and_unrestricted_keys_pass() {
+        // Pre-condition.
+        let mut store = ValidatingStore::new(inmemory::start())
+            .rule("count:", integer())
+            .rule("count:small:", Arc::new(|value| value.len() < 3));
+
+        // Action.
+        store.set("count:a".into(), "-10".into()).await.unwrap();
+        store.set("name:a".into(), "ten".into()).await.unwrap();
+        store
+            .set("count:small:a".into(), "99".into())
+            .await
+            .unwrap();
+        let too_big = store.set("count:small:b".into(), "100".into()).await;
+
+        // Post-condition.
+        assert!(too_big.unwrap_err().is::<Invalid>());
+        assert_eq!(store.get("count:a").await.unwrap(), Some("-10".into()));
+        assert_eq!(store.get("name:a").await.unwrap(), Some("ten".into()));
+        assert_eq!(store.get("count:small:a").await.unwrap(), Some("99".into()));
+    }
+
+    #[tokio::test]
+    async fn values_computed_by_store_cannot_break_rules() {
+        // Pre-condition.
+        let inner = inmemory::start();
+        let mut store = ValidatingStore::new(inner.clone())
+            .rule("count:", integer())
+            .rule("count:small:", Arc::new(|value| value.len() < 3));
+        store.set("s".into(), "ten".into()).await.unwrap();
+        store
+            .set("count:small:a".into(), "99".into())
+            .await
+            .unwrap();
+
+        // Action.
+        let lpush = store.lpush("count:x".into(), "a".into()).await;
+        let incr_by = store.incr_by("count:small:a".into(), 1).await;
+        let max = store
+            .set_extremum("count:small:a".into(), Extremum::Max, 100)
+            .await;
+        let rename = store.rename("s".into(), "count:x".into()).await;
+        let transaction = store
+            .transaction(vec![Operation::Rename {
+                src: "s".into(),
+                dst: "count:x".into(),
+            }])
+            .await;
+        let rename_narrower = store.rename("count:y".into(), "count:small:y".into()).await;
+        let min = store
+            .set_extremum("count:small:a".into(), Extremum::Min, -9)
+            .await
+            .unwrap();
+        let rename_broader = store
+            .rename("count:small:a".into(), "count:z".into())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        for outcome in [
+            lpush.map(drop),
+            incr_by.map(drop),
+            max.map(drop),
+            rename.map(drop),
+            transaction.map(drop),
+            rename_narrower.map(drop),
+        ] {
+            let e = outcome.unwrap_err();
+            assert!(e.is::<Invalid>(), "{}", e);
+        }
+        assert_eq!(min, Some(-9));
+        assert!(rename_broader);
+        let mut snapshot = inner.snapshot().await.unwrap();
+        snapshot.sort();
+        assert_eq!(
+            snapshot,
+            vec![("count:z".into(), "-9".into()), ("s".into(), "ten".into()),]
+        );
+    }
+}
@@ -0,0 +1,1092 @@
+//! Write-ahead log that makes an inner store durable across restarts.
+//!
+//! Every mutation is applied to the inner store and appended to the log by a
+//! single background task, so the log order always matches the order in which
+//! mutations were applied. Appends are group-committed: the task buffers
+//! records and fsyncs them once either `max_batch` records are pending or
+//! `flush_interval` has elapsed since the first pending one, whichever comes
+//! first. A mutation only completes once its record is durable.
+//!
+//! Reads go straight to the inner store, hence they may observe a mutation
+//! whose record is still pending a flush.
+//!
+//! Since the log only ever grows, it can be compacted: the task rewrites it
+//! into a temporary file holding one SET record per key of a snapshot of the
+//! inner store, fsyncs it, then renames it over the log. Compaction runs on
+//! demand, and periodically every `compact_interval` if set.
+//!
+//! Syncing flushes pending records right away rather than once the batch
+//! fills up or times out.
+//!
+//! A flush that fails may leave a torn record in the log, after which nothing
+//! appended would be replayed. The log is then poisoned: further mutations and
+//! syncs are refused, until a compaction rewrites the log from the inner store,
+//! including the mutations whose flush failed.
+//!
+//! Expiry is not logged, hence `EXPIRE` and `SETEX` are unsupported, nor are
+//! increments, appends, or conditional writes, hence `INCRBY`, `MAX`, `MIN`,
+//! `LPUSH`, `LLEN`, `SETIFMATCH`, `SETIDEM`, `DELIFEQ`, `MSETNX`, and `WATCH`
+//! are unsupported too.
+//!
+//! # Record format
+//!
+//! Records are length-prefixed so that arbitrary bytes survive, and a torn
+//! record at the tail of the log (e.g. after a crash mid-write) is ignored on
+//! replay.
+//!
+//! - SET
+//!     - `0x00 $key_len:u32be $key $value_len:u32be $value`
+//! - RENAME
+//!     - `0x01 $src_len:u32be $src $dst_len:u32be $dst`
+//! - BATCH (records of a transaction, replayed all-or-nothing)
+//!     - `0x02 $records_len:u32be $records`
+//! - DELETE
+//!     - `0x03 $key_len:u32be $key`
+
+use super::{
+    types::{
+        self, Capabilities, Expiry, Key, KeyRef, LogEntry, Operation, Outcome, QueueDepth, Value,
+    },
+    Store,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use std::{
+    convert::TryInto,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::{broadcast, mpsc, oneshot},
+    time::{self, Instant},
+};
+use tracing::error;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WalConfig {
+    /// Number of pending records that triggers a flush.
+    pub max_batch: usize,
+    /// Longest time a record waits to be flushed.
+    pub flush_interval: Duration,
+    /// Time between periodic compactions of the log, if any.
+    pub compact_interval: Option<Duration>,
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        Self {
+            max_batch: 64,
+            flush_interval: Duration::from_millis(5),
+            compact_interval: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WalStore<S> {
+    inner: S,
+    messages: mpsc::Sender<Message>,
+}
+
+#[derive(Debug)]
+enum Message {
+    Append(Append),
+    Compact { cb: oneshot::Sender<Result<()>> },
+    Sync { cb: oneshot::Sender<Result<()>> },
+}
+
+#[derive(Debug)]
+struct Append {
+    mutation: Mutation,
+    cb: oneshot::Sender<Result<Vec<Outcome>>>,
+}
+
+#[derive(Debug)]
+enum Mutation {
+    Single(Operation),
+    Transaction(Vec<Operation>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Record {
+    Set { key: Key, value: Value },
+    Rename { src: Key, dst: Key },
+    Batch(Vec<Record>),
+    Delete { key: Key },
+}
+
+impl<S> WalStore<S>
+where
+    S: Store<Err = anyhow::Error> + Clone + Send + Sync + 'static,
+{
+    /// Replays the log at `path` into `inner`, then starts appending to it.
+    pub async fn open(path: impl AsRef<Path>, mut inner: S, config: WalConfig) -> Result<Self> {
+        let path = path.as_ref();
+
+        let mut log = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("unable to open WAL at {}", path.display()))?;
+
+        let valid_len = replay(&mut log, &mut inner).await?;
+        log.set_len(valid_len)
+            .await
+            .context("unable to truncate torn WAL tail")?;
+
+        let (tx, rx) = mpsc::channel(config.max_batch.max(1));
+
+        let writer = Writer {
+            log,
+            path: path.to_owned(),
+            store: inner.clone(),
+            messages: rx,
+            config,
+            poisoned: None,
+        };
+
+        tokio::spawn(writer.start());
+
+        if let Some(interval) = config.compact_interval {
+            tokio::spawn(compact_periodically(tx.downgrade(), interval));
+        }
+
+        Ok(Self {
+            inner,
+            messages: tx,
+        })
+    }
+
+    async fn append(&self, mutation: Mutation) -> Result<Vec<Outcome>> {
+        let (tx, rx) = oneshot::channel();
+        self.messages
+            .send(Message::Append(Append { mutation, cb: tx }))
+            .await
+            .context("unable to send mutation to WAL")?;
+        rx.await.context("unable to access result of WAL append")?
+    }
+
+    async fn append_applied(&self, op: Operation) -> Result<bool> {
+        match self.append(Mutation::Single(op)).await?.as_slice() {
+            [Outcome::Applied(applied)] => Ok(*applied),
+            outcomes => bail!("unexpected WAL outcomes: {:?}", outcomes),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> Store for WalStore<S>
+where
+    S: Store<Err = anyhow::Error> + Clone + Send + Sync + 'static,
+{
+    type Err = anyhow::Error;
+
+    async fn get<'k>(&self, key: KeyRef<'k>) -> Result<Option<Value>, Self::Err> {
+        self.inner.get(key).await
+    }
+
+    async fn get_range<'k>(
+        &self,
+        key: KeyRef<'k>,
+        start: i64,
+        end: i64,
+    ) -> Result<Option<Value>, Self::Err> {
+        self.inner.get_range(key, start, end).await
+    }
+
+    async fn set(&mut self, key: Key, value: Value) -> Result<(), Self::Err> {
+        self.append(Mutation::Single(Operation::Set { key, value }))
+            .await
+            .map(|_| ())
+    }
+
+    async fn set_nx(&mut self, key: Key, value: Value) -> Result<bool, Self::Err> {
+        self.append_applied(Operation::SetNx { key, value }).await
+    }
+
+    async fn rename(&mut self, src: Key, dst: Key) -> Result<bool, Self::Err> {
+        self.append_applied(Operation::Rename { src, dst }).await
+    }
+
+    async fn get_del(&mut self, key: Key) -> Result<Option<Value>, Self::Err> {
+        match self
+            .append(Mutation::Single(Operation::GetDel { key }))
+            .await?
+            .as_slice()
+        {
+            [Outcome::Value(value)] => Ok(value.clone()),
+            outcomes => bail!("unexpected WAL outcomes: {:?}", outcomes),
+        }
+    }
+
+    async fn transaction(&mut self, ops: Vec<Operation>) -> Result<Vec<Outcome>, Self::Err> {
+        self.append(Mutation::Transaction(ops)).await
+    }
+
+    async fn snapshot(&self) -> Result<Vec<(Key, Value)>, Self::Err> {
+        self.inner.snapshot().await
+    }
+
+    async fn get_or_wait<'k>(
+        &self,
+        key: KeyRef<'k>,
+        timeout: Duration,
+    ) -> Result<Option<Value>, Self::Err> {
+        self.inner.get_or_wait(key, timeout).await
+    }
+
+    async fn get_prefix<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, Value)>, Self::Err> {
+        self.inner.get_prefix(prefix).await
+    }
+
+    async fn mem_usage<'p>(&self, prefix: KeyRef<'p>) -> Result<Vec<(Key, usize)>, Self::Err> {
+        self.inner.mem_usage(prefix).await
+    }
+
+    async fn children<'p>(
+        &self,
+        prefix: KeyRef<'p>,
+        separator: &'p str,
+    ) -> Result<Vec<String>, Self::Err> {
+        self.inner.children(prefix, separator).await
+    }
+
+    async fn find_value<'n>(&self, needle: &'n [u8]) -> Result<Vec<Key>, Self::Err> {
+        self.inner.find_value(needle).await
+    }
+
+    async fn queue_depth(&self) -> Result<QueueDepth, Self::Err> {
+        self.inner.queue_depth().await
+    }
+
+    async fn db_size(&self) -> Result<usize, Self::Err> {
+        self.inner.db_size().await
+    }
+
+    async fn last_seq(&self) -> Result<u64, Self::Err> {
+        self.inner.last_seq().await
+    }
+
+    async fn get_log(&self, count: usize) -> Result<Vec<LogEntry>, Self::Err> {
+        self.inner.get_log(count).await
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            expiry: false,
+            sync: true,
+            ..self.inner.capabilities()
+        }
+    }
+
+    async fn ping(&self) -> Result<(), Self::Err> {
+        self.inner.ping().await
+    }
+
+    async fn checksum<'k>(&self, key: KeyRef<'k>) -> Result<Option<u32>, Self::Err> {
+        self.inner.checksum(key).await
+    }
+
+    async fn idle_time<'k>(&self, key: KeyRef<'k>) -> Result<Option<Duration>, Self::Err> {
+        self.inner.idle_time(key).await
+    }
+
+    async fn hot_keys(&self, count: usize) -> Result<Vec<Key>, Self::Err> {
+        self.inner.hot_keys(count).await
+    }
+
+    async fn ttl<'k>(&self, key: KeyRef<'k>) -> Result<Option<Expiry>, Self::Err> {
+        self.inner.ttl(key).await
+    }
+
+    async fn subscribe(&self) -> Result<broadcast::Receiver<types::Mutation>, Self::Err> {
+        self.inner.subscribe().await
+    }
+
+    async fn get_with_ttl<'k>(
+        &self,
+        key: KeyRef<'k>,
+    ) -> Result<Option<(Value, Expiry)>, Self::Err> {
+        self.inner.get_with_ttl(key).await
+    }
+
+    async fn compact(&mut self) -> Result<(), Self::Err> {
+        let (tx, rx) = oneshot::channel();
+        self.messages
+            .send(Message::Compact { cb: tx })
+            .await
+            .context("unable to send compaction to WAL")?;
+        rx.await
+            .context("unable to access result of WAL compaction")?
+    }
+
+    async fn sync(&self) -> Result<(), Self::Err> {
+        let (tx, rx) = oneshot::channel();
+        self.messages
+            .send(Message::Sync { cb: tx })
+            .await
+            .context("unable to send sync to WAL")?;
+        rx.await.context("unable to access result of WAL sync")??;
+        self.inner.sync().await
+    }
+}
+
+/// Compacts the log every `interval` for as long as the store is in use.
+async fn compact_periodically(messages: mpsc::WeakSender<Message>, interval: Duration) {
+    let mut ticks = time::interval_at(Instant::now() + interval, interval);
+    loop {
+        ticks.tick().await;
+
+        let messages = match messages.upgrade() {
+            Some(messages) => messages,
+            None => break,
+        };
+        let (tx, rx) = oneshot::channel();
+        if messages.send(Message::Compact { cb: tx }).await.is_err() {
+            break;
+        }
+        if let Ok(Err(e)) = rx.await {
+            error!(reason = %e, "unable to compact WAL");
+        }
+    }
+}
+
+/// Outcome of an applied mutation, delivered through `cb` once its record is durable.
+type Pending = (oneshot::Sender<Result<Vec<Outcome>>>, Result<Vec<Outcome>>);
+
+struct Writer<S> {
+    log: File,
+    path: PathBuf,
+    store: S,
+    messages: mpsc::Receiver<Message>,
+    config: WalConfig,
+    /// Why the log may hold a torn record, refusing to append until compacted, if ever.
+    poisoned: Option<String>,
+}
+
+impl<S> Writer<S>
+where
+    S: Store<Err = anyhow::Error>,
+{
+    async fn start(mut self) {
+        let mut buffer = Vec::new();
+        let mut pending = Vec::new();
+
+        let deadline = time::sleep(self.config.flush_interval);
+        tokio::pin!(deadline);
+
+        loop {
+            tokio::select! {
+                message = self.messages.recv() => match message {
+                    Some(Message::Append(Append { mutation, cb })) => {
+                        if let Some(reason) = &self.poisoned {
+                            let _ = cb.send(Err(anyhow!("WAL is poisoned: {}", reason)));
+                            continue;
+                        }
+                        if pending.is_empty() {
+                            deadline.as_mut().reset(Instant::now() + self.config.flush_interval);
+                        }
+
+                        let outcome = self.apply(mutation, &mut buffer).await;
+                        pending.push((cb, outcome));
+
+                        if pending.len() >= self.config.max_batch {
+                            self.flush(&mut buffer, &mut pending).await;
+                        }
+                    }
+                    Some(Message::Compact { cb }) => {
+                        self.flush(&mut buffer, &mut pending).await;
+                        let compacted = self.compact().await;
+                        if compacted.is_ok() {
+                            self.poisoned = None;
+                        }
+                        let _ = cb.send(compacted);
+                    }
+                    Some(Message::Sync { cb }) => {
+                        self.flush(&mut buffer, &mut pending).await;
+                        let synced = match &self.poisoned {
+                            Some(reason) => Err(anyhow!("WAL is poisoned: {}", reason)),
+                            None => self.log.sync_all().await.context("unable to sync WAL"),
+                        };
+                        let _ = cb.send(synced);
+                    }
+                    None => {
+                        self.flush(&mut buffer, &mut pending).await;
+                        break;
+                    }
+                },
+                _ = &mut deadline, if !pending.is_empty() => {
+                    self.flush(&mut buffer, &mut pending).await;
+                }
+            }
+        }
+    }
+
+    async fn apply(&mut self, mutation: Mutation, buffer: &mut Vec<u8>) -> Result<Vec<Outcome>> {
+        let (record, outcomes) = match mutation {
+            Mutation::Single(op) => {
+                let outcome = self.apply_single(op.clone()).await?;
+                (Record::of(op, &outcome), vec![outcome])
+            }
+            Mutation::Transaction(ops) => {
+                let outcomes = self.store.transaction(ops.clone()).await?;
+                let records: Vec<_> = ops
+                    .into_iter()
+                    .zip(&outcomes)
+                    .filter_map(|(op, outcome)| Record::of(op, outcome))
+                    .collect();
+                let record = (!records.is_empty()).then_some(Record::Batch(records));
+                (record, outcomes)
+            }
+        };
+
+        if let Some(record) = record {
+            record.encode(buffer);
+        }
+
+        Ok(outcomes)
+    }
+
+    async fn apply_single(&mut self, op: Operation) -> Result<Outcome> {
+        let outcome = match op {
+            Operation::Get { key } => Outcome::Value(self.store.get(&key).await?),
+            Operation::Set { key, value } => {
+                self.store.set(key, value).await?;
+                Outcome::Done
+            }
+            Operation::SetNx { key, value } => {
+                Outcome::Applied(self.store.set_nx(key, value).await?)
+            }
+            Operation::Rename { src, dst } => Outcome::Applied(self.store.rename(src, dst).await?),
+            Operation::GetDel { key } => Outcome::Value(self.store.get_del(key).await?),
+        };
+        Ok(outcome)
+    }
+
+    async fn flush(&mut self, buffer: &mut Vec<u8>, pending: &mut Vec<Pending>) {
+        if pending.is_empty() {
+            return;
+        }
+
+        let flushed = self.write(buffer).await;
+        if let Err(e) = &flushed {
+            error!(reason = %e, "unable to flush WAL, poisoning it until compacted");
+            self.poisoned = Some(e.to_string());
+        }
+        buffer.clear();
+
+        for (cb, outcome) in pending.drain(..) {
+            let outcome = match &flushed {
+                Ok(()) => outcome,
+                Err(e) => Err(anyhow!("unable to flush WAL: {}", e)),
+            };
+            let _ = cb.send(outcome);
+        }
+    }
+
+    async fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        if !buffer.is_empty() {
+            self.log.write_all(buffer).await?;
+            // Surfaces the failure of a write still in flight, if any.
+            self.log.flush().await?;
+            self.log.sync_data().await?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the log with one holding a SET record per key currently in the store.
+    ///
+    /// The log is swapped atomically, so that a crash leaves either the old or the new one.
+    async fn compact(&mut self) -> Result<()> {
+        let mut bytes = Vec::new();
+        for (key, value) in self.store.snapshot().await? {
+            Record::Set { key, value }.encode(&mut bytes);
+        }
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut compacted = File::create(&tmp_path)
+            .await
+            .with_context(|| format!("unable to create {}", tmp_path.display()))?;
+        compacted
+            .write_all(&bytes)
+            .await
+            .context("unable to write compacted WAL")?;
+        compacted
+            .sync_all()
+            .await
+            .context("unable to sync compacted WAL")?;
+
+        fs::rename(&tmp_path, &self.path)
+            .await
+            .context("unable to swap in compacted WAL")?;
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)
+            .await?
+            .sync_all()
+            .await
+            .context("unable to sync WAL directory")?;
+
+        // The compacted log is positioned at its end, hence further records are appended.
+        self.log = compacted;
+        Ok(())
+    }
+}
+
+/// Applies every complete record to `store`, returning the length of the log they span.
+async fn replay<S>(log: &mut File, store: &mut S) -> Result<u64>
+where
+    S: Store<Err = anyhow::Error>,
+{
+    let mut bytes = Vec::new();
+    log.read_to_end(&mut bytes)
+        .await
+        .context("unable to read WAL")?;
+
+    let mut remaining = bytes.as_slice();
+    while let Some(record) = Record::decode(&mut remaining)? {
+        record.replay(store).await?;
+    }
+
+    Ok((bytes.len() - remaining.len()) as u64)
+}
+
+impl Record {
+    const SET: u8 = 0x00;
+    const RENAME: u8 = 0x01;
+    const BATCH: u8 = 0x02;
+    const DELETE: u8 = 0x03;
+
+    /// Record of the effect `op` had, if any.
+    fn of(op: Operation, outcome: &Outcome) -> Option<Self> {
+        match (op, outcome) {
+            (Operation::Set { key, value }, _)
+            | (Operation::SetNx { key, value }, Outcome::Applied(true)) => {
+                Some(Record::Set { key, value })
+            }
+            (Operation::Rename { src, dst }, Outcome::Applied(true)) => {
+                Some(Record::Rename { src, dst })
+            }
+            (Operation::GetDel { key }, Outcome::Value(Some(_))) => Some(Record::Delete { key }),
+            _ => None,
+        }
+    }
+
+    async fn replay<S>(self, store: &mut S) -> Result<()>
+    where
+        S: Store<Err = anyhow::Error>,
+    {
+        match self {
+            Record::Set { key, value } => store.set(key, value).await?,
+            Record::Rename { src, dst } => {
+                store.rename(src, dst).await?;
+            }
+            Record::Batch(records) => {
+                for record in records {
+                    Box::pin(record.replay(store)).await?;
+                }
+            }
+            Record::Delete { key } => {
+                store.get_del(key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn encode(&self, dst: &mut Vec<u8>) {
+        match self {
+            Record::Set { key, value } => {
+                dst.push(Self::SET);
+                put_field(dst, key.as_bytes());
+                put_field(dst, value);
+            }
+            Record::Rename { src, dst: to } => {
+                dst.push(Self::RENAME);
+                put_field(dst, src.as_bytes());
+                put_field(dst, to.as_bytes());
+            }
+            Record::Batch(records) => {
+                let mut nested = Vec::new();
+                for record in records {
+                    record.encode(&mut nested);
+                }
+                dst.push(Self::BATCH);
+                put_field(dst, &nested);
+            }
+            Record::Delete { key } => {
+                dst.push(Self::DELETE);
+                put_field(dst, key.as_bytes());
+            }
+        }
+    }
+
+    /// Decodes the next record, or `None` when `src` holds no complete record.
+    fn decode(src: &mut &[u8]) -> Result<Option<Self>> {
+        let mut cursor = *src;
+
+        let tag = match cursor.split_first() {
+            Some((tag, rest)) => {
+                cursor = rest;
+                *tag
+            }
+            None => return Ok(None),
+        };
+
+        let record = match tag {
+            Self::SET => match (take_field(&mut cursor), take_field(&mut cursor)) {
+                (Some(key), Some(value)) => Record::Set {
+                    key: into_string(key)?,
+                    value: Value::copy_from_slice(value),
+                },
+                _ => return Ok(None),
+            },
+            Self::RENAME => match (take_field(&mut cursor), take_field(&mut cursor)) {
+                (Some(src), Some(dst)) => Record::Rename {
+                    src: into_string(src)?,
+                    dst: into_string(dst)?,
+                },
+                _ => return Ok(None),
+            },
+            Self::BATCH => {
+                let mut nested = match take_field(&mut cursor) {
+                    Some(nested) => nested,
+                    None => return Ok(None),
+                };
+
+                let mut records = Vec::new();
+                while !nested.is_empty() {
+                    let record = Record::decode(&mut nested)?.context("WAL batch is truncated")?;
+                    records.push(record);
+                }
+                Record::Batch(records)
+            }
+            Self::DELETE => match take_field(&mut cursor) {
+                Some(key) => Record::Delete {
+                    key: into_string(key)?,
+                },
+                None => return Ok(None),
+            },
+            _ => bail!("unrecognized WAL record tag: {:#04x}", tag),
+        };
+
+        *src = cursor;
+        Ok(Some(record))
+    }
+}
+
+fn put_field(dst: &mut Vec<u8>, field: &[u8]) {
+    dst.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    dst.extend_from_slice(field);
+}
+
+fn take_field<'a>(src: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if src.len() < 4 {
+        return None;
+    }
+    let (len, rest) = src.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().expect("length prefix has 4 bytes")) as usize;
+
+    if rest.len() < len {
+        return None;
+    }
+    let (field, rest) = rest.split_at(len);
+
+    *src = rest;
+    Some(field)
+}
+
+fn into_string(field: &[u8]) -> Result<String> {
+    String::from_utf8(field.to_vec()).context("WAL record is not valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::inmemory;
+
+    #[tokio::test]
+    async fn concurrent_sets_survive_restart() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        let config = WalConfig {
+            max_batch: 8,
+            flush_interval: Duration::from_millis(5),
+            compact_interval: None,
+        };
+
+        let store = WalStore::open(&path, inmemory::start(), config)
+            .await
+            .unwrap();
+
+        // Action.
+        let sets = (0..100).map(|i| {
+            let mut store = store.clone();
+            tokio::spawn(
+                async move { store.set(format!("k{}", i), format!("v{}", i).into()).await },
+            )
+        });
+        for set in futures::future::join_all(sets).await {
+            set.unwrap().unwrap();
+        }
+        drop(store);
+
+        let store = WalStore::open(&path, inmemory::start(), config)
+            .await
+            .unwrap();
+
+        // Post-condition.
+        for i in 0..100 {
+            let value = store.get(&format!("k{}", i)).await.unwrap();
+            assert_eq!(value, Some(format!("v{}", i).into()));
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_applies_only_effective_mutations_in_order() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+
+        let mut store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+        store.set("k".into(), "a".into()).await.unwrap();
+        assert!(!store.set_nx("k".into(), "b".into()).await.unwrap());
+        assert!(store.rename("k".into(), "j".into()).await.unwrap());
+        assert!(!store.rename("k".into(), "i".into()).await.unwrap());
+        store.set("h".into(), "c".into()).await.unwrap();
+        assert_eq!(store.get_del("h".into()).await.unwrap(), Some("c".into()));
+        assert_eq!(store.get_del("h".into()).await.unwrap(), None);
+        drop(store);
+
+        // Action.
+        let store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(store.get("k").await.unwrap(), None);
+        assert_eq!(store.get("i").await.unwrap(), None);
+        assert_eq!(store.get("h").await.unwrap(), None);
+        assert_eq!(store.get("j").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn transaction_survives_restart() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+
+        let mut store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+
+        // Action.
+        let outcomes = store
+            .transaction(vec![
+                Operation::Set {
+                    key: "k".into(),
+                    value: "a".into(),
+                },
+                Operation::SetNx {
+                    key: "k".into(),
+                    value: "b".into(),
+                },
+                Operation::Rename {
+                    src: "k".into(),
+                    dst: "j".into(),
+                },
+                Operation::Get { key: "j".into() },
+            ])
+            .await
+            .unwrap();
+        drop(store);
+
+        let store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Done,
+                Outcome::Applied(false),
+                Outcome::Applied(true),
+                Outcome::Value(Some("a".into())),
+            ]
+        );
+        assert_eq!(store.get("k").await.unwrap(), None);
+        assert_eq!(store.get("j").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn set_get_returns_displaced_value_and_survives_restart() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+
+        let mut store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+        store.set("k".into(), "a".into()).await.unwrap();
+
+        // Action.
+        let displaced = store.set_get("k".into(), "b".into()).await.unwrap();
+        drop(store);
+
+        let store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(displaced, Some("a".into()));
+        assert_eq!(store.get("k").await.unwrap(), Some("b".into()));
+    }
+
+    #[tokio::test]
+    async fn appends_after_torn_tail_survive_restart() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+
+        let mut store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+        store.set("k".into(), "a".into()).await.unwrap();
+        drop(store);
+
+        let mut log = OpenOptions::new().append(true).open(&path).await.unwrap();
+        log.write_all(&[Record::SET, 0, 0]).await.unwrap();
+        log.flush().await.unwrap();
+
+        // Action.
+        let mut store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+        store.set("j".into(), "b".into()).await.unwrap();
+        drop(store);
+
+        let store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(store.get("k").await.unwrap(), Some("a".into()));
+        assert_eq!(store.get("j").await.unwrap(), Some("b".into()));
+    }
+
+    #[tokio::test]
+    async fn compaction_shrinks_log_preserving_data() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+
+        let mut store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+        for i in 0..20 {
+            store
+                .set("k".into(), format!("v{}", i).into())
+                .await
+                .unwrap();
+        }
+        store.set("j".into(), "a".into()).await.unwrap();
+        let before = fs::metadata(&path).await.unwrap().len();
+
+        // Action.
+        store.compact().await.unwrap();
+        let after = fs::metadata(&path).await.unwrap().len();
+
+        store.set("i".into(), "b".into()).await.unwrap();
+        drop(store);
+
+        let store = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert!(after < before, "{} >= {}", after, before);
+        assert_eq!(store.get("k").await.unwrap(), Some("v19".into()));
+        assert_eq!(store.get("j").await.unwrap(), Some("a".into()));
+        assert_eq!(store.get("i").await.unwrap(), Some("b".into()));
+        assert_eq!(store.db_size().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn sync_flushes_pending_records_without_waiting_for_batch() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        let config = WalConfig {
+            max_batch: 64,
+            flush_interval: Duration::from_secs(3600),
+            compact_interval: None,
+        };
+
+        let store = WalStore::open(&path, inmemory::start(), config)
+            .await
+            .unwrap();
+        let mut writer = store.clone();
+        let mut set = writer.set("k".into(), "a".into());
+        assert!(futures::poll!(&mut set).is_pending());
+        assert_eq!(fs::metadata(&path).await.unwrap().len(), 0);
+
+        // Action.
+        store.sync().await.unwrap();
+        set.await.unwrap();
+
+        let reopened = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert_eq!(reopened.get("k").await.unwrap(), Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn failed_flush_poisons_log_until_compacted() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        let inner = inmemory::start();
+        let (tx, rx) = mpsc::channel(1);
+        let writer = Writer {
+            // Every write fails as if the disk were full.
+            log: OpenOptions::new()
+                .write(true)
+                .open("/dev/full")
+                .await
+                .unwrap(),
+            path: path.clone(),
+            store: inner.clone(),
+            messages: rx,
+            config: WalConfig::default(),
+            poisoned: None,
+        };
+        tokio::spawn(writer.start());
+        let mut store = WalStore {
+            inner,
+            messages: tx,
+        };
+
+        // Action.
+        let failed = store.set("k".into(), "a".into()).await;
+        let refused = store.set("j".into(), "b".into()).await;
+        let unsynced = store.sync().await;
+        store.compact().await.unwrap();
+        store.set("i".into(), "c".into()).await.unwrap();
+        drop(store);
+
+        let reopened = WalStore::open(&path, inmemory::start(), WalConfig::default())
+            .await
+            .unwrap();
+
+        // Post-condition.
+        assert!(failed.is_err());
+        assert!(format!("{:#}", refused.unwrap_err()).contains("poisoned"));
+        assert!(format!("{:#}", unsynced.unwrap_err()).contains("poisoned"));
+        assert_eq!(reopened.get("k").await.unwrap(), Some("a".into()));
+        assert_eq!(reopened.get("j").await.unwrap(), None);
+        assert_eq!(reopened.get("i").await.unwrap(), Some("c".into()));
+    }
+
+    #[tokio::test]
+    async fn log_is_compacted_periodically() {
+        // Pre-condition.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        let config = WalConfig {
+            compact_interval: Some(Duration::from_millis(50)),
+            ..WalConfig::default()
+        };
+
+        let mut store = WalStore::open(&path, inmemory::start(), config)
+            .await
+            .unwrap();
+        for i in 0..20 {
+            store
+                .set("k".into(), format!("v{}", i).into())
+                .await
+                .unwrap();
+        }
+        let before = fs::metadata(&path).await.unwrap().len();
+
+        // Action.
+        time::sleep(Duration::from_millis(200)).await;
+
+        // Post-condition.
+        let after = fs::metadata(&path).await.unwrap().len();
+        assert!(after < before, "{} >= {}", after, before);
+    }
+
+    #[test]
+    fn decode_ignores_torn_batch_at_tail() {
+        // Pre-condition.
+        let mut bytes = Vec::new();
+        Record::Batch(vec![
+            Record::Set {
+                key: "k".into(),
+                value: "a".into(),
+            },
+            Record::Rename {
+                src: "k".into(),
+                dst: "j".into(),
+            },
+        ])
+        .encode(&mut bytes);
+        bytes.truncate(bytes.len() - 1);
+
+        let mut remaining = bytes.as_slice();
+
+        // Action.
+        let record = Record::decode(&mut remaining).unwrap();
+
+        // Post-condition.
+        assert_eq!(record, None);
+        assert_eq!(remaining.len(), bytes.len());
+    }
+
+    #[test]
+    fn decode_ignores_torn_record_at_tail() {
+        // Pre-condition.
+        let mut bytes = Vec::new();
+        Record::Set {
+            key: "k".into(),
+            value: "a".into(),
+        }
+        .encode(&mut bytes);
+        Record::Set {
+            key: "j".into(),
+            value: "b".into(),
+        }
+        .encode(&mut bytes);
+        bytes.truncate(bytes.len() - 1);
+
+        let mut remaining = bytes.as_slice();
+
+        // Action.
+        let first = Record::decode(&mut remaining).unwrap();
+        let second = Record::decode(&mut remaining).unwrap();
+
+        // Post-condition.
+        assert_eq!(
+            first,
+            Some(Record::Set {
+                key: "k".into(),
+                value: "a".into()
+            })
+        );
+        assert_eq!(second, None);
+    }
+}
@@ -0,0 +1,135 @@
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use toy_storage::{api::Server, storage::inmemory};
+
+#[tokio::test]
+async fn set_then_get_returns_value_set() {
+    // Pre-condition.
+    let mut client = Client::connect(serve().await).await;
+
+    // Action.
+    let set = client.exchange("SET k v\n").await;
+    let get = client.exchange("GET k\n").await;
+
+    // Post-condition.
+    assert_eq!(set, "OKAY k\n");
+    assert_eq!(get, "OKAY k v\n");
+}
+
+#[tokio::test]
+async fn get_of_missing_key_fails() {
+    // Pre-condition.
+    let mut client = Client::connect(serve().await).await;
+
+    // Action.
+    let get = client.exchange("GET k\n").await;
+
+    // Post-condition.
+    assert_eq!(get, "FAIL k\n");
+}
+
+#[tokio::test]
+async fn clients_share_the_same_store() {
+    // Pre-condition.
+    let addr = serve().await;
+    let mut first = Client::connect(addr).await;
+    let mut second = Client::connect(addr).await;
+
+    // Action.
+    first.exchange("SET k v\n").await;
+    let get = second.exchange("GET k\n").await;
+
+    // Post-condition.
+    assert_eq!(get, "OKAY k v\n");
+}
+
+#[tokio::test]
+#[cfg(all(
+    feature = "pubsub",
+    feature = "admin",
+    feature = "expiry",
+    feature = "extended"
+))]
+async fn writes_on_leader_appear_on_follower() {
+    use toy_storage::api::follower::FollowerTask;
+
+    // Pre-condition.
+    let leader_addr = serve().await;
+    let mut leader = Client::connect(leader_addr).await;
+    leader.exchange("SET k a\n").await;
+    leader.exchange("SET j b\n").await;
+
+    let replica = inmemory::start();
+    tokio::spawn(FollowerTask::new(leader_addr, replica.clone()).start());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let follower_addr = listener.local_addr().unwrap();
+    tokio::spawn(Server::new(listener, replica).read_only(true).start());
+    let mut follower = Client::connect(follower_addr).await;
+
+    // Action.
+    leader.exchange("SET k c\n").await;
+    leader.exchange("GETDEL j\n").await;
+    leader.exchange("SET i d\n").await;
+
+    // Post-condition.
+    assert_eq!(
+        follower.await_response("GET i\n", "OKAY i d\n").await,
+        "OKAY i d\n"
+    );
+    assert_eq!(follower.exchange("GET k\n").await, "OKAY k c\n");
+    assert_eq!(follower.exchange("GET j\n").await, "FAIL j\n");
+    assert_eq!(
+        follower.exchange("SET k e\n").await,
+        "ERR read-only server\n"
+    );
+}
+
+/// Starts a server backed by an in-memory store on an ephemeral port, returning its address.
+async fn serve() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(Server::new(listener, inmemory::start()).start());
+    addr
+}
+
+struct Client(BufReader<TcpStream>);
+
+impl Client {
+    async fn connect(addr: SocketAddr) -> Self {
+        Self(BufReader::new(TcpStream::connect(addr).await.unwrap()))
+    }
+
+    /// Sends `request`, returning the response line.
+    async fn exchange(&mut self, request: &str) -> String {
+        self.0.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        self.0.read_line(&mut response).await.unwrap();
+        response
+    }
+
+    /// Sends `request` until answered with `expected`, giving up after a while, returning the
+    /// last response line.
+    #[cfg(all(
+        feature = "pubsub",
+        feature = "admin",
+        feature = "expiry",
+        feature = "extended"
+    ))]
+    async fn await_response(&mut self, request: &str, expected: &str) -> String {
+        use std::time::Duration;
+        use tokio::time;
+
+        let mut response = String::new();
+        for _ in 0..100 {
+            response = self.exchange(request).await;
+            if response == expected {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        response
+    }
+}